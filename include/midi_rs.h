@@ -0,0 +1,167 @@
+#ifndef MIDI_RS_H
+#define MIDI_RS_H
+
+/* Generated from src/ffi.rs; regenerate with `cbindgen --config cbindgen.toml --crate midi-rs --output include/midi_rs.h` */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+#define PitchBend_CENTER 8192
+
+#define PitchBend_MAX 16383
+
+#define MIDI_OK 0
+
+#define MIDI_ERR_NULL -1
+
+#define MIDI_ERR_RANGE -2
+
+#define MIDI_ERR_UNSUPPORTED -3
+
+#define MIDI_ERR_BACKEND -4
+
+typedef struct MidiEventIter MidiEventIter;
+
+typedef struct MidiFile MidiFile;
+
+typedef struct MidiOutput MidiOutput;
+
+typedef struct MidiPlayer MidiPlayer;
+
+/**
+ * Flattened view of one event. `data1`/`data2` hold the channel message
+ * bytes, `meta_type` is set for meta events and `value` carries wide values
+ * such as 14-bit controllers and pitch bends.
+ */
+typedef struct MidiEventInfo {
+  uint32_t tick;
+  uint32_t delta_tick;
+  uint8_t status;
+  uint8_t meta_type;
+  uint8_t data1;
+  uint8_t data2;
+  uint32_t value;
+} MidiEventInfo;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Returns null when the file can't be opened or parsed
+ *
+ * # Safety
+ * `path` must be a valid NUL-terminated string
+ */
+struct MidiFile *midi_file_parse(const char *path);
+
+/**
+ * # Safety
+ * `file` must come from `midi_file_parse` and not be used afterwards
+ */
+void midi_file_free(struct MidiFile *file);
+
+/**
+ * # Safety
+ * `file` must be null or a live pointer from `midi_file_parse`
+ */
+uint16_t midi_file_division(const struct MidiFile *file);
+
+/**
+ * # Safety
+ * `file` must be null or a live pointer from `midi_file_parse`
+ */
+uint32_t midi_file_tempo(const struct MidiFile *file);
+
+/**
+ * # Safety
+ * `file` must be null or a live pointer from `midi_file_parse`
+ */
+uintptr_t midi_file_track_count(const struct MidiFile *file);
+
+/**
+ * Copies the track's name into `buffer` as a NUL-terminated string, cut to
+ * fit `capacity`, and returns the name's full length in bytes like
+ * `snprintf` does. Returns 0 for an unknown track.
+ *
+ * # Safety
+ * `file` must be null or a live pointer from `midi_file_parse`, and
+ * `buffer` must be null or hold `capacity` writable bytes
+ */
+uintptr_t midi_file_track_name(const struct MidiFile *file,
+                               uintptr_t track,
+                               char *buffer,
+                               uintptr_t capacity);
+
+/**
+ * Returns null for an unknown track. The iterator copies the events, so it
+ * stays valid even after the file is freed.
+ *
+ * # Safety
+ * `file` must be null or a live pointer from `midi_file_parse`
+ */
+struct MidiEventIter *midi_event_iter_new(const struct MidiFile *file, uintptr_t track);
+
+/**
+ * Writes the next event into `out` and returns 1, or returns 0 at the end
+ *
+ * # Safety
+ * `iter` must come from `midi_event_iter_new` and `out` must be writable
+ */
+int midi_event_iter_next(struct MidiEventIter *iter, struct MidiEventInfo *out);
+
+/**
+ * # Safety
+ * `iter` must come from `midi_event_iter_new` and not be used afterwards
+ */
+void midi_event_iter_free(struct MidiEventIter *iter);
+
+/**
+ * Opens an output device by index, returning null on failure
+ *
+ * # Safety
+ * The returned pointer must be released with `midi_output_close`
+ */
+struct MidiOutput *midi_output_open(uint32_t device_id);
+
+/**
+ * # Safety
+ * `output` must be null or a live pointer from `midi_output_open`
+ */
+int midi_output_send(struct MidiOutput *output, uint8_t status, uint8_t data1, uint8_t data2);
+
+/**
+ * # Safety
+ * `output` must come from `midi_output_open` and not be used afterwards
+ */
+void midi_output_close(struct MidiOutput *output);
+
+/**
+ * Takes ownership of `file`; it must not be freed separately afterwards
+ *
+ * # Safety
+ * `file` must be null or a live pointer from `midi_file_parse`
+ */
+struct MidiPlayer *midi_player_create(struct MidiFile *file);
+
+/**
+ * Plays the whole file on `output`, blocking until it finishes
+ *
+ * # Safety
+ * `player` and `output` must be live pointers from this API
+ */
+int midi_player_play(struct MidiPlayer *player, struct MidiOutput *output);
+
+/**
+ * # Safety
+ * `player` must come from `midi_player_create` and not be used afterwards
+ */
+void midi_player_free(struct MidiPlayer *player);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* MIDI_RS_H */
@@ -0,0 +1,195 @@
+use std::{collections::HashMap, error::Error, fmt::Write};
+
+use crate::{
+    duration::NoteValue,
+    key::{Key, Mode},
+    meter::SignatureMap,
+    parser::MidiFile,
+};
+
+const LETTERS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+
+fn key_field(key: &Key) -> String {
+    match key.mode {
+        Mode::Major => key.tonic_name().to_string(),
+        Mode::Minor => format!("{}m", key.tonic_name()),
+    }
+}
+
+/// A length in ABC's unit of an eighth note, e.g. "3/2"; empty for one unit
+fn length(ticks: u32, division: u16) -> String {
+    let value = NoteValue::fraction(ticks * 2, division as u32);
+    match (value.numerator, value.denominator) {
+        (1, 1) => String::new(),
+        (n, 1) => n.to_string(),
+        (1, d) => format!("/{}", d),
+        (n, d) => format!("{}/{}", n, d),
+    }
+}
+
+/// Writes a tune bar by bar, keeping track of the accidentals a bar has
+/// already set
+struct AbcWriter {
+    division: u16,
+    map: SignatureMap,
+    key: Key,
+    keys: Vec<(u32, Key)>,
+    /// Alteration in effect for a letter and octave until the bar line
+    bar_accidentals: HashMap<(u8, i8), i8>,
+    bars: u32,
+    out: String,
+}
+
+impl AbcWriter {
+    fn pitch(&mut self, midi_key: u8) -> String {
+        let (letter, alteration, octave) = self.key.spell(midi_key);
+        let current = self
+            .bar_accidentals
+            .get(&(letter, octave))
+            .copied()
+            .unwrap_or_else(|| self.key.signature_alteration(letter));
+        let mut out = String::new();
+        if alteration != current {
+            out.push(match alteration {
+                1 => '^',
+                -1 => '_',
+                _ => '=',
+            });
+            self.bar_accidentals.insert((letter, octave), alteration);
+        }
+        let name = LETTERS[letter as usize];
+        if octave >= 5 {
+            out.push(name.to_ascii_lowercase());
+            out.push_str(&"'".repeat(octave as usize - 5));
+        } else {
+            out.push(name);
+            out.push_str(&",".repeat((4 - octave) as usize));
+        }
+        out
+    }
+
+    /// Bar line before `tick`, with any meter or key change that starts
+    /// there, and a line break every four bars
+    fn bar_line(&mut self, tick: u32) {
+        self.bar_accidentals.clear();
+        self.bars += 1;
+        self.out.push_str(if self.bars.is_multiple_of(4) {
+            " |\n"
+        } else {
+            " | "
+        });
+        if let Some(change) = self
+            .map
+            .changes
+            .iter()
+            .find(|c| c.tick == tick && c.tick > 0)
+        {
+            let signature = change.signature;
+            let _ = write!(
+                self.out,
+                "[M:{}/{}] ",
+                signature.numerator, signature.denominator
+            );
+        }
+        while let Some(&(_, key)) = self.keys.first().filter(|(t, _)| *t <= tick) {
+            self.keys.remove(0);
+            if key != self.key {
+                self.key = key;
+                let _ = write!(self.out, "[K:{}] ", key_field(&key));
+            }
+        }
+    }
+
+    /// A note, or a rest when `key` is None, from `start` to `end`, split and
+    /// tied across bar lines
+    fn span(&mut self, key: Option<u8>, mut start: u32, end: u32) {
+        while start < end {
+            let bar_end = self.map.next_bar_start(start);
+            let stop = if bar_end > start {
+                end.min(bar_end)
+            } else {
+                end
+            };
+            let symbol = match key {
+                Some(key) => self.pitch(key),
+                None => "z".to_string(),
+            };
+            let _ = write!(
+                self.out,
+                "{}{}",
+                symbol,
+                length(stop - start, self.division)
+            );
+            if key.is_some() && stop < end {
+                self.out.push('-');
+            }
+            if stop == bar_end {
+                self.bar_line(stop);
+            } else {
+                self.out.push(' ');
+            }
+            start = stop;
+        }
+    }
+}
+
+impl MidiFile {
+    /// The melody of one track as an ABC tune, snapped to sixteenth notes.
+    /// Chords keep only their top note. Meter and key come from the whole
+    /// file, so they may sit on a separate conductor track.
+    pub fn to_abc(&self, track: usize) -> Result<String, Box<dyn Error>> {
+        let file = self.loaded();
+        let melody = file
+            .tracks
+            .get(track)
+            .ok_or_else(|| format!("No track {}", track))?
+            .melody(self.division as u32 / 4);
+        let title = match file.tracks[track].name.as_str() {
+            "" => format!("Track {}", track + 1),
+            name => name.to_string(),
+        };
+
+        let mut keys = self.key_signatures();
+        let key = match keys.first() {
+            Some(&(0, key)) => {
+                keys.remove(0);
+                key
+            }
+            _ => Key::from_signature(0, Mode::Major).ok_or("Bad key")?,
+        };
+        let map = self.signature_map();
+        let signature = map.signature_at(0).signature;
+
+        let mut out = String::new();
+        let _ = writeln!(out, "X:1");
+        let _ = writeln!(out, "T:{}", title);
+        let _ = writeln!(out, "M:{}/{}", signature.numerator, signature.denominator);
+        let _ = writeln!(out, "L:1/8");
+        let _ = writeln!(out, "Q:1/4={}", self.bpm);
+        let _ = writeln!(out, "K:{}", key_field(&key));
+
+        let mut writer = AbcWriter {
+            division: self.division,
+            map,
+            key,
+            keys,
+            bar_accidentals: HashMap::new(),
+            bars: 0,
+            out: String::new(),
+        };
+        let mut position = 0;
+        for note in melody.iter() {
+            writer.span(None, position, note.start);
+            writer.span(Some(note.key), note.start, note.end());
+            position = note.end();
+        }
+        // finish the last bar with a rest rather than leave it short
+        if writer.map.bar_start(position) < position {
+            let end = writer.map.next_bar_start(position);
+            writer.span(None, position, end);
+        }
+        let body = writer.out.trim_end().trim_end_matches('|').trim_end();
+        let _ = writeln!(out, "{} |]", body);
+        Ok(out)
+    }
+}
@@ -0,0 +1,100 @@
+use crate::{
+    note::{pair_notes, Note},
+    parser::MidiTrack,
+};
+
+/// How a note is played, judged from how much of the time until the next
+/// note on its channel it sounds for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Articulation {
+    /// Clearly shorter than the gap to the next note
+    Staccato,
+    /// Neither short nor held, with an audible gap
+    Normal,
+    /// Held for nearly its full value
+    Tenuto,
+    /// Reaching or overlapping the next note
+    Legato,
+}
+
+impl Articulation {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Staccato => "Staccato",
+            Self::Normal => "Normal",
+            Self::Tenuto => "Tenuto",
+            Self::Legato => "Legato",
+        }
+    }
+}
+
+/// Shares of the inter-onset interval a note's duration is compared with
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ArticulationThresholds {
+    /// Below this a note is staccato
+    pub staccato: f32,
+    /// From this a note is tenuto
+    pub tenuto: f32,
+    /// From this a note is legato
+    pub legato: f32,
+}
+
+impl ArticulationThresholds {
+    pub fn create() -> Self {
+        Self {
+            staccato: 0.5,
+            tenuto: 0.85,
+            legato: 1.0,
+        }
+    }
+
+    pub fn classify(&self, duration: u32, interval: u32) -> Articulation {
+        let share = duration as f32 / interval.max(1) as f32;
+        if share >= self.legato {
+            Articulation::Legato
+        } else if share >= self.tenuto {
+            Articulation::Tenuto
+        } else if share >= self.staccato {
+            Articulation::Normal
+        } else {
+            Articulation::Staccato
+        }
+    }
+}
+
+impl MidiTrack {
+    pub fn articulations(&self) -> Vec<(Note, Option<Articulation>)> {
+        self.articulations_with(&ArticulationThresholds::create())
+    }
+
+    /// Every note in start order with its articulation. Notes of a chord
+    /// share an onset, so each is measured to the next later onset on its
+    /// channel; the last note of each channel has nothing to measure
+    /// against and gets None.
+    pub fn articulations_with(
+        &self,
+        thresholds: &ArticulationThresholds,
+    ) -> Vec<(Note, Option<Articulation>)> {
+        let mut notes = pair_notes(self.iter_ticks());
+        notes.sort_by_key(|note| (note.start, note.channel, note.key));
+        let mut onsets: [Vec<u32>; 16] = Default::default();
+        for note in notes.iter() {
+            let channel = &mut onsets[note.channel as usize & 0x0f];
+            if channel.last() != Some(&note.start) {
+                channel.push(note.start);
+            }
+        }
+        notes
+            .into_iter()
+            .map(|note| {
+                let channel = &onsets[note.channel as usize & 0x0f];
+                let next = channel
+                    .get(channel.partition_point(|start| *start <= note.start))
+                    .copied();
+                let articulation =
+                    next.map(|next| thresholds.classify(note.duration, next - note.start));
+                (note, articulation)
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,79 @@
+/// A 14-bit pitch-bend position, stored as the raw 0..=16383 wire value
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct PitchBend {
+    raw: u16,
+}
+
+impl PitchBend {
+    pub const CENTER: u16 = 8192;
+    pub const MAX: u16 = 16383;
+
+    pub fn center() -> Self {
+        Self { raw: Self::CENTER }
+    }
+
+    pub fn from_raw(raw: u16) -> Self {
+        Self {
+            raw: raw.min(Self::MAX),
+        }
+    }
+
+    pub fn from_bytes(least_bytes: u8, most_bytes: u8) -> Self {
+        Self::from_raw((most_bytes as u16 & 0x7f) << 7 | least_bytes as u16 & 0x7f)
+    }
+
+    /// `value` is signed around the center, -8192..=8191
+    pub fn from_value(value: i16) -> Self {
+        Self::from_raw((value as i32 + Self::CENTER as i32).clamp(0, Self::MAX as i32) as u16)
+    }
+
+    pub fn from_normalized(normalized: f32) -> Self {
+        let normalized = normalized.clamp(-1.0, 1.0);
+        let scale = if normalized < 0.0 { 8192.0 } else { 8191.0 };
+        // half away from zero, as `round` would without needing std
+        let scaled = normalized * scale;
+        let rounded = if scaled < 0.0 {
+            scaled - 0.5
+        } else {
+            scaled + 0.5
+        };
+        Self::from_value(rounded as i16)
+    }
+
+    pub fn from_semitones(semitones: f32, range: f32) -> Self {
+        if range <= 0.0 {
+            return Self::center();
+        }
+        Self::from_normalized(semitones / range)
+    }
+
+    pub fn raw(self) -> u16 {
+        self.raw
+    }
+
+    pub fn least_bytes(self) -> u8 {
+        (self.raw & 0x7f) as u8
+    }
+
+    pub fn most_bytes(self) -> u8 {
+        (self.raw >> 7) as u8
+    }
+
+    pub fn value(self) -> i16 {
+        self.raw as i16 - Self::CENTER as i16
+    }
+
+    pub fn normalized(self) -> f32 {
+        let value = self.value() as f32;
+        if value < 0.0 {
+            value / 8192.0
+        } else {
+            value / 8191.0
+        }
+    }
+
+    pub fn semitones(self, range: f32) -> f32 {
+        self.normalized() * range
+    }
+}
@@ -0,0 +1,174 @@
+use std::{env, error::Error, fs, path::Path, process};
+
+use midi_rs::{note::pair_notes, parser::MidiFile};
+
+const USAGE: &str = "\
+Usage: midi-rs <command> <file> [options]
+
+Commands:
+  info <file>                  Header, tempo and a summary of each track
+  dump <file> [--track N]      Every event with its tick
+  play <file> [--device N]     Plays through the platform backend
+  convert <input> <output>     Converts between .mid, .json and .csv, or
+                               exports .ly, .musicxml or .abc [--track N]
+                               [--format 0|1]";
+
+fn extension(path: &str) -> String {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .unwrap_or("")
+        .to_ascii_lowercase()
+}
+
+/// The value following `--name`, if given
+fn option(args: &[String], name: &str) -> Result<Option<usize>, Box<dyn Error>> {
+    match args.iter().position(|arg| arg == name) {
+        Some(i) => {
+            let value = args.get(i + 1).ok_or(format!("{} needs a value", name))?;
+            let number = value
+                .parse()
+                .map_err(|_| format!("{} must be a number, not {}", name, value))?;
+            Ok(Some(number))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Reads a MIDI file, or its JSON or midicsv form or a text score by
+/// extension
+fn load(path: &str) -> Result<MidiFile, Box<dyn Error>> {
+    match extension(path).as_str() {
+        "json" => MidiFile::from_json(&fs::read_to_string(path)?),
+        "csv" => MidiFile::from_midicsv(&fs::read_to_string(path)?),
+        #[cfg(feature = "score")]
+        "score" => MidiFile::from_score(&fs::read_to_string(path)?),
+        _ => {
+            let mut file = MidiFile::create();
+            file.parse(path)?;
+            Ok(file)
+        }
+    }
+}
+
+fn info(file: &MidiFile) {
+    let file = file.loaded();
+    let format = file.format.unwrap_or(file.header_format());
+    let end = file
+        .tracks
+        .iter()
+        .map(|track| track.end_tick())
+        .max()
+        .unwrap_or(0);
+    println!("Format:   {}", format);
+    println!("Tracks:   {}", file.tracks.len());
+    println!("Division: {} ticks per quarter note", file.division);
+    println!("Tempo:    {} ({} bpm)", file.tempo, file.bpm);
+    println!(
+        "Length:   {} ticks, {:.2} seconds",
+        end,
+        file.tempo_map().seconds_at(end)
+    );
+    for (index, (track, role)) in file.tracks.iter().zip(file.roles()).enumerate() {
+        let mut channels: Vec<u8> = track
+            .events
+            .iter()
+            .filter(|event| event.status.is_channel_message())
+            .map(|event| event.status.channel() + 1)
+            .collect();
+        channels.sort();
+        channels.dedup();
+        println!(
+            "Track {}: {:?} {} events, {} notes, channels {:?}, {:?}",
+            index,
+            track.name,
+            track.events.len(),
+            pair_notes(track.iter_ticks()).len(),
+            channels,
+            role
+        );
+    }
+}
+
+fn dump(file: &MidiFile, only: Option<usize>) -> Result<(), Box<dyn Error>> {
+    if let Some(index) = only.filter(|i| *i >= file.tracks.len()) {
+        return Err(format!("No track {}", index).into());
+    }
+    for (index, track) in file.tracks.iter().enumerate() {
+        if only.is_some_and(|i| i != index) {
+            continue;
+        }
+        println!("Track {}: {:?}", index, track.name);
+        for (tick, event) in track.iter_ticks() {
+            match event.status.is_channel_message() {
+                true => println!(
+                    "{:>10} {:?} Channel: {}, {}",
+                    tick,
+                    event.status.status_type,
+                    event.status.channel() + 1,
+                    event.data
+                ),
+                false => println!("{:>10} {}", tick, event.data),
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(windows)]
+fn play(file: &MidiFile, device: usize) -> Result<(), Box<dyn Error>> {
+    unsafe { midi_rs::win::play_stream(device as u32, file) }
+}
+
+#[cfg(not(windows))]
+fn play(_file: &MidiFile, _device: usize) -> Result<(), Box<dyn Error>> {
+    Err("Playback needs the Windows backend".into())
+}
+
+fn convert(file: &MidiFile, output: &str, track: usize) -> Result<(), Box<dyn Error>> {
+    match extension(output).as_str() {
+        "mid" | "midi" => file.save(output),
+        "json" => Ok(fs::write(output, file.to_json())?),
+        "csv" => Ok(fs::write(output, file.to_midicsv())?),
+        "ly" => file.save_lilypond(output),
+        "musicxml" | "xml" => file.save_musicxml(output),
+        "abc" => Ok(fs::write(output, file.to_abc(track)?)?),
+        other => Err(format!("Cannot write .{} files", other).into()),
+    }
+}
+
+fn run(args: &[String]) -> Result<(), Box<dyn Error>> {
+    let (Some(command), Some(path)) = (args.first(), args.get(1)) else {
+        return Err(USAGE.into());
+    };
+    let options = &args[2..];
+    match command.as_str() {
+        "info" => info(&load(path)?),
+        "dump" => dump(&load(path)?, option(options, "--track")?)?,
+        "play" => play(&load(path)?, option(options, "--device")?.unwrap_or(0))?,
+        "convert" => {
+            let output = options
+                .first()
+                .filter(|o| !o.starts_with("--"))
+                .ok_or("convert needs an output file")?;
+            let track = option(options, "--track")?.unwrap_or(0);
+            let file = match option(options, "--format")? {
+                None => load(path)?,
+                Some(0) => load(path)?.to_format0()?,
+                Some(1) => load(path)?.to_format1(),
+                Some(other) => return Err(format!("No format {}, only 0 or 1", other).into()),
+            };
+            convert(&file, output, track)?;
+        }
+        other => return Err(format!("Unknown command {}\n\n{}", other, USAGE).into()),
+    }
+    Ok(())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(e) = run(&args) {
+        eprintln!("{}", e);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,273 @@
+use alloc::{
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+
+use crate::{
+    bend::PitchBend,
+    parser::{EventData, MetaData, MidiEvent, MidiFile, MidiTrack, SysExMeta, DEFAULT_DIVISION},
+    status::StatusType,
+};
+
+/// Builds a track from events placed at absolute ticks, in any order. Events
+/// without a tick of their own go at the cursor set by `at`.
+#[derive(Debug, Clone)]
+pub struct TrackBuilder {
+    name: String,
+    channel: u8,
+    cursor: u32,
+    end: u32,
+    events: Vec<(u32, MidiEvent)>,
+}
+
+impl TrackBuilder {
+    pub fn create() -> Self {
+        Self {
+            name: String::new(),
+            channel: 0,
+            cursor: 0,
+            end: 0,
+            events: vec![],
+        }
+    }
+
+    pub fn name(&mut self, name: &str) -> &mut Self {
+        self.name = name.to_string();
+        self.events
+            .retain(|(_, event)| !is_meta(event, SysExMeta::MetaTrackName));
+        let name = MetaData::SingleString(name.to_string());
+        self.event(0, MidiEvent::meta(SysExMeta::MetaTrackName, name))
+    }
+
+    /// Channel, 0-15, for the channel messages added after it
+    pub fn channel(&mut self, channel: u8) -> &mut Self {
+        self.channel = channel & 0x0f;
+        self
+    }
+
+    pub fn at(&mut self, tick: u32) -> &mut Self {
+        self.cursor = tick;
+        self
+    }
+
+    pub fn event(&mut self, tick: u32, event: MidiEvent) -> &mut Self {
+        self.events.push((tick, event));
+        self
+    }
+
+    fn channel_event(&mut self, tick: u32, status_type: StatusType, data: EventData) -> &mut Self {
+        let event = MidiEvent::channel_message(status_type, self.channel, data);
+        self.event(tick, event)
+    }
+
+    /// Notes last at least a tick, since an off on the tick of its on would
+    /// be sorted ahead of it
+    pub fn note(&mut self, key: u8, velocity: u8, start: u32, duration: u32) -> &mut Self {
+        let key = key & 0x7f;
+        let end = start.saturating_add(duration.max(1));
+        let on = EventData::NoteOnOffData {
+            key,
+            velocity: velocity.clamp(1, 127),
+        };
+        let off = EventData::NoteOnOffData { key, velocity: 0 };
+        self.channel_event(start, StatusType::NoteOn, on)
+            .channel_event(end, StatusType::NoteOff, off)
+    }
+
+    /// Notes struck together, as for a chord
+    pub fn notes(&mut self, keys: &[u8], velocity: u8, start: u32, duration: u32) -> &mut Self {
+        for key in keys {
+            self.note(*key, velocity, start, duration);
+        }
+        self
+    }
+
+    pub fn program(&mut self, program: u8) -> &mut Self {
+        let data = EventData::ProgramChangeData {
+            program_id: program & 0x7f,
+        };
+        self.channel_event(self.cursor, StatusType::ProgramChange, data)
+    }
+
+    pub fn control(&mut self, control_id: u8, value: u8) -> &mut Self {
+        let data = EventData::ControlData {
+            control_id: control_id & 0x7f,
+            control_value: value & 0x7f,
+        };
+        self.channel_event(self.cursor, StatusType::CtrlChange, data)
+    }
+
+    pub fn pitch_bend(&mut self, bend: PitchBend) -> &mut Self {
+        let data = EventData::PitchBendData { bend };
+        self.channel_event(self.cursor, StatusType::PitchBendChange, data)
+    }
+
+    pub fn tempo(&mut self, bpm: f64) -> &mut Self {
+        let micros = (60_000_000.0 / bpm.max(1.0) + 0.5) as u32;
+        let [_, a, b, c] = micros.min(0xff_ffff).to_be_bytes();
+        let event = MidiEvent::meta(SysExMeta::MetaSetTempo, MetaData::TripleU8(a, b, c));
+        self.event(self.cursor, event)
+    }
+
+    /// `denominator` is the note value itself, e.g. 8 for 6/8
+    pub fn time_signature(&mut self, numerator: u8, denominator: u8) -> &mut Self {
+        let signature = MetaData::QuadU8(numerator, denominator, 24, 8);
+        let event = MidiEvent::meta(SysExMeta::MetaTimeSignature, signature);
+        self.event(self.cursor, event)
+    }
+
+    /// `accidentals` counts sharps when positive and flats when negative
+    pub fn key_signature(&mut self, accidentals: i8, minor: bool) -> &mut Self {
+        let signature = MetaData::DoubleU8(accidentals.clamp(-7, 7) as u8, minor as u8);
+        let event = MidiEvent::meta(SysExMeta::MetaKeySignature, signature);
+        self.event(self.cursor, event)
+    }
+
+    pub fn text(&mut self, meta_type: SysExMeta, text: &str) -> &mut Self {
+        let event = MidiEvent::meta(meta_type, MetaData::SingleString(text.to_string()));
+        self.event(self.cursor, event)
+    }
+
+    /// Keeps the track going until at least `tick`, e.g. for a final rest
+    pub fn end_at(&mut self, tick: u32) -> &mut Self {
+        self.end = self.end.max(tick);
+        self
+    }
+
+    pub fn build(&self) -> MidiTrack {
+        let mut events = self.events.clone();
+        // a note ending where the next one on its key starts must end first
+        events.sort_by_key(|(tick, event)| {
+            let order = match (event.status.status_type, &event.data) {
+                (StatusType::NoteOff, _) => 0,
+                (StatusType::NoteOn, EventData::NoteOnOffData { velocity: 0, .. }) => 0,
+                (StatusType::NoteOn, _) => 2,
+                _ => 1,
+            };
+            (*tick, order)
+        });
+        events.push((self.end, MidiEvent::end_of_track(0)));
+        let mut track = MidiTrack::from_absolute(events);
+        track.name = self.name.clone();
+        track
+    }
+}
+
+fn is_meta(event: &MidiEvent, meta_type: SysExMeta) -> bool {
+    matches!(
+        event.data,
+        EventData::SysexData { meta_type: Some(t), .. } if t == meta_type
+    )
+}
+
+/// Builds a file from scratch. Tempo and meter go in a conductor track
+/// ahead of the others.
+#[derive(Debug, Clone)]
+pub struct MidiFileBuilder {
+    division: u16,
+    conductor: TrackBuilder,
+    tracks: Vec<TrackBuilder>,
+}
+
+impl MidiFileBuilder {
+    pub fn create() -> Self {
+        Self {
+            division: DEFAULT_DIVISION,
+            conductor: TrackBuilder::create(),
+            tracks: vec![],
+        }
+    }
+
+    /// Ticks per quarter note
+    pub fn division(&mut self, division: u16) -> &mut Self {
+        self.division = division.clamp(1, 0x7fff);
+        self
+    }
+
+    pub fn ticks_per_quarter(&self) -> u32 {
+        self.division as u32
+    }
+
+    pub fn tempo(&mut self, bpm: f64) -> &mut Self {
+        self.conductor.at(0).tempo(bpm);
+        self
+    }
+
+    pub fn time_signature(&mut self, numerator: u8, denominator: u8) -> &mut Self {
+        self.conductor.at(0).time_signature(numerator, denominator);
+        self
+    }
+
+    /// The track holding tempo and meter changes, for ones after the start
+    pub fn conductor(&mut self) -> &mut TrackBuilder {
+        &mut self.conductor
+    }
+
+    /// Starts a new track, on channel 0 until told otherwise
+    pub fn add_track(&mut self) -> &mut TrackBuilder {
+        self.tracks.push(TrackBuilder::create());
+        self.tracks.last_mut().unwrap()
+    }
+
+    pub fn tracks(&mut self) -> &mut [TrackBuilder] {
+        &mut self.tracks
+    }
+
+    pub fn build(&self) -> MidiFile {
+        let mut file = MidiFile::create();
+        file.division = self.division;
+        if !self.conductor.events.is_empty() || self.tracks.is_empty() {
+            file.tracks.push(self.conductor.build());
+        }
+        file.tracks
+            .extend(self.tracks.iter().map(TrackBuilder::build));
+        file.refresh_tempo();
+        file
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::note::pair_notes;
+
+    fn notes(track: &MidiTrack) -> Vec<(u8, u32, u32)> {
+        pair_notes(track.iter_ticks())
+            .iter()
+            .map(|note| (note.key, note.start, note.duration))
+            .collect()
+    }
+
+    #[test]
+    fn zero_length_note_lasts_a_tick() {
+        let track = TrackBuilder::create().note(60, 100, 10, 0).build();
+        assert_eq!(notes(&track), vec![(60, 10, 1)]);
+    }
+
+    #[test]
+    fn long_note_stops_at_the_last_tick() {
+        let track = TrackBuilder::create().note(60, 100, 10, u32::MAX).build();
+        assert_eq!(notes(&track), vec![(60, 10, u32::MAX - 10)]);
+    }
+
+    #[test]
+    fn repeated_key_is_released_before_struck() {
+        let track = TrackBuilder::create()
+            .note(60, 100, 0, 96)
+            .note(60, 100, 96, 96)
+            .build();
+        assert_eq!(notes(&track), vec![(60, 0, 96), (60, 96, 96)]);
+    }
+
+    #[test]
+    fn conductor_is_left_out_when_empty() {
+        let mut builder = MidiFileBuilder::create();
+        builder.add_track().note(60, 100, 0, 96);
+        assert_eq!(builder.build().tracks.len(), 1);
+        builder.tempo(90.0);
+        let file = builder.build();
+        assert_eq!(file.tracks.len(), 2);
+        assert_eq!(file.tempo, 666_667);
+    }
+}
@@ -0,0 +1,121 @@
+use std::{error::Error, fmt};
+
+use crate::parser::{MidiFile, MidiTrack};
+
+/// A channel of one track moved to avoid sharing it with another track
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Reassignment {
+    pub track: usize,
+    pub from: u8,
+    pub to: u8,
+}
+
+impl fmt::Display for Reassignment {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "track {}: channel {} -> {}",
+            self.track,
+            self.from + 1,
+            self.to + 1
+        )
+    }
+}
+
+impl MidiTrack {
+    /// Every channel this track sends channel messages on, in order
+    pub fn channels(&self) -> Vec<u8> {
+        let mut used = [false; 16];
+        for event in self.events.iter().filter(|e| e.status.is_channel_message()) {
+            used[event.status.channel() as usize] = true;
+        }
+        (0..16).filter(|c| used[*c as usize]).collect()
+    }
+
+    /// Moves channel messages on `from` to `to` for each pair in `map`
+    pub fn remap_channels(&mut self, map: &[(u8, u8)]) {
+        for event in self
+            .events
+            .iter_mut()
+            .filter(|e| e.status.is_channel_message())
+        {
+            let channel = event.status.channel();
+            if let Some((_, to)) = map.iter().find(|(from, _)| *from == channel) {
+                event.status.raw_status = event.status.raw_status & 0xf0 | (to & 0x0f);
+            }
+        }
+    }
+}
+
+impl MidiFile {
+    /// Gives each track channels no other track uses, moving the later track
+    /// of any clash to a free channel. The drum channel stays put and every
+    /// track may share it. Nothing changes if there are not enough channels.
+    pub fn allocate_channels(&mut self) -> Result<Vec<Reassignment>, Box<dyn Error>> {
+        self.load_all()?;
+        let used: Vec<Vec<u8>> = self.tracks.iter().map(|t| t.channels()).collect();
+        let mut wanted = [false; 16];
+        for channel in used.iter().flatten() {
+            wanted[*channel as usize] = true;
+        }
+        let mut owner: [Option<usize>; 16] = [None; 16];
+        let mut reassignments = vec![];
+        for (track, channels) in used.iter().enumerate() {
+            for &channel in channels.iter() {
+                if Some(channel) == self.drum_channel {
+                    continue;
+                }
+                if owner[channel as usize].is_none_or(|o| o == track) {
+                    owner[channel as usize] = Some(track);
+                    continue;
+                }
+                // prefer channels no track asked for, so fewer tracks move
+                let free = |c: &u8| Some(*c) != self.drum_channel && owner[*c as usize].is_none();
+                let to = (0..16)
+                    .filter(free)
+                    .find(|c| !wanted[*c as usize])
+                    .or_else(|| (0..16).filter(free).find(|c| !channels.contains(c)))
+                    .ok_or("Not enough free channels")?;
+                owner[to as usize] = Some(track);
+                reassignments.push(Reassignment {
+                    track,
+                    from: channel,
+                    to,
+                });
+            }
+        }
+        for (i, track) in self.tracks.iter_mut().enumerate() {
+            let map: Vec<(u8, u8)> = reassignments
+                .iter()
+                .filter(|r| r.track == i)
+                .map(|r| (r.from, r.to))
+                .collect();
+            track.remap_channels(&map);
+        }
+        Ok(reassignments)
+    }
+
+    /// Adds the tracks of `other`, rescaled to this file's division, and
+    /// allocates channels so the overlaid parts do not collide. On failure
+    /// the file is left as it was.
+    pub fn overlay(&mut self, mut other: MidiFile) -> Result<Vec<Reassignment>, Box<dyn Error>> {
+        self.load_all()?;
+        other.load_all()?;
+        let (from, to) = (other.division.max(1) as u64, self.division.max(1) as u64);
+        let first = self.tracks.len();
+        for mut track in other.tracks {
+            if from != to {
+                let events = track
+                    .take_absolute()
+                    .into_iter()
+                    .map(|(tick, event)| ((tick as u64 * to / from) as u32, event))
+                    .collect();
+                track.set_absolute(events);
+            }
+            self.tracks.push(track);
+        }
+        self.allocate_channels().inspect_err(|_| {
+            self.tracks.truncate(first);
+        })
+    }
+}
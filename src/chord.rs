@@ -0,0 +1,157 @@
+use crate::{
+    note::Note,
+    parser::{EventData, MetaData, MidiFile, MidiTrack, SysExMeta},
+};
+
+/// Intervals for each chord suffix; a bare root is a major triad
+const QUALITIES: [(&str, &[u8]); 27] = [
+    ("maj9", &[0, 4, 7, 11, 14]),
+    ("maj7", &[0, 4, 7, 11]),
+    ("m7b5", &[0, 3, 6, 10]),
+    ("dim7", &[0, 3, 6, 9]),
+    ("sus2", &[0, 2, 7]),
+    ("sus4", &[0, 5, 7]),
+    ("add9", &[0, 4, 7, 14]),
+    ("min7", &[0, 3, 7, 10]),
+    ("aug", &[0, 4, 8]),
+    ("dim", &[0, 3, 6]),
+    ("min", &[0, 3, 7]),
+    ("maj", &[0, 4, 7]),
+    ("sus", &[0, 5, 7]),
+    ("m9", &[0, 3, 7, 10, 14]),
+    ("m7", &[0, 3, 7, 10]),
+    ("m6", &[0, 3, 7, 9]),
+    ("M7", &[0, 4, 7, 11]),
+    ("ø", &[0, 3, 6, 10]),
+    ("°", &[0, 3, 6]),
+    ("13", &[0, 4, 7, 10, 14, 21]),
+    ("11", &[0, 4, 7, 10, 14, 17]),
+    ("9", &[0, 4, 7, 10, 14]),
+    ("7", &[0, 4, 7, 10]),
+    ("6", &[0, 4, 7, 9]),
+    ("+", &[0, 4, 8]),
+    ("m", &[0, 3, 7]),
+    ("-", &[0, 3, 7]),
+];
+
+/// Lowest key a voiced chord root may take (C3)
+const ROOT_FLOOR: u8 = 48;
+
+fn pitch_class(text: &str) -> Option<(u8, &str)> {
+    let mut chars = text.chars();
+    let letter = match chars.next()? {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = &text[1..];
+    Some(match rest.chars().next() {
+        Some('#') => ((letter + 1) % 12, &rest[1..]),
+        Some('b') => ((letter + 11) % 12, &rest[1..]),
+        _ => (letter, rest),
+    })
+}
+
+/// A lead-sheet chord symbol such as "Am7", "F#dim" or "C/E"
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChordSymbol {
+    /// Pitch class, 0 being C
+    pub root: u8,
+    /// Semitones above the root
+    pub intervals: Vec<u8>,
+    pub bass: Option<u8>,
+}
+
+impl ChordSymbol {
+    pub fn parse(text: &str) -> Option<Self> {
+        let text = text.trim();
+        let (symbol, bass) = match text.split_once('/') {
+            Some((symbol, bass)) => match pitch_class(bass.trim())? {
+                (bass, "") => (symbol, Some(bass)),
+                _ => return None,
+            },
+            None => (text, None),
+        };
+        let (root, suffix) = pitch_class(symbol)?;
+        let intervals = match suffix {
+            "" => vec![0, 4, 7],
+            suffix => QUALITIES
+                .iter()
+                .find(|(name, _)| *name == suffix)?
+                .1
+                .to_vec(),
+        };
+        Some(Self {
+            root,
+            intervals,
+            bass,
+        })
+    }
+
+    /// Close position from the root above C3, with any slash bass an octave
+    /// below the root
+    pub fn voicing(&self) -> Vec<u8> {
+        let root = ROOT_FLOOR + self.root;
+        let mut keys: Vec<u8> = self.intervals.iter().map(|i| root + i).collect();
+        if let Some(bass) = self.bass {
+            keys.retain(|k| k % 12 != bass);
+            keys.insert(0, ROOT_FLOOR - 12 + bass);
+        }
+        keys
+    }
+}
+
+impl MidiFile {
+    /// Every text or marker event that reads as a chord symbol, by tick
+    pub fn chord_symbols(&self) -> Vec<(u32, ChordSymbol)> {
+        let mut chords: Vec<(u32, ChordSymbol)> = self
+            .loaded()
+            .tracks
+            .iter()
+            .flat_map(|track| track.iter_ticks())
+            .filter_map(|(tick, event)| match &event.data {
+                EventData::SysexData {
+                    meta_type: Some(SysExMeta::MetaText | SysExMeta::MetaMarker),
+                    meta: MetaData::SingleString(text),
+                } => Some((tick, ChordSymbol::parse(text)?)),
+                _ => None,
+            })
+            .collect();
+        chords.sort_by_key(|(tick, _)| *tick);
+        chords
+    }
+
+    /// A track holding each chord symbol's voicing until the next symbol,
+    /// the last one lasting to the end of the file
+    pub fn comping_track(&self, channel: u8, velocity: u8) -> MidiTrack {
+        let file = self.loaded();
+        let chords = file.chord_symbols();
+        let end = file.tracks.iter().map(|t| t.end_tick()).max().unwrap_or(0);
+        let mut events = vec![];
+        for (i, (start, chord)) in chords.iter().enumerate() {
+            let until = chords.get(i + 1).map_or(end, |(tick, _)| *tick);
+            if until <= *start {
+                continue;
+            }
+            for key in chord.voicing() {
+                let note = Note {
+                    channel,
+                    key,
+                    velocity,
+                    start: *start,
+                    duration: until - start,
+                    release_velocity: 0,
+                };
+                events.extend(note.events());
+            }
+        }
+        let mut track = MidiTrack::from_absolute(events);
+        track.name = "Comping".to_string();
+        track
+    }
+}
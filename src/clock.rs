@@ -0,0 +1,307 @@
+use std::{
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    thread::sleep,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    parser::{EventData, MidiEvent},
+    status::StatusType,
+    tempo::TempoMap,
+};
+
+/// A source of session time in microseconds. Playback and recording read
+/// time only through this, so it can come from the OS, an audio device,
+/// external sync or a test.
+pub trait Clock {
+    fn now(&self) -> u64;
+
+    /// Returns once `now()` has reached `micros`
+    fn wait_until(&self, micros: u64) {
+        while self.now() < micros {
+            sleep(Duration::from_micros(100));
+        }
+    }
+
+    /// Like `wait_until` but busy-waits, for the last stretch before a
+    /// deadline where sleeping is too coarse
+    fn spin_until(&self, micros: u64) {
+        self.wait_until(micros)
+    }
+}
+
+/// Monotonic OS time since the clock was created
+#[derive(Debug, Clone, Copy)]
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn create() -> Self {
+        Self {
+            start: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+
+    fn wait_until(&self, micros: u64) {
+        let now = self.now();
+        if micros > now {
+            sleep(Duration::from_micros(micros - now));
+        }
+    }
+
+    fn spin_until(&self, micros: u64) {
+        while self.now() < micros {
+            std::hint::spin_loop();
+        }
+    }
+}
+
+/// Time that only moves when told to. Waiting jumps straight to the target,
+/// so anything driven by it runs deterministically and instantly.
+#[derive(Debug, Default)]
+pub struct VirtualClock {
+    micros: AtomicU64,
+}
+
+impl VirtualClock {
+    pub fn create() -> Self {
+        Self::default()
+    }
+
+    pub fn set(&self, micros: u64) {
+        self.micros.store(micros, Ordering::SeqCst);
+    }
+
+    pub fn advance(&self, micros: u64) {
+        self.micros.fetch_add(micros, Ordering::SeqCst);
+    }
+}
+
+impl Clock for VirtualClock {
+    fn now(&self) -> u64 {
+        self.micros.load(Ordering::SeqCst)
+    }
+
+    fn wait_until(&self, micros: u64) {
+        self.micros.fetch_max(micros, Ordering::SeqCst);
+    }
+}
+
+/// Counts frames rendered by an audio callback, so MIDI stays locked to the
+/// audio stream rather than to the OS timer
+#[derive(Debug)]
+pub struct AudioClock {
+    pub sample_rate: u32,
+    frames: AtomicU64,
+}
+
+impl AudioClock {
+    pub fn create(sample_rate: u32) -> Self {
+        Self {
+            sample_rate: sample_rate.max(1),
+            frames: AtomicU64::new(0),
+        }
+    }
+
+    /// Call from the audio callback after rendering `frames` frames
+    pub fn advance(&self, frames: u64) {
+        self.frames.fetch_add(frames, Ordering::SeqCst);
+    }
+}
+
+impl Clock for AudioClock {
+    fn now(&self) -> u64 {
+        self.frames.load(Ordering::SeqCst) * 1_000_000 / self.sample_rate as u64
+    }
+}
+
+impl<C: Clock + ?Sized> Clock for Arc<C> {
+    fn now(&self) -> u64 {
+        (**self).now()
+    }
+
+    fn wait_until(&self, micros: u64) {
+        (**self).wait_until(micros)
+    }
+
+    fn spin_until(&self, micros: u64) {
+        (**self).spin_until(micros)
+    }
+}
+
+/// MIDI beat clock resolution
+pub const CLOCKS_PER_QUARTER: u32 = 24;
+
+/// Produces the times of 0xF8 clock pulses for a file, following its tempo
+/// map so tempo changes reach the slaves.
+#[derive(Debug, Clone)]
+pub struct ClockMaster {
+    pub tempo_map: TempoMap,
+    next: u64,
+}
+
+impl ClockMaster {
+    pub fn create(tempo_map: TempoMap) -> Self {
+        Self { tempo_map, next: 0 }
+    }
+
+    /// Tick of the n-th clock pulse, which may fall between file ticks
+    pub fn pulse_tick(&self, pulse: u64) -> f64 {
+        pulse as f64 * self.tempo_map.division as f64 / CLOCKS_PER_QUARTER as f64
+    }
+
+    /// Continues from the first pulse at or after `tick`
+    pub fn seek(&mut self, tick: u32) {
+        self.next =
+            (tick as u64 * CLOCKS_PER_QUARTER as u64).div_ceil(self.tempo_map.division as u64);
+    }
+
+    /// Time in microseconds of the next pulse
+    pub fn peek(&self) -> f64 {
+        self.tempo_map.micros_at(self.pulse_tick(self.next))
+    }
+
+    /// Returns the time of the next pulse and moves past it
+    pub fn next_pulse(&mut self) -> f64 {
+        let micros = self.peek();
+        self.next += 1;
+        micros
+    }
+}
+
+/// MIDI beats (sixteenth notes) per quarter note, the unit of Song Position
+pub const SONG_POSITION_PER_QUARTER: u32 = 4;
+
+/// Song Position of `tick`, rounded down to a sixteenth note
+pub fn song_position(tick: u32, division: u16) -> u16 {
+    (tick as u64 * SONG_POSITION_PER_QUARTER as u64 / division.max(1) as u64).min(0x3fff) as u16
+}
+
+pub fn song_position_tick(position: u16, division: u16) -> u32 {
+    position as u32 * division as u32 / SONG_POSITION_PER_QUARTER
+}
+
+/// Follows an external clock master. Feed it every incoming real-time and
+/// song position message with its arrival time; it tracks the song position
+/// in pulses and estimates the master's tempo from the pulse spacing.
+#[derive(Debug, Clone)]
+pub struct ClockFollower {
+    pub running: bool,
+    /// Bumped whenever the master relocates with Start or Song Position
+    pub relocations: u32,
+    pulses: u64,
+    last_pulse: Option<f64>,
+    /// Smoothed microseconds between pulses
+    interval: Option<f64>,
+}
+
+impl ClockFollower {
+    pub fn create() -> Self {
+        Self {
+            running: false,
+            relocations: 0,
+            pulses: 0,
+            last_pulse: None,
+            interval: None,
+        }
+    }
+
+    pub fn feed(&mut self, event: &MidiEvent, micros: f64) {
+        match (event.status.status_type, &event.data) {
+            (StatusType::TimingClock, _) => {
+                if let Some(last) = self.last_pulse {
+                    let sample = micros - last;
+                    self.interval = Some(match self.interval {
+                        Some(interval) => interval * 0.75 + sample * 0.25,
+                        None => sample,
+                    });
+                }
+                self.last_pulse = Some(micros);
+                if self.running {
+                    self.pulses += 1;
+                }
+            }
+            (StatusType::Start, _) => {
+                self.pulses = 0;
+                self.running = true;
+                self.last_pulse = None;
+                self.relocations += 1;
+            }
+            (StatusType::Continue, _) => {
+                self.running = true;
+                self.last_pulse = None;
+            }
+            (StatusType::Stop, _) => self.running = false,
+            // one MIDI beat is a sixteenth note, six pulses
+            (StatusType::SongPosition, EventData::SongPositionData { position }) => {
+                self.pulses = *position as u64 * 6;
+                self.relocations += 1;
+            }
+            _ => {}
+        }
+    }
+
+    /// Master tempo in microseconds per quarter note, once two pulses arrived
+    pub fn tempo(&self) -> Option<u32> {
+        self.interval
+            .map(|interval| (interval * CLOCKS_PER_QUARTER as f64).round() as u32)
+    }
+
+    /// Song position in file ticks at time `micros`, interpolated between
+    /// pulses but never running ahead of the next one
+    pub fn position(&self, division: u16, micros: f64) -> f64 {
+        let ticks_per_pulse = division as f64 / CLOCKS_PER_QUARTER as f64;
+        let fraction = match (self.running, self.last_pulse, self.interval) {
+            (true, Some(last), Some(interval)) if interval > 0.0 => {
+                ((micros - last) / interval).clamp(0.0, 1.0)
+            }
+            _ => 0.0,
+        };
+        (self.pulses as f64 + fraction) * ticks_per_pulse
+    }
+}
+
+/// Session time derived from an external MIDI clock: the master's song
+/// position mapped through the file's tempo map. Stands still while the
+/// master is stopped.
+pub struct SyncedClock {
+    pub follower: Arc<Mutex<ClockFollower>>,
+    pub tempo_map: TempoMap,
+    pub system: SystemClock,
+}
+
+impl SyncedClock {
+    /// `system` must be the clock the follower's feed timestamps come from
+    pub fn create(
+        follower: Arc<Mutex<ClockFollower>>,
+        tempo_map: TempoMap,
+        system: SystemClock,
+    ) -> Self {
+        Self {
+            follower,
+            tempo_map,
+            system,
+        }
+    }
+}
+
+impl Clock for SyncedClock {
+    fn now(&self) -> u64 {
+        let position = self
+            .follower
+            .lock()
+            .unwrap()
+            .position(self.tempo_map.division, self.system.now() as f64);
+        self.tempo_map.micros_at(position) as u64
+    }
+}
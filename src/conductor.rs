@@ -0,0 +1,56 @@
+use std::error::Error;
+
+use crate::{meter::SignatureMap, parser::MidiFile, tempo::TempoMap};
+
+fn rescale(tick: u32, from: u16, to: u16) -> u32 {
+    (tick as u64 * to as u64 / from.max(1) as u64) as u32
+}
+
+/// The tempo and meter of a session, usually read from a conductor track or
+/// sync file and applied to the notes of another file.
+#[derive(Debug, Clone)]
+pub struct Conductor {
+    pub tempo_map: TempoMap,
+    pub signature_map: SignatureMap,
+}
+
+impl Conductor {
+    pub fn from_file(file: &MidiFile) -> Self {
+        Self {
+            tempo_map: file.tempo_map(),
+            signature_map: file.signature_map(),
+        }
+    }
+
+    /// Reads only the maps from a sync file; its notes are ignored
+    pub fn load(filename: &str) -> Result<Self, Box<dyn Error>> {
+        let mut file = MidiFile::create();
+        file.parse(filename)?;
+        Ok(Self::from_file(&file))
+    }
+
+    /// Both maps with their ticks rescaled to `division`, so they line up
+    /// with a file using a different resolution
+    pub fn for_division(&self, division: u16) -> Self {
+        let tempo_map = self.tempo_map.for_division(division);
+        let from = self.signature_map.division;
+        let signature_map = SignatureMap::from_signatures(
+            division,
+            self.signature_map
+                .changes
+                .iter()
+                .map(|c| (rescale(c.tick, from, division), c.signature))
+                .collect(),
+        );
+        Self {
+            tempo_map,
+            signature_map,
+        }
+    }
+}
+
+impl MidiFile {
+    pub fn conductor(&self) -> Conductor {
+        Conductor::from_file(self)
+    }
+}
@@ -0,0 +1,400 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    parser::{EventData, MidiEvent, MidiTrack},
+    status::StatusType,
+};
+
+pub const LSB_OFFSET: u8 = 32;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ControlChange {
+    BankSelect,
+    Modulation,
+    Breath,
+    Foot,
+    PortamentoTime,
+    DataEntry,
+    Volume,
+    Balance,
+    Pan,
+    Expression,
+    EffectControl1,
+    EffectControl2,
+    BankSelectLsb,
+    DataEntryLsb,
+    Sustain,
+    Portamento,
+    Sostenuto,
+    SoftPedal,
+    Legato,
+    Hold2,
+    SoundVariation,
+    Resonance,
+    ReleaseTime,
+    AttackTime,
+    Brightness,
+    DecayTime,
+    VibratoRate,
+    VibratoDepth,
+    VibratoDelay,
+    PortamentoControl,
+    Reverb,
+    Tremolo,
+    Chorus,
+    Detune,
+    Phaser,
+    DataIncrement,
+    DataDecrement,
+    NrpnLsb,
+    NrpnMsb,
+    RpnLsb,
+    RpnMsb,
+    AllSoundOff,
+    ResetAllControllers,
+    LocalControl,
+    AllNotesOff,
+    OmniOff,
+    OmniOn,
+    MonoOn,
+    PolyOn,
+    Other(u8),
+}
+
+impl ControlChange {
+    pub fn from(id: u8) -> Self {
+        match id {
+            0 => Self::BankSelect,
+            1 => Self::Modulation,
+            2 => Self::Breath,
+            4 => Self::Foot,
+            5 => Self::PortamentoTime,
+            6 => Self::DataEntry,
+            7 => Self::Volume,
+            8 => Self::Balance,
+            10 => Self::Pan,
+            11 => Self::Expression,
+            12 => Self::EffectControl1,
+            13 => Self::EffectControl2,
+            32 => Self::BankSelectLsb,
+            38 => Self::DataEntryLsb,
+            64 => Self::Sustain,
+            65 => Self::Portamento,
+            66 => Self::Sostenuto,
+            67 => Self::SoftPedal,
+            68 => Self::Legato,
+            69 => Self::Hold2,
+            70 => Self::SoundVariation,
+            71 => Self::Resonance,
+            72 => Self::ReleaseTime,
+            73 => Self::AttackTime,
+            74 => Self::Brightness,
+            75 => Self::DecayTime,
+            76 => Self::VibratoRate,
+            77 => Self::VibratoDepth,
+            78 => Self::VibratoDelay,
+            84 => Self::PortamentoControl,
+            91 => Self::Reverb,
+            92 => Self::Tremolo,
+            93 => Self::Chorus,
+            94 => Self::Detune,
+            95 => Self::Phaser,
+            96 => Self::DataIncrement,
+            97 => Self::DataDecrement,
+            98 => Self::NrpnLsb,
+            99 => Self::NrpnMsb,
+            100 => Self::RpnLsb,
+            101 => Self::RpnMsb,
+            120 => Self::AllSoundOff,
+            121 => Self::ResetAllControllers,
+            122 => Self::LocalControl,
+            123 => Self::AllNotesOff,
+            124 => Self::OmniOff,
+            125 => Self::OmniOn,
+            126 => Self::MonoOn,
+            127 => Self::PolyOn,
+            _ => Self::Other(id),
+        }
+    }
+
+    pub fn id(self) -> u8 {
+        match self {
+            Self::BankSelect => 0,
+            Self::Modulation => 1,
+            Self::Breath => 2,
+            Self::Foot => 4,
+            Self::PortamentoTime => 5,
+            Self::DataEntry => 6,
+            Self::Volume => 7,
+            Self::Balance => 8,
+            Self::Pan => 10,
+            Self::Expression => 11,
+            Self::EffectControl1 => 12,
+            Self::EffectControl2 => 13,
+            Self::BankSelectLsb => 32,
+            Self::DataEntryLsb => 38,
+            Self::Sustain => 64,
+            Self::Portamento => 65,
+            Self::Sostenuto => 66,
+            Self::SoftPedal => 67,
+            Self::Legato => 68,
+            Self::Hold2 => 69,
+            Self::SoundVariation => 70,
+            Self::Resonance => 71,
+            Self::ReleaseTime => 72,
+            Self::AttackTime => 73,
+            Self::Brightness => 74,
+            Self::DecayTime => 75,
+            Self::VibratoRate => 76,
+            Self::VibratoDepth => 77,
+            Self::VibratoDelay => 78,
+            Self::PortamentoControl => 84,
+            Self::Reverb => 91,
+            Self::Tremolo => 92,
+            Self::Chorus => 93,
+            Self::Detune => 94,
+            Self::Phaser => 95,
+            Self::DataIncrement => 96,
+            Self::DataDecrement => 97,
+            Self::NrpnLsb => 98,
+            Self::NrpnMsb => 99,
+            Self::RpnLsb => 100,
+            Self::RpnMsb => 101,
+            Self::AllSoundOff => 120,
+            Self::ResetAllControllers => 121,
+            Self::LocalControl => 122,
+            Self::AllNotesOff => 123,
+            Self::OmniOff => 124,
+            Self::OmniOn => 125,
+            Self::MonoOn => 126,
+            Self::PolyOn => 127,
+            Self::Other(id) => id,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::BankSelect => "Bank Select",
+            Self::Modulation => "Modulation Wheel",
+            Self::Breath => "Breath Controller",
+            Self::Foot => "Foot Controller",
+            Self::PortamentoTime => "Portamento Time",
+            Self::DataEntry => "Data Entry MSB",
+            Self::Volume => "Channel Volume",
+            Self::Balance => "Balance",
+            Self::Pan => "Pan",
+            Self::Expression => "Expression",
+            Self::EffectControl1 => "Effect Control 1",
+            Self::EffectControl2 => "Effect Control 2",
+            Self::BankSelectLsb => "Bank Select LSB",
+            Self::DataEntryLsb => "Data Entry LSB",
+            Self::Sustain => "Sustain Pedal",
+            Self::Portamento => "Portamento On/Off",
+            Self::Sostenuto => "Sostenuto",
+            Self::SoftPedal => "Soft Pedal",
+            Self::Legato => "Legato Footswitch",
+            Self::Hold2 => "Hold 2",
+            Self::SoundVariation => "Sound Variation",
+            Self::Resonance => "Resonance",
+            Self::ReleaseTime => "Release Time",
+            Self::AttackTime => "Attack Time",
+            Self::Brightness => "Brightness",
+            Self::DecayTime => "Decay Time",
+            Self::VibratoRate => "Vibrato Rate",
+            Self::VibratoDepth => "Vibrato Depth",
+            Self::VibratoDelay => "Vibrato Delay",
+            Self::PortamentoControl => "Portamento Control",
+            Self::Reverb => "Reverb Send",
+            Self::Tremolo => "Tremolo Depth",
+            Self::Chorus => "Chorus Send",
+            Self::Detune => "Detune",
+            Self::Phaser => "Phaser Depth",
+            Self::DataIncrement => "Data Increment",
+            Self::DataDecrement => "Data Decrement",
+            Self::NrpnLsb => "NRPN LSB",
+            Self::NrpnMsb => "NRPN MSB",
+            Self::RpnLsb => "RPN LSB",
+            Self::RpnMsb => "RPN MSB",
+            Self::AllSoundOff => "All Sound Off",
+            Self::ResetAllControllers => "Reset All Controllers",
+            Self::LocalControl => "Local Control",
+            Self::AllNotesOff => "All Notes Off",
+            Self::OmniOff => "Omni Mode Off",
+            Self::OmniOn => "Omni Mode On",
+            Self::MonoOn => "Mono Mode On",
+            Self::PolyOn => "Poly Mode On",
+            Self::Other(_) => "Undefined",
+        }
+    }
+
+    pub fn is_channel_mode(self) -> bool {
+        self.id() >= 120
+    }
+}
+
+impl EventData {
+    pub fn control_change(&self) -> Option<(ControlChange, u8)> {
+        match *self {
+            EventData::ControlData {
+                control_id,
+                control_value,
+            } => Some((ControlChange::from(control_id), control_value)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PairInput {
+    Passthrough,
+    Value(u8, u16),
+}
+
+/// Combines controller MSBs (0-31) with their LSBs (32-63). An MSB resets the
+/// low bits as the spec requires, while a lone LSB refines the last MSB seen.
+pub struct ControllerPairer {
+    msb: [[Option<u8>; 32]; 16],
+}
+
+impl ControllerPairer {
+    pub fn create() -> Self {
+        Self {
+            msb: [[None; 32]; 16],
+        }
+    }
+
+    pub fn feed(&mut self, channel: u8, control_id: u8, control_value: u8) -> PairInput {
+        let msb = &mut self.msb[channel as usize & 0x0f];
+        match control_id {
+            0..=31 => {
+                msb[control_id as usize] = Some(control_value);
+                PairInput::Value(control_id, (control_value as u16) << 7)
+            }
+            32..=63 => match msb[(control_id - LSB_OFFSET) as usize] {
+                Some(high) => PairInput::Value(
+                    control_id - LSB_OFFSET,
+                    (high as u16) << 7 | control_value as u16,
+                ),
+                None => PairInput::Passthrough,
+            },
+            _ => PairInput::Passthrough,
+        }
+    }
+}
+
+pub fn split_14bit(control_id: u8, value: u16) -> [(u8, u8); 2] {
+    [
+        (control_id, (value >> 7) as u8 & 0x7f),
+        (control_id + LSB_OFFSET, value as u8 & 0x7f),
+    ]
+}
+
+impl MidiTrack {
+    /// Merges each controller MSB (0-31) with the LSB (32-63) sent for it on
+    /// the same tick into one `Control14Data` event. MSBs without an LSB and
+    /// LSBs without an MSB stay as they are.
+    pub fn pair_controllers(&mut self) {
+        let mut pairer = ControllerPairer::create();
+        let mut events: Vec<(u32, MidiEvent)> = vec![];
+        // the MSB each LSB could still pair with
+        let mut last_msb: [[Option<usize>; 32]; 16] = [[None; 32]; 16];
+
+        for (tick, event) in self.take_absolute() {
+            let channel = event.status.channel();
+            let input = match event.data {
+                EventData::ControlData {
+                    control_id,
+                    control_value,
+                } if event.status.status_type == StatusType::CtrlChange => {
+                    pairer.feed(channel, control_id, control_value)
+                }
+                _ => PairInput::Passthrough,
+            };
+
+            match input {
+                PairInput::Passthrough => events.push((tick, event)),
+                PairInput::Value(control_id, value) => {
+                    let slot = &mut last_msb[channel as usize][control_id as usize];
+                    let is_lsb = matches!(event.data, EventData::ControlData { control_id: id, .. } if id >= LSB_OFFSET);
+                    match *slot {
+                        Some(i) if is_lsb && events[i].0 == tick => {
+                            events[i].1.data = EventData::Control14Data { control_id, value };
+                            *slot = None;
+                        }
+                        _ if is_lsb => events.push((tick, event)),
+                        _ => {
+                            *slot = Some(events.len());
+                            events.push((tick, event));
+                        }
+                    }
+                }
+            }
+        }
+
+        self.set_absolute(events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::MidiFileBuilder, parser::MidiFile};
+
+    /// Volume as MSB and LSB on one tick, then expression with no LSB and a
+    /// lone volume LSB
+    fn file() -> MidiFile {
+        let mut builder = MidiFileBuilder::create();
+        builder
+            .add_track()
+            .control(7, 100)
+            .control(39, 64)
+            .at(10)
+            .control(11, 90)
+            .at(20)
+            .control(39, 5);
+        builder.build()
+    }
+
+    fn data(file: &MidiFile) -> Vec<&EventData> {
+        file.tracks[0].events.iter().map(|ev| &ev.data).collect()
+    }
+
+    #[test]
+    fn only_msb_with_lsb_is_paired() {
+        let mut file = file();
+        file.tracks[0].pair_controllers();
+        assert_eq!(
+            data(&file)[..3],
+            [
+                &EventData::Control14Data {
+                    control_id: 7,
+                    value: 100 << 7 | 64,
+                },
+                &EventData::ControlData {
+                    control_id: 11,
+                    control_value: 90,
+                },
+                &EventData::ControlData {
+                    control_id: 39,
+                    control_value: 5,
+                },
+            ]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn unpaired_controllers_are_written_unchanged() {
+        let mut builder = MidiFileBuilder::create();
+        builder.add_track().control(7, 100).at(10).control(7, 90);
+        let original = builder.build();
+        let mut paired = original.clone();
+        paired.tracks[0].pair_controllers();
+        assert_eq!(paired.to_smf(), original.to_smf());
+
+        let original = file();
+        let mut paired = file();
+        paired.tracks[0].pair_controllers();
+        assert_eq!(paired.to_smf(), original.to_smf());
+    }
+}
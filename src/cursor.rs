@@ -0,0 +1,166 @@
+use crate::{
+    meter::SignatureMap,
+    parser::{EventData, MidiEvent, MidiFile, MidiTrack},
+    status::StatusType,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CursorEvent<'a> {
+    pub tick: u32,
+    /// Always 0 on a single track
+    pub track: usize,
+    pub event: &'a MidiEvent,
+}
+
+/// Something a cursor can walk: a track, or a whole file with its tracks
+/// interleaved. Tracks left unparsed by lazy parsing count as empty until
+/// loaded.
+pub trait Timeline {
+    /// Every event in play order
+    fn timeline(&self) -> Vec<CursorEvent<'_>>;
+}
+
+impl Timeline for MidiTrack {
+    fn timeline(&self) -> Vec<CursorEvent<'_>> {
+        self.iter_ticks()
+            .map(|(tick, event)| CursorEvent {
+                tick,
+                track: 0,
+                event,
+            })
+            .collect()
+    }
+}
+
+impl Timeline for MidiFile {
+    fn timeline(&self) -> Vec<CursorEvent<'_>> {
+        let mut events: Vec<CursorEvent> = self
+            .tracks
+            .iter()
+            .enumerate()
+            .flat_map(|(track, t)| {
+                t.iter_ticks()
+                    .map(move |(tick, event)| CursorEvent { tick, track, event })
+            })
+            .collect();
+        // stable, so events on one tick keep track order
+        events.sort_by_key(|e| e.tick);
+        events
+    }
+}
+
+/// A position on a timeline. It holds a tick rather than an index, so edits
+/// never leave it dangling: if the event it was on goes away it moves on to
+/// whatever comes next.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Cursor {
+    pub tick: u32,
+    /// Which of the events on `tick` it is on
+    pub ordinal: usize,
+}
+
+impl Cursor {
+    pub fn create() -> Self {
+        Self::default()
+    }
+
+    pub fn at_tick(tick: u32) -> Self {
+        Self { tick, ordinal: 0 }
+    }
+
+    pub fn seek(&mut self, tick: u32) {
+        *self = Self::at_tick(tick);
+    }
+
+    /// Index of the event the cursor is on, or of the first one after it
+    /// when it sits between events
+    fn index(&self, events: &[CursorEvent]) -> usize {
+        let first = events.partition_point(|e| e.tick < self.tick);
+        let here = events[first..]
+            .iter()
+            .take_while(|e| e.tick == self.tick)
+            .count();
+        first + self.ordinal.min(here)
+    }
+
+    /// Index of the first event after the cursor
+    fn after(&self, events: &[CursorEvent]) -> usize {
+        let first = events.partition_point(|e| e.tick < self.tick);
+        let index = self.index(events);
+        match events.get(index) {
+            Some(e) if e.tick == self.tick && index - first == self.ordinal => index + 1,
+            _ => index,
+        }
+    }
+
+    fn move_to(&mut self, events: &[CursorEvent], index: usize) {
+        self.tick = events[index].tick;
+        self.ordinal = index - events.partition_point(|e| e.tick < self.tick);
+    }
+
+    /// The event under the cursor
+    pub fn get<'a, T: Timeline>(&self, timeline: &'a T) -> Option<CursorEvent<'a>> {
+        let events = timeline.timeline();
+        let index = self.index(&events);
+        events.get(index).copied()
+    }
+
+    /// Steps to the following event. Stays put at the end.
+    pub fn next<'a, T: Timeline>(&mut self, timeline: &'a T) -> Option<CursorEvent<'a>> {
+        let events = timeline.timeline();
+        let index = self.after(&events);
+        let event = events.get(index).copied()?;
+        self.move_to(&events, index);
+        Some(event)
+    }
+
+    /// Steps to the preceding event. Stays put at the start.
+    pub fn prev<'a, T: Timeline>(&mut self, timeline: &'a T) -> Option<CursorEvent<'a>> {
+        let events = timeline.timeline();
+        let index = self.index(&events).checked_sub(1)?;
+        self.move_to(&events, index);
+        Some(events[index])
+    }
+
+    /// Steps to the next struck note, skipping everything else
+    pub fn next_note<'a, T: Timeline>(&mut self, timeline: &'a T) -> Option<CursorEvent<'a>> {
+        let events = timeline.timeline();
+        let start = self.after(&events);
+        let index = start + events.get(start..)?.iter().position(|e| {
+            e.event.status.status_type == StatusType::NoteOn
+                && matches!(e.event.data, EventData::NoteOnOffData { velocity, .. } if velocity > 0)
+        })?;
+        self.move_to(&events, index);
+        Some(events[index])
+    }
+
+    /// Moves to the start of the next bar and returns its tick
+    pub fn next_bar(&mut self, map: &SignatureMap) -> u32 {
+        self.seek(map.next_bar_start(self.tick));
+        self.tick
+    }
+
+    /// Moves to the start of this bar, or of the one before when already on
+    /// a bar line, and returns its tick
+    pub fn prev_bar(&mut self, map: &SignatureMap) -> u32 {
+        let start = map.bar_start(self.tick);
+        let tick = match start == self.tick && self.ordinal == 0 {
+            true => map.bar_start(self.tick.saturating_sub(1)),
+            false => start,
+        };
+        self.seek(tick);
+        self.tick
+    }
+
+    /// The events from the cursor up to `ticks` later, without moving
+    pub fn peek<'a, T: Timeline>(&self, timeline: &'a T, ticks: u32) -> Vec<CursorEvent<'a>> {
+        let events = timeline.timeline();
+        let end = self.tick.saturating_add(ticks);
+        let index = self.index(&events);
+        events
+            .into_iter()
+            .skip(index)
+            .take_while(|e| e.tick < end)
+            .collect()
+    }
+}
@@ -0,0 +1,171 @@
+use crate::status::DRUM_CHANNEL;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DrumNote {
+    AcousticBassDrum = 35,
+    BassDrum1 = 36,
+    SideStick = 37,
+    AcousticSnare = 38,
+    HandClap = 39,
+    ElectricSnare = 40,
+    LowFloorTom = 41,
+    ClosedHiHat = 42,
+    HighFloorTom = 43,
+    PedalHiHat = 44,
+    LowTom = 45,
+    OpenHiHat = 46,
+    LowMidTom = 47,
+    HiMidTom = 48,
+    CrashCymbal1 = 49,
+    HighTom = 50,
+    RideCymbal1 = 51,
+    ChineseCymbal = 52,
+    RideBell = 53,
+    Tambourine = 54,
+    SplashCymbal = 55,
+    Cowbell = 56,
+    CrashCymbal2 = 57,
+    Vibraslap = 58,
+    RideCymbal2 = 59,
+    HiBongo = 60,
+    LowBongo = 61,
+    MuteHiConga = 62,
+    OpenHiConga = 63,
+    LowConga = 64,
+    HighTimbale = 65,
+    LowTimbale = 66,
+    HighAgogo = 67,
+    LowAgogo = 68,
+    Cabasa = 69,
+    Maracas = 70,
+    ShortWhistle = 71,
+    LongWhistle = 72,
+    ShortGuiro = 73,
+    LongGuiro = 74,
+    Claves = 75,
+    HiWoodBlock = 76,
+    LowWoodBlock = 77,
+    MuteCuica = 78,
+    OpenCuica = 79,
+    MuteTriangle = 80,
+    OpenTriangle = 81,
+}
+
+impl DrumNote {
+    pub fn from(key: u8) -> Option<Self> {
+        match key {
+            35 => Some(Self::AcousticBassDrum),
+            36 => Some(Self::BassDrum1),
+            37 => Some(Self::SideStick),
+            38 => Some(Self::AcousticSnare),
+            39 => Some(Self::HandClap),
+            40 => Some(Self::ElectricSnare),
+            41 => Some(Self::LowFloorTom),
+            42 => Some(Self::ClosedHiHat),
+            43 => Some(Self::HighFloorTom),
+            44 => Some(Self::PedalHiHat),
+            45 => Some(Self::LowTom),
+            46 => Some(Self::OpenHiHat),
+            47 => Some(Self::LowMidTom),
+            48 => Some(Self::HiMidTom),
+            49 => Some(Self::CrashCymbal1),
+            50 => Some(Self::HighTom),
+            51 => Some(Self::RideCymbal1),
+            52 => Some(Self::ChineseCymbal),
+            53 => Some(Self::RideBell),
+            54 => Some(Self::Tambourine),
+            55 => Some(Self::SplashCymbal),
+            56 => Some(Self::Cowbell),
+            57 => Some(Self::CrashCymbal2),
+            58 => Some(Self::Vibraslap),
+            59 => Some(Self::RideCymbal2),
+            60 => Some(Self::HiBongo),
+            61 => Some(Self::LowBongo),
+            62 => Some(Self::MuteHiConga),
+            63 => Some(Self::OpenHiConga),
+            64 => Some(Self::LowConga),
+            65 => Some(Self::HighTimbale),
+            66 => Some(Self::LowTimbale),
+            67 => Some(Self::HighAgogo),
+            68 => Some(Self::LowAgogo),
+            69 => Some(Self::Cabasa),
+            70 => Some(Self::Maracas),
+            71 => Some(Self::ShortWhistle),
+            72 => Some(Self::LongWhistle),
+            73 => Some(Self::ShortGuiro),
+            74 => Some(Self::LongGuiro),
+            75 => Some(Self::Claves),
+            76 => Some(Self::HiWoodBlock),
+            77 => Some(Self::LowWoodBlock),
+            78 => Some(Self::MuteCuica),
+            79 => Some(Self::OpenCuica),
+            80 => Some(Self::MuteTriangle),
+            81 => Some(Self::OpenTriangle),
+            _ => None,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::AcousticBassDrum => "Acoustic Bass Drum",
+            Self::BassDrum1 => "Bass Drum 1",
+            Self::SideStick => "Side Stick",
+            Self::AcousticSnare => "Acoustic Snare",
+            Self::HandClap => "Hand Clap",
+            Self::ElectricSnare => "Electric Snare",
+            Self::LowFloorTom => "Low Floor Tom",
+            Self::ClosedHiHat => "Closed Hi-Hat",
+            Self::HighFloorTom => "High Floor Tom",
+            Self::PedalHiHat => "Pedal Hi-Hat",
+            Self::LowTom => "Low Tom",
+            Self::OpenHiHat => "Open Hi-Hat",
+            Self::LowMidTom => "Low-Mid Tom",
+            Self::HiMidTom => "Hi-Mid Tom",
+            Self::CrashCymbal1 => "Crash Cymbal 1",
+            Self::HighTom => "High Tom",
+            Self::RideCymbal1 => "Ride Cymbal 1",
+            Self::ChineseCymbal => "Chinese Cymbal",
+            Self::RideBell => "Ride Bell",
+            Self::Tambourine => "Tambourine",
+            Self::SplashCymbal => "Splash Cymbal",
+            Self::Cowbell => "Cowbell",
+            Self::CrashCymbal2 => "Crash Cymbal 2",
+            Self::Vibraslap => "Vibraslap",
+            Self::RideCymbal2 => "Ride Cymbal 2",
+            Self::HiBongo => "Hi Bongo",
+            Self::LowBongo => "Low Bongo",
+            Self::MuteHiConga => "Mute Hi Conga",
+            Self::OpenHiConga => "Open Hi Conga",
+            Self::LowConga => "Low Conga",
+            Self::HighTimbale => "High Timbale",
+            Self::LowTimbale => "Low Timbale",
+            Self::HighAgogo => "High Agogo",
+            Self::LowAgogo => "Low Agogo",
+            Self::Cabasa => "Cabasa",
+            Self::Maracas => "Maracas",
+            Self::ShortWhistle => "Short Whistle",
+            Self::LongWhistle => "Long Whistle",
+            Self::ShortGuiro => "Short Guiro",
+            Self::LongGuiro => "Long Guiro",
+            Self::Claves => "Claves",
+            Self::HiWoodBlock => "Hi Wood Block",
+            Self::LowWoodBlock => "Low Wood Block",
+            Self::MuteCuica => "Mute Cuica",
+            Self::OpenCuica => "Open Cuica",
+            Self::MuteTriangle => "Mute Triangle",
+            Self::OpenTriangle => "Open Triangle",
+        }
+    }
+
+    pub fn key(self) -> u8 {
+        self as u8
+    }
+}
+
+pub fn drum_name(key: u8) -> Option<&'static str> {
+    DrumNote::from(key).map(DrumNote::name)
+}
+
+pub fn is_drum_channel(channel: u8) -> bool {
+    channel == DRUM_CHANNEL
+}
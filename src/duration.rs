@@ -0,0 +1,94 @@
+use crate::parser::MidiFile;
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+/// A note length measured in quarter notes, kept as an exact fraction so
+/// tuplets and dots only round once, when converted to ticks
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct NoteValue {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl NoteValue {
+    pub fn fraction(numerator: u32, denominator: u32) -> Self {
+        let divisor = gcd(numerator, denominator).max(1);
+        Self {
+            numerator: numerator / divisor,
+            denominator: denominator.max(1) / divisor,
+        }
+    }
+
+    pub fn whole() -> Self {
+        Self::fraction(4, 1)
+    }
+
+    pub fn half() -> Self {
+        Self::fraction(2, 1)
+    }
+
+    pub fn quarter() -> Self {
+        Self::fraction(1, 1)
+    }
+
+    pub fn eighth() -> Self {
+        Self::fraction(1, 2)
+    }
+
+    pub fn sixteenth() -> Self {
+        Self::fraction(1, 4)
+    }
+
+    pub fn thirty_second() -> Self {
+        Self::fraction(1, 8)
+    }
+
+    pub fn dotted(self) -> Self {
+        Self::fraction(self.numerator * 3, self.denominator * 2)
+    }
+
+    pub fn double_dotted(self) -> Self {
+        Self::fraction(self.numerator * 7, self.denominator * 4)
+    }
+
+    /// `count` notes played in the time of `in_time_of`, e.g. (3, 2) for triplets
+    pub fn tuplet(self, count: u32, in_time_of: u32) -> Self {
+        Self::fraction(self.numerator * in_time_of, self.denominator * count)
+    }
+
+    pub fn triplet(self) -> Self {
+        self.tuplet(3, 2)
+    }
+
+    pub fn quintuplet(self) -> Self {
+        self.tuplet(5, 4)
+    }
+
+    pub fn times(self, n: u32) -> Self {
+        Self::fraction(self.numerator * n, self.denominator)
+    }
+
+    pub fn plus(self, other: Self) -> Self {
+        Self::fraction(
+            self.numerator * other.denominator + other.numerator * self.denominator,
+            self.denominator * other.denominator,
+        )
+    }
+
+    pub fn ticks(&self, division: u16) -> u32 {
+        let exact = division as u64 * self.numerator as u64;
+        ((exact + self.denominator as u64 / 2) / self.denominator as u64) as u32
+    }
+}
+
+impl MidiFile {
+    pub fn ticks(&self, value: NoteValue) -> u32 {
+        value.ticks(self.division)
+    }
+}
@@ -0,0 +1,192 @@
+use alloc::{boxed::Box, format, vec::Vec};
+use core::error::Error;
+
+use crate::parser::{MidiEvent, MidiFile, MidiTrack};
+
+/// Where a tick that falls between two ticks of a new resolution goes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Rounding {
+    #[default]
+    Nearest,
+    Down,
+    Up,
+}
+
+impl Rounding {
+    /// `tick` measured at `from` ticks per quarter, in ticks at `to`
+    pub fn rescale(self, tick: u32, from: u16, to: u16) -> u32 {
+        let (scaled, from) = (tick as u64 * to as u64, from.max(1) as u64);
+        let tick = match self {
+            Rounding::Nearest => (scaled + from / 2) / from,
+            Rounding::Down => scaled / from,
+            Rounding::Up => scaled.div_ceil(from),
+        };
+        tick.min(u32::MAX as u64) as u32
+    }
+}
+
+impl MidiTrack {
+    /// Inserts `event` at an absolute tick, after any events already there,
+    /// returning its index. Inserting past the end moves the end of the
+    /// track out to it.
+    pub fn insert_at_tick(&mut self, tick: u32, mut event: MidiEvent) -> usize {
+        let mut at = 0;
+        let mut index = 0;
+        for existing in self.events.iter() {
+            if existing.is_end_of_track() || at + existing.delta_tick > tick {
+                break;
+            }
+            at += existing.delta_tick;
+            index += 1;
+        }
+        event.delta_tick = tick - at;
+        if let Some(next) = self.events.get_mut(index) {
+            next.delta_tick = next.delta_tick.saturating_sub(event.delta_tick);
+        }
+        self.events.insert(index, event);
+        index
+    }
+
+    /// Removes the events `remove` picks, given each one's absolute tick,
+    /// and returns how many went. The events after a removed one keep their
+    /// ticks, and the end of track always stays.
+    pub fn remove_if(&mut self, mut remove: impl FnMut(u32, &MidiEvent) -> bool) -> usize {
+        let mut tick = 0;
+        let mut carried = 0;
+        let before = self.events.len();
+        self.events.retain_mut(|event| {
+            tick += event.delta_tick;
+            if !event.is_end_of_track() && remove(tick, event) {
+                carried += event.delta_tick;
+                false
+            } else {
+                event.delta_tick += carried;
+                carried = 0;
+                true
+            }
+        });
+        before - self.events.len()
+    }
+
+    /// Replaces every event but the end of track with what `map` makes of it
+    /// and its absolute tick; returning a new tick moves the event there
+    pub fn map_events(&mut self, mut map: impl FnMut(u32, MidiEvent) -> (u32, MidiEvent)) {
+        let events: Vec<(u32, MidiEvent)> = self
+            .take_absolute()
+            .into_iter()
+            .map(|(tick, event)| match event.is_end_of_track() {
+                true => (tick, event),
+                false => map(tick, event),
+            })
+            .collect();
+        self.set_absolute(events);
+    }
+
+    /// Moves every event to where it falls at a different resolution.
+    /// Absolute ticks are rounded rather than deltas, so rounding never
+    /// builds up over the length of the track.
+    pub fn rescale(&mut self, from: u16, to: u16, rounding: Rounding) {
+        let events = self
+            .take_absolute()
+            .into_iter()
+            .map(|(tick, event)| (rounding.rescale(tick, from, to), event))
+            .collect();
+        self.set_absolute(events);
+    }
+}
+
+impl MidiFile {
+    /// Changes the ticks per quarter note, moving every event in every track
+    /// to keep its place in the music. Going up, e.g. from 96 to 960, is
+    /// exact; going down rounds as `rounding` says.
+    pub fn set_division(
+        &mut self,
+        division: u16,
+        rounding: Rounding,
+    ) -> Result<(), Box<dyn Error>> {
+        if division == 0 || division > 0x7fff {
+            return Err(format!("Division must be 1 to 32767, not {}", division).into());
+        }
+        if self.division & 0x8000 != 0 {
+            return Err("Can't re-tick a file timed in SMPTE frames".into());
+        }
+        self.load_all()?;
+        for track in self.tracks.iter_mut() {
+            track.rescale(self.division, division, rounding);
+        }
+        self.division = division;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use alloc::vec;
+
+    use super::*;
+    use crate::{builder::MidiFileBuilder, note::pair_notes};
+
+    fn file(division: u16, notes: &[(u32, u32)]) -> MidiFile {
+        let mut builder = MidiFileBuilder::create();
+        builder.division(division);
+        let track = builder.add_track();
+        for (start, duration) in notes {
+            track.note(60, 100, *start, *duration);
+        }
+        builder.build()
+    }
+
+    fn notes(file: &MidiFile) -> Vec<(u32, u32)> {
+        pair_notes(file.tracks[0].iter_ticks())
+            .iter()
+            .map(|note| (note.start, note.duration))
+            .collect()
+    }
+
+    #[test]
+    fn going_up_and_back_is_exact() {
+        let mut file = file(96, &[(0, 24), (37, 5), (1000, 1)]);
+        file.set_division(960, Rounding::Nearest).unwrap();
+        assert_eq!(notes(&file), vec![(0, 240), (370, 50), (10000, 10)]);
+        file.set_division(96, Rounding::Nearest).unwrap();
+        assert_eq!(notes(&file), vec![(0, 24), (37, 5), (1000, 1)]);
+        assert_eq!(file.division, 96);
+    }
+
+    #[test]
+    fn going_down_rounds_as_asked() {
+        for (rounding, expected) in [
+            (Rounding::Nearest, vec![(3, 0), (7, 6)]),
+            (Rounding::Down, vec![(2, 1), (6, 7)]),
+            (Rounding::Up, vec![(3, 1), (7, 7)]),
+        ] {
+            let mut file = file(96, &[(4, 1), (10, 10)]);
+            file.set_division(64, rounding).unwrap();
+            assert_eq!(notes(&file), expected, "{:?}", rounding);
+        }
+    }
+
+    #[test]
+    fn rounding_never_builds_up() {
+        let starts: Vec<(u32, u32)> = (0..300).map(|i| (i * 3, 1)).collect();
+        let mut file = file(96, &starts);
+        file.set_division(64, Rounding::Nearest).unwrap();
+        assert_eq!(notes(&file).last().unwrap().0, 598);
+    }
+
+    #[test]
+    fn notes_squeezed_to_nothing_stay_struck_first() {
+        let mut file = file(96, &[(7, 1)]);
+        file.set_division(48, Rounding::Nearest).unwrap();
+        assert_eq!(notes(&file), vec![(4, 0)]);
+    }
+
+    #[test]
+    fn bad_divisions_are_errors() {
+        let mut file = file(96, &[(0, 1)]);
+        assert!(file.set_division(0, Rounding::Nearest).is_err());
+        assert!(file.set_division(0x8000, Rounding::Nearest).is_err());
+        file.division = 0xe728;
+        assert!(file.set_division(96, Rounding::Nearest).is_err());
+    }
+}
@@ -0,0 +1,18 @@
+use std::{error::Error, fmt};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MidiError {
+    /// A failed call into the platform MIDI API, with its result code and the
+    /// system's description of it
+    Backend(u32, String),
+}
+
+impl fmt::Display for MidiError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Backend(code, text) => write!(f, "MIDI backend error {}: {}", code, text),
+        }
+    }
+}
+
+impl Error for MidiError {}
@@ -0,0 +1,323 @@
+use std::error::Error;
+
+use crate::{
+    bend::PitchBend,
+    control::ControlChange,
+    parser::{EventData, MidiEvent, MidiTrack},
+    status::{Status, StatusType},
+};
+
+pub const DEFAULT_BEND_RANGE: f32 = 2.0;
+const GESTURE_STEPS: u32 = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GestureShape {
+    Linear,
+    EaseIn,
+    EaseOut,
+}
+
+impl GestureShape {
+    fn apply(self, t: f32) -> f32 {
+        match self {
+            Self::Linear => t,
+            Self::EaseIn => t * t,
+            Self::EaseOut => 1.0 - (1.0 - t) * (1.0 - t),
+        }
+    }
+}
+
+/// Builds pitch-bend points moving from `from` to `to` semitones, relative to
+/// the note's own key, between `start` and `start + duration` ticks
+pub fn bend_ramp(
+    from: f32,
+    to: f32,
+    start: u32,
+    duration: u32,
+    bend_range: f32,
+    shape: GestureShape,
+) -> Result<Vec<ExpressionPoint>, Box<dyn Error>> {
+    if bend_range <= 0.0 {
+        return Err("Bend range must be positive".into());
+    }
+    if from.abs() > bend_range || to.abs() > bend_range {
+        return Err(format!(
+            "Bend of {} to {} semitones exceeds the bend range of {}",
+            from, to, bend_range
+        )
+        .into());
+    }
+
+    let steps = GESTURE_STEPS.min(duration).max(1);
+    Ok((0..=steps)
+        .map(|i| {
+            let t = i as f32 / steps as f32;
+            ExpressionPoint {
+                offset: start + duration * i / steps,
+                value: (from + (to - from) * shape.apply(t)) / bend_range,
+            }
+        })
+        .collect())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExpressionKind {
+    PitchBend,
+    Pressure,
+    Timbre,
+}
+
+/// `value` is -1.0..=1.0 for pitch bend and 0.0..=1.0 for pressure and timbre
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ExpressionPoint {
+    pub offset: u32,
+    pub value: f32,
+}
+
+#[derive(Debug, Clone)]
+pub struct ExpressiveNote {
+    pub key: u8,
+    pub velocity: u8,
+    pub start: u32,
+    pub duration: u32,
+    pub pitch_bend: Vec<ExpressionPoint>,
+    pub pressure: Vec<ExpressionPoint>,
+    pub timbre: Vec<ExpressionPoint>,
+}
+
+impl ExpressiveNote {
+    pub fn create(key: u8, velocity: u8, start: u32, duration: u32) -> Self {
+        Self {
+            key,
+            velocity,
+            start,
+            duration,
+            pitch_bend: vec![],
+            pressure: vec![],
+            timbre: vec![],
+        }
+    }
+
+    pub fn curve(&self, kind: ExpressionKind) -> &[ExpressionPoint] {
+        match kind {
+            ExpressionKind::PitchBend => &self.pitch_bend,
+            ExpressionKind::Pressure => &self.pressure,
+            ExpressionKind::Timbre => &self.timbre,
+        }
+    }
+
+    pub fn curve_mut(&mut self, kind: ExpressionKind) -> &mut Vec<ExpressionPoint> {
+        match kind {
+            ExpressionKind::PitchBend => &mut self.pitch_bend,
+            ExpressionKind::Pressure => &mut self.pressure,
+            ExpressionKind::Timbre => &mut self.timbre,
+        }
+    }
+
+    pub fn add_point(&mut self, kind: ExpressionKind, offset: u32, value: f32) {
+        let curve = self.curve_mut(kind);
+        let pos = curve.partition_point(|p| p.offset <= offset);
+        curve.insert(pos, ExpressionPoint { offset, value });
+    }
+
+    pub fn end(&self) -> u32 {
+        self.start + self.duration
+    }
+
+    fn replace_bend(&mut self, from: u32, to: u32, points: Vec<ExpressionPoint>) {
+        self.pitch_bend.retain(|p| p.offset < from || p.offset > to);
+        for point in points {
+            self.add_point(ExpressionKind::PitchBend, point.offset, point.value);
+        }
+    }
+
+    /// Slides from the note's key to `target` over the last `duration` ticks
+    pub fn glissando(
+        &mut self,
+        target: u8,
+        duration: u32,
+        bend_range: f32,
+    ) -> Result<(), Box<dyn Error>> {
+        let duration = duration.min(self.duration);
+        let start = self.duration - duration;
+        let semitones = target as f32 - self.key as f32;
+        let points = bend_ramp(
+            0.0,
+            semitones,
+            start,
+            duration,
+            bend_range,
+            GestureShape::Linear,
+        )?;
+        self.replace_bend(start, self.duration, points);
+        Ok(())
+    }
+
+    /// Approaches the note from `semitones` below over the first `duration` ticks
+    pub fn scoop(
+        &mut self,
+        semitones: f32,
+        duration: u32,
+        bend_range: f32,
+    ) -> Result<(), Box<dyn Error>> {
+        let duration = duration.min(self.duration);
+        let points = bend_ramp(
+            -semitones,
+            0.0,
+            0,
+            duration,
+            bend_range,
+            GestureShape::EaseOut,
+        )?;
+        self.replace_bend(0, duration, points);
+        Ok(())
+    }
+
+    /// Drops `semitones` below the note over the last `duration` ticks
+    pub fn fall(
+        &mut self,
+        semitones: f32,
+        duration: u32,
+        bend_range: f32,
+    ) -> Result<(), Box<dyn Error>> {
+        let duration = duration.min(self.duration);
+        let start = self.duration - duration;
+        let points = bend_ramp(
+            0.0,
+            -semitones,
+            start,
+            duration,
+            bend_range,
+            GestureShape::EaseIn,
+        )?;
+        self.replace_bend(start, self.duration, points);
+        Ok(())
+    }
+}
+
+pub(crate) fn expression_event(
+    kind: ExpressionKind,
+    channel: u8,
+    value: f32,
+) -> (Status, EventData) {
+    match kind {
+        ExpressionKind::PitchBend => (
+            Status::channel_message(StatusType::PitchBendChange, channel),
+            EventData::PitchBendData {
+                bend: PitchBend::from_normalized(value),
+            },
+        ),
+        ExpressionKind::Pressure => (
+            Status::channel_message(StatusType::ChannelAftertouch, channel),
+            EventData::ChannelData {
+                channel_pressure: (value.clamp(0.0, 1.0) * 127.0).round() as u8,
+            },
+        ),
+        ExpressionKind::Timbre => (
+            Status::channel_message(StatusType::CtrlChange, channel),
+            EventData::ControlData {
+                control_id: ControlChange::Brightness.id(),
+                control_value: (value.clamp(0.0, 1.0) * 127.0).round() as u8,
+            },
+        ),
+    }
+}
+
+pub struct MpeExporter {
+    pub master_channel: u8,
+    pub member_channels: Vec<u8>,
+}
+
+impl MpeExporter {
+    /// Lower zone layout: master on channel 1, members on channels 2-16
+    pub fn lower_zone() -> Self {
+        Self {
+            master_channel: 0,
+            member_channels: (1..16).collect(),
+        }
+    }
+
+    pub fn upper_zone() -> Self {
+        Self {
+            master_channel: 15,
+            member_channels: (0..15).rev().collect(),
+        }
+    }
+
+    /// Renders every note onto its own member channel, rotating through the
+    /// zone and stealing the longest-held channel once all of them are busy
+    pub fn render(&self, notes: &[ExpressiveNote]) -> MidiTrack {
+        let mut order: Vec<&ExpressiveNote> = notes.iter().collect();
+        order.sort_by_key(|n| n.start);
+
+        // (busy until, last used) per member channel
+        let mut usage: Vec<(u32, usize)> = vec![(0, 0); self.member_channels.len()];
+        let mut events: Vec<(u32, MidiEvent)> = vec![];
+        let mut push = |tick: u32, (status, data): (Status, EventData)| {
+            events.push((
+                tick,
+                MidiEvent {
+                    status,
+                    data,
+                    delta_tick: 0,
+                },
+            ))
+        };
+
+        for (i, note) in order.into_iter().enumerate() {
+            let slot = (0..usage.len())
+                .filter(|&s| usage[s].0 <= note.start)
+                .min_by_key(|&s| usage[s].1)
+                .unwrap_or_else(|| (0..usage.len()).min_by_key(|&s| usage[s].1).unwrap());
+            usage[slot] = (note.end(), i + 1);
+            let channel = self.member_channels[slot];
+
+            for kind in [
+                ExpressionKind::PitchBend,
+                ExpressionKind::Pressure,
+                ExpressionKind::Timbre,
+            ] {
+                let curve = note.curve(kind);
+                let initial = match (kind, curve.first()) {
+                    (_, Some(point)) if point.offset == 0 => point.value,
+                    (ExpressionKind::PitchBend, _) => 0.0,
+                    (ExpressionKind::Timbre, _) => 0.5,
+                    (ExpressionKind::Pressure, _) => 0.0,
+                };
+                push(note.start, expression_event(kind, channel, initial));
+                for point in curve
+                    .iter()
+                    .filter(|p| p.offset > 0 && p.offset <= note.duration)
+                {
+                    push(
+                        note.start + point.offset,
+                        expression_event(kind, channel, point.value),
+                    );
+                }
+            }
+
+            push(
+                note.start,
+                (
+                    Status::channel_message(StatusType::NoteOn, channel),
+                    EventData::NoteOnOffData {
+                        key: note.key,
+                        velocity: note.velocity,
+                    },
+                ),
+            );
+            push(
+                note.end(),
+                (
+                    Status::channel_message(StatusType::NoteOff, channel),
+                    EventData::NoteOnOffData {
+                        key: note.key,
+                        velocity: 0,
+                    },
+                ),
+            );
+        }
+
+        MidiTrack::from_absolute(events)
+    }
+}
@@ -0,0 +1,341 @@
+use std::{
+    ffi::CStr,
+    os::raw::{c_char, c_int},
+    panic::{catch_unwind, AssertUnwindSafe},
+    ptr,
+};
+
+use crate::parser::{EventData, MidiFile};
+
+#[cfg(windows)]
+use crate::handle::OutputHandle;
+
+pub const MIDI_OK: c_int = 0;
+pub const MIDI_ERR_NULL: c_int = -1;
+pub const MIDI_ERR_RANGE: c_int = -2;
+pub const MIDI_ERR_UNSUPPORTED: c_int = -3;
+pub const MIDI_ERR_BACKEND: c_int = -4;
+
+/// Flattened view of one event. `data1`/`data2` hold the channel message
+/// bytes, `meta_type` is set for meta events and `value` carries wide values
+/// such as 14-bit controllers and pitch bends.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct MidiEventInfo {
+    pub tick: u32,
+    pub delta_tick: u32,
+    pub status: u8,
+    pub meta_type: u8,
+    pub data1: u8,
+    pub data2: u8,
+    pub value: u32,
+}
+
+impl MidiEventInfo {
+    fn from(tick: u32, event: &crate::parser::MidiEvent) -> Self {
+        let mut info = Self {
+            tick,
+            delta_tick: event.delta_tick,
+            status: event.status.raw_status,
+            ..Self::default()
+        };
+        match &event.data {
+            EventData::NoteOnOffData { key, velocity } => {
+                info.data1 = *key;
+                info.data2 = *velocity;
+            }
+            EventData::ControlData {
+                control_id,
+                control_value,
+            } => {
+                info.data1 = *control_id;
+                info.data2 = *control_value;
+                info.value = *control_value as u32;
+            }
+            EventData::Control14Data { control_id, value } => {
+                info.data1 = *control_id;
+                info.data2 = (*value >> 7) as u8;
+                info.value = *value as u32;
+            }
+            EventData::ProgramChangeData { program_id } => info.data1 = *program_id,
+            EventData::ChannelData { channel_pressure } => info.data1 = *channel_pressure,
+            EventData::PitchBendData { bend } => {
+                info.data1 = bend.least_bytes();
+                info.data2 = bend.most_bytes();
+                info.value = bend.raw() as u32;
+            }
+            EventData::RpnData { change } => {
+                info.data1 = (change.parameter >> 7) as u8;
+                info.data2 = change.parameter as u8 & 0x7f;
+                info.value = change.value as u32;
+            }
+            EventData::SysexData { meta_type, .. } => {
+                info.meta_type = meta_type.map_or(0, |t| t as u8);
+            }
+            EventData::QuarterFrameData { piece, value } => {
+                info.data1 = *piece;
+                info.data2 = *value;
+            }
+            EventData::SongPositionData { position } => info.value = *position as u32,
+            EventData::SongSelectData { song } => info.data1 = *song,
+            EventData::NoData | EventData::Unparsed { .. } => {}
+        }
+        info
+    }
+}
+
+pub struct MidiEventIter {
+    events: Vec<MidiEventInfo>,
+    pos: usize,
+}
+
+pub struct MidiPlayer {
+    #[cfg_attr(not(windows), allow(dead_code))]
+    file: MidiFile,
+}
+
+pub struct MidiOutput {
+    #[cfg(windows)]
+    device: OutputHandle,
+}
+
+/// Returns null when the file can't be opened or parsed
+///
+/// # Safety
+/// `path` must be a valid NUL-terminated string
+#[no_mangle]
+pub unsafe extern "C" fn midi_file_parse(path: *const c_char) -> *mut MidiFile {
+    if path.is_null() {
+        return ptr::null_mut();
+    }
+    let path = match CStr::from_ptr(path).to_str() {
+        Ok(path) => path.to_string(),
+        Err(_) => return ptr::null_mut(),
+    };
+    let parsed = catch_unwind(AssertUnwindSafe(|| {
+        let mut file = MidiFile::create();
+        file.parse(&path).ok().map(|_| file)
+    }));
+    match parsed {
+        Ok(Some(file)) => Box::into_raw(Box::new(file)),
+        _ => ptr::null_mut(),
+    }
+}
+
+/// # Safety
+/// `file` must come from `midi_file_parse` and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn midi_file_free(file: *mut MidiFile) {
+    if !file.is_null() {
+        drop(Box::from_raw(file));
+    }
+}
+
+/// # Safety
+/// `file` must be null or a live pointer from `midi_file_parse`
+#[no_mangle]
+pub unsafe extern "C" fn midi_file_division(file: *const MidiFile) -> u16 {
+    file.as_ref().map_or(0, |f| f.division)
+}
+
+/// # Safety
+/// `file` must be null or a live pointer from `midi_file_parse`
+#[no_mangle]
+pub unsafe extern "C" fn midi_file_tempo(file: *const MidiFile) -> u32 {
+    file.as_ref().map_or(0, |f| f.tempo)
+}
+
+/// # Safety
+/// `file` must be null or a live pointer from `midi_file_parse`
+#[no_mangle]
+pub unsafe extern "C" fn midi_file_track_count(file: *const MidiFile) -> usize {
+    file.as_ref().map_or(0, |f| f.tracks.len())
+}
+
+/// Copies the track's name into `buffer` as a NUL-terminated string, cut to
+/// fit `capacity`, and returns the name's full length in bytes like
+/// `snprintf` does. Returns 0 for an unknown track.
+///
+/// # Safety
+/// `file` must be null or a live pointer from `midi_file_parse`, and
+/// `buffer` must be null or hold `capacity` writable bytes
+#[no_mangle]
+pub unsafe extern "C" fn midi_file_track_name(
+    file: *const MidiFile,
+    track: usize,
+    buffer: *mut c_char,
+    capacity: usize,
+) -> usize {
+    let name = match file.as_ref().and_then(|f| f.tracks.get(track)) {
+        Some(track) => track.name.as_bytes(),
+        None => return 0,
+    };
+    if !buffer.is_null() && capacity > 0 {
+        let length = name.len().min(capacity - 1);
+        ptr::copy_nonoverlapping(name.as_ptr() as *const c_char, buffer, length);
+        *buffer.add(length) = 0;
+    }
+    name.len()
+}
+
+/// Returns null for an unknown track. The iterator copies the events, so it
+/// stays valid even after the file is freed.
+///
+/// # Safety
+/// `file` must be null or a live pointer from `midi_file_parse`
+#[no_mangle]
+pub unsafe extern "C" fn midi_event_iter_new(
+    file: *const MidiFile,
+    track: usize,
+) -> *mut MidiEventIter {
+    let track = match file.as_ref().and_then(|f| f.tracks.get(track)) {
+        Some(track) => track,
+        None => return ptr::null_mut(),
+    };
+    let events = track
+        .iter_ticks()
+        .map(|(tick, event)| MidiEventInfo::from(tick, event))
+        .collect();
+    Box::into_raw(Box::new(MidiEventIter { events, pos: 0 }))
+}
+
+/// Writes the next event into `out` and returns 1, or returns 0 at the end
+///
+/// # Safety
+/// `iter` must come from `midi_event_iter_new` and `out` must be writable
+#[no_mangle]
+pub unsafe extern "C" fn midi_event_iter_next(
+    iter: *mut MidiEventIter,
+    out: *mut MidiEventInfo,
+) -> c_int {
+    let iter = match iter.as_mut() {
+        Some(iter) => iter,
+        None => return 0,
+    };
+    match (iter.events.get(iter.pos), out.is_null()) {
+        (Some(info), false) => {
+            *out = *info;
+            iter.pos += 1;
+            1
+        }
+        _ => 0,
+    }
+}
+
+/// # Safety
+/// `iter` must come from `midi_event_iter_new` and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn midi_event_iter_free(iter: *mut MidiEventIter) {
+    if !iter.is_null() {
+        drop(Box::from_raw(iter));
+    }
+}
+
+/// Opens an output device by index, returning null on failure
+///
+/// # Safety
+/// The returned pointer must be released with `midi_output_close`
+#[no_mangle]
+pub unsafe extern "C" fn midi_output_open(device_id: u32) -> *mut MidiOutput {
+    #[cfg(windows)]
+    {
+        match OutputHandle::open(device_id) {
+            Ok(device) => Box::into_raw(Box::new(MidiOutput { device })),
+            Err(_) => ptr::null_mut(),
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = device_id;
+        ptr::null_mut()
+    }
+}
+
+/// # Safety
+/// `output` must be null or a live pointer from `midi_output_open`
+#[no_mangle]
+pub unsafe extern "C" fn midi_output_send(
+    output: *mut MidiOutput,
+    status: u8,
+    data1: u8,
+    data2: u8,
+) -> c_int {
+    let output = match output.as_ref() {
+        Some(output) => output,
+        None => return MIDI_ERR_NULL,
+    };
+    if status < 0x80 || data1 > 0x7f || data2 > 0x7f {
+        return MIDI_ERR_RANGE;
+    }
+    #[cfg(windows)]
+    {
+        let message = status as u32 | (data1 as u32) << 8 | (data2 as u32) << 16;
+        match output.device.send_message(message) {
+            Ok(()) => MIDI_OK,
+            Err(_) => MIDI_ERR_BACKEND,
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = output;
+        MIDI_ERR_UNSUPPORTED
+    }
+}
+
+/// # Safety
+/// `output` must come from `midi_output_open` and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn midi_output_close(output: *mut MidiOutput) {
+    if !output.is_null() {
+        drop(Box::from_raw(output));
+    }
+}
+
+/// Takes ownership of `file`; it must not be freed separately afterwards
+///
+/// # Safety
+/// `file` must be null or a live pointer from `midi_file_parse`
+#[no_mangle]
+pub unsafe extern "C" fn midi_player_create(file: *mut MidiFile) -> *mut MidiPlayer {
+    if file.is_null() {
+        return ptr::null_mut();
+    }
+    let file = *Box::from_raw(file);
+    Box::into_raw(Box::new(MidiPlayer { file }))
+}
+
+/// Plays the whole file on `output`, blocking until it finishes
+///
+/// # Safety
+/// `player` and `output` must be live pointers from this API
+#[no_mangle]
+pub unsafe extern "C" fn midi_player_play(
+    player: *mut MidiPlayer,
+    output: *mut MidiOutput,
+) -> c_int {
+    let (player, output) = match (player.as_ref(), output.as_ref()) {
+        (Some(player), Some(output)) => (player, output),
+        _ => return MIDI_ERR_NULL,
+    };
+    #[cfg(windows)]
+    {
+        match catch_unwind(AssertUnwindSafe(|| output.device.play(&player.file))) {
+            Ok(Ok(())) => MIDI_OK,
+            Ok(Err(_)) | Err(_) => MIDI_ERR_BACKEND,
+        }
+    }
+    #[cfg(not(windows))]
+    {
+        let _ = (player, output);
+        MIDI_ERR_UNSUPPORTED
+    }
+}
+
+/// # Safety
+/// `player` must come from `midi_player_create` and not be used afterwards
+#[no_mangle]
+pub unsafe extern "C" fn midi_player_free(player: *mut MidiPlayer) {
+    if !player.is_null() {
+        drop(Box::from_raw(player));
+    }
+}
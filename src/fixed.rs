@@ -0,0 +1,265 @@
+use heapless::Vec;
+
+use crate::{
+    bend::PitchBend,
+    parser::{EventData, MidiEvent, MidiTrack},
+    status::{Status, StatusType},
+};
+
+/// A channel, system common or real-time message. Copy-able and never
+/// allocates, unlike `MidiEvent`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ShortMessage {
+    pub status: u8,
+    pub data1: u8,
+    pub data2: u8,
+}
+
+impl ShortMessage {
+    pub fn create(status: u8, data1: u8, data2: u8) -> Self {
+        Self {
+            status,
+            data1: data1 & 0x7f,
+            data2: data2 & 0x7f,
+        }
+    }
+
+    fn channel_message(status_type: StatusType, channel: u8, data1: u8, data2: u8) -> Self {
+        Self::create(status_type as u8 | (channel & 0x0f), data1, data2)
+    }
+
+    pub fn note_on(channel: u8, key: u8, velocity: u8) -> Self {
+        Self::channel_message(StatusType::NoteOn, channel, key, velocity)
+    }
+
+    pub fn note_off(channel: u8, key: u8, velocity: u8) -> Self {
+        Self::channel_message(StatusType::NoteOff, channel, key, velocity)
+    }
+
+    pub fn control_change(channel: u8, control_id: u8, value: u8) -> Self {
+        Self::channel_message(StatusType::CtrlChange, channel, control_id, value)
+    }
+
+    pub fn program_change(channel: u8, program: u8) -> Self {
+        Self::channel_message(StatusType::ProgramChange, channel, program, 0)
+    }
+
+    pub fn pitch_bend(channel: u8, bend: PitchBend) -> Self {
+        Self::channel_message(
+            StatusType::PitchBendChange,
+            channel,
+            bend.least_bytes(),
+            bend.most_bytes(),
+        )
+    }
+
+    pub fn realtime(status_type: StatusType) -> Self {
+        Self::create(status_type as u8, 0, 0)
+    }
+
+    /// Number of data bytes that follow the status on the wire
+    pub fn data_length(&self) -> usize {
+        Status::from_live_byte(self.status)
+            .ok()
+            .and_then(|s| s.data_length())
+            .unwrap_or(0)
+    }
+
+    /// Wire bytes and how many of them are used
+    pub fn to_bytes(&self) -> ([u8; 3], usize) {
+        (
+            [self.status, self.data1, self.data2],
+            1 + self.data_length(),
+        )
+    }
+
+    /// Packed form used by `midiOutShortMsg` and input callbacks
+    pub fn to_packed(&self) -> u32 {
+        self.status as u32 | (self.data1 as u32) << 8 | (self.data2 as u32) << 16
+    }
+
+    pub fn to_event(&self, delta_tick: u32) -> Option<MidiEvent> {
+        let status = Status::from_live_byte(self.status).ok()?;
+        Some(MidiEvent {
+            status,
+            data: status.short_data(self.data1, self.data2),
+            delta_tick,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WireMessage<const N: usize> {
+    Short(ShortMessage),
+    /// Payload between F0 and F7, exclusive
+    SysEx(Vec<u8, N>),
+    /// A SysEx message longer than the buffer; its bytes were dropped
+    SysExOverflow,
+}
+
+/// Incremental parser for a DIN/USB MIDI byte stream. Handles running status
+/// and real-time bytes interleaved with other messages; SysEx is buffered up
+/// to `N` bytes.
+pub struct WireParser<const N: usize> {
+    running_status: u8,
+    data: [u8; 2],
+    received: usize,
+    sysex: Vec<u8, N>,
+    in_sysex: bool,
+    overflowed: bool,
+}
+
+impl<const N: usize> WireParser<N> {
+    pub fn create() -> Self {
+        Self {
+            running_status: 0,
+            data: [0; 2],
+            received: 0,
+            sysex: Vec::new(),
+            in_sysex: false,
+            overflowed: false,
+        }
+    }
+
+    pub fn feed(&mut self, byte: u8) -> Option<WireMessage<N>> {
+        if byte >= 0xf8 {
+            return Some(WireMessage::Short(ShortMessage::create(byte, 0, 0)));
+        }
+        if byte == 0xf0 {
+            self.in_sysex = true;
+            self.overflowed = false;
+            self.sysex.clear();
+            self.running_status = 0;
+            return None;
+        }
+        if byte & 0x80 != 0 {
+            // any status ends a SysEx, F7 being the proper way
+            let finished = self.finish_sysex();
+            if byte != 0xf7 {
+                self.running_status = byte;
+                self.received = 0;
+                let message = ShortMessage::create(byte, 0, 0);
+                if message.data_length() == 0 {
+                    self.running_status = 0;
+                    if finished.is_none() {
+                        return Some(WireMessage::Short(message));
+                    }
+                }
+            }
+            return finished;
+        }
+
+        if self.in_sysex {
+            if self.sysex.push(byte).is_err() {
+                self.overflowed = true;
+            }
+            return None;
+        }
+        if self.running_status == 0 {
+            return None;
+        }
+        self.data[self.received] = byte;
+        self.received += 1;
+        let message = ShortMessage::create(self.running_status, self.data[0], self.data[1]);
+        if self.received < message.data_length() {
+            return None;
+        }
+        self.received = 0;
+        self.data = [0; 2];
+        if self.running_status >= 0xf0 {
+            // system common messages don't establish running status
+            self.running_status = 0;
+        }
+        Some(WireMessage::Short(message))
+    }
+
+    fn finish_sysex(&mut self) -> Option<WireMessage<N>> {
+        if !self.in_sysex {
+            return None;
+        }
+        self.in_sysex = false;
+        if self.overflowed {
+            return Some(WireMessage::SysExOverflow);
+        }
+        Some(WireMessage::SysEx(core::mem::take(&mut self.sysex)))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FixedEvent {
+    pub delta_tick: u32,
+    pub message: ShortMessage,
+}
+
+/// A track of at most `N` short messages, stored inline
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FixedTrack<const N: usize> {
+    pub events: Vec<FixedEvent, N>,
+}
+
+impl<const N: usize> FixedTrack<N> {
+    pub fn create() -> Self {
+        Self { events: Vec::new() }
+    }
+
+    /// Hands the event back when the track is full
+    pub fn push(&mut self, delta_tick: u32, message: ShortMessage) -> Result<(), FixedEvent> {
+        self.events.push(FixedEvent {
+            delta_tick,
+            message,
+        })
+    }
+
+    /// Copies the channel messages of `track`, folding the deltas of skipped
+    /// meta and SysEx events into the next one. `None` if they don't fit.
+    pub fn from_track(track: &MidiTrack) -> Option<Self> {
+        let mut fixed = Self::create();
+        let mut carried = 0;
+        for event in track.events.iter() {
+            carried += event.delta_tick;
+            if let Some(message) = short_message(event) {
+                fixed.push(carried, message).ok()?;
+                carried = 0;
+            }
+        }
+        Some(fixed)
+    }
+
+    pub fn to_track(&self) -> MidiTrack {
+        let mut track = MidiTrack::create();
+        let mut tick = 0;
+        let events = self
+            .events
+            .iter()
+            .filter_map(|e| {
+                tick += e.delta_tick;
+                Some((tick, e.message.to_event(0)?))
+            })
+            .collect();
+        track.set_absolute(events);
+        track
+    }
+}
+
+/// The wire form of a channel message event; `None` for anything longer
+pub fn short_message(event: &MidiEvent) -> Option<ShortMessage> {
+    let status = event.status.raw_status;
+    if !event.status.is_channel_message() {
+        return None;
+    }
+    Some(match event.data {
+        EventData::NoteOnOffData { key, velocity } => ShortMessage::create(status, key, velocity),
+        EventData::ControlData {
+            control_id,
+            control_value,
+        } => ShortMessage::create(status, control_id, control_value),
+        EventData::ProgramChangeData { program_id } => ShortMessage::create(status, program_id, 0),
+        EventData::ChannelData { channel_pressure } => {
+            ShortMessage::create(status, channel_pressure, 0)
+        }
+        EventData::PitchBendData { bend } => {
+            ShortMessage::create(status, bend.least_bytes(), bend.most_bytes())
+        }
+        _ => return None,
+    })
+}
@@ -0,0 +1,97 @@
+use crate::{duration::NoteValue, meter::SignatureMap};
+
+/// Grid lines restart at every bar line, so odd meters and signature changes
+/// never leave a grid drifting against the bars
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Grid {
+    Note(NoteValue),
+    Beat,
+    Bar,
+}
+
+impl Grid {
+    /// Parses "1/8", "1/16T", "1/4.", "beat" or "bar"
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "bar" => return Some(Self::Bar),
+            "beat" => return Some(Self::Beat),
+            _ => {}
+        }
+        let (value, modifier) = match s.chars().last()? {
+            'T' | 't' => (&s[..s.len() - 1], Some('T')),
+            '.' => (&s[..s.len() - 1], Some('.')),
+            _ => (s, None),
+        };
+        let (num, den) = value.split_once('/')?;
+        let num: u32 = num.parse().ok()?;
+        let den: u32 = den.parse().ok()?;
+        if num == 0 || den == 0 {
+            return None;
+        }
+        let value = NoteValue::fraction(num * 4, den);
+        Some(Self::Note(match modifier {
+            Some('T') => value.triplet(),
+            Some('.') => value.dotted(),
+            _ => value,
+        }))
+    }
+
+    /// The text `parse` reads back as this grid
+    pub fn name(&self) -> String {
+        match self {
+            Self::Note(value) => format!("{}/{}", value.numerator, value.denominator * 4),
+            Self::Beat => "beat".to_string(),
+            Self::Bar => "bar".to_string(),
+        }
+    }
+
+    pub fn step_at(&self, tick: u32, map: &SignatureMap) -> u32 {
+        let signature = map.signature_at(tick).signature;
+        let step = match self {
+            Self::Note(value) => value.ticks(map.division),
+            Self::Beat => signature.ticks_per_beat(map.division),
+            Self::Bar => signature.ticks_per_bar(map.division),
+        };
+        step.max(1)
+    }
+
+    pub fn floor(&self, tick: u32, map: &SignatureMap) -> u32 {
+        let bar_start = map.bar_start(tick);
+        let step = self.step_at(tick, map);
+        bar_start + (tick - bar_start) / step * step
+    }
+
+    pub fn ceil(&self, tick: u32, map: &SignatureMap) -> u32 {
+        let floor = self.floor(tick, map);
+        if floor == tick {
+            tick
+        } else {
+            (floor + self.step_at(tick, map)).min(map.next_bar_start(tick))
+        }
+    }
+
+    pub fn snap(&self, tick: u32, map: &SignatureMap) -> u32 {
+        let floor = self.floor(tick, map);
+        let ceil = self.ceil(tick, map);
+        if tick - floor <= ceil - tick {
+            floor
+        } else {
+            ceil
+        }
+    }
+
+    pub fn next(&self, tick: u32, map: &SignatureMap) -> u32 {
+        let floor = self.floor(tick, map);
+        (floor + self.step_at(tick, map)).min(map.next_bar_start(tick))
+    }
+
+    pub fn lines(&self, start: u32, end: u32, map: &SignatureMap) -> Vec<u32> {
+        let mut lines = vec![];
+        let mut tick = self.ceil(start, map);
+        while tick < end {
+            lines.push(tick);
+            tick = self.next(tick, map);
+        }
+        lines
+    }
+}
@@ -0,0 +1,335 @@
+use std::{error::Error, fs};
+
+use bytes::{Buf, BufMut, BytesMut};
+
+use crate::{
+    grid::Grid,
+    meter::SignatureMap,
+    note::{merge_notes, split_notes},
+    parser::MidiTrack,
+};
+
+const MAGIC: &[u8; 4] = b"MGRV";
+const VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GrooveStep {
+    /// How late notes land, in grid steps; negative is early
+    pub offset: f64,
+    /// Velocity relative to the track's average
+    pub velocity: f64,
+}
+
+impl GrooveStep {
+    pub fn straight() -> Self {
+        Self {
+            offset: 0.0,
+            velocity: 1.0,
+        }
+    }
+}
+
+/// The feel of one bar: where notes fall around each grid line and how hard
+/// they are played. Kept in grid steps rather than ticks so it fits any
+/// division.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Groove {
+    pub grid: Grid,
+    /// One per grid line of a bar
+    pub steps: Vec<GrooveStep>,
+}
+
+/// The grid line nearest `tick`, its index within the bar and the step size
+fn locate(grid: Grid, tick: u32, map: &SignatureMap) -> (u32, usize, u32) {
+    let line = grid.snap(tick, map);
+    let step = grid.step_at(line, map);
+    (line, ((line - map.bar_start(line)) / step) as usize, step)
+}
+
+impl Groove {
+    /// Averages the timing and velocity of the track's notes at each grid
+    /// line of the bar. None when the track has no notes.
+    pub fn extract(track: &MidiTrack, grid: Grid, map: &SignatureMap) -> Option<Self> {
+        let (notes, _) = split_notes(track.iter_ticks().map(|(t, e)| (t, e.clone())).collect());
+        if notes.is_empty() {
+            return None;
+        }
+        let mean = notes.iter().map(|n| n.velocity as f64).sum::<f64>() / notes.len() as f64;
+        let per_bar = map.signature_at(0).signature.ticks_per_bar(map.division);
+        let count = (per_bar / grid.step_at(0, map)).max(1) as usize;
+
+        // (offset sum, velocity sum, notes) per line
+        let mut sums = vec![(0.0, 0.0, 0); count];
+        for note in notes.iter() {
+            let (line, index, step) = locate(grid, note.start, map);
+            let Some(sum) = sums.get_mut(index) else {
+                continue;
+            };
+            sum.0 += (note.start as f64 - line as f64) / step as f64;
+            sum.1 += note.velocity as f64;
+            sum.2 += 1;
+        }
+        let steps = sums
+            .into_iter()
+            .map(|(offset, velocity, count)| match count {
+                0 => GrooveStep::straight(),
+                count => GrooveStep {
+                    offset: offset / count as f64,
+                    velocity: velocity / count as f64 / mean,
+                },
+            })
+            .collect();
+        Some(Self { grid, steps })
+    }
+
+    /// Moves each note from its nearest grid line by that line's offset and
+    /// scales its velocity, keeping durations. Lines past the end of the
+    /// groove wrap around to its start.
+    pub fn apply(&self, track: &mut MidiTrack, map: &SignatureMap) {
+        if self.steps.is_empty() {
+            return;
+        }
+        let (mut notes, others) = split_notes(track.take_absolute());
+        for note in notes.iter_mut() {
+            let (line, index, step) = locate(self.grid, note.start, map);
+            let groove = self.steps[index % self.steps.len()];
+            let start = line as f64 + groove.offset * step as f64;
+            note.start = start.round().max(0.0) as u32;
+            note.velocity = (note.velocity as f64 * groove.velocity)
+                .round()
+                .clamp(1.0, 127.0) as u8;
+        }
+        track.set_absolute(merge_notes(&notes, others));
+    }
+}
+
+impl MidiTrack {
+    pub fn extract_groove(&self, grid: Grid, map: &SignatureMap) -> Option<Groove> {
+        Groove::extract(self, grid, map)
+    }
+
+    /// Plays the track with the feel of another, as from
+    /// `other.extract_groove`
+    pub fn apply_groove(&mut self, groove: &Groove, map: &SignatureMap) {
+        groove.apply(self, map);
+    }
+}
+
+/// Named grooves, stored as a small binary file so collections can be
+/// shared
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct GrooveLibrary {
+    pub grooves: Vec<(String, Groove)>,
+}
+
+impl GrooveLibrary {
+    pub fn create() -> Self {
+        Self { grooves: vec![] }
+    }
+
+    /// Adds `groove`, replacing any with the same name
+    pub fn add(&mut self, name: &str, groove: Groove) {
+        self.remove(name);
+        self.grooves.push((name.to_string(), groove));
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Groove> {
+        let i = self.grooves.iter().position(|(n, _)| n == name)?;
+        Some(self.grooves.remove(i).1)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Groove> {
+        self.grooves
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, groove)| groove)
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.grooves.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    pub fn apply_groove(
+        &self,
+        name: &str,
+        track: &mut MidiTrack,
+        map: &SignatureMap,
+    ) -> Result<(), Box<dyn Error>> {
+        let groove = self
+            .get(name)
+            .ok_or_else(|| format!("No groove named {}", name))?;
+        groove.apply(track, map);
+        Ok(())
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = BytesMut::new();
+        out.put_slice(MAGIC);
+        out.put_u8(VERSION);
+        out.put_u16(self.grooves.len() as u16);
+        for (name, groove) in self.grooves.iter() {
+            for text in [name.as_str(), groove.grid.name().as_str()] {
+                out.put_u16(text.len() as u16);
+                out.put_slice(text.as_bytes());
+            }
+            out.put_u16(groove.steps.len() as u16);
+            for step in groove.steps.iter() {
+                out.put_f32(step.offset as f32);
+                out.put_f32(step.velocity as f32);
+            }
+        }
+        out.to_vec()
+    }
+
+    pub fn from_bytes(data: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let mut bytes = data;
+        if bytes.len() < 7 || &bytes[..4] != MAGIC {
+            return Err("Not a groove library".into());
+        }
+        bytes.advance(4);
+        let version = bytes.get_u8();
+        if version != VERSION {
+            return Err(format!("Unsupported groove library version {}", version).into());
+        }
+
+        fn text(bytes: &mut &[u8]) -> Result<String, Box<dyn Error>> {
+            if bytes.remaining() < 2 {
+                return Err("Truncated groove library".into());
+            }
+            let length = bytes.get_u16() as usize;
+            if bytes.remaining() < length {
+                return Err("Truncated groove library".into());
+            }
+            let text = String::from_utf8(bytes[..length].to_vec())?;
+            bytes.advance(length);
+            Ok(text)
+        }
+
+        let mut library = Self::create();
+        for _ in 0..bytes.get_u16() {
+            let name = text(&mut bytes)?;
+            let grid = text(&mut bytes)?;
+            let grid = Grid::parse(&grid).ok_or_else(|| format!("Bad grid: {}", grid))?;
+            if bytes.remaining() < 2 {
+                return Err("Truncated groove library".into());
+            }
+            let count = bytes.get_u16() as usize;
+            if bytes.remaining() < count * 8 {
+                return Err("Truncated groove library".into());
+            }
+            let steps = (0..count)
+                .map(|_| GrooveStep {
+                    offset: bytes.get_f32() as f64,
+                    velocity: bytes.get_f32() as f64,
+                })
+                .collect();
+            library.add(&name, Groove { grid, steps });
+        }
+        Ok(library)
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_bytes(&fs::read(path)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::MidiFileBuilder, note::pair_notes, parser::MidiFile};
+
+    /// Eighth notes through a 4/4 bar at 96 ticks per quarter, each offset
+    /// and at a velocity `feel` gives for its index
+    fn bar(feel: impl Fn(u32) -> (u32, u8)) -> MidiFile {
+        let mut builder = MidiFileBuilder::create();
+        builder.division(96).time_signature(4, 4);
+        let track = builder.add_track();
+        for i in 0..8 {
+            let (offset, velocity) = feel(i);
+            track.note(60, velocity, i * 48 + offset, 24);
+        }
+        builder.build()
+    }
+
+    fn laid_back(i: u32) -> (u32, u8) {
+        match i % 2 {
+            0 => (0, 100),
+            _ => (12, 60),
+        }
+    }
+
+    fn eighths() -> Grid {
+        Grid::parse("1/8").unwrap()
+    }
+
+    #[test]
+    fn extracts_timing_and_accents() {
+        let file = bar(laid_back);
+        let map = file.signature_map();
+        let groove = file.tracks.last().unwrap().extract_groove(eighths(), &map);
+        let steps = groove.unwrap().steps;
+        assert_eq!(steps.len(), 8);
+        assert_eq!((steps[0].offset, steps[0].velocity), (0.0, 1.25));
+        assert_eq!((steps[1].offset, steps[1].velocity), (0.25, 0.75));
+    }
+
+    #[test]
+    fn applying_plays_one_track_with_anothers_feel() {
+        let source = bar(laid_back);
+        let map = source.signature_map();
+        let groove = source
+            .tracks
+            .last()
+            .unwrap()
+            .extract_groove(eighths(), &map);
+        let mut target = bar(|_| (0, 80));
+        let track = target.tracks.last_mut().unwrap();
+        track.apply_groove(&groove.unwrap(), &map);
+        let notes: Vec<(u32, u32, u8)> = pair_notes(track.iter_ticks())
+            .iter()
+            .map(|note| (note.start, note.duration, note.velocity))
+            .collect();
+        let expected: Vec<(u32, u32, u8)> = (0..8)
+            .map(|i| {
+                let (offset, velocity) = laid_back(i);
+                (i * 48 + offset, 24, velocity)
+            })
+            .collect();
+        assert_eq!(notes, expected);
+    }
+
+    #[test]
+    fn empty_track_has_no_groove() {
+        let file = bar(laid_back);
+        let map = file.signature_map();
+        assert!(MidiTrack::create()
+            .extract_groove(eighths(), &map)
+            .is_none());
+    }
+
+    #[test]
+    fn library_round_trips_through_bytes() {
+        let file = bar(laid_back);
+        let map = file.signature_map();
+        let groove = file.tracks.last().unwrap().extract_groove(eighths(), &map);
+        let mut library = GrooveLibrary::create();
+        library.add("laid back", groove.unwrap());
+        library.add(
+            "straight",
+            Groove {
+                grid: Grid::parse("1/16T").unwrap(),
+                steps: vec![GrooveStep::straight(); 3],
+            },
+        );
+        let bytes = library.to_bytes();
+        assert_eq!(GrooveLibrary::from_bytes(&bytes).unwrap(), library);
+        for cut in 0..bytes.len() {
+            assert!(GrooveLibrary::from_bytes(&bytes[..cut]).is_err());
+        }
+    }
+}
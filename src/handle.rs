@@ -0,0 +1,211 @@
+use std::{error::Error, time::Instant};
+
+use windows::Win32::Media::{
+    Audio::{
+        midiInClose, midiInOpen, midiInReset, midiInStart, midiInStop, midiOutClose, midiOutOpen,
+        midiOutReset, midiOutShortMsg, CALLBACK_FUNCTION, CALLBACK_NULL, HMIDIIN, HMIDIOUT,
+    },
+    MM_MIM_DATA,
+};
+
+use crate::{
+    bend::PitchBend,
+    error::MidiError,
+    input::{InputFilter, InputMessage},
+    output::MidiOutput,
+    parser::MidiFile,
+    queue::{channel, Consumer, OverflowPolicy, Producer},
+    status::StatusType,
+    win::{self, check_in, check_out, PlayOptions},
+};
+
+/// An open output device, reset and closed when dropped
+pub struct OutputHandle {
+    device: HMIDIOUT,
+}
+
+impl OutputHandle {
+    pub fn open(device_id: u32) -> Result<Self, MidiError> {
+        let mut device = HMIDIOUT::default();
+        check_out(unsafe { midiOutOpen(&mut device, device_id, 0, 0, CALLBACK_NULL) })?;
+        Ok(Self { device })
+    }
+
+    /// The raw handle for the `win` functions. It stays owned by this one,
+    /// so it must not be closed or used after this is dropped.
+    pub fn raw(&self) -> HMIDIOUT {
+        self.device
+    }
+
+    /// Sends a packed short message, status in the low byte
+    pub fn send_message(&self, message: u32) -> Result<(), MidiError> {
+        check_out(unsafe { midiOutShortMsg(self.device, message) })
+    }
+
+    pub fn send(
+        &self,
+        status: StatusType,
+        channel: u32,
+        low: u32,
+        high: u32,
+    ) -> Result<(), MidiError> {
+        unsafe { win::send_midi(self.device, status, channel, low, high) }
+    }
+
+    pub fn send_single(&self, status: StatusType, channel: u32, low: u32) -> Result<(), MidiError> {
+        unsafe { win::send_midi_single(self.device, status, channel, low) }
+    }
+
+    pub fn send_pitch_bend(&self, channel: u32, bend: PitchBend) -> Result<(), MidiError> {
+        unsafe { win::send_pitch_bend(self.device, channel, bend) }
+    }
+
+    pub fn send_control_14bit(
+        &self,
+        channel: u32,
+        control_id: u8,
+        value: u16,
+    ) -> Result<(), MidiError> {
+        unsafe { win::send_control_14bit(self.device, channel, control_id, value) }
+    }
+
+    pub fn send_realtime(&self, status: StatusType) -> Result<(), MidiError> {
+        unsafe { win::send_realtime(self.device, status) }
+    }
+
+    pub fn send_song_position(&self, position: u16) -> Result<(), MidiError> {
+        unsafe { win::send_song_position(self.device, position) }
+    }
+
+    pub fn send_sysex(&self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        unsafe { win::send_sysex(self.device, data) }
+    }
+
+    pub fn play(&self, midi: &MidiFile) -> Result<(), MidiError> {
+        unsafe { win::play_file(self.device, midi) }
+    }
+
+    pub fn play_with(&self, midi: &MidiFile, options: PlayOptions) -> Result<(), MidiError> {
+        unsafe { win::play_file_with(self.device, midi, options) }
+    }
+}
+
+impl MidiOutput for OutputHandle {
+    fn send_short(&mut self, message: u32) {
+        let _ = self.send_message(message);
+    }
+}
+
+impl Drop for OutputHandle {
+    fn drop(&mut self) {
+        unsafe {
+            midiOutReset(self.device);
+            midiOutClose(self.device);
+        }
+    }
+}
+
+extern "system" fn queue_in_proc(
+    _h_device: HMIDIIN,
+    w_msg: u32,
+    dw_instance: usize,
+    dw_param1: usize,
+    dw_param2: usize,
+) {
+    if w_msg != MM_MIM_DATA {
+        return;
+    }
+    let target = unsafe { &*(dw_instance as *const QueueTarget) };
+    let message = InputMessage {
+        micros: dw_param2 as u64 * 1000,
+        message: dw_param1 as u32,
+    };
+    if target.filter.allows(&message) {
+        target.producer.push(message);
+    }
+}
+
+/// What the input callback writes to
+struct QueueTarget {
+    producer: Producer<InputMessage>,
+    filter: InputFilter,
+}
+
+/// A started input device whose messages arrive on a lock-free queue.
+/// Stopped and closed when dropped, before the queue's producer half goes.
+pub struct InputHandle {
+    device: HMIDIIN,
+    /// Read by the callback through the pointer it was opened with
+    _target: Box<QueueTarget>,
+    started: Instant,
+}
+
+impl InputHandle {
+    /// Opens and starts input device `device_id`. The consumer yields each
+    /// raw message with its driver timestamp, counted from when the device
+    /// started.
+    pub fn open(
+        device_id: u32,
+        capacity: usize,
+        policy: OverflowPolicy,
+    ) -> Result<(Self, Consumer<InputMessage>), MidiError> {
+        Self::open_filtered(device_id, capacity, policy, InputFilter::create())
+    }
+
+    /// Like `open`, but messages `filter` rejects are dropped in the driver
+    /// callback and never take up room in the queue
+    pub fn open_filtered(
+        device_id: u32,
+        capacity: usize,
+        policy: OverflowPolicy,
+        filter: InputFilter,
+    ) -> Result<(Self, Consumer<InputMessage>), MidiError> {
+        let (producer, consumer) = channel(capacity, policy);
+        let target = Box::new(QueueTarget { producer, filter });
+        let mut device = HMIDIIN::default();
+        check_in(unsafe {
+            midiInOpen(
+                &mut device,
+                device_id,
+                queue_in_proc as *const () as usize,
+                &*target as *const QueueTarget as usize,
+                CALLBACK_FUNCTION,
+            )
+        })?;
+        // from here on dropping the handle closes the device
+        let mut input = Self {
+            device,
+            _target: target,
+            started: Instant::now(),
+        };
+        check_in(unsafe { midiInStart(device) })?;
+        input.started = Instant::now();
+        Ok((input, consumer))
+    }
+
+    /// When the device started, the zero of every message's timestamp
+    pub fn started(&self) -> Instant {
+        self.started
+    }
+
+    /// When `message` arrived, on the monotonic clock
+    pub fn arrival(&self, message: &InputMessage) -> Instant {
+        self.started + message.elapsed()
+    }
+
+    /// The raw handle. It stays owned by this one, so it must not be closed
+    /// or used after this is dropped.
+    pub fn raw(&self) -> HMIDIIN {
+        self.device
+    }
+}
+
+impl Drop for InputHandle {
+    fn drop(&mut self) {
+        unsafe {
+            midiInStop(self.device);
+            midiInReset(self.device);
+            midiInClose(self.device);
+        }
+    }
+}
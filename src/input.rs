@@ -0,0 +1,299 @@
+use std::{error::Error, fs, time::Duration};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    parser::MidiEvent,
+    pipeline::{Piped, Pipeline},
+    queue::Consumer,
+    status::{Status, StatusType},
+};
+
+/// A raw short message as delivered by an input device, packed like
+/// `midiOutShortMsg` expects, with its arrival time in microseconds
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InputMessage {
+    /// Since the device started, from the driver's timestamp
+    pub micros: u64,
+    pub message: u32,
+}
+
+impl InputMessage {
+    pub fn elapsed(&self) -> Duration {
+        Duration::from_micros(self.micros)
+    }
+
+    pub fn event(&self) -> Result<MidiEvent, Box<dyn Error>> {
+        MidiEvent::from_short_message(self.message)
+    }
+}
+
+/// A source of incoming messages, live or replayed
+pub trait MidiInput {
+    /// Returns the next message that has arrived, without blocking
+    fn poll(&mut self) -> Option<InputMessage>;
+
+    /// Passes on only the messages `filter` allows
+    fn filtered(self, filter: InputFilter) -> Filtered<Self>
+    where
+        Self: Sized,
+    {
+        Filtered {
+            input: self,
+            filter,
+        }
+    }
+
+    /// Runs `pipeline`'s live steps on every message
+    fn piped(self, pipeline: Pipeline) -> Piped<Self>
+    where
+        Self: Sized,
+    {
+        Piped {
+            input: self,
+            pipeline,
+        }
+    }
+}
+
+/// Which incoming messages get through. Channels only apply to channel
+/// messages.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputFilter {
+    /// One bit per channel, channel 1 lowest
+    pub channels: u16,
+    /// Types to keep; empty keeps every type
+    pub types: Vec<StatusType>,
+    /// Types to drop even if `types` keeps them
+    pub blocked: Vec<StatusType>,
+}
+
+impl InputFilter {
+    /// Lets everything through
+    pub fn create() -> Self {
+        Self {
+            channels: 0xffff,
+            types: vec![],
+            blocked: vec![],
+        }
+    }
+
+    /// Everything but active sensing and clock, which chatty devices send
+    /// several times a second
+    pub fn quiet() -> Self {
+        Self {
+            blocked: vec![StatusType::ActiveSensing, StatusType::TimingClock],
+            ..Self::create()
+        }
+    }
+
+    /// Channel messages of `types` on `channel` only, 0 being channel 1
+    pub fn channel(channel: u8, types: &[StatusType]) -> Self {
+        Self {
+            channels: 1 << (channel & 0x0f),
+            types: types.to_vec(),
+            blocked: vec![],
+        }
+    }
+
+    pub fn allows(&self, message: &InputMessage) -> bool {
+        let Ok(status) = Status::from_live_byte(message.message as u8) else {
+            return self.types.is_empty();
+        };
+        if status.is_channel_message() && self.channels & 1 << status.channel() == 0 {
+            return false;
+        }
+        (self.types.is_empty() || self.types.contains(&status.status_type))
+            && !self.blocked.contains(&status.status_type)
+    }
+}
+
+/// Wraps another input and silently drops what the filter rejects
+pub struct Filtered<I: MidiInput> {
+    pub input: I,
+    pub filter: InputFilter,
+}
+
+impl<I: MidiInput> MidiInput for Filtered<I> {
+    fn poll(&mut self) -> Option<InputMessage> {
+        loop {
+            let message = self.input.poll()?;
+            if self.filter.allows(&message) {
+                return Some(message);
+            }
+        }
+    }
+}
+
+impl MidiInput for Consumer<InputMessage> {
+    fn poll(&mut self) -> Option<InputMessage> {
+        self.pop()
+    }
+}
+
+const SESSION_MAGIC: &[u8; 4] = b"MRIS";
+const SESSION_VERSION: u8 = 1;
+
+/// A captured input session. On disk every message is a variable length
+/// time delta followed by its status and data bytes.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Session {
+    pub messages: Vec<InputMessage>,
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn read_varint(bytes: &[u8], pos: &mut usize) -> Option<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let byte = *bytes.get(*pos)?;
+        *pos += 1;
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+    }
+    None
+}
+
+/// Unknown statuses keep both data bytes so nothing is lost on a round trip
+fn data_length(status: u8) -> usize {
+    Status::from_live_byte(status)
+        .ok()
+        .and_then(|s| s.data_length())
+        .unwrap_or(2)
+}
+
+impl Session {
+    pub fn create() -> Self {
+        Self { messages: vec![] }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = SESSION_MAGIC.to_vec();
+        out.push(SESSION_VERSION);
+        let mut prev = 0;
+        for message in self.messages.iter() {
+            write_varint(&mut out, message.micros.saturating_sub(prev));
+            prev = message.micros.max(prev);
+            let length = data_length(message.message as u8);
+            out.extend(message.message.to_le_bytes().iter().take(1 + length));
+        }
+        out
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Box<dyn Error>> {
+        let body = bytes
+            .strip_prefix(SESSION_MAGIC)
+            .ok_or("Not an input session log")?;
+        if body.first() != Some(&SESSION_VERSION) {
+            return Err("Unsupported input session version".into());
+        }
+        let mut pos = 1;
+        let mut micros = 0;
+        let mut messages = vec![];
+        while pos < body.len() {
+            micros += read_varint(body, &mut pos).ok_or("Truncated time delta")?;
+            let status = *body.get(pos).ok_or("Truncated message")?;
+            let length = data_length(status);
+            let data = body.get(pos..pos + 1 + length).ok_or("Truncated message")?;
+            pos += 1 + length;
+            let mut packed = [0u8; 4];
+            packed[..data.len()].copy_from_slice(data);
+            messages.push(InputMessage {
+                micros,
+                message: u32::from_le_bytes(packed),
+            });
+        }
+        Ok(Self { messages })
+    }
+
+    pub fn save(&self, filename: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(filename, self.to_bytes())?;
+        Ok(())
+    }
+
+    pub fn load(filename: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_bytes(&fs::read(filename)?)
+    }
+
+    /// Plays the session back at `speed` times the original rate
+    pub fn replay(self, speed: f64) -> Replay {
+        self.replay_with_clock(speed, Box::new(SystemClock::create()))
+    }
+
+    pub fn replay_with_clock(self, speed: f64, clock: Box<dyn Clock + Send>) -> Replay {
+        Replay {
+            session: self,
+            pos: 0,
+            speed: if speed > 0.0 { speed } else { 1.0 },
+            clock,
+            started: None,
+        }
+    }
+}
+
+/// Wraps another input and records everything that passes through it
+pub struct Recorder<I: MidiInput> {
+    pub input: I,
+    pub session: Session,
+}
+
+impl<I: MidiInput> Recorder<I> {
+    pub fn create(input: I) -> Self {
+        Self {
+            input,
+            session: Session::create(),
+        }
+    }
+}
+
+impl<I: MidiInput> MidiInput for Recorder<I> {
+    fn poll(&mut self) -> Option<InputMessage> {
+        let message = self.input.poll()?;
+        self.session.messages.push(message);
+        Some(message)
+    }
+}
+
+/// Replays a recorded session in real time. The clock starts on the first
+/// poll, and timestamps are scaled to match the playback speed.
+pub struct Replay {
+    session: Session,
+    pos: usize,
+    speed: f64,
+    clock: Box<dyn Clock + Send>,
+    started: Option<u64>,
+}
+
+impl Replay {
+    pub fn is_finished(&self) -> bool {
+        self.pos >= self.session.messages.len()
+    }
+}
+
+impl MidiInput for Replay {
+    fn poll(&mut self) -> Option<InputMessage> {
+        let now = self.clock.now();
+        let started = *self.started.get_or_insert(now);
+        let message = self.session.messages.get(self.pos)?;
+        let due = message.micros as f64 / self.speed;
+        if ((now - started) as f64) < due {
+            return None;
+        }
+        self.pos += 1;
+        Some(InputMessage {
+            micros: due as u64,
+            message: message.message,
+        })
+    }
+}
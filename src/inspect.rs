@@ -0,0 +1,235 @@
+use std::{error::Error, fs};
+
+use crate::json::{number, Json};
+
+/// One event as it appears on disk. `offset` points at the delta-time and
+/// `bytes` holds everything up to the next event, delta included.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawEvent {
+    pub offset: usize,
+    pub delta_tick: u32,
+    pub status: u8,
+    pub running_status: bool,
+    pub bytes: Vec<u8>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawChunk {
+    pub id: [u8; 4],
+    pub offset: usize,
+    pub declared_length: u32,
+    /// Bytes actually present, which is less than declared for truncated files
+    pub length: usize,
+    pub events: Vec<RawEvent>,
+    pub error: Option<String>,
+}
+
+/// Low-level layout of a file, kept as close to the bytes as possible so
+/// damaged files can be examined without the parser giving up on them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawStructure {
+    pub file_length: usize,
+    pub chunks: Vec<RawChunk>,
+    pub trailing_bytes: usize,
+}
+
+struct Scanner<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Scanner<'a> {
+    fn u8(&mut self) -> Option<u8> {
+        let byte = *self.data.get(self.pos)?;
+        self.pos += 1;
+        Some(byte)
+    }
+
+    fn take(&mut self, length: usize) -> Option<&'a [u8]> {
+        let end = self.pos.checked_add(length)?;
+        let slice = self.data.get(self.pos..end)?;
+        self.pos = end;
+        Some(slice)
+    }
+
+    fn value(&mut self) -> Option<u32> {
+        let mut value = 0u32;
+        for _ in 0..4 {
+            let byte = self.u8()?;
+            value = (value << 7) | (byte as u32 & 0x7f);
+            if byte & 0x80 == 0 {
+                return Some(value);
+            }
+        }
+        None
+    }
+}
+
+/// Number of data bytes following a channel or system common status
+fn data_length(status: u8) -> usize {
+    match status & 0xf0 {
+        0xc0 | 0xd0 => 1,
+        0x80..=0xe0 => 2,
+        _ => match status {
+            0xf1 | 0xf3 => 1,
+            0xf2 => 2,
+            _ => 0,
+        },
+    }
+}
+
+fn scan_track(chunk: &mut RawChunk, data: &[u8]) {
+    let mut scanner = Scanner { data, pos: 0 };
+    let mut prev_status = 0u8;
+    while scanner.pos < data.len() {
+        let start = scanner.pos;
+        let event = (|| {
+            let delta_tick = scanner.value().ok_or("Bad delta-time")?;
+            let mut status = scanner.u8().ok_or("Missing status byte")?;
+            let running_status = status < 0x80;
+            if running_status {
+                if prev_status == 0 {
+                    return Err("Running status without a previous status");
+                }
+                status = prev_status;
+                scanner.pos -= 1;
+            }
+            match status {
+                0xff => {
+                    scanner.u8().ok_or("Missing meta type")?;
+                    let length = scanner.value().ok_or("Bad meta length")?;
+                    scanner
+                        .take(length as usize)
+                        .ok_or("Truncated meta event")?;
+                }
+                0xf0 | 0xf7 => {
+                    let length = scanner.value().ok_or("Bad sysex length")?;
+                    scanner.take(length as usize).ok_or("Truncated sysex")?;
+                }
+                _ => {
+                    scanner
+                        .take(data_length(status))
+                        .ok_or("Truncated channel message")?;
+                    if status < 0xf0 {
+                        prev_status = status;
+                    }
+                }
+            }
+            Ok((delta_tick, status, running_status))
+        })();
+        match event {
+            Ok((delta_tick, status, running_status)) => chunk.events.push(RawEvent {
+                offset: chunk.offset + 8 + start,
+                delta_tick,
+                status,
+                running_status,
+                bytes: data[start..scanner.pos].to_vec(),
+            }),
+            Err(e) => {
+                chunk.error = Some(format!("{} at offset {}", e, chunk.offset + 8 + start));
+                return;
+            }
+        }
+    }
+}
+
+/// Walks the chunk structure of `data`, recording as much as it can. Never
+/// fails; problems are attached to the chunk they were found in.
+pub fn inspect(data: &[u8]) -> RawStructure {
+    let mut scanner = Scanner { data, pos: 0 };
+    let mut chunks = vec![];
+    while let Some(header) = scanner.take(8) {
+        let offset = scanner.pos - 8;
+        let id = [header[0], header[1], header[2], header[3]];
+        let declared_length = u32::from_be_bytes([header[4], header[5], header[6], header[7]]);
+        let available = data.len() - scanner.pos;
+        let length = available.min(declared_length as usize);
+        let body = scanner.take(length).unwrap_or_default();
+
+        let mut chunk = RawChunk {
+            id,
+            offset,
+            declared_length,
+            length,
+            events: vec![],
+            error: None,
+        };
+        if &id == b"MTrk" {
+            scan_track(&mut chunk, body);
+        }
+        if length < declared_length as usize {
+            chunk.error.get_or_insert(format!(
+                "Chunk declares {} bytes but only {} remain",
+                declared_length, length
+            ));
+        }
+        chunks.push(chunk);
+    }
+
+    RawStructure {
+        file_length: data.len(),
+        chunks,
+        trailing_bytes: data.len() - scanner.pos,
+    }
+}
+
+pub fn inspect_file(filename: &str) -> Result<RawStructure, Box<dyn Error>> {
+    Ok(inspect(&fs::read(filename)?))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl RawStructure {
+    /// Serialises the structure as JSON with raw bytes written as hex strings
+    pub fn to_json(&self) -> String {
+        let object = |fields: Vec<(&str, Json)>| {
+            Json::Object(
+                fields
+                    .into_iter()
+                    .map(|(name, value)| (name.to_string(), value))
+                    .collect(),
+            )
+        };
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|chunk| {
+                let events = chunk
+                    .events
+                    .iter()
+                    .map(|event| {
+                        object(vec![
+                            ("offset", number(event.offset as f64)),
+                            ("delta_tick", number(event.delta_tick)),
+                            ("status", number(event.status)),
+                            ("running_status", Json::Bool(event.running_status)),
+                            ("bytes", Json::String(hex(&event.bytes))),
+                        ])
+                    })
+                    .collect();
+                object(vec![
+                    (
+                        "id",
+                        Json::String(String::from_utf8_lossy(&chunk.id).to_string()),
+                    ),
+                    ("offset", number(chunk.offset as f64)),
+                    ("declared_length", number(chunk.declared_length)),
+                    ("length", number(chunk.length as f64)),
+                    (
+                        "error",
+                        chunk.error.clone().map_or(Json::Null, Json::String),
+                    ),
+                    ("events", Json::Array(events)),
+                ])
+            })
+            .collect();
+        object(vec![
+            ("file_length", number(self.file_length as f64)),
+            ("trailing_bytes", number(self.trailing_bytes as f64)),
+            ("chunks", Json::Array(chunks)),
+        ])
+        .to_string()
+    }
+}
@@ -0,0 +1,855 @@
+use std::{
+    error::Error,
+    fmt::{self, Write},
+};
+
+use crate::{
+    bend::PitchBend,
+    parser::{EventData, MetaData, MidiEvent, MidiFile, MidiTrack, SysExMeta},
+    rpn::{ParameterKind, RpnChange},
+    status::{Status, StatusType},
+};
+
+const VERSION: u64 = 1;
+
+/// A parsed JSON value. Objects keep their fields in order.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Json {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Json>),
+    Object(Vec<(String, Json)>),
+}
+
+impl Json {
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut reader = Reader {
+            chars: text.chars().collect(),
+            pos: 0,
+        };
+        let value = reader.value()?;
+        reader.skip_whitespace();
+        if reader.pos < reader.chars.len() {
+            return Err(format!("Unexpected text after JSON at {}", reader.pos).into());
+        }
+        Ok(value)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Json> {
+        match self {
+            Self::Object(fields) => fields.iter().find(|(n, _)| n == name).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+
+    pub fn as_f64(&self) -> Option<f64> {
+        match self {
+            Self::Number(n) => Some(*n),
+            _ => None,
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.as_f64()
+            .filter(|n| n.fract() == 0.0 && *n >= 0.0)
+            .map(|n| n as u64)
+    }
+
+    pub fn as_i64(&self) -> Option<i64> {
+        self.as_f64().filter(|n| n.fract() == 0.0).map(|n| n as i64)
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self {
+            Self::Bool(b) => Some(*b),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Self::String(s) => Some(s),
+            _ => None,
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Json]> {
+        match self {
+            Self::Array(items) => Some(items),
+            _ => None,
+        }
+    }
+}
+
+fn write_string(f: &mut fmt::Formatter<'_>, s: &str) -> fmt::Result {
+    f.write_char('"')?;
+    for c in s.chars() {
+        match c {
+            '"' => f.write_str("\\\"")?,
+            '\\' => f.write_str("\\\\")?,
+            '\n' => f.write_str("\\n")?,
+            '\r' => f.write_str("\\r")?,
+            '\t' => f.write_str("\\t")?,
+            c if (c as u32) < 0x20 => write!(f, "\\u{:04x}", c as u32)?,
+            c => f.write_char(c)?,
+        }
+    }
+    f.write_char('"')
+}
+
+impl fmt::Display for Json {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Null => f.write_str("null"),
+            Self::Bool(b) => write!(f, "{}", b),
+            Self::Number(n) if n.fract() == 0.0 && n.abs() < 1e15 => write!(f, "{}", *n as i64),
+            Self::Number(n) if n.is_finite() => write!(f, "{}", n),
+            Self::Number(_) => f.write_str("null"),
+            Self::String(s) => write_string(f, s),
+            Self::Array(items) => {
+                f.write_char('[')?;
+                for (i, item) in items.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write!(f, "{}", item)?;
+                }
+                f.write_char(']')
+            }
+            Self::Object(fields) => {
+                f.write_char('{')?;
+                for (i, (name, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        f.write_char(',')?;
+                    }
+                    write_string(f, name)?;
+                    write!(f, ":{}", value)?;
+                }
+                f.write_char('}')
+            }
+        }
+    }
+}
+
+struct Reader {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Reader {
+    fn skip_whitespace(&mut self) {
+        while self.chars.get(self.pos).is_some_and(|c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.get(self.pos).copied()
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Box<dyn Error>> {
+        match self.peek() {
+            Some(found) if found == c => {
+                self.pos += 1;
+                Ok(())
+            }
+            _ => Err(format!("Expected '{}' at {}", c, self.pos).into()),
+        }
+    }
+
+    fn word(&mut self, word: &str, value: Json) -> Result<Json, Box<dyn Error>> {
+        let end = self.pos + word.chars().count();
+        if self
+            .chars
+            .get(self.pos..end)
+            .is_some_and(|s| s.iter().copied().eq(word.chars()))
+        {
+            self.pos = end;
+            Ok(value)
+        } else {
+            Err(format!("Unexpected text at {}", self.pos).into())
+        }
+    }
+
+    fn value(&mut self) -> Result<Json, Box<dyn Error>> {
+        match self.peek().ok_or("Unexpected end of JSON")? {
+            '{' => {
+                self.pos += 1;
+                let mut fields = vec![];
+                if self.peek() == Some('}') {
+                    self.pos += 1;
+                    return Ok(Json::Object(fields));
+                }
+                loop {
+                    self.skip_whitespace();
+                    let name = self.string()?;
+                    self.expect(':')?;
+                    fields.push((name, self.value()?));
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        _ => break,
+                    }
+                }
+                self.expect('}')?;
+                Ok(Json::Object(fields))
+            }
+            '[' => {
+                self.pos += 1;
+                let mut items = vec![];
+                if self.peek() == Some(']') {
+                    self.pos += 1;
+                    return Ok(Json::Array(items));
+                }
+                loop {
+                    items.push(self.value()?);
+                    match self.peek() {
+                        Some(',') => self.pos += 1,
+                        _ => break,
+                    }
+                }
+                self.expect(']')?;
+                Ok(Json::Array(items))
+            }
+            '"' => Ok(Json::String(self.string()?)),
+            't' => self.word("true", Json::Bool(true)),
+            'f' => self.word("false", Json::Bool(false)),
+            'n' => self.word("null", Json::Null),
+            _ => {
+                let start = self.pos;
+                while self
+                    .chars
+                    .get(self.pos)
+                    .is_some_and(|c| c.is_ascii_digit() || "+-.eE".contains(*c))
+                {
+                    self.pos += 1;
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                let number = text
+                    .parse()
+                    .map_err(|_| format!("Bad number at {}: {}", start, text))?;
+                Ok(Json::Number(number))
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Result<u32, Box<dyn Error>> {
+        let digits: String = self
+            .chars
+            .get(self.pos..self.pos + 4)
+            .ok_or("Truncated escape")?
+            .iter()
+            .collect();
+        self.pos += 4;
+        Ok(u32::from_str_radix(&digits, 16)?)
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        if self.chars.get(self.pos) != Some(&'"') {
+            return Err(format!("Expected a string at {}", self.pos).into());
+        }
+        self.pos += 1;
+        let mut out = String::new();
+        loop {
+            let c = *self.chars.get(self.pos).ok_or("Unterminated string")?;
+            self.pos += 1;
+            match c {
+                '"' => return Ok(out),
+                '\\' => {
+                    let escape = *self.chars.get(self.pos).ok_or("Unterminated string")?;
+                    self.pos += 1;
+                    out.push(match escape {
+                        'n' => '\n',
+                        'r' => '\r',
+                        't' => '\t',
+                        'b' => '\u{8}',
+                        'f' => '\u{c}',
+                        'u' => {
+                            let mut code = self.hex4()?;
+                            // the high half of a surrogate pair
+                            if (0xd800..0xdc00).contains(&code)
+                                && self.chars.get(self.pos..self.pos + 2) == Some(&['\\', 'u'])
+                            {
+                                self.pos += 2;
+                                let low = self.hex4()?;
+                                code = 0x10000
+                                    + ((code - 0xd800) << 10)
+                                    + (low.wrapping_sub(0xdc00) & 0x3ff);
+                            }
+                            char::from_u32(code).unwrap_or('\u{fffd}')
+                        }
+                        other => other,
+                    });
+                }
+                c => out.push(c),
+            }
+        }
+    }
+}
+
+pub(crate) fn number(n: impl Into<f64>) -> Json {
+    Json::Number(n.into())
+}
+
+fn bytes(data: &[u8]) -> Json {
+    Json::Array(data.iter().map(|b| number(*b)).collect())
+}
+
+fn meta_name(meta: SysExMeta) -> &'static str {
+    match meta {
+        SysExMeta::MetaSequence => "sequence_number",
+        SysExMeta::MetaText => "text",
+        SysExMeta::MetaCopyright => "copyright",
+        SysExMeta::MetaTrackName => "track_name",
+        SysExMeta::MetaInstrumentName => "instrument_name",
+        SysExMeta::MetaLyrics => "lyrics",
+        SysExMeta::MetaMarker => "marker",
+        SysExMeta::MetaCuePoint => "cue_point",
+        SysExMeta::MetaProgramName => "program_name",
+        SysExMeta::MetaDeviceName => "device_name",
+        SysExMeta::MetaChannelPrefix => "channel_prefix",
+        SysExMeta::MetaPort => "port",
+        SysExMeta::MetaEndOfTrack => "end_of_track",
+        SysExMeta::MetaSetTempo => "set_tempo",
+        SysExMeta::MetaSMPTEOffset => "smpte_offset",
+        SysExMeta::MetaTimeSignature => "time_signature",
+        SysExMeta::MetaKeySignature => "key_signature",
+        SysExMeta::MetaSequencerSpecific => "sequencer_specific",
+    }
+}
+
+fn meta_fields(meta_type: SysExMeta, meta: &MetaData) -> Vec<(&'static str, Json)> {
+    match (meta_type, meta) {
+        (SysExMeta::MetaSequence, MetaData::DoubleU8(a, b)) => {
+            vec![("number", number((*a as u16) << 8 | *b as u16))]
+        }
+        (SysExMeta::MetaKeySignature, MetaData::DoubleU8(sharps, minor)) => vec![
+            ("sharps", number(*sharps as i8)),
+            ("minor", Json::Bool(*minor == 1)),
+        ],
+        (_, MetaData::SingleString(text)) => vec![("text", Json::String(text.clone()))],
+        (SysExMeta::MetaPort, MetaData::SingleU8(port)) => vec![("port", number(*port))],
+        (_, MetaData::SingleU8(channel)) => vec![("channel", number(*channel + 1))],
+        (_, MetaData::DoubleU8(a, b)) => vec![("data", bytes(&[*a, *b]))],
+        (_, MetaData::TripleU8(a, b, c)) => {
+            vec![(
+                "tempo",
+                number((*a as u32) << 16 | (*b as u32) << 8 | *c as u32),
+            )]
+        }
+        (_, MetaData::QuadU8(numerator, denominator, clocks, thirty_seconds)) => vec![
+            ("numerator", number(*numerator)),
+            ("denominator", number(*denominator)),
+            ("clocks", number(*clocks)),
+            ("thirty_seconds", number(*thirty_seconds)),
+        ],
+        (_, MetaData::QuintripleU8(hours, minutes, seconds, frames, subframes)) => vec![
+            ("hours", number(*hours)),
+            ("minutes", number(*minutes)),
+            ("seconds", number(*seconds)),
+            ("frames", number(*frames)),
+            ("subframes", number(*subframes)),
+        ],
+        (_, MetaData::Bytes(data)) => vec![("data", bytes(data))],
+        (_, MetaData::None) => vec![],
+    }
+}
+
+fn event_to_json(tick: u32, event: &MidiEvent) -> Json {
+    let status = event.status;
+    let channel = ("channel", number(status.channel() + 1));
+    let (kind, fields): (&str, Vec<(&str, Json)>) = match &event.data {
+        EventData::NoteOnOffData { key, velocity } => {
+            let (kind, amount) = match status.status_type {
+                StatusType::NoteOn => ("note_on", "velocity"),
+                StatusType::NoteOff => ("note_off", "velocity"),
+                _ => ("poly_aftertouch", "pressure"),
+            };
+            (
+                kind,
+                vec![channel, ("key", number(*key)), (amount, number(*velocity))],
+            )
+        }
+        EventData::ControlData {
+            control_id,
+            control_value,
+        } => (
+            "control",
+            vec![
+                channel,
+                ("controller", number(*control_id)),
+                ("value", number(*control_value)),
+            ],
+        ),
+        EventData::ProgramChangeData { program_id } => {
+            ("program", vec![channel, ("program", number(*program_id))])
+        }
+        EventData::ChannelData { channel_pressure } => (
+            "channel_pressure",
+            vec![channel, ("pressure", number(*channel_pressure))],
+        ),
+        EventData::PitchBendData { bend } => {
+            ("pitch_bend", vec![channel, ("value", number(bend.raw()))])
+        }
+        EventData::Control14Data { control_id, value } => (
+            "control14",
+            vec![
+                channel,
+                ("controller", number(*control_id)),
+                ("value", number(*value)),
+            ],
+        ),
+        EventData::RpnData { change } => (
+            match change.kind {
+                ParameterKind::Registered => "rpn",
+                ParameterKind::NonRegistered => "nrpn",
+            },
+            vec![
+                channel,
+                ("parameter", number(change.parameter)),
+                ("value", number(change.value)),
+            ],
+        ),
+        EventData::QuarterFrameData { piece, value } => (
+            "quarter_frame",
+            vec![("piece", number(*piece)), ("value", number(*value))],
+        ),
+        EventData::SongPositionData { position } => {
+            ("song_position", vec![("position", number(*position))])
+        }
+        EventData::SongSelectData { song } => ("song_select", vec![("song", number(*song))]),
+        EventData::NoData => ("system", vec![("status", number(status.raw_status))]),
+        EventData::SysexData {
+            meta_type: Some(meta_type),
+            meta,
+        } => {
+            let mut fields = vec![("meta", Json::String(meta_name(*meta_type).to_string()))];
+            fields.extend(meta_fields(*meta_type, meta));
+            ("meta", fields)
+        }
+        EventData::SysexData {
+            meta_type: None,
+            meta,
+        } => {
+            let data = match meta {
+                MetaData::Bytes(data) => bytes(data),
+                _ => Json::Array(vec![]),
+            };
+            (
+                "sysex",
+                vec![("status", number(status.raw_status)), ("data", data)],
+            )
+        }
+        EventData::Unparsed {
+            raw,
+            offset,
+            reason,
+        } => (
+            "unparsed",
+            vec![
+                ("status", number(status.raw_status)),
+                ("data", bytes(raw)),
+                ("offset", number(*offset as f64)),
+                ("reason", Json::String(reason.clone())),
+            ],
+        ),
+    };
+    let mut object = vec![
+        ("tick".to_string(), number(tick)),
+        ("type".to_string(), Json::String(kind.to_string())),
+    ];
+    object.extend(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value)),
+    );
+    Json::Object(object)
+}
+
+pub(crate) fn field<'a>(object: &'a Json, name: &str) -> Result<&'a Json, Box<dyn Error>> {
+    object
+        .get(name)
+        .ok_or_else(|| format!("Missing field {}", name).into())
+}
+
+pub(crate) fn uint(object: &Json, name: &str, max: u64) -> Result<u64, Box<dyn Error>> {
+    field(object, name)?
+        .as_u64()
+        .filter(|n| *n <= max)
+        .ok_or_else(|| format!("Field {} must be a whole number up to {}", name, max).into())
+}
+
+/// The 1 to 16 `channel` field, counted from 0
+fn channel(object: &Json) -> Result<u8, Box<dyn Error>> {
+    match uint(object, "channel", 16)? {
+        0 => Err("Field channel must be from 1 to 16".into()),
+        channel => Ok(channel as u8 - 1),
+    }
+}
+
+fn data_bytes(object: &Json, name: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    field(object, name)?
+        .as_array()
+        .ok_or_else(|| format!("Field {} must be an array", name))?
+        .iter()
+        .map(|b| {
+            b.as_u64()
+                .filter(|b| *b <= 0xff)
+                .map(|b| b as u8)
+                .ok_or_else(|| format!("Field {} must hold bytes", name).into())
+        })
+        .collect()
+}
+
+pub(crate) fn text(object: &Json, name: &str) -> Result<String, Box<dyn Error>> {
+    Ok(field(object, name)?
+        .as_str()
+        .ok_or_else(|| format!("Field {} must be a string", name))?
+        .to_string())
+}
+
+fn meta_from_json(object: &Json) -> Result<EventData, Box<dyn Error>> {
+    let name = text(object, "meta")?;
+    let meta_type = SysExMeta::ALL
+        .into_iter()
+        .find(|m| meta_name(*m) == name)
+        .ok_or_else(|| format!("Unknown meta event {}", name))?;
+    let u8_field = |name: &str| uint(object, name, 0xff).map(|n| n as u8);
+    let meta = match meta_type {
+        SysExMeta::MetaSequence => match object.get("number") {
+            Some(_) => {
+                let [a, b] = (uint(object, "number", 0xffff)? as u16).to_be_bytes();
+                MetaData::DoubleU8(a, b)
+            }
+            None => MetaData::None,
+        },
+        SysExMeta::MetaKeySignature => {
+            let sharps = field(object, "sharps")?
+                .as_i64()
+                .filter(|n| (-7..=7).contains(n))
+                .ok_or("Field sharps must be a whole number from -7 to 7")?;
+            let minor = field(object, "minor")?
+                .as_bool()
+                .ok_or("Field minor must be true or false")?;
+            MetaData::DoubleU8(sharps as i8 as u8, minor as u8)
+        }
+        SysExMeta::MetaChannelPrefix => MetaData::SingleU8(channel(object)?),
+        SysExMeta::MetaPort => MetaData::SingleU8(uint(object, "port", 0x7f)? as u8),
+        SysExMeta::MetaEndOfTrack => MetaData::None,
+        SysExMeta::MetaSetTempo => {
+            let [_, a, b, c] = (uint(object, "tempo", 0xff_ffff)? as u32).to_be_bytes();
+            MetaData::TripleU8(a, b, c)
+        }
+        SysExMeta::MetaSMPTEOffset => MetaData::QuintripleU8(
+            u8_field("hours")?,
+            u8_field("minutes")?,
+            u8_field("seconds")?,
+            u8_field("frames")?,
+            u8_field("subframes")?,
+        ),
+        SysExMeta::MetaTimeSignature => MetaData::QuadU8(
+            u8_field("numerator")?,
+            u8_field("denominator")?,
+            u8_field("clocks")?,
+            u8_field("thirty_seconds")?,
+        ),
+        // sequencer-specific data and text that is not UTF-8 come as bytes
+        _ => match object.get("data") {
+            Some(_) => MetaData::Bytes(data_bytes(object, "data")?),
+            None => MetaData::SingleString(text(object, "text")?),
+        },
+    };
+    Ok(EventData::SysexData {
+        meta_type: Some(meta_type),
+        meta,
+    })
+}
+
+fn event_from_json(object: &Json) -> Result<(u32, MidiEvent), Box<dyn Error>> {
+    let tick = uint(object, "tick", u32::MAX as u64)? as u32;
+    let kind = text(object, "type")?;
+    let u7 = |name: &str| uint(object, name, 0x7f).map(|n| n as u8);
+    let u14 = |name: &str| uint(object, name, 0x3fff).map(|n| n as u16);
+    let channel_status = |status_type: StatusType| -> Result<Status, Box<dyn Error>> {
+        Ok(Status::channel_message(status_type, channel(object)?))
+    };
+    let system_status = || -> Result<Status, Box<dyn Error>> {
+        Status::from_live_byte(uint(object, "status", 0xff)? as u8)
+    };
+    let (status, data) = match kind.as_str() {
+        "note_on" | "note_off" | "poly_aftertouch" => {
+            let (status_type, amount) = match kind.as_str() {
+                "note_on" => (StatusType::NoteOn, "velocity"),
+                "note_off" => (StatusType::NoteOff, "velocity"),
+                _ => (StatusType::PolyphonicAftertouch, "pressure"),
+            };
+            (
+                channel_status(status_type)?,
+                EventData::NoteOnOffData {
+                    key: u7("key")?,
+                    velocity: u7(amount)?,
+                },
+            )
+        }
+        "control" => (
+            channel_status(StatusType::CtrlChange)?,
+            EventData::ControlData {
+                control_id: u7("controller")?,
+                control_value: u7("value")?,
+            },
+        ),
+        "program" => (
+            channel_status(StatusType::ProgramChange)?,
+            EventData::ProgramChangeData {
+                program_id: u7("program")?,
+            },
+        ),
+        "channel_pressure" => (
+            channel_status(StatusType::ChannelAftertouch)?,
+            EventData::ChannelData {
+                channel_pressure: u7("pressure")?,
+            },
+        ),
+        "pitch_bend" => (
+            channel_status(StatusType::PitchBendChange)?,
+            EventData::PitchBendData {
+                bend: PitchBend::from_raw(u14("value")?),
+            },
+        ),
+        "control14" => (
+            channel_status(StatusType::CtrlChange)?,
+            EventData::Control14Data {
+                control_id: u7("controller")?,
+                value: u14("value")?,
+            },
+        ),
+        "rpn" | "nrpn" => (
+            channel_status(StatusType::CtrlChange)?,
+            EventData::RpnData {
+                change: RpnChange {
+                    kind: match kind.as_str() {
+                        "rpn" => ParameterKind::Registered,
+                        _ => ParameterKind::NonRegistered,
+                    },
+                    parameter: u14("parameter")?,
+                    value: u14("value")?,
+                },
+            },
+        ),
+        "quarter_frame" => (
+            Status::from_live_byte(0xf1)?,
+            EventData::QuarterFrameData {
+                piece: uint(object, "piece", 7)? as u8,
+                value: uint(object, "value", 0x0f)? as u8,
+            },
+        ),
+        "song_position" => (
+            Status::from_live_byte(0xf2)?,
+            EventData::SongPositionData {
+                position: u14("position")?,
+            },
+        ),
+        "song_select" => (
+            Status::from_live_byte(0xf3)?,
+            EventData::SongSelectData { song: u7("song")? },
+        ),
+        "system" => (system_status()?, EventData::NoData),
+        "meta" => (Status::from_byte(0xff)?, meta_from_json(object)?),
+        "sysex" => (
+            system_status()?,
+            EventData::SysexData {
+                meta_type: None,
+                meta: MetaData::Bytes(data_bytes(object, "data")?),
+            },
+        ),
+        "unparsed" => (
+            Status {
+                status_type: StatusType::SystemMsg,
+                raw_status: uint(object, "status", 0xff)? as u8,
+            },
+            EventData::Unparsed {
+                raw: data_bytes(object, "data")?,
+                offset: uint(object, "offset", u64::MAX)?,
+                reason: text(object, "reason")?,
+            },
+        ),
+        _ => return Err(format!("Unknown event type {}", kind).into()),
+    };
+    Ok((
+        tick,
+        MidiEvent {
+            status,
+            data,
+            delta_tick: 0,
+        },
+    ))
+}
+
+impl MidiFile {
+    /// The file as JSON:
+    ///
+    /// `{"version":1,"format":1,"division":480,"tracks":[{"name":..,"instrument":..,"events":[..]}]}`
+    ///
+    /// Every event has an absolute `tick` and a `type`: `note_on`, `note_off`
+    /// and `poly_aftertouch` (`channel`, `key`, `velocity` or `pressure`),
+    /// `control` (`channel`, `controller`, `value`), `program`,
+    /// `channel_pressure`, `pitch_bend` (raw 0 to 16383 `value`),
+    /// `control14`, `rpn` and `nrpn` (`parameter`, `value`),
+    /// `quarter_frame`, `song_position`, `song_select`, `system` (`status`),
+    /// `sysex` (`status` and the `data` bytes after it), `unparsed` and
+    /// `meta`. Meta events name their kind in `meta` and carry `text`,
+    /// `tempo`, `channel`, time signature and SMPTE fields, or raw `data`.
+    /// Channels count from 1.
+    pub fn to_json(&self) -> String {
+        let file = self.loaded();
+        let tracks = file
+            .tracks
+            .iter()
+            .map(|track| {
+                Json::Object(vec![
+                    ("name".to_string(), Json::String(track.name.clone())),
+                    (
+                        "instrument".to_string(),
+                        Json::String(track.instrument.clone()),
+                    ),
+                    (
+                        "events".to_string(),
+                        Json::Array(
+                            track
+                                .iter_ticks()
+                                .map(|(tick, event)| event_to_json(tick, event))
+                                .collect(),
+                        ),
+                    ),
+                ])
+            })
+            .collect();
+        Json::Object(vec![
+            ("version".to_string(), number(VERSION as f64)),
+            ("format".to_string(), number(file.header_format())),
+            ("division".to_string(), number(file.division)),
+            ("tracks".to_string(), Json::Array(tracks)),
+        ])
+        .to_string()
+    }
+
+    /// Reads what `to_json` writes. Events may come in any order within a
+    /// track. Without a `format` the file is written by its track count.
+    pub fn from_json(text: &str) -> Result<Self, Box<dyn Error>> {
+        let root = Json::parse(text)?;
+        let version = uint(&root, "version", u64::MAX)?;
+        if version != VERSION {
+            return Err(format!("Unsupported JSON version {}", version).into());
+        }
+        let mut file = MidiFile::create();
+        if root.get("format").is_some() {
+            file.format = Some(uint(&root, "format", 2)? as u16);
+        }
+        file.division = uint(&root, "division", u16::MAX as u64)? as u16;
+        for track_json in field(&root, "tracks")?
+            .as_array()
+            .ok_or("Field tracks must be an array")?
+        {
+            let events = field(track_json, "events")?
+                .as_array()
+                .ok_or("Field events must be an array")?
+                .iter()
+                .map(event_from_json)
+                .collect::<Result<Vec<_>, _>>()?;
+            let mut track = MidiTrack::from_absolute(events);
+            if let Some(name) = track_json.get("name").and_then(Json::as_str) {
+                track.name = name.to_string();
+            }
+            if let Some(instrument) = track_json.get("instrument").and_then(Json::as_str) {
+                track.instrument = instrument.to_string();
+            }
+            file.tracks.push(track);
+        }
+        file.refresh_tempo();
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Every kind of event the format carries, on two tracks
+    fn file() -> MidiFile {
+        let lead = [
+            0x00, 0xff, 0x03, 0x04, b'L', b'e', b'a', b'd', 0x00, 0xff, 0x51, 0x03, 0x07, 0xa1,
+            0x20, 0x00, 0xff, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08, 0x00, 0xc0, 0x05, 0x00, 0xb0,
+            0x07, 0x64, 0x00, 0xe0, 0x00, 0x50, 0x00, 0xd0, 0x30, 0x00, 0xf0, 0x05, 0x7e, 0x7f,
+            0x09, 0x01, 0xf7, 0x00, 0x90, 0x3c, 0x64, 0x60, 0x80, 0x3c, 0x40, 0x00, 0xff, 0x2f,
+            0x00,
+        ];
+        let bass = [
+            0x00, 0x91, 0x24, 0x50, 0x83, 0x00, 0x91, 0x24, 0x00, 0x00, 0xff, 0x2f, 0x00,
+        ];
+        let mut data = b"MThd\0\0\0\x06\0\x01\0\x02\x01\xe0".to_vec();
+        for track in [&lead[..], &bass[..]] {
+            data.extend(b"MTrk");
+            data.extend((track.len() as u32).to_be_bytes());
+            data.extend(track);
+        }
+        let mut file = MidiFile::create();
+        file.parse_bytes(&data).unwrap();
+        file
+    }
+
+    #[test]
+    fn every_event_round_trips() {
+        let file = file();
+        let back = MidiFile::from_json(&file.to_json()).unwrap();
+        assert!(back == file);
+        assert_eq!(back.to_smf(), file.to_smf());
+        assert_eq!(
+            (back.tempo, back.tracks[0].name.as_str()),
+            (500_000, "Lead")
+        );
+    }
+
+    #[test]
+    fn events_may_come_in_any_order() {
+        let file = file();
+        let Json::Object(mut root) = Json::parse(&file.to_json()).unwrap() else {
+            panic!("Expected an object");
+        };
+        for (_, tracks) in root.iter_mut().filter(|(name, _)| name == "tracks") {
+            let Json::Array(tracks) = tracks else {
+                panic!("Expected an array");
+            };
+            for track in tracks.iter_mut() {
+                let Json::Object(fields) = track else {
+                    panic!("Expected an object");
+                };
+                for (_, events) in fields.iter_mut().filter(|(name, _)| name == "events") {
+                    let Json::Array(events) = events else {
+                        panic!("Expected an array");
+                    };
+                    events.reverse();
+                }
+            }
+        }
+        // events on one tick keep the order given, so only the music matches
+        let back = MidiFile::from_json(&Json::Object(root).to_string()).unwrap();
+        assert_eq!(back.canonical_bytes(), file.canonical_bytes());
+    }
+
+    #[test]
+    fn unknown_version_is_an_error() {
+        let json = file()
+            .to_json()
+            .replacen("\"version\":1", "\"version\":2", 1);
+        assert!(MidiFile::from_json(&json).is_err());
+    }
+
+    #[test]
+    fn bad_event_is_an_error() {
+        let json =
+            r#"{"version":1,"division":96,"tracks":[{"events":[{"tick":0,"type":"wobble"}]}]}"#;
+        assert!(MidiFile::from_json(json).is_err());
+    }
+}
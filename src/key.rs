@@ -0,0 +1,191 @@
+use crate::{
+    note::Notes,
+    parser::{EventData, MetaData, MidiFile, SysExMeta},
+};
+
+const MAJOR_STEPS: [i32; 7] = [0, 2, 4, 5, 7, 9, 11];
+const MINOR_STEPS: [i32; 7] = [0, 2, 3, 5, 7, 8, 10];
+/// Letters in the order the signature sharps them, from F to B
+const SHARP_ORDER: [u8; 7] = [3, 0, 4, 1, 5, 2, 6];
+/// Major tonics by signature, from seven flats to seven sharps
+const MAJOR_TONICS: [&str; 15] = [
+    "Cb", "Gb", "Db", "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#",
+];
+const MINOR_TONICS: [&str; 15] = [
+    "Ab", "Eb", "Bb", "F", "C", "G", "D", "A", "E", "B", "F#", "C#", "G#", "D#", "A#",
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Major,
+    Minor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Key {
+    pub tonic: Notes,
+    pub mode: Mode,
+    pub accidentals: i8,
+}
+
+impl Key {
+    /// `accidentals` counts sharps when positive and flats when negative
+    pub fn from_signature(accidentals: i8, mode: Mode) -> Option<Self> {
+        if !(-7..=7).contains(&accidentals) {
+            return None;
+        }
+        let relative = match mode {
+            Mode::Major => Notes::C,
+            Mode::Minor => Notes::A,
+        };
+        let (tonic, _) = relative.transpose(7 * accidentals as i32);
+        Some(Self {
+            tonic,
+            mode,
+            accidentals,
+        })
+    }
+
+    pub fn from_meta(meta: &MetaData) -> Option<Self> {
+        match *meta {
+            MetaData::DoubleU8(sf, mi) => {
+                let mode = if mi == 0 { Mode::Major } else { Mode::Minor };
+                Self::from_signature(sf as i8, mode)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn scale_notes(&self) -> Vec<Notes> {
+        let steps = match self.mode {
+            Mode::Major => MAJOR_STEPS,
+            Mode::Minor => MINOR_STEPS,
+        };
+        steps
+            .iter()
+            .map(|&step| self.tonic.transpose(step).0)
+            .collect()
+    }
+
+    pub fn contains(&self, note: Notes) -> bool {
+        self.scale_notes().contains(&note)
+    }
+
+    /// The tonic as written, with `b` or `#`, e.g. "Eb" rather than D#
+    pub fn tonic_name(&self) -> &'static str {
+        let index = (self.accidentals + 7) as usize;
+        match self.mode {
+            Mode::Major => MAJOR_TONICS[index],
+            Mode::Minor => MINOR_TONICS[index],
+        }
+    }
+
+    /// What the signature does to a letter (0 for C up to 6 for B): 1 for
+    /// sharp, -1 for flat
+    pub fn signature_alteration(&self, letter: u8) -> i8 {
+        let count = self.accidentals.unsigned_abs() as usize;
+        match self.accidentals {
+            a if a > 0 && SHARP_ORDER[..count].contains(&letter) => 1,
+            a if a < 0 && SHARP_ORDER[7 - count..].contains(&letter) => -1,
+            _ => 0,
+        }
+    }
+
+    /// Letter (0 for C up to 6 for B), alteration and octave of a MIDI key,
+    /// with black keys spelled as sharps in sharp keys and flats in flat
+    /// ones. Middle C is octave 4.
+    pub fn spell(&self, key: u8) -> (u8, i8, i8) {
+        // letters as spelled with sharps
+        const LETTERS: [u8; 12] = [0, 0, 1, 1, 2, 3, 3, 4, 4, 5, 5, 6];
+        let class = key as usize % 12;
+        let (letter, alteration) = match [1, 3, 6, 8, 10].contains(&class) {
+            true if self.accidentals < 0 => (LETTERS[class] + 1, -1),
+            true => (LETTERS[class], 1),
+            false => (LETTERS[class], 0),
+        };
+        (letter, alteration, (key / 12) as i8 - 1)
+    }
+}
+
+impl MidiFile {
+    pub fn key_signatures(&self) -> Vec<(u32, Key)> {
+        let mut keys = vec![];
+        for track in self.loaded().tracks.iter() {
+            for (tick, event) in track.iter_ticks() {
+                if let EventData::SysexData {
+                    meta_type: Some(SysExMeta::MetaKeySignature),
+                    meta,
+                } = &event.data
+                {
+                    if let Some(key) = Key::from_meta(meta) {
+                        keys.push((tick, key));
+                    }
+                }
+            }
+        }
+        keys.sort_by_key(|(tick, _)| *tick);
+        keys
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signature_bytes_match_the_spec() {
+        // FF 59 02 sf mi: sf is signed, mi is 1 for minor
+        for (sf, mi, tonic, name) in [
+            (0x00, 0, Notes::C, "C"),
+            (0x02, 0, Notes::D, "D"),
+            (0xfd, 1, Notes::C, "C"),
+            (0xf9, 0, Notes::B, "Cb"),
+            (0x07, 1, Notes::ASharp, "A#"),
+        ] {
+            let key = Key::from_meta(&MetaData::DoubleU8(sf, mi)).unwrap();
+            assert_eq!((key.tonic, key.tonic_name()), (tonic, name));
+            assert_eq!(key.accidentals, sf as i8);
+            assert_eq!(key.mode == Mode::Minor, mi == 1);
+        }
+        assert_eq!(Key::from_meta(&MetaData::DoubleU8(0x08, 0)), None);
+    }
+
+    #[test]
+    fn signatures_are_read_from_a_file() {
+        let track = [
+            0x00, 0xff, 0x59, 0x02, 0xfd, 0x01, 0x60, 0xff, 0x59, 0x02, 0x03, 0x00, 0x00, 0xff,
+            0x2f, 0x00,
+        ];
+        let mut data = b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk\0\0\0\x10".to_vec();
+        data.extend(track);
+        let mut file = MidiFile::create();
+        file.parse_bytes(&data).unwrap();
+        let keys: Vec<(u32, &str, Mode)> = file
+            .key_signatures()
+            .iter()
+            .map(|(tick, key)| (*tick, key.tonic_name(), key.mode))
+            .collect();
+        assert_eq!(keys, vec![(0, "C", Mode::Minor), (96, "A", Mode::Major)]);
+    }
+
+    #[test]
+    fn signature_alters_the_right_letters() {
+        // B flat major flats B and E
+        let key = Key::from_signature(-2, Mode::Major).unwrap();
+        let altered: Vec<i8> = (0..7)
+            .map(|letter| key.signature_alteration(letter))
+            .collect();
+        assert_eq!(altered, vec![0, 0, -1, 0, 0, 0, -1]);
+        // E major sharps F, C, G and D
+        let key = Key::from_signature(4, Mode::Major).unwrap();
+        let altered: Vec<i8> = (0..7)
+            .map(|letter| key.signature_alteration(letter))
+            .collect();
+        assert_eq!(altered, vec![1, 1, 0, 1, 1, 0, 0]);
+        assert_eq!(key.spell(63), (1, 1, 4));
+        assert_eq!(
+            Key::from_signature(-3, Mode::Major).unwrap().spell(63),
+            (2, -1, 4)
+        );
+    }
+}
@@ -0,0 +1,87 @@
+/// White keys along the home row and black keys above them, as in most DAWs
+const KEYS: &[(u8, u8)] = &[
+    (b'a', 0),
+    (b'w', 1),
+    (b's', 2),
+    (b'e', 3),
+    (b'd', 4),
+    (b'f', 5),
+    (b't', 6),
+    (b'g', 7),
+    (b'y', 8),
+    (b'h', 9),
+    (b'u', 10),
+    (b'j', 11),
+    (b'k', 12),
+    (b'o', 13),
+    (b'l', 14),
+    (b'p', 15),
+    (b';', 16),
+    (b'\'', 17),
+];
+
+const ESCAPE: u8 = 0x1b;
+const VELOCITY_STEP: u8 = 20;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyAction {
+    /// Note number to strike
+    Note(u8),
+    /// The octave after a shift
+    Octave(i8),
+    /// The velocity after a change
+    Velocity(u8),
+    Quit,
+}
+
+/// Plays notes from a computer keyboard: `a` to `'` are the notes, `z` and
+/// `x` shift the octave, `c` and `v` change the velocity and escape quits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QwertyKeyboard {
+    /// Octave of the `a` key, 4 putting it on middle C
+    pub octave: i8,
+    pub velocity: u8,
+    pub channel: u8,
+}
+
+impl QwertyKeyboard {
+    pub fn create() -> Self {
+        Self {
+            octave: 4,
+            velocity: 100,
+            channel: 0,
+        }
+    }
+
+    /// The note `key` plays at the current octave, if any
+    pub fn note(&self, key: u8) -> Option<u8> {
+        let key = key.to_ascii_lowercase();
+        let (_, offset) = KEYS.iter().find(|(k, _)| *k == key)?;
+        let note = (self.octave as i16 + 1) * 12 + *offset as i16;
+        (0..128).contains(&note).then_some(note as u8)
+    }
+
+    /// What `key` does, after applying any octave or velocity change
+    pub fn press(&mut self, key: u8) -> Option<KeyAction> {
+        match key.to_ascii_lowercase() {
+            ESCAPE => Some(KeyAction::Quit),
+            b'z' => {
+                self.octave = (self.octave - 1).max(-1);
+                Some(KeyAction::Octave(self.octave))
+            }
+            b'x' => {
+                self.octave = (self.octave + 1).min(9);
+                Some(KeyAction::Octave(self.octave))
+            }
+            b'c' => {
+                self.velocity = self.velocity.saturating_sub(VELOCITY_STEP).max(1);
+                Some(KeyAction::Velocity(self.velocity))
+            }
+            b'v' => {
+                self.velocity = (self.velocity + VELOCITY_STEP).min(127);
+                Some(KeyAction::Velocity(self.velocity))
+            }
+            _ => self.note(key).map(KeyAction::Note),
+        }
+    }
+}
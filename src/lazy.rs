@@ -0,0 +1,186 @@
+use alloc::{borrow::Cow, boxed::Box, format, string::String};
+use core::error::Error;
+
+use bytes::{Buf, BytesMut};
+
+use crate::parser::{read_value, MidiFile, MidiTrack};
+
+/// Where an unparsed track's `MTrk` chunk sits in the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TrackChunk {
+    /// Byte offset of the chunk header
+    pub offset: usize,
+    pub length: u32,
+}
+
+impl MidiFile {
+    /// The chunk's bytes, header included, cut short if the file is
+    fn chunk_bytes(&self, chunk: TrackChunk) -> Result<BytesMut, Box<dyn Error>> {
+        let source = self
+            .source
+            .as_ref()
+            .ok_or("No source bytes to parse from")?;
+        let end = (chunk.offset + 8 + chunk.length as usize).min(source.len());
+        Ok(BytesMut::from(&source[chunk.offset..end]))
+    }
+
+    /// Parses the track if lazy parsing left it for later. In lazy mode
+    /// `tracks` holds empty placeholders until then, and chunk lengths are
+    /// trusted as given. The crate's own readers, writers and transforms
+    /// load what they need first, but code reading `tracks` directly must
+    /// call `load_all` itself.
+    pub fn track(&mut self, index: usize) -> Result<&MidiTrack, Box<dyn Error>> {
+        if index >= self.tracks.len() {
+            return Err(format!("No track {}", index).into());
+        }
+        if let Some(chunk) = self.unloaded.get(index).copied().flatten() {
+            let mut bytes = self.chunk_bytes(chunk)?;
+            let end = chunk.offset + bytes.len();
+            self.tracks[index] = self.parse_track(&mut bytes, end)?;
+            self.unloaded[index] = None;
+            self.refresh_tempo();
+        }
+        Ok(&self.tracks[index])
+    }
+
+    pub fn is_loaded(&self, index: usize) -> bool {
+        index < self.tracks.len() && !matches!(self.unloaded.get(index), Some(Some(_)))
+    }
+
+    /// Parses every track lazy parsing left for later
+    pub fn load_all(&mut self) -> Result<(), Box<dyn Error>> {
+        for index in 0..self.tracks.len() {
+            self.track(index)?;
+        }
+        Ok(())
+    }
+
+    /// Parses every track left for later as `load_all` does, but reads any
+    /// that fail leniently, keeping what cannot be read as `Unparsed` events,
+    /// so no track stays an empty placeholder
+    pub fn load_leniently(&mut self) {
+        for index in 0..self.tracks.len() {
+            if self.track(index).is_err() {
+                let lenient = core::mem::replace(&mut self.lenient, true);
+                let _ = self.track(index);
+                self.lenient = lenient;
+            }
+        }
+    }
+
+    /// The file with every track parsed: itself when none was left for
+    /// later, otherwise a copy loaded with `load_leniently`. Everything that
+    /// reads the tracks of a file it cannot change goes through this.
+    pub fn loaded(&self) -> Cow<'_, MidiFile> {
+        if self.unloaded.iter().all(Option::is_none) {
+            return Cow::Borrowed(self);
+        }
+        let mut file = self.clone();
+        file.load_leniently();
+        Cow::Owned(file)
+    }
+
+    /// The track's name, read from the meta events at its very start when it
+    /// has not been parsed yet
+    pub fn track_name(&self, index: usize) -> Option<String> {
+        let track = self.tracks.get(index)?;
+        let Some(chunk) = self.unloaded.get(index).copied().flatten() else {
+            return Some(track.name.clone()).filter(|name| !name.is_empty());
+        };
+        let mut bytes = self.chunk_bytes(chunk).ok()?;
+        bytes.advance(8.min(bytes.remaining()));
+        while bytes.remaining() >= 3 {
+            if read_value(&mut bytes) != 0 || bytes.remaining() < 3 || bytes.get_u8() != 0xff {
+                return None;
+            }
+            let meta_type = bytes.get_u8();
+            let length = read_value(&mut bytes) as usize;
+            if length > bytes.remaining() {
+                return None;
+            }
+            let data = bytes.split_to(length);
+            if meta_type == 0x03 {
+                return Some(String::from_utf8_lossy(&data).into_owned());
+            }
+        }
+        None
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use crate::{builder::MidiFileBuilder, note::pair_notes};
+
+    fn smf() -> Vec<u8> {
+        let mut builder = MidiFileBuilder::create();
+        builder.division(96).tempo(100.0);
+        for key in [60, 64, 67] {
+            builder.add_track().note(key, 100, 0, 96);
+        }
+        builder.build().to_smf()
+    }
+
+    fn lazy(data: &[u8]) -> MidiFile {
+        let mut file = MidiFile::create();
+        file.options.lazy = true;
+        file.parse_bytes(data).unwrap();
+        file
+    }
+
+    fn keys(file: &MidiFile) -> Vec<u8> {
+        file.tracks
+            .iter()
+            .flat_map(|track| pair_notes(track.iter_ticks()))
+            .map(|note| note.key)
+            .collect()
+    }
+
+    #[test]
+    fn writing_loads_every_track() {
+        let data = smf();
+        let file = lazy(&data);
+        assert!(!file.is_loaded(1));
+        assert_eq!(file.to_smf(), data);
+    }
+
+    #[test]
+    fn loaded_borrows_once_everything_is_parsed() {
+        let mut file = lazy(&smf());
+        assert!(matches!(file.loaded(), Cow::Owned(_)));
+        assert_eq!(keys(&file.loaded()), vec![60, 64, 67]);
+        file.load_all().unwrap();
+        assert!(matches!(file.loaded(), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn transforms_load_before_changing() {
+        let mut file = lazy(&smf());
+        file.transpose(2).unwrap();
+        assert_eq!(keys(&file), vec![62, 66, 69]);
+        let mut file = lazy(&smf());
+        file.normalize();
+        assert_eq!(keys(&file), vec![60, 64, 67]);
+    }
+
+    #[test]
+    fn validation_sees_unparsed_tracks() {
+        let mut data = smf();
+        let last = data.len() - 1;
+        data[last - 2] = 0x00;
+        let file = lazy(&data);
+        assert!(!file.validate().is_empty());
+    }
+
+    #[test]
+    fn broken_tracks_load_leniently() {
+        let mut data = smf();
+        data.truncate(data.len() - 3);
+        let mut file = lazy(&data);
+        assert!(file.load_all().is_err());
+        file.load_leniently();
+        assert!((0..4).all(|index| file.is_loaded(index)));
+        assert_eq!(keys(&file), vec![60, 64, 67]);
+    }
+}
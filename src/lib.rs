@@ -1,10 +1,155 @@
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod abc;
+#[cfg(feature = "std")]
+pub mod articulation;
+pub mod bend;
+pub mod builder;
+#[cfg(feature = "std")]
+pub mod channels;
+#[cfg(feature = "std")]
+pub mod chord;
+#[cfg(feature = "std")]
+pub mod clock;
+#[cfg(feature = "std")]
+pub mod conductor;
+pub mod control;
+#[cfg(feature = "std")]
+pub mod cursor;
+#[cfg(feature = "std")]
+pub mod drum;
+#[cfg(feature = "std")]
+pub mod duration;
+pub mod edit;
+#[cfg(feature = "std")]
+pub mod error;
+#[cfg(feature = "std")]
+pub mod expression;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(feature = "fixed")]
+pub mod fixed;
+pub mod gm;
+#[cfg(feature = "std")]
+pub mod grid;
+#[cfg(feature = "std")]
+pub mod groove;
+#[cfg(all(windows, feature = "std"))]
+pub mod handle;
+#[cfg(feature = "std")]
+pub mod input;
+#[cfg(feature = "std")]
+pub mod inspect;
+#[cfg(feature = "std")]
+pub mod json;
+#[cfg(feature = "std")]
+pub mod key;
+#[cfg(feature = "std")]
+pub mod keyboard;
+pub mod lazy;
+#[cfg(feature = "std")]
+pub mod lilypond;
+pub mod merge;
+#[cfg(feature = "std")]
+pub mod meter;
+#[cfg(feature = "std")]
+pub mod metronome;
+#[cfg(feature = "std")]
+pub mod midicsv;
+#[cfg(feature = "std")]
+pub mod mmc;
+#[cfg(feature = "std")]
+pub mod monitor;
+#[cfg(feature = "std")]
+pub mod mpe;
+#[cfg(feature = "std")]
+pub mod msc;
+#[cfg(feature = "std")]
+pub mod mtc;
+#[cfg(feature = "std")]
+pub mod mts;
+#[cfg(feature = "std")]
+pub mod musicxml;
+#[cfg(feature = "std")]
+pub mod normalize;
 pub mod note;
+#[cfg(feature = "std")]
+pub mod offset;
+#[cfg(feature = "std")]
+pub mod ornament;
+#[cfg(feature = "std")]
+pub mod output;
 pub mod parser;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod pitch;
+#[cfg(feature = "std")]
+pub mod playback;
+#[cfg(all(windows, feature = "std"))]
+pub mod player;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "python")]
+pub mod python;
+#[cfg(feature = "std")]
+pub mod quantize;
+#[cfg(feature = "std")]
+pub mod queue;
+#[cfg(feature = "std")]
+pub mod region;
+#[cfg(feature = "std")]
+pub mod repair;
+#[cfg(feature = "std")]
+pub mod resample;
+#[cfg(feature = "std")]
+pub mod role;
+#[cfg(feature = "std")]
+pub mod routing;
+pub mod rpn;
+#[cfg(feature = "std")]
+pub mod scheduler;
+#[cfg(feature = "score")]
+pub mod score;
+#[cfg(feature = "std")]
+pub mod script;
+#[cfg(feature = "std")]
+pub mod selection;
+#[cfg(feature = "std")]
+pub mod smf;
+#[cfg(feature = "std")]
+pub mod snippet;
 pub mod status;
-#[cfg(windows)]
+#[cfg(feature = "std")]
+pub mod swing;
+pub mod sysex;
+#[cfg(feature = "std")]
+pub mod tab;
+#[cfg(feature = "std")]
+pub mod tempo;
+#[cfg(feature = "std")]
+pub mod transform;
+pub mod ump;
+#[cfg(feature = "std")]
+pub mod validate;
+#[cfg(feature = "watch")]
+pub mod watch;
+/// The unsafe layer over the Windows MIDI API, working on raw handles the
+/// caller keeps open; `handle` wraps them in owners that close them on drop
+#[cfg(all(windows, feature = "std"))]
 pub mod win;
+#[cfg(feature = "std")]
+pub mod window;
 
-#[cfg(windows)]
+/// Plays `test.mid` to the first output device
+///
+/// # Safety
+///
+/// As for `win::output`.
+#[cfg(all(windows, feature = "std"))]
 pub unsafe fn output() {
     win::output()
 }
@@ -0,0 +1,229 @@
+use std::{error::Error, fmt::Write, fs};
+
+use crate::{
+    key::{Key, Mode},
+    meter::SignatureMap,
+    note::Notes,
+    parser::MidiFile,
+};
+
+const LETTERS: [&str; 7] = ["c", "d", "e", "f", "g", "a", "b"];
+
+/// Lengths in sixteenth notes LilyPond can write as one note, longest first
+const DURATIONS: [(u32, &str); 8] = [
+    (16, "1"),
+    (12, "2."),
+    (8, "2"),
+    (6, "4."),
+    (4, "4"),
+    (3, "8."),
+    (2, "8"),
+    (1, "16"),
+];
+
+/// Dutch note names, which LilyPond uses by default: "fis", "bes", "es"
+fn pitch_name(letter: u8, alteration: i8) -> String {
+    let name = LETTERS[letter as usize];
+    match alteration {
+        1 => format!("{}is", name),
+        -1 if name == "e" || name == "a" => format!("{}s", name),
+        -1 => format!("{}es", name),
+        _ => name.to_string(),
+    }
+}
+
+fn key_command(key: &Key) -> String {
+    let tonic = key.tonic_name();
+    let letter = LETTERS
+        .iter()
+        .position(|l| tonic[..1].eq_ignore_ascii_case(l))
+        .unwrap_or(0) as u8;
+    let alteration = match &tonic[1..] {
+        "#" => 1,
+        "b" => -1,
+        _ => 0,
+    };
+    let mode = match key.mode {
+        Mode::Major => "major",
+        Mode::Minor => "minor",
+    };
+    format!("\\key {} \\{}", pitch_name(letter, alteration), mode)
+}
+
+fn quote(text: &str) -> String {
+    format!("\"{}\"", text.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// Writes one staff bar by bar, splitting and tying across bar lines
+struct StaffWriter<'a> {
+    division: u16,
+    map: &'a SignatureMap,
+    key: Key,
+    keys: Vec<(u32, Key)>,
+    out: String,
+}
+
+impl StaffWriter<'_> {
+    fn pitch(&self, midi_key: u8) -> String {
+        let (letter, alteration, octave) = self.key.spell(midi_key);
+        let marks = match octave - 3 {
+            up if up > 0 => "'".repeat(up as usize),
+            down => ",".repeat(-down as usize),
+        };
+        format!("{}{}", pitch_name(letter, alteration), marks)
+    }
+
+    /// Meter and key changes that take effect at `tick`
+    fn changes(&mut self, tick: u32) {
+        if let Some(change) = self
+            .map
+            .changes
+            .iter()
+            .find(|c| c.tick == tick && c.tick > 0)
+        {
+            let signature = change.signature;
+            let _ = write!(
+                self.out,
+                "\\time {}/{} ",
+                signature.numerator, signature.denominator
+            );
+        }
+        while let Some(&(_, key)) = self.keys.first().filter(|(t, _)| *t <= tick) {
+            self.keys.remove(0);
+            if key != self.key {
+                self.key = key;
+                let _ = write!(self.out, "{} ", key_command(&key));
+            }
+        }
+    }
+
+    /// A chord, single note or rest (no keys) from `start` to `end`
+    fn span(&mut self, keys: &[u8], mut start: u32, end: u32) {
+        while start < end {
+            if self.map.bar_start(start) == start {
+                self.changes(start);
+            }
+            let bar_end = self.map.next_bar_start(start);
+            let stop = if bar_end > start {
+                end.min(bar_end)
+            } else {
+                end
+            };
+            let symbol = match keys {
+                [] => "r".to_string(),
+                [key] => self.pitch(*key),
+                keys => {
+                    let pitches: Vec<String> = keys.iter().map(|k| self.pitch(*k)).collect();
+                    format!("<{}>", pitches.join(" "))
+                }
+            };
+            let mut sixteenths =
+                ((stop - start) as u64 * 4 + self.division as u64 / 2) / self.division as u64;
+            sixteenths = sixteenths.max(1);
+            while sixteenths > 0 {
+                let (length, name) = DURATIONS
+                    .iter()
+                    .find(|(length, _)| *length as u64 <= sixteenths)
+                    .copied()
+                    .unwrap_or(DURATIONS[7]);
+                sixteenths -= length as u64;
+                let _ = write!(self.out, "{}{}", symbol, name);
+                let tied = !keys.is_empty() && (sixteenths > 0 || stop < end);
+                self.out.push_str(if tied { "~ " } else { " " });
+            }
+            if stop == bar_end {
+                self.out.push_str("|\n      ");
+            }
+            start = stop;
+        }
+    }
+}
+
+impl MidiFile {
+    /// A LilyPond score with a staff per track that has notes, snapped to
+    /// sixteenth notes. Notes struck together are written as chords.
+    pub fn to_lilypond(&self) -> String {
+        let file = self.loaded();
+        let grid = (file.division as u32 / 4).max(1);
+        let map = file.signature_map();
+        let keys = file.key_signatures();
+        let signature = map.signature_at(0).signature;
+
+        let mut out = String::new();
+        let _ = writeln!(out, "\\version \"2.24.0\"");
+        let _ = writeln!(out);
+        let _ = writeln!(out, "\\score {{");
+        let _ = writeln!(out, "  <<");
+        for (index, track) in file.tracks.iter().enumerate() {
+            let chords = track.chords(grid);
+            if chords.is_empty() {
+                continue;
+            }
+            let name = match track.name.as_str() {
+                "" => format!("Track {}", index + 1),
+                name => name.to_string(),
+            };
+            let count: usize = chords.iter().map(|(_, _, keys)| keys.len()).sum();
+            let mean = chords
+                .iter()
+                .flat_map(|(_, _, keys)| keys.iter())
+                .map(|k| *k as usize)
+                .sum::<usize>()
+                / count;
+            let clef = if mean < 60 { "bass" } else { "treble" };
+
+            let mut remaining = keys.clone();
+            let key = match remaining.first() {
+                Some((0, _)) => remaining.remove(0).1,
+                _ => Key {
+                    tonic: Notes::C,
+                    mode: Mode::Major,
+                    accidentals: 0,
+                },
+            };
+            let _ = writeln!(
+                out,
+                "    \\new Staff \\with {{ instrumentName = {} }} {{",
+                quote(&name)
+            );
+            let _ = writeln!(out, "      \\clef {}", clef);
+            let _ = writeln!(out, "      {}", key_command(&key));
+            let _ = writeln!(
+                out,
+                "      \\time {}/{}",
+                signature.numerator, signature.denominator
+            );
+            let _ = writeln!(out, "      \\tempo 4 = {}", file.bpm);
+
+            let mut writer = StaffWriter {
+                division: file.division.max(1),
+                map: &map,
+                key,
+                keys: remaining,
+                out: String::new(),
+            };
+            let mut position = 0;
+            for (start, length, keys) in chords.iter() {
+                writer.span(&[], position, *start);
+                writer.span(keys, *start, start + length);
+                position = start + length;
+            }
+            if map.bar_start(position) < position {
+                writer.span(&[], position, map.next_bar_start(position));
+            }
+            let _ = writeln!(out, "      {}", writer.out.trim_end());
+            let _ = writeln!(out, "      \\bar \"|.\"");
+            let _ = writeln!(out, "    }}");
+        }
+        let _ = writeln!(out, "  >>");
+        let _ = writeln!(out, "  \\layout {{ }}");
+        let _ = writeln!(out, "}}");
+        out
+    }
+
+    /// Writes `to_lilypond` to a `.ly` file
+    pub fn save_lilypond(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.to_lilypond())?;
+        Ok(())
+    }
+}
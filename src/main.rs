@@ -1,5 +0,0 @@
-use midi_rs::output;
-
-fn main() {
-    unsafe { output() }
-}
@@ -0,0 +1,230 @@
+use alloc::{boxed::Box, format, string::ToString, vec, vec::Vec};
+use core::error::Error;
+
+use crate::{
+    gm,
+    parser::{EventData, MetaData, MidiEvent, MidiFile, MidiTrack, SysExMeta},
+    status::DRUM_CHANNEL,
+};
+
+fn is_track_name(event: &MidiEvent) -> bool {
+    matches!(
+        event.data,
+        EventData::SysexData {
+            meta_type: Some(SysExMeta::MetaTrackName),
+            ..
+        }
+    )
+}
+
+impl MidiTrack {
+    /// One track per channel the track uses, in channel order, each named
+    /// after its first GM program. Events on no channel, such as tempo and
+    /// other meta events, go in a track of their own ahead of them, left
+    /// out when there are none.
+    pub fn split_by_channel(&self) -> Vec<MidiTrack> {
+        let mut shared: Vec<(u32, MidiEvent)> = vec![];
+        let mut channels: [Vec<(u32, MidiEvent)>; 16] = Default::default();
+        let mut end = 0;
+        for (tick, event) in self.iter_ticks() {
+            end = tick;
+            if event.is_end_of_track() {
+                continue;
+            }
+            match event.status.is_channel_message() {
+                true => channels[event.status.channel() as usize].push((tick, event.clone())),
+                false => shared.push((tick, event.clone())),
+            }
+        }
+
+        let mut tracks = vec![];
+        if !shared.is_empty() {
+            let mut track = MidiTrack::from_absolute(shared);
+            track.name = self.name.clone();
+            track.instrument = self.instrument.clone();
+            tracks.push(track);
+        }
+        for (channel, mut events) in channels.into_iter().enumerate() {
+            if events.is_empty() {
+                continue;
+            }
+            let program = events.iter().find_map(|(_, event)| match event.data {
+                EventData::ProgramChangeData { program_id } => Some(program_id),
+                _ => None,
+            });
+            let name = match (channel as u8, program.and_then(gm::program_name)) {
+                (DRUM_CHANNEL, _) => "Drums".to_string(),
+                (_, Some(name)) => name.to_string(),
+                (_, None) => format!("Channel {}", channel + 1),
+            };
+            let meta = MetaData::SingleString(name.clone());
+            events.insert(0, (0, MidiEvent::meta(SysExMeta::MetaTrackName, meta)));
+            events.push((end, MidiEvent::end_of_track(0)));
+            let mut track = MidiTrack::from_absolute(events);
+            track.name = name;
+            tracks.push(track);
+        }
+        tracks
+    }
+}
+
+impl MidiFile {
+    /// Merges the tracks at `indices` into one, in place of the first of
+    /// them. Events on the same tick keep the order of `indices`, and the
+    /// merged track keeps the first track's name.
+    pub fn merge_tracks(&mut self, indices: &[usize]) -> Result<(), Box<dyn Error>> {
+        let Some(&first) = indices.first() else {
+            return Ok(());
+        };
+        for (i, index) in indices.iter().enumerate() {
+            if *index >= self.tracks.len() {
+                return Err(format!("No track {} to merge", index).into());
+            }
+            if indices[..i].contains(index) {
+                return Err(format!("Track {} is listed twice", index).into());
+            }
+            if self.unloaded.get(*index).is_some_and(Option::is_some) {
+                self.track(*index)?;
+            }
+        }
+
+        let mut events: Vec<(u32, MidiEvent)> = vec![];
+        for (i, index) in indices.iter().enumerate() {
+            let track = &self.tracks[*index];
+            // a merged track has one name
+            events.extend(
+                track
+                    .iter_ticks()
+                    .filter(|(_, event)| i == 0 || !is_track_name(event))
+                    .map(|(tick, event)| (tick, event.clone())),
+            );
+        }
+        // stable, so each tick keeps the events of earlier tracks first
+        events.sort_by_key(|(tick, _)| *tick);
+        let mut merged = MidiTrack::from_absolute(events);
+        merged.name = self.tracks[first].name.clone();
+        merged.instrument = self.tracks[first].instrument.clone();
+
+        let mut removed: Vec<usize> = indices[1..].to_vec();
+        removed.sort_unstable_by(|a, b| b.cmp(a));
+        self.tracks[first] = merged;
+        for index in removed {
+            self.tracks.remove(index);
+            if index < self.unloaded.len() {
+                self.unloaded.remove(index);
+            }
+        }
+        Ok(())
+    }
+
+    /// The file as a single track, every track merged by absolute time, as
+    /// format 0 players want
+    pub fn to_format0(&self) -> Result<MidiFile, Box<dyn Error>> {
+        let mut file = self.clone();
+        let all: Vec<usize> = (0..file.tracks.len()).collect();
+        file.merge_tracks(&all)?;
+        file.format = Some(0);
+        Ok(file)
+    }
+
+    /// A single-track file split into a conductor track holding tempo and
+    /// other meta events followed by one track per channel. Files that
+    /// already have several tracks keep them, marked format 1.
+    pub fn to_format1(&self) -> MidiFile {
+        let mut file = self.clone();
+        if let [track] = &self.tracks[..] {
+            let mut tracks = track.split_by_channel();
+            if tracks
+                .first()
+                .is_none_or(|t| t.events.iter().any(|e| e.status.is_channel_message()))
+            {
+                tracks.insert(0, MidiTrack::from_absolute(vec![]));
+            }
+            file.tracks = tracks;
+            file.unloaded = vec![];
+        }
+        file.format = Some(1);
+        file
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+
+    const NOTE: &[u8] = &[
+        0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0, 0x00, 0xff, 0x2f, 0x00,
+    ];
+
+    fn smf(format: u16, tracks: usize) -> Vec<u8> {
+        let mut out = b"MThd\0\0\0\x06".to_vec();
+        out.extend(format.to_be_bytes());
+        out.extend((tracks as u16).to_be_bytes());
+        out.extend(96u16.to_be_bytes());
+        for _ in 0..tracks {
+            out.extend(b"MTrk");
+            out.extend((NOTE.len() as u32).to_be_bytes());
+            out.extend(NOTE);
+        }
+        out
+    }
+
+    fn parse(data: &[u8]) -> MidiFile {
+        let mut file = MidiFile::create();
+        file.parse_bytes(data).unwrap();
+        file
+    }
+
+    fn written_format(file: &MidiFile) -> u16 {
+        u16::from_be_bytes([file.to_smf()[8], file.to_smf()[9]])
+    }
+
+    #[test]
+    fn header_format_is_kept() {
+        for (format, tracks) in [(0, 1), (1, 1), (1, 3), (2, 2)] {
+            let data = smf(format, tracks);
+            let file = parse(&data);
+            assert_eq!(file.format, Some(format));
+            assert_eq!(file.to_smf(), data);
+        }
+    }
+
+    #[test]
+    fn format_0_with_added_tracks_is_written_as_1() {
+        let mut file = parse(&smf(0, 1));
+        file.tracks.push(file.tracks[0].clone());
+        assert_eq!(written_format(&file), 1);
+    }
+
+    #[test]
+    fn files_made_in_code_go_by_track_count() {
+        let mut file = MidiFile::create();
+        file.tracks.push(MidiTrack::create());
+        assert_eq!(written_format(&file), 0);
+        file.tracks.push(MidiTrack::create());
+        assert_eq!(written_format(&file), 1);
+    }
+
+    #[test]
+    fn conversions_set_the_format() {
+        let file = parse(&smf(2, 2));
+        let format0 = file.to_format0().unwrap();
+        assert_eq!((format0.tracks.len(), written_format(&format0)), (1, 0));
+        let format1 = parse(&smf(0, 1)).to_format1();
+        assert_eq!(written_format(&format1), 1);
+        assert_eq!(written_format(&file.to_format1()), 1);
+    }
+
+    #[test]
+    fn text_formats_keep_the_header_format() {
+        let file = parse(&smf(1, 1));
+        assert_eq!(
+            MidiFile::from_midicsv(&file.to_midicsv()).unwrap().format,
+            Some(1)
+        );
+        assert_eq!(
+            MidiFile::from_json(&file.to_json()).unwrap().format,
+            Some(1)
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use crate::parser::{EventData, MetaData, MidiFile, SysExMeta};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimeSignature {
+    pub numerator: u8,
+    pub denominator: u8,
+    pub clocks_per_click: u8,
+    pub thirty_seconds_per_quarter: u8,
+}
+
+impl TimeSignature {
+    pub fn create(numerator: u8, denominator: u8) -> Self {
+        Self {
+            numerator,
+            denominator,
+            clocks_per_click: 24,
+            thirty_seconds_per_quarter: 8,
+        }
+    }
+
+    pub fn from_meta(meta: &MetaData) -> Option<Self> {
+        match *meta {
+            MetaData::QuadU8(
+                numerator,
+                denominator,
+                clocks_per_click,
+                thirty_seconds_per_quarter,
+            ) if numerator > 0 && denominator > 0 => Some(Self {
+                numerator,
+                denominator,
+                clocks_per_click,
+                thirty_seconds_per_quarter,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn ticks_per_beat(&self, division: u16) -> u32 {
+        division as u32 * 4 / self.denominator as u32
+    }
+
+    pub fn ticks_per_bar(&self, division: u16) -> u32 {
+        self.ticks_per_beat(division) * self.numerator as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BarBeat {
+    pub bar: u32,
+    pub beat: u32,
+    pub tick: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignatureChange {
+    pub tick: u32,
+    pub bar: u32,
+    pub signature: TimeSignature,
+}
+
+/// Bars and beats are counted from 1. A signature change that falls inside a
+/// bar cuts that bar short and starts a new one.
+#[derive(Debug, Clone)]
+pub struct SignatureMap {
+    pub division: u16,
+    pub changes: Vec<SignatureChange>,
+}
+
+impl SignatureMap {
+    pub fn from_file(file: &MidiFile) -> Self {
+        let file = file.loaded();
+        let mut signatures: Vec<(u32, TimeSignature)> = vec![];
+        for track in file.tracks.iter() {
+            for (tick, event) in track.iter_ticks() {
+                if let EventData::SysexData {
+                    meta_type: Some(SysExMeta::MetaTimeSignature),
+                    meta,
+                } = &event.data
+                {
+                    if let Some(signature) = TimeSignature::from_meta(meta) {
+                        signatures.push((tick, signature));
+                    }
+                }
+            }
+        }
+        Self::from_signatures(file.division, signatures)
+    }
+
+    pub fn from_signatures(division: u16, mut signatures: Vec<(u32, TimeSignature)>) -> Self {
+        signatures.sort_by_key(|(tick, _)| *tick);
+        if !matches!(signatures.first(), Some((0, _))) {
+            signatures.insert(0, (0, TimeSignature::create(4, 4)));
+        }
+
+        let mut changes: Vec<SignatureChange> = vec![];
+        for (tick, signature) in signatures {
+            let bar = match changes.last() {
+                Some(prev) if prev.tick == tick => {
+                    changes.pop();
+                    changes
+                        .last()
+                        .map_or(1, |p| p.bar_at_or_after(tick, division))
+                }
+                Some(prev) => prev.bar_at_or_after(tick, division),
+                None => 1,
+            };
+            changes.push(SignatureChange {
+                tick,
+                bar,
+                signature,
+            });
+        }
+
+        Self { division, changes }
+    }
+
+    pub fn signature_at(&self, tick: u32) -> &SignatureChange {
+        let pos = self.changes.partition_point(|c| c.tick <= tick);
+        &self.changes[pos.saturating_sub(1)]
+    }
+
+    pub fn tick_to_bar_beat(&self, tick: u32) -> BarBeat {
+        let change = self.signature_at(tick);
+        let per_bar = change.signature.ticks_per_bar(self.division).max(1);
+        let per_beat = change.signature.ticks_per_beat(self.division).max(1);
+        let offset = tick - change.tick;
+        BarBeat {
+            bar: change.bar + offset / per_bar,
+            beat: offset % per_bar / per_beat + 1,
+            tick: offset % per_bar % per_beat,
+        }
+    }
+
+    pub fn bar_start(&self, tick: u32) -> u32 {
+        self.bar_beat_to_tick(self.tick_to_bar_beat(tick).bar, 1)
+    }
+
+    pub fn next_bar_start(&self, tick: u32) -> u32 {
+        self.bar_beat_to_tick(self.tick_to_bar_beat(tick).bar + 1, 1)
+    }
+
+    pub fn bar_beat_to_tick(&self, bar: u32, beat: u32) -> u32 {
+        let pos = self.changes.partition_point(|c| c.bar <= bar.max(1));
+        let change = &self.changes[pos.saturating_sub(1)];
+        change.tick
+            + (bar.max(1) - change.bar) * change.signature.ticks_per_bar(self.division)
+            + (beat.max(1) - 1) * change.signature.ticks_per_beat(self.division)
+    }
+}
+
+impl SignatureChange {
+    fn bar_at_or_after(&self, tick: u32, division: u16) -> u32 {
+        let per_bar = self.signature.ticks_per_bar(division).max(1);
+        self.bar + (tick - self.tick).div_ceil(per_bar)
+    }
+}
+
+impl MidiFile {
+    pub fn signature_map(&self) -> SignatureMap {
+        SignatureMap::from_file(self)
+    }
+
+    pub fn tick_to_bar_beat(&self, tick: u32) -> BarBeat {
+        self.signature_map().tick_to_bar_beat(tick)
+    }
+
+    pub fn bar_start(&self, tick: u32) -> u32 {
+        self.bar_beat_to_tick(self.tick_to_bar_beat(tick).bar, 1)
+    }
+
+    pub fn next_bar_start(&self, tick: u32) -> u32 {
+        self.bar_beat_to_tick(self.tick_to_bar_beat(tick).bar + 1, 1)
+    }
+
+    pub fn bar_beat_to_tick(&self, bar: u32, beat: u32) -> u32 {
+        self.signature_map().bar_beat_to_tick(bar, beat)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 6/8 with a dotted-quarter click, then 3/4 from the third bar, at 96
+    /// ticks per quarter
+    const TRACK: [u8; 21] = [
+        0x00, 0xff, 0x58, 0x04, 0x06, 0x03, 0x24, 0x08, 0x84, 0x40, 0xff, 0x58, 0x04, 0x03, 0x02,
+        0x18, 0x08, 0x00, 0xff, 0x2f, 0x00,
+    ];
+
+    fn file() -> MidiFile {
+        let mut data = b"MThd\0\0\0\x06\0\0\0\x01\0\x60MTrk\0\0\0\x15".to_vec();
+        data.extend(TRACK);
+        let mut file = MidiFile::create();
+        file.parse_bytes(&data).unwrap();
+        file
+    }
+
+    #[test]
+    fn signature_bytes_match_the_spec() {
+        // FF 58 04 nn dd cc bb, the denominator given as a power of two
+        let map = file().signature_map();
+        let signatures: Vec<(u32, u32, TimeSignature)> = map
+            .changes
+            .iter()
+            .map(|change| (change.tick, change.bar, change.signature))
+            .collect();
+        assert_eq!(
+            signatures,
+            vec![
+                (
+                    0,
+                    1,
+                    TimeSignature {
+                        numerator: 6,
+                        denominator: 8,
+                        clocks_per_click: 36,
+                        thirty_seconds_per_quarter: 8,
+                    }
+                ),
+                (576, 3, TimeSignature::create(3, 4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn signatures_are_written_as_powers_of_two() {
+        let data = file().to_smf();
+        assert_eq!(data[22..], TRACK);
+    }
+
+    #[test]
+    fn bars_and_beats_follow_the_signatures() {
+        let map = file().signature_map();
+        let at = |tick| {
+            let position = map.tick_to_bar_beat(tick);
+            (position.bar, position.beat, position.tick)
+        };
+        // eighth-note beats in 6/8
+        assert_eq!(at(0), (1, 1, 0));
+        assert_eq!(at(340), (2, 2, 4));
+        assert_eq!(at(600), (3, 1, 24));
+        assert_eq!(at(576 + 96 * 3), (4, 1, 0));
+        assert_eq!(map.bar_beat_to_tick(2, 4), 288 + 48 * 3);
+        assert_eq!(map.bar_beat_to_tick(4, 2), 576 + 288 + 96);
+        assert_eq!((map.bar_start(700), map.next_bar_start(700)), (576, 864));
+    }
+
+    #[test]
+    fn a_change_inside_a_bar_starts_a_new_one() {
+        let map = SignatureMap::from_signatures(96, vec![(200, TimeSignature::create(3, 4))]);
+        assert_eq!(map.changes[1].bar, 2);
+        assert_eq!(map.tick_to_bar_beat(199).bar, 1);
+        assert_eq!(map.tick_to_bar_beat(200).bar, 2);
+    }
+}
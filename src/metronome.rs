@@ -0,0 +1,64 @@
+use crate::{
+    drum::DrumNote, meter::SignatureMap, note::Note, parser::MidiEvent, status::DRUM_CHANNEL,
+};
+
+/// Click settings. The first beat of every bar uses the accent key and
+/// velocity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Metronome {
+    pub channel: u8,
+    pub accent_key: u8,
+    pub accent_velocity: u8,
+    pub key: u8,
+    pub velocity: u8,
+    /// Click length in ticks
+    pub length: u32,
+}
+
+impl Metronome {
+    pub fn create() -> Self {
+        Self {
+            channel: DRUM_CHANNEL,
+            accent_key: DrumNote::HiWoodBlock.key(),
+            accent_velocity: 110,
+            key: DrumNote::LowWoodBlock.key(),
+            velocity: 80,
+            length: 30,
+        }
+    }
+
+    /// One click per beat from tick 0 up to `end`, following time signature
+    /// changes
+    pub fn clicks(&self, map: &SignatureMap, end: u32) -> Vec<Note> {
+        let mut notes = vec![];
+        let mut tick = 0;
+        while tick < end {
+            let signature = map.signature_at(tick).signature;
+            let accent = map.tick_to_bar_beat(tick).beat == 1;
+            notes.push(Note {
+                channel: self.channel,
+                key: if accent { self.accent_key } else { self.key },
+                velocity: if accent {
+                    self.accent_velocity
+                } else {
+                    self.velocity
+                },
+                start: tick,
+                duration: self.length,
+                release_velocity: 0,
+            });
+            tick += signature.ticks_per_beat(map.division).max(1);
+        }
+        notes
+    }
+
+    pub fn events(&self, map: &SignatureMap, end: u32) -> Vec<(u32, MidiEvent)> {
+        let mut events: Vec<(u32, MidiEvent)> = self
+            .clicks(map, end)
+            .iter()
+            .flat_map(|note| note.events())
+            .collect();
+        events.sort_by_key(|(tick, _)| *tick);
+        events
+    }
+}
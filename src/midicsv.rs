@@ -0,0 +1,590 @@
+use std::{error::Error, fmt::Write};
+
+use crate::{
+    bend::PitchBend,
+    control::split_14bit,
+    parser::{EventData, MetaData, MidiEvent, MidiFile, MidiTrack, SysExMeta},
+    smf::meta_bytes,
+    status::{Status, StatusType},
+};
+
+/// Quoted, with quotes doubled and backslashes, control characters and
+/// bytes that are not UTF-8 escaped the way midicsv does
+fn quote(text: &[u8]) -> String {
+    let mut out = String::from("\"");
+    for chunk in text.utf8_chunks() {
+        for c in chunk.valid().chars() {
+            match c {
+                '"' => out.push_str("\"\""),
+                '\\' => out.push_str("\\\\"),
+                c if (c as u32) < 0x20 || c as u32 == 0x7f => {
+                    let _ = write!(out, "\\{:03o}", c as u32);
+                }
+                c => out.push(c),
+            }
+        }
+        for byte in chunk.invalid() {
+            let _ = write!(out, "\\{:03o}", byte);
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// The bytes a quoted field stands for, each octal escape being one byte
+fn unquote(field: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let inner = field
+        .strip_prefix('"')
+        .and_then(|f| f.strip_suffix('"'))
+        .ok_or_else(|| format!("Expected a quoted string: {}", field))?;
+    let mut out = vec![];
+    let mut chars = inner.chars().peekable();
+    while let Some(c) = chars.next() {
+        let c = match c {
+            '"' => {
+                chars.next_if_eq(&'"');
+                '"'
+            }
+            '\\' => match chars.peek() {
+                Some(d) if d.is_digit(8) => {
+                    let mut code = 0;
+                    for _ in 0..3 {
+                        match chars.next_if(|d| d.is_digit(8)) {
+                            Some(d) => code = code * 8 + d.to_digit(8).unwrap_or(0),
+                            None => break,
+                        }
+                    }
+                    match u8::try_from(code) {
+                        Ok(byte) => {
+                            out.push(byte);
+                            continue;
+                        }
+                        Err(_) => char::from_u32(code).unwrap_or('\u{fffd}'),
+                    }
+                }
+                Some(_) => chars.next().unwrap_or('\\'),
+                None => '\\',
+            },
+            c => c,
+        };
+        out.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes());
+    }
+    Ok(out)
+}
+
+/// Splits on commas outside quotes, trimming each field
+fn fields(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in line.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => fields.push(std::mem::take(&mut current).trim().to_string()),
+            c => current.push(c),
+        }
+    }
+    fields.push(current.trim().to_string());
+    fields
+}
+
+fn text_record(meta: SysExMeta) -> Option<&'static str> {
+    match meta {
+        SysExMeta::MetaText => Some("Text_t"),
+        SysExMeta::MetaCopyright => Some("Copyright_t"),
+        SysExMeta::MetaTrackName => Some("Title_t"),
+        SysExMeta::MetaInstrumentName => Some("Instrument_name_t"),
+        SysExMeta::MetaLyrics => Some("Lyric_t"),
+        SysExMeta::MetaMarker => Some("Marker_t"),
+        SysExMeta::MetaCuePoint => Some("Cue_point_t"),
+        _ => None,
+    }
+}
+
+/// Metas midicsv has no record for, such as program and device names, as
+/// its catch-all record so that they still round-trip
+fn unknown_meta(meta_type: SysExMeta, meta: &MetaData) -> String {
+    format!(
+        "Unknown_meta_event, {}, {}",
+        meta_type as u8,
+        byte_list(&meta_bytes(meta_type, meta))
+    )
+}
+
+fn byte_list(data: &[u8]) -> String {
+    let mut out = data.len().to_string();
+    for byte in data {
+        let _ = write!(out, ", {}", byte);
+    }
+    out
+}
+
+/// The records after track and tick for one event; decoded controller
+/// events expand to the control changes they came from
+fn records(event: &MidiEvent) -> Vec<String> {
+    let channel = event.status.channel();
+    let controls = |pairs: &[(u8, u8)]| -> Vec<String> {
+        pairs
+            .iter()
+            .map(|(id, value)| format!("Control_c, {}, {}, {}", channel, id, value))
+            .collect()
+    };
+    let record = match &event.data {
+        EventData::NoteOnOffData { key, velocity } => {
+            let name = match event.status.status_type {
+                StatusType::NoteOn => "Note_on_c",
+                StatusType::NoteOff => "Note_off_c",
+                _ => "Poly_aftertouch_c",
+            };
+            format!("{}, {}, {}, {}", name, channel, key, velocity)
+        }
+        EventData::ControlData {
+            control_id,
+            control_value,
+        } => return controls(&[(*control_id, *control_value)]),
+        EventData::Control14Data { control_id, value } => {
+            return controls(&split_14bit(*control_id, *value))
+        }
+        EventData::RpnData { change } => return controls(&change.to_controls()),
+        EventData::ProgramChangeData { program_id } => {
+            format!("Program_c, {}, {}", channel, program_id)
+        }
+        EventData::ChannelData { channel_pressure } => {
+            format!("Channel_aftertouch_c, {}, {}", channel, channel_pressure)
+        }
+        EventData::PitchBendData { bend } => format!("Pitch_bend_c, {}, {}", channel, bend.raw()),
+        EventData::SysexData {
+            meta_type: None,
+            meta: MetaData::Bytes(data),
+        } => match event.status.raw_status {
+            0xf7 => format!("System_exclusive_packet, {}", byte_list(data)),
+            _ => format!("System_exclusive, {}", byte_list(data)),
+        },
+        EventData::SysexData {
+            meta_type: Some(meta_type),
+            meta,
+        } => match (meta_type, meta) {
+            (SysExMeta::MetaEndOfTrack, _) => return vec![],
+            (SysExMeta::MetaSequencerSpecific, MetaData::SingleString(text)) => {
+                format!("Sequencer_specific, {}", byte_list(text.as_bytes()))
+            }
+            (SysExMeta::MetaSequencerSpecific, MetaData::Bytes(data)) => {
+                format!("Sequencer_specific, {}", byte_list(data))
+            }
+            (meta_type, MetaData::SingleString(text)) => match text_record(*meta_type) {
+                Some(name) => format!("{}, {}", name, quote(text.as_bytes())),
+                None => unknown_meta(*meta_type, meta),
+            },
+            (meta_type, MetaData::Bytes(text)) => match text_record(*meta_type) {
+                Some(name) => format!("{}, {}", name, quote(text)),
+                None => unknown_meta(*meta_type, meta),
+            },
+            (SysExMeta::MetaSequence, MetaData::DoubleU8(a, b)) => {
+                format!("Sequence_number, {}", (*a as u16) << 8 | *b as u16)
+            }
+            (SysExMeta::MetaChannelPrefix, MetaData::SingleU8(channel)) => {
+                format!("Channel_prefix, {}", channel)
+            }
+            (SysExMeta::MetaPort, MetaData::SingleU8(port)) => format!("MIDI_port, {}", port),
+            (SysExMeta::MetaSetTempo, MetaData::TripleU8(a, b, c)) => {
+                format!(
+                    "Tempo, {}",
+                    (*a as u32) << 16 | (*b as u32) << 8 | *c as u32
+                )
+            }
+            (SysExMeta::MetaSMPTEOffset, MetaData::QuintripleU8(h, m, s, f, ff)) => {
+                format!("SMPTE_offset, {}, {}, {}, {}, {}", h, m, s, f, ff)
+            }
+            (SysExMeta::MetaTimeSignature, MetaData::QuadU8(n, d, c, b)) => {
+                format!(
+                    "Time_signature, {}, {}, {}, {}",
+                    n,
+                    d.trailing_zeros(),
+                    c,
+                    b
+                )
+            }
+            (SysExMeta::MetaKeySignature, MetaData::DoubleU8(key, minor)) => format!(
+                "Key_signature, {}, \"{}\"",
+                *key as i8,
+                if *minor == 1 { "minor" } else { "major" }
+            ),
+            (meta_type, meta) => unknown_meta(*meta_type, meta),
+        },
+        EventData::Unparsed { offset, reason, .. } => {
+            format!("# Unparsed at byte {}: {}", offset, reason)
+        }
+        _ => return vec![],
+    };
+    vec![record]
+}
+
+fn number<T: std::str::FromStr>(fields: &[String], index: usize) -> Result<T, Box<dyn Error>> {
+    let field = fields
+        .get(index)
+        .ok_or_else(|| format!("Missing field {} in {}", index + 1, fields.join(", ")))?;
+    field
+        .parse()
+        .map_err(|_| format!("Bad number {} in {}", field, fields.join(", ")).into())
+}
+
+/// A length followed by that many bytes, starting at `index`
+fn bytes(fields: &[String], index: usize) -> Result<Vec<u8>, Box<dyn Error>> {
+    let length: usize = number(fields, index)?;
+    (index + 1..index + 1 + length)
+        .map(|i| number(fields, i))
+        .collect()
+}
+
+fn meta(meta_type: SysExMeta, meta: MetaData) -> (Status, EventData) {
+    (
+        Status {
+            status_type: StatusType::SystemMsg,
+            raw_status: 0xff,
+        },
+        EventData::SysexData {
+            meta_type: Some(meta_type),
+            meta,
+        },
+    )
+}
+
+fn channel_event(
+    fields: &[String],
+    status_type: StatusType,
+    data: impl FnOnce() -> Result<EventData, Box<dyn Error>>,
+) -> Result<(Status, EventData), Box<dyn Error>> {
+    let channel: u8 = number(fields, 3)?;
+    if channel > 15 {
+        return Err(format!("Bad channel {} in {}", channel, fields.join(", ")).into());
+    }
+    Ok((Status::channel_message(status_type, channel), data()?))
+}
+
+/// Reads one record after the track and tick, or `None` for records that
+/// carry no event
+fn parse_record(fields: &[String]) -> Result<Option<(Status, EventData)>, Box<dyn Error>> {
+    let u7 = |index: usize| -> Result<u8, Box<dyn Error>> {
+        let value: u8 = number(fields, index)?;
+        match value {
+            0..=127 => Ok(value),
+            _ => Err(format!("Bad data byte {} in {}", value, fields.join(", ")).into()),
+        }
+    };
+    let text = || unquote(fields.get(3).map_or("", |f| f.as_str()));
+    let event = match fields[2].as_str() {
+        "Note_on_c" | "Note_off_c" | "Poly_aftertouch_c" => {
+            let status_type = match fields[2].as_str() {
+                "Note_on_c" => StatusType::NoteOn,
+                "Note_off_c" => StatusType::NoteOff,
+                _ => StatusType::PolyphonicAftertouch,
+            };
+            channel_event(fields, status_type, || {
+                Ok(EventData::NoteOnOffData {
+                    key: u7(4)?,
+                    velocity: u7(5)?,
+                })
+            })?
+        }
+        "Control_c" => channel_event(fields, StatusType::CtrlChange, || {
+            Ok(EventData::ControlData {
+                control_id: u7(4)?,
+                control_value: u7(5)?,
+            })
+        })?,
+        "Program_c" => channel_event(fields, StatusType::ProgramChange, || {
+            Ok(EventData::ProgramChangeData { program_id: u7(4)? })
+        })?,
+        "Channel_aftertouch_c" => channel_event(fields, StatusType::ChannelAftertouch, || {
+            Ok(EventData::ChannelData {
+                channel_pressure: u7(4)?,
+            })
+        })?,
+        "Pitch_bend_c" => channel_event(fields, StatusType::PitchBendChange, || {
+            let value: u16 = number(fields, 4)?;
+            Ok(EventData::PitchBendData {
+                bend: PitchBend::from_raw(value.min(0x3fff)),
+            })
+        })?,
+        "System_exclusive" | "System_exclusive_packet" => (
+            Status {
+                status_type: StatusType::SystemMsg,
+                raw_status: match fields[2].as_str() {
+                    "System_exclusive" => 0xf0,
+                    _ => 0xf7,
+                },
+            },
+            EventData::SysexData {
+                meta_type: None,
+                meta: MetaData::Bytes(bytes(fields, 3)?),
+            },
+        ),
+        "Text_t" => meta(SysExMeta::MetaText, MetaData::text(text()?)),
+        "Copyright_t" => meta(SysExMeta::MetaCopyright, MetaData::text(text()?)),
+        "Title_t" => meta(SysExMeta::MetaTrackName, MetaData::text(text()?)),
+        "Instrument_name_t" => meta(SysExMeta::MetaInstrumentName, MetaData::text(text()?)),
+        "Lyric_t" => meta(SysExMeta::MetaLyrics, MetaData::text(text()?)),
+        "Marker_t" => meta(SysExMeta::MetaMarker, MetaData::text(text()?)),
+        "Cue_point_t" => meta(SysExMeta::MetaCuePoint, MetaData::text(text()?)),
+        "Sequencer_specific" => meta(
+            SysExMeta::MetaSequencerSpecific,
+            MetaData::Bytes(bytes(fields, 3)?),
+        ),
+        "Sequence_number" => {
+            let [a, b] = number::<u16>(fields, 3)?.to_be_bytes();
+            meta(SysExMeta::MetaSequence, MetaData::DoubleU8(a, b))
+        }
+        "Channel_prefix" => meta(
+            SysExMeta::MetaChannelPrefix,
+            MetaData::SingleU8(number(fields, 3)?),
+        ),
+        "MIDI_port" => meta(SysExMeta::MetaPort, MetaData::SingleU8(number(fields, 3)?)),
+        "Tempo" => {
+            let [_, a, b, c] = number::<u32>(fields, 3)?.min(0xff_ffff).to_be_bytes();
+            meta(SysExMeta::MetaSetTempo, MetaData::TripleU8(a, b, c))
+        }
+        "SMPTE_offset" => meta(
+            SysExMeta::MetaSMPTEOffset,
+            MetaData::QuintripleU8(
+                number(fields, 3)?,
+                number(fields, 4)?,
+                number(fields, 5)?,
+                number(fields, 6)?,
+                number(fields, 7)?,
+            ),
+        ),
+        "Time_signature" => {
+            let power: u32 = number(fields, 4)?;
+            let denominator = 1u8
+                .checked_shl(power)
+                .ok_or_else(|| format!("Bad denominator 2^{}", power))?;
+            meta(
+                SysExMeta::MetaTimeSignature,
+                MetaData::QuadU8(
+                    number(fields, 3)?,
+                    denominator,
+                    number(fields, 5)?,
+                    number(fields, 6)?,
+                ),
+            )
+        }
+        "Key_signature" => {
+            let key: i8 = number(fields, 3)?;
+            let minor = unquote(fields.get(4).map_or("", |f| f.as_str()))?;
+            meta(
+                SysExMeta::MetaKeySignature,
+                MetaData::DoubleU8(key as u8, minor.eq_ignore_ascii_case(b"minor") as u8),
+            )
+        }
+        "End_track" => meta(SysExMeta::MetaEndOfTrack, MetaData::None),
+        // unknown meta events have no place in the model
+        "Unknown_meta_event" => {
+            let data = bytes(fields, 4)?;
+            match SysExMeta::try_from(number::<u8>(fields, 3)?) {
+                Ok(meta_type @ (SysExMeta::MetaProgramName | SysExMeta::MetaDeviceName)) => {
+                    meta(meta_type, MetaData::text(data))
+                }
+                Ok(meta_type) => meta(meta_type, MetaData::Bytes(data)),
+                Err(_) => return Ok(None),
+            }
+        }
+        "Start_track" => return Ok(None),
+        other => return Err(format!("Unknown record type {}", other).into()),
+    };
+    Ok(Some(event))
+}
+
+impl MidiFile {
+    /// The file in the text format of the `midicsv` tool: a `Header` record,
+    /// then each track between `Start_track` and `End_track`, one event per
+    /// line as `track, tick, type, parameters`. Channels count from 0 as in
+    /// midicsv. Decoded RPN and 14-bit controller events are written as the
+    /// control changes they came from.
+    pub fn to_midicsv(&self) -> String {
+        let file = self.loaded();
+        let mut out = format!(
+            "0, 0, Header, {}, {}, {}\n",
+            file.header_format(),
+            file.tracks.len(),
+            file.division
+        );
+        for (index, track) in file.tracks.iter().enumerate() {
+            let number = index + 1;
+            let _ = writeln!(out, "{}, 0, Start_track", number);
+            let mut end = 0;
+            for (tick, event) in track.iter_ticks() {
+                end = tick;
+                for record in records(event) {
+                    match record.starts_with('#') {
+                        true => {
+                            let _ = writeln!(out, "{}", record);
+                        }
+                        false => {
+                            let _ = writeln!(out, "{}, {}, {}", number, tick, record);
+                        }
+                    }
+                }
+            }
+            let _ = writeln!(out, "{}, {}, End_track", number, end);
+        }
+        out.push_str("0, 0, End_of_file\n");
+        out
+    }
+
+    /// Reads midicsv text. Blank lines and lines starting with `#` or `;`
+    /// are skipped; port and unknown meta records are dropped.
+    pub fn from_midicsv(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut file = MidiFile::create();
+        let mut tracks: Vec<Vec<(u32, MidiEvent)>> = vec![];
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+                continue;
+            }
+            let fields = fields(line);
+            if fields.len() < 3 {
+                return Err(format!("Too few fields: {}", line).into());
+            }
+            let track: usize = number(&fields, 0)?;
+            let tick: u32 = number(&fields, 1)?;
+            if track == 0 {
+                match fields[2].as_str() {
+                    "Header" => {
+                        file.format = Some(number(&fields, 3)?);
+                        file.division = number(&fields, 5)?;
+                    }
+                    "End_of_file" => break,
+                    other => return Err(format!("Unexpected {} record on track 0", other).into()),
+                }
+                continue;
+            }
+            if tracks.len() < track {
+                tracks.resize(track, vec![]);
+            }
+            if let Some((status, data)) = parse_record(&fields)? {
+                tracks[track - 1].push((
+                    tick,
+                    MidiEvent {
+                        status,
+                        data,
+                        delta_tick: 0,
+                    },
+                ));
+            }
+        }
+
+        for events in tracks {
+            let mut track = MidiTrack::create();
+            for (_, event) in events.iter() {
+                let (meta_type, meta) = match &event.data {
+                    EventData::SysexData {
+                        meta_type:
+                            Some(
+                                meta_type @ (SysExMeta::MetaTrackName
+                                | SysExMeta::MetaInstrumentName),
+                            ),
+                        meta,
+                    } => (meta_type, meta),
+                    _ => continue,
+                };
+                let text = match meta {
+                    MetaData::SingleString(text) => text.clone(),
+                    MetaData::Bytes(text) => String::from_utf8_lossy(text).to_string(),
+                    _ => continue,
+                };
+                match meta_type {
+                    SysExMeta::MetaTrackName => track.name = text,
+                    _ => track.instrument = text,
+                }
+            }
+            track.set_absolute(events);
+            file.tracks.push(track);
+        }
+        file.refresh_tempo();
+        Ok(file)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::builder::MidiFileBuilder;
+
+    /// Every kind of event the format carries, on two tracks
+    fn file() -> MidiFile {
+        let lead = [
+            0x00, 0xff, 0x03, 0x04, b'L', b'e', b'a', b'd', 0x00, 0xff, 0x51, 0x03, 0x07, 0xa1,
+            0x20, 0x00, 0xff, 0x58, 0x04, 0x03, 0x02, 0x18, 0x08, 0x00, 0xc0, 0x05, 0x00, 0xb0,
+            0x07, 0x64, 0x00, 0xe0, 0x00, 0x50, 0x00, 0xd0, 0x30, 0x00, 0xf0, 0x05, 0x7e, 0x7f,
+            0x09, 0x01, 0xf7, 0x00, 0x90, 0x3c, 0x64, 0x60, 0x80, 0x3c, 0x40, 0x00, 0xff, 0x2f,
+            0x00,
+        ];
+        let bass = [
+            0x00, 0x91, 0x24, 0x50, 0x83, 0x00, 0x91, 0x24, 0x00, 0x00, 0xff, 0x2f, 0x00,
+        ];
+        let mut data = b"MThd\0\0\0\x06\0\x01\0\x02\x01\xe0".to_vec();
+        for track in [&lead[..], &bass[..]] {
+            data.extend(b"MTrk");
+            data.extend((track.len() as u32).to_be_bytes());
+            data.extend(track);
+        }
+        let mut file = MidiFile::create();
+        file.parse_bytes(&data).unwrap();
+        file
+    }
+
+    #[test]
+    fn every_event_round_trips() {
+        let file = file();
+        let back = MidiFile::from_midicsv(&file.to_midicsv()).unwrap();
+        assert!(back == file);
+        assert_eq!(back.to_smf(), file.to_smf());
+    }
+
+    #[test]
+    fn reads_midicsv_output() {
+        let text = "0, 0, Header, 1, 1, 96
+1, 0, Start_track
+1, 0, Title_t, \"Lead, \"\"live\"\"\"
+1, 0, Note_on_c, 0, 60, 100
+
+; a comment
+1, 96, Note_off_c, 0, 60, 0
+1, 96, End_track
+0, 0, End_of_file
+";
+        let file = MidiFile::from_midicsv(text).unwrap();
+        assert_eq!((file.division, file.format), (96, Some(1)));
+        assert_eq!(file.tracks[0].name, "Lead, \"live\"");
+        assert_eq!(file.to_midicsv().lines().count(), 7);
+    }
+
+    #[test]
+    fn bad_records_are_errors() {
+        for text in [
+            "0, 0, Header, 1, 1",
+            "1, 0, Note_on_c, 0, 60",
+            "1, x, Note_on_c, 0, 60, 100",
+            "1, 0, Wobble_c, 0",
+            "0, 0, Note_on_c, 0, 60, 100",
+        ] {
+            assert!(MidiFile::from_midicsv(text).is_err(), "{}", text);
+        }
+    }
+
+    #[test]
+    fn program_and_device_names_round_trip() {
+        let mut builder = MidiFileBuilder::create();
+        builder
+            .add_track()
+            .text(SysExMeta::MetaProgramName, "Piano")
+            .text(SysExMeta::MetaDeviceName, "Port A");
+        let file = builder.build();
+        let text = file.to_midicsv();
+        assert!(text.contains("0, Unknown_meta_event, 8, 5, 80, 105, 97, 110, 111\n"));
+        assert!(text.contains("0, Unknown_meta_event, 9, 6, 80, 111, 114, 116, 32, 65\n"));
+        assert!(MidiFile::from_midicsv(&text).unwrap() == file);
+    }
+}
@@ -0,0 +1,216 @@
+use crate::{
+    mtc::{FrameRate, Timecode},
+    parser::EventData,
+    sysex::{ManufacturerId, SysExEvent},
+};
+
+/// Device id addressing every device on the bus
+pub const ALL_DEVICES: u8 = 0x7f;
+
+/// Real-time universal SysEx sub-id for MMC commands
+const MMC_COMMAND: u8 = 0x06;
+const LOCATE: u8 = 0x44;
+const LOCATE_TARGET: u8 = 0x01;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MmcCommand {
+    Stop,
+    Play,
+    DeferredPlay,
+    FastForward,
+    Rewind,
+    RecordStrobe,
+    RecordExit,
+    RecordPause,
+    Pause,
+    Eject,
+    Chase,
+    Reset,
+    Locate(Timecode),
+    Other(u8),
+}
+
+impl MmcCommand {
+    pub fn from(id: u8) -> Self {
+        match id {
+            0x01 => Self::Stop,
+            0x02 => Self::Play,
+            0x03 => Self::DeferredPlay,
+            0x04 => Self::FastForward,
+            0x05 => Self::Rewind,
+            0x06 => Self::RecordStrobe,
+            0x07 => Self::RecordExit,
+            0x08 => Self::RecordPause,
+            0x09 => Self::Pause,
+            0x0a => Self::Eject,
+            0x0b => Self::Chase,
+            0x0d => Self::Reset,
+            id => Self::Other(id),
+        }
+    }
+
+    pub fn id(&self) -> u8 {
+        match *self {
+            Self::Stop => 0x01,
+            Self::Play => 0x02,
+            Self::DeferredPlay => 0x03,
+            Self::FastForward => 0x04,
+            Self::Rewind => 0x05,
+            Self::RecordStrobe => 0x06,
+            Self::RecordExit => 0x07,
+            Self::RecordPause => 0x08,
+            Self::Pause => 0x09,
+            Self::Eject => 0x0a,
+            Self::Chase => 0x0b,
+            Self::Reset => 0x0d,
+            Self::Locate(_) => LOCATE,
+            Self::Other(id) => id,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MmcMessage {
+    pub device_id: u8,
+    pub command: MmcCommand,
+}
+
+impl MmcMessage {
+    pub fn create(device_id: u8, command: MmcCommand) -> Self {
+        Self { device_id, command }
+    }
+
+    pub fn play() -> Self {
+        Self::create(ALL_DEVICES, MmcCommand::Play)
+    }
+
+    pub fn stop() -> Self {
+        Self::create(ALL_DEVICES, MmcCommand::Stop)
+    }
+
+    pub fn record_strobe() -> Self {
+        Self::create(ALL_DEVICES, MmcCommand::RecordStrobe)
+    }
+
+    pub fn locate(timecode: Timecode) -> Self {
+        Self::create(ALL_DEVICES, MmcCommand::Locate(timecode))
+    }
+
+    pub fn to_sysex(&self) -> SysExEvent {
+        let mut data = vec![self.device_id, MMC_COMMAND, self.command.id()];
+        if let MmcCommand::Locate(timecode) = self.command {
+            data.extend([
+                0x06,
+                LOCATE_TARGET,
+                (timecode.rate as u8) << 5 | timecode.hours,
+                timecode.minutes,
+                timecode.seconds,
+                timecode.frames,
+                0x00,
+            ]);
+        }
+        SysExEvent::create(ManufacturerId::Short(0x7f), data)
+    }
+
+    /// The full message including F0 and F7
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_sysex().to_bytes()
+    }
+
+    pub fn from_sysex(sysex: &SysExEvent) -> Option<Self> {
+        if sysex.manufacturer != ManufacturerId::Short(0x7f) {
+            return None;
+        }
+        match sysex.data[..] {
+            [device_id, MMC_COMMAND, LOCATE, _, LOCATE_TARGET, hours, minutes, seconds, frames, ..] => {
+                Some(Self {
+                    device_id,
+                    command: MmcCommand::Locate(Timecode::create(
+                        hours & 0x1f,
+                        minutes,
+                        seconds,
+                        frames,
+                        FrameRate::from(hours >> 5),
+                    )),
+                })
+            }
+            [device_id, MMC_COMMAND, id, ..] if id != LOCATE => Some(Self {
+                device_id,
+                command: MmcCommand::from(id),
+            }),
+            _ => None,
+        }
+    }
+
+    /// Parses a message with or without its leading F0
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        Self::from_sysex(&SysExEvent::from_payload(bytes)?)
+    }
+
+    /// Whether a device with `device_id` should act on this message
+    pub fn addresses(&self, device_id: u8) -> bool {
+        self.device_id == ALL_DEVICES || self.device_id == device_id
+    }
+}
+
+impl EventData {
+    pub fn mmc(&self) -> Option<MmcMessage> {
+        MmcMessage::from_sysex(&self.sysex()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transport_commands_match_the_spec() {
+        assert_eq!(
+            MmcMessage::play().to_bytes(),
+            [0xf0, 0x7f, 0x7f, 0x06, 0x02, 0xf7]
+        );
+        assert_eq!(
+            MmcMessage::stop().to_bytes(),
+            [0xf0, 0x7f, 0x7f, 0x06, 0x01, 0xf7]
+        );
+        assert_eq!(
+            MmcMessage::create(0x10, MmcCommand::RecordStrobe).to_bytes(),
+            [0xf0, 0x7f, 0x10, 0x06, 0x06, 0xf7]
+        );
+        assert_eq!(
+            MmcMessage::parse(&[0xf0, 0x7f, 0x7f, 0x06, 0x09, 0xf7]),
+            Some(MmcMessage::create(ALL_DEVICES, MmcCommand::Pause))
+        );
+    }
+
+    #[test]
+    fn locate_matches_the_spec() {
+        let timecode = Timecode::create(1, 2, 3, 4, FrameRate::Fps30);
+        let bytes = MmcMessage::locate(timecode).to_bytes();
+        // hours byte is 0rrhhhhh, then frames and subframes
+        assert_eq!(
+            bytes,
+            [0xf0, 0x7f, 0x7f, 0x06, 0x44, 0x06, 0x01, 0x61, 0x02, 0x03, 0x04, 0x00, 0xf7]
+        );
+        assert_eq!(
+            MmcMessage::parse(&bytes),
+            Some(MmcMessage::locate(timecode))
+        );
+    }
+
+    #[test]
+    fn other_messages_are_not_mmc() {
+        // an MTC full frame is real-time universal too
+        assert_eq!(
+            MmcMessage::parse(&[0xf0, 0x7f, 0x7f, 0x01, 0x01, 0x20, 0, 0, 0, 0xf7]),
+            None
+        );
+        assert_eq!(
+            MmcMessage::parse(&[0xf0, 0x7e, 0x7f, 0x06, 0x02, 0xf7]),
+            None
+        );
+        assert!(MmcMessage::create(0x10, MmcCommand::Play).addresses(0x10));
+        assert!(!MmcMessage::create(0x10, MmcCommand::Play).addresses(0x11));
+        assert!(MmcMessage::play().addresses(0x11));
+    }
+}
@@ -0,0 +1,193 @@
+use std::{error::Error, fmt::Write};
+
+use crate::{
+    cursor::Timeline,
+    input::InputMessage,
+    parser::{EventData, MetaData, MidiEvent, MidiFile},
+    sysex::SYSEX_START,
+};
+
+/// A message decoded for display, from live input or a file
+#[derive(Debug, Clone)]
+pub struct MonitorMessage {
+    /// Since input started, or since the start of the file
+    pub micros: u64,
+    /// Track and tick, for events read from a file
+    pub position: Option<(usize, u32)>,
+    pub event: MidiEvent,
+    /// Wire bytes; empty for meta and decoded multi-message events
+    pub bytes: Vec<u8>,
+}
+
+/// Status and data bytes of a packed short message
+fn short_bytes(event: &MidiEvent, message: u32) -> Vec<u8> {
+    let length = event.status.data_length().unwrap_or(0);
+    message.to_le_bytes()[..1 + length].to_vec()
+}
+
+impl MonitorMessage {
+    pub fn live(message: &InputMessage) -> Result<Self, Box<dyn Error>> {
+        let event = message.event()?;
+        Ok(Self {
+            micros: message.micros,
+            position: None,
+            bytes: short_bytes(&event, message.message),
+            event,
+        })
+    }
+
+    pub fn from_event(micros: u64, track: usize, tick: u32, event: &MidiEvent) -> Self {
+        let bytes = match (&event.data, event.to_short_message()) {
+            (_, Some(message)) => short_bytes(event, message),
+            (
+                EventData::SysexData {
+                    meta_type: None,
+                    meta: MetaData::Bytes(payload),
+                },
+                None,
+            ) => [&[SYSEX_START], payload.as_slice()].concat(),
+            (EventData::Unparsed { raw, .. }, None) => raw.clone(),
+            _ => vec![],
+        };
+        Self {
+            micros,
+            position: Some((track, tick)),
+            event: event.clone(),
+            bytes,
+        }
+    }
+
+    pub fn seconds(&self) -> f64 {
+        self.micros as f64 / 1_000_000.0
+    }
+
+    pub fn channel(&self) -> Option<u8> {
+        self.event
+            .status
+            .is_channel_message()
+            .then(|| self.event.status.channel())
+    }
+
+    pub fn hex(&self) -> String {
+        let mut out = String::new();
+        for (i, byte) in self.bytes.iter().enumerate() {
+            if i > 0 {
+                out.push(' ');
+            }
+            let _ = write!(out, "{:02X}", byte);
+        }
+        out
+    }
+}
+
+/// Turns decoded messages into lines of text
+pub trait MonitorFormat {
+    /// Line written before the first message, if the format has one
+    fn header(&self) -> Option<String> {
+        None
+    }
+
+    fn format(&self, message: &MonitorMessage) -> String;
+}
+
+/// Time, status and decoded data, as the input monitor has always printed
+pub struct HumanFormat;
+
+impl MonitorFormat for HumanFormat {
+    fn format(&self, message: &MonitorMessage) -> String {
+        let position = match message.position {
+            Some((track, tick)) => format!(" [{}:{}]", track, tick),
+            None => String::new(),
+        };
+        format!(
+            "{:>10.3}s{} Status: {:?} - {}",
+            message.seconds(),
+            position,
+            message.event.status.status_type,
+            message.event.data
+        )
+    }
+}
+
+/// Time and wire bytes, falling back to the decoded text for meta events
+pub struct HexFormat;
+
+impl MonitorFormat for HexFormat {
+    fn format(&self, message: &MonitorMessage) -> String {
+        match message.bytes.is_empty() {
+            true => format!("{:>10.3}s ({})", message.seconds(), message.event.data),
+            false => format!("{:>10.3}s {}", message.seconds(), message.hex()),
+        }
+    }
+}
+
+/// One row per message, for spreadsheets and scripts
+pub struct CsvFormat;
+
+impl MonitorFormat for CsvFormat {
+    fn header(&self) -> Option<String> {
+        Some("seconds,track,tick,status,channel,bytes,description".to_string())
+    }
+
+    fn format(&self, message: &MonitorMessage) -> String {
+        let (track, tick) = match message.position {
+            Some((track, tick)) => (track.to_string(), tick.to_string()),
+            None => (String::new(), String::new()),
+        };
+        format!(
+            "{:.6},{},{},{:?},{},{},\"{}\"",
+            message.seconds(),
+            track,
+            tick,
+            message.event.status.status_type,
+            message
+                .channel()
+                .map_or(String::new(), |c| (c + 1).to_string()),
+            message.hex(),
+            message.event.data.to_string().replace('"', "\"\"")
+        )
+    }
+}
+
+/// Renders live input or whole files through one format
+pub struct Monitor {
+    pub format: Box<dyn MonitorFormat + Send + Sync>,
+}
+
+impl Monitor {
+    pub fn create(format: impl MonitorFormat + Send + Sync + 'static) -> Self {
+        Self {
+            format: Box::new(format),
+        }
+    }
+
+    pub fn human() -> Self {
+        Self::create(HumanFormat)
+    }
+
+    pub fn header(&self) -> Option<String> {
+        self.format.header()
+    }
+
+    /// One line for an incoming message; ones that fail to decode are shown
+    /// with the reason instead
+    pub fn line(&self, message: &InputMessage) -> String {
+        match MonitorMessage::live(message) {
+            Ok(decoded) => self.format.format(&decoded),
+            Err(e) => format!("{:>10.3}s {}", message.elapsed().as_secs_f64(), e),
+        }
+    }
+
+    /// Every event of the file in play order, header first, timed with the
+    /// file's tempo map
+    pub fn dump(&self, file: &MidiFile) -> Vec<String> {
+        let tempo_map = file.tempo_map();
+        let mut lines: Vec<String> = self.header().into_iter().collect();
+        for e in file.timeline() {
+            let micros = tempo_map.micros_at(e.tick as f64).round() as u64;
+            let message = MonitorMessage::from_event(micros, e.track, e.tick, e.event);
+            lines.push(self.format.format(&message));
+        }
+        lines
+    }
+}
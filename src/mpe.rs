@@ -0,0 +1,448 @@
+use crate::{
+    control::ControlChange,
+    expression::{expression_event, ExpressionKind, ExpressiveNote, MpeExporter},
+    output::MidiOutput,
+    parser::{EventData, MidiEvent, MidiFile, MidiTrack},
+    rpn::{ParameterKind, RpnAssembler, RpnChange, RpnInput, MPE_CONFIGURATION},
+    status::{Status, StatusType},
+};
+
+/// A lower zone is mastered on channel 1 and takes member channels upwards
+/// from channel 2, an upper zone is mastered on channel 16 and takes them
+/// downwards from channel 15
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MpeZone {
+    pub master_channel: u8,
+    pub member_count: u8,
+}
+
+impl MpeZone {
+    pub fn lower(member_count: u8) -> Self {
+        Self {
+            master_channel: 0,
+            member_count: member_count.min(15),
+        }
+    }
+
+    pub fn upper(member_count: u8) -> Self {
+        Self {
+            master_channel: 15,
+            member_count: member_count.min(15),
+        }
+    }
+
+    /// The zone an MPE Configuration Message (RPN 6) sets up, if `change`
+    /// is one sent on a master channel. A member count of 0 turns the zone
+    /// off.
+    pub fn from_rpn(channel: u8, change: &RpnChange) -> Option<Self> {
+        if change.kind != ParameterKind::Registered || change.parameter != MPE_CONFIGURATION {
+            return None;
+        }
+        let member_count = (change.value >> 7) as u8;
+        match channel {
+            0 => Some(Self::lower(member_count)),
+            15 => Some(Self::upper(member_count)),
+            _ => None,
+        }
+    }
+
+    /// Member channels in the order notes are given them
+    pub fn member_channels(&self) -> Vec<u8> {
+        let count = self.member_count.min(15);
+        if self.master_channel == 0 {
+            (1..=count).collect()
+        } else {
+            (15 - count..15).rev().collect()
+        }
+    }
+
+    pub fn is_member(&self, channel: u8) -> bool {
+        self.member_channels().contains(&channel)
+    }
+
+    pub fn configuration(&self) -> RpnChange {
+        RpnChange {
+            kind: ParameterKind::Registered,
+            parameter: MPE_CONFIGURATION,
+            value: (self.member_count as u16) << 7,
+        }
+    }
+
+    /// The configuration as an event on the master channel, for the start of
+    /// a track
+    pub fn configuration_event(&self) -> MidiEvent {
+        MidiEvent {
+            status: Status::channel_message(StatusType::CtrlChange, self.master_channel),
+            data: EventData::RpnData {
+                change: self.configuration(),
+            },
+            delta_tick: 0,
+        }
+    }
+
+    /// Sends the configuration on the master channel, then deselects the
+    /// parameter so later data entry can't change it
+    pub fn configure(&self, output: &mut impl MidiOutput) {
+        let status = StatusType::CtrlChange as u32 | self.master_channel as u32;
+        let null = [
+            (ControlChange::RpnMsb.id(), 0x7f),
+            (ControlChange::RpnLsb.id(), 0x7f),
+        ];
+        for (control_id, value) in self.configuration().to_controls().into_iter().chain(null) {
+            output.send_short(status | (control_id as u32) << 8 | (value as u32) << 16);
+        }
+    }
+
+    /// Shrinks this zone so it leaves `other`'s channels alone, turning it
+    /// off if `other` takes its master channel
+    fn yield_to(&mut self, other: &MpeZone) {
+        if other.member_count == 0 {
+            return;
+        }
+        let free = 14 - other.member_count.min(14);
+        self.member_count = if other.member_count == 15 {
+            0
+        } else {
+            self.member_count.min(free)
+        };
+    }
+}
+
+impl MpeExporter {
+    pub fn from_zone(zone: &MpeZone) -> Self {
+        Self {
+            master_channel: zone.master_channel,
+            member_channels: zone.member_channels(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct Slot {
+    key: Option<u8>,
+    last_used: usize,
+}
+
+/// Sends notes into a zone live, giving each sounding note a member channel
+/// of its own so bends and pressure reach only that note
+pub struct MpeSender {
+    zone: MpeZone,
+    slots: Vec<Slot>,
+    notes_sent: usize,
+}
+
+impl MpeSender {
+    pub fn create(zone: MpeZone) -> Self {
+        Self {
+            zone,
+            slots: vec![Slot::default(); zone.member_count.min(15) as usize],
+            notes_sent: 0,
+        }
+    }
+
+    pub fn zone(&self) -> &MpeZone {
+        &self.zone
+    }
+
+    /// Member channel the most recent sounding `key` was given
+    pub fn channel_of(&self, key: u8) -> Option<u8> {
+        let slot = self
+            .slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.key == Some(key))
+            .max_by_key(|(_, slot)| slot.last_used)?
+            .0;
+        Some(self.zone.member_channels()[slot])
+    }
+
+    fn send(output: &mut impl MidiOutput, status: Status, data: EventData) {
+        let event = MidiEvent {
+            status,
+            data,
+            delta_tick: 0,
+        };
+        if let Some(message) = event.to_short_message() {
+            output.send_short(message);
+        }
+    }
+
+    fn send_note(output: &mut impl MidiOutput, on: bool, channel: u8, key: u8, velocity: u8) {
+        let status_type = if on {
+            StatusType::NoteOn
+        } else {
+            StatusType::NoteOff
+        };
+        Self::send(
+            output,
+            Status::channel_message(status_type, channel),
+            EventData::NoteOnOffData { key, velocity },
+        );
+    }
+
+    /// Starts `key` on the least recently used free member channel. With
+    /// every channel busy the longest-held note is cut off and its channel
+    /// taken. None when the zone has no member channels.
+    pub fn note_on(&mut self, output: &mut impl MidiOutput, key: u8, velocity: u8) -> Option<u8> {
+        let oldest = |slots: &mut dyn Iterator<Item = (usize, &Slot)>| {
+            slots.min_by_key(|(_, slot)| slot.last_used).map(|(i, _)| i)
+        };
+        let index = oldest(
+            &mut self
+                .slots
+                .iter()
+                .enumerate()
+                .filter(|(_, s)| s.key.is_none()),
+        )
+        .or_else(|| oldest(&mut self.slots.iter().enumerate()))?;
+        let channel = self.zone.member_channels()[index];
+        if let Some(stolen) = self.slots[index].key {
+            Self::send_note(output, false, channel, stolen, 0);
+        }
+        self.notes_sent += 1;
+        self.slots[index] = Slot {
+            key: Some(key),
+            last_used: self.notes_sent,
+        };
+        Self::send_note(output, true, channel, key, velocity);
+        Some(channel)
+    }
+
+    /// Ends `key`, returning the channel it sounded on. None if it is not
+    /// sounding, e.g. because a later note took its channel.
+    pub fn note_off(&mut self, output: &mut impl MidiOutput, key: u8, velocity: u8) -> Option<u8> {
+        let channel = self.channel_of(key)?;
+        let members = self.zone.member_channels();
+        let index = members.iter().position(|c| *c == channel)?;
+        self.slots[index].key = None;
+        Self::send_note(output, false, channel, key, velocity);
+        Some(channel)
+    }
+
+    /// Sends a bend, pressure or timbre change to the channel of `key` only.
+    /// `value` ranges as in `ExpressionPoint`. Returns false if `key` is not
+    /// sounding.
+    pub fn express(
+        &mut self,
+        output: &mut impl MidiOutput,
+        key: u8,
+        kind: ExpressionKind,
+        value: f32,
+    ) -> bool {
+        let Some(channel) = self.channel_of(key) else {
+            return false;
+        };
+        let (status, data) = expression_event(kind, channel, value);
+        Self::send(output, status, data);
+        true
+    }
+}
+
+/// The expression a member-channel event carries, valued as in
+/// `ExpressionPoint`
+fn expression(event: &MidiEvent) -> Option<(ExpressionKind, f32)> {
+    match (event.status.status_type, &event.data) {
+        (StatusType::PitchBendChange, EventData::PitchBendData { bend }) => {
+            Some((ExpressionKind::PitchBend, bend.normalized()))
+        }
+        (StatusType::ChannelAftertouch, EventData::ChannelData { channel_pressure }) => {
+            Some((ExpressionKind::Pressure, *channel_pressure as f32 / 127.0))
+        }
+        (
+            StatusType::CtrlChange,
+            EventData::ControlData {
+                control_id,
+                control_value,
+            },
+        ) if *control_id == ControlChange::Brightness.id() => {
+            Some((ExpressionKind::Timbre, *control_value as f32 / 127.0))
+        }
+        _ => None,
+    }
+}
+
+fn kind_index(kind: ExpressionKind) -> usize {
+    match kind {
+        ExpressionKind::PitchBend => 0,
+        ExpressionKind::Pressure => 1,
+        ExpressionKind::Timbre => 2,
+    }
+}
+
+const KINDS: [ExpressionKind; 3] = [
+    ExpressionKind::PitchBend,
+    ExpressionKind::Pressure,
+    ExpressionKind::Timbre,
+];
+
+/// Reads MPE streams back into notes carrying their own expression. Pitch
+/// bend, channel pressure and CC 74 on a member channel belong to the note
+/// sounding there, or to the next note started there when sent ahead of it,
+/// as MPE senders do to set a note's initial state.
+pub struct MpeDecoder {
+    zones: Vec<MpeZone>,
+    rpn: RpnAssembler,
+    /// (channel, note) for each note still sounding
+    sounding: Vec<(u8, ExpressiveNote)>,
+    /// Expression sent on a channel while no note sounded there
+    pending: [[Option<f32>; 3]; 16],
+    notes: Vec<ExpressiveNote>,
+}
+
+impl MpeDecoder {
+    /// Configuration messages in the stream replace `zones` as they arrive
+    pub fn create(zones: Vec<MpeZone>) -> Self {
+        Self {
+            zones,
+            rpn: RpnAssembler::create(),
+            sounding: vec![],
+            pending: [[None; 3]; 16],
+            notes: vec![],
+        }
+    }
+
+    pub fn zones(&self) -> &[MpeZone] {
+        &self.zones
+    }
+
+    fn set_zone(&mut self, zone: MpeZone) {
+        self.zones
+            .retain(|z| z.master_channel != zone.master_channel);
+        for other in self.zones.iter_mut() {
+            other.yield_to(&zone);
+        }
+        self.zones.push(zone);
+    }
+
+    fn is_member(&self, channel: u8) -> bool {
+        self.zones.iter().any(|zone| zone.is_member(channel))
+    }
+
+    pub fn feed(&mut self, tick: u32, event: &MidiEvent) {
+        let channel = event.status.channel();
+        let change = match (event.status.status_type, &event.data) {
+            (StatusType::CtrlChange, EventData::RpnData { change }) => Some(*change),
+            (
+                StatusType::CtrlChange,
+                EventData::ControlData {
+                    control_id,
+                    control_value,
+                },
+            ) => match self.rpn.feed(channel, *control_id, *control_value) {
+                RpnInput::Change(change) => Some(change),
+                _ => None,
+            },
+            _ => None,
+        };
+        if let Some(zone) = change.and_then(|change| MpeZone::from_rpn(channel, &change)) {
+            self.set_zone(zone);
+            return;
+        }
+
+        match (event.status.status_type, &event.data) {
+            (StatusType::NoteOn, EventData::NoteOnOffData { key, velocity }) if *velocity > 0 => {
+                let mut note = ExpressiveNote::create(*key, *velocity, tick, 0);
+                for kind in KINDS {
+                    if let Some(value) = self.pending[channel as usize][kind_index(kind)].take() {
+                        note.add_point(kind, 0, value);
+                    }
+                }
+                self.sounding.push((channel, note));
+            }
+            (StatusType::NoteOn | StatusType::NoteOff, EventData::NoteOnOffData { key, .. }) => {
+                if let Some(i) = self
+                    .sounding
+                    .iter()
+                    .position(|(c, note)| *c == channel && note.key == *key)
+                {
+                    let (_, mut note) = self.sounding.remove(i);
+                    note.duration = tick - note.start;
+                    self.notes.push(note);
+                }
+            }
+            (StatusType::PolyphonicAftertouch, EventData::NoteOnOffData { key, velocity })
+                if self.is_member(channel) =>
+            {
+                if let Some((_, note)) = self
+                    .sounding
+                    .iter_mut()
+                    .rev()
+                    .find(|(c, note)| *c == channel && note.key == *key)
+                {
+                    let offset = tick - note.start;
+                    note.add_point(ExpressionKind::Pressure, offset, *velocity as f32 / 127.0);
+                }
+            }
+            _ if self.is_member(channel) => {
+                let Some((kind, value)) = expression(event) else {
+                    return;
+                };
+                match self.sounding.iter_mut().rev().find(|(c, _)| *c == channel) {
+                    Some((_, note)) => {
+                        let offset = tick - note.start;
+                        note.add_point(kind, offset, value);
+                    }
+                    None => self.pending[channel as usize][kind_index(kind)] = Some(value),
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Every note fed so far in start order, notes still sounding ending at
+    /// `end`
+    pub fn finish(mut self, end: u32) -> Vec<ExpressiveNote> {
+        for (_, mut note) in self.sounding.drain(..) {
+            note.duration = end.saturating_sub(note.start);
+            self.notes.push(note);
+        }
+        self.notes.sort_by_key(|note| (note.start, note.key));
+        self.notes
+    }
+}
+
+impl MidiTrack {
+    /// The track's notes with the expression of their member channels
+    /// attached, starting from `zones` until the track configures its own
+    pub fn mpe_notes(&self, zones: &[MpeZone]) -> Vec<ExpressiveNote> {
+        let mut decoder = MpeDecoder::create(zones.to_vec());
+        let mut end = 0;
+        for (tick, event) in self.iter_ticks() {
+            decoder.feed(tick, event);
+            end = tick;
+        }
+        decoder.finish(end)
+    }
+}
+
+impl MidiFile {
+    /// Zones the file's MPE Configuration Messages leave set up, in any
+    /// track
+    pub fn mpe_zones(&self) -> Vec<MpeZone> {
+        let mut decoder = MpeDecoder::create(vec![]);
+        for track in self.loaded().tracks.iter() {
+            for event in track.events.iter().filter(|e| {
+                e.status.status_type == StatusType::CtrlChange
+                    && matches!(e.status.channel(), 0 | 15)
+            }) {
+                decoder.feed(0, event);
+            }
+        }
+        decoder.zones.retain(|zone| zone.member_count > 0);
+        decoder.zones
+    }
+
+    /// Each track's notes grouped with their expression, using the zones the
+    /// file configures or a full lower zone if it configures none
+    pub fn mpe_notes(&self) -> Vec<Vec<ExpressiveNote>> {
+        let file = self.loaded();
+        let mut zones = file.mpe_zones();
+        if zones.is_empty() {
+            zones.push(MpeZone::lower(15));
+        }
+        file.tracks
+            .iter()
+            .map(|track| track.mpe_notes(&zones))
+            .collect()
+    }
+}
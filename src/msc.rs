@@ -0,0 +1,243 @@
+use crate::{
+    parser::EventData,
+    sysex::{ManufacturerId, SysExEvent},
+};
+
+/// Real-time universal SysEx sub-id for MIDI Show Control
+const MSC: u8 = 0x02;
+
+/// Command formats address classes of equipment
+pub const LIGHTING: u8 = 0x01;
+pub const MOVING_LIGHTS: u8 = 0x02;
+pub const SOUND: u8 = 0x10;
+pub const MACHINERY: u8 = 0x20;
+pub const VIDEO: u8 = 0x30;
+pub const PYRO: u8 = 0x60;
+pub const ALL_TYPES: u8 = 0x7f;
+
+/// A cue number with optional list and path, each ASCII digits and dots
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cue {
+    pub number: String,
+    pub list: Option<String>,
+    pub path: Option<String>,
+}
+
+fn is_cue_part(part: &str) -> bool {
+    !part.is_empty() && part.chars().all(|c| c.is_ascii_digit() || c == '.')
+}
+
+impl Cue {
+    pub fn create(number: &str) -> Option<Self> {
+        is_cue_part(number).then(|| Self {
+            number: number.to_string(),
+            list: None,
+            path: None,
+        })
+    }
+
+    pub fn in_list(mut self, list: &str) -> Option<Self> {
+        self.list = Some(list.to_string());
+        is_cue_part(list).then_some(self)
+    }
+
+    pub fn in_path(mut self, path: &str) -> Option<Self> {
+        self.path = Some(path.to_string());
+        is_cue_part(path).then_some(self)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.number.as_bytes().to_vec();
+        for part in [&self.list, &self.path].into_iter().flatten() {
+            bytes.push(0x00);
+            bytes.extend(part.as_bytes());
+        }
+        bytes
+    }
+
+    fn parse(data: &[u8]) -> Option<Self> {
+        let mut parts = data
+            .split(|&b| b == 0x00)
+            .map(|part| String::from_utf8_lossy(part).to_string());
+        let mut cue = Self::create(&parts.next()?)?;
+        if let Some(list) = parts.next() {
+            cue = cue.in_list(&list)?;
+        }
+        if let Some(path) = parts.next() {
+            cue = cue.in_path(&path)?;
+        }
+        Some(cue)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MscCommand {
+    /// Without a cue, GO advances to the next cue
+    Go(Option<Cue>),
+    Stop(Option<Cue>),
+    Resume(Option<Cue>),
+    Load(Cue),
+    Fire(u8),
+    AllOff,
+    Restore,
+    Reset,
+    GoOff(Option<Cue>),
+    Other(u8, Vec<u8>),
+}
+
+impl MscCommand {
+    pub fn id(&self) -> u8 {
+        match self {
+            Self::Go(_) => 0x01,
+            Self::Stop(_) => 0x02,
+            Self::Resume(_) => 0x03,
+            Self::Load(_) => 0x05,
+            Self::Fire(_) => 0x07,
+            Self::AllOff => 0x08,
+            Self::Restore => 0x09,
+            Self::Reset => 0x0a,
+            Self::GoOff(_) => 0x0b,
+            Self::Other(id, _) => *id,
+        }
+    }
+
+    fn data(&self) -> Vec<u8> {
+        match self {
+            Self::Go(cue) | Self::Stop(cue) | Self::Resume(cue) | Self::GoOff(cue) => {
+                cue.as_ref().map_or(vec![], Cue::to_bytes)
+            }
+            Self::Load(cue) => cue.to_bytes(),
+            Self::Fire(number) => vec![*number & 0x7f],
+            Self::AllOff | Self::Restore | Self::Reset => vec![],
+            Self::Other(_, data) => data.clone(),
+        }
+    }
+
+    fn parse(id: u8, data: &[u8]) -> Option<Self> {
+        let cue = || match data {
+            [] => Some(None),
+            data => Cue::parse(data).map(Some),
+        };
+        Some(match id {
+            0x01 => Self::Go(cue()?),
+            0x02 => Self::Stop(cue()?),
+            0x03 => Self::Resume(cue()?),
+            0x05 => Self::Load(Cue::parse(data)?),
+            0x07 => Self::Fire(*data.first()?),
+            0x08 => Self::AllOff,
+            0x09 => Self::Restore,
+            0x0a => Self::Reset,
+            0x0b => Self::GoOff(cue()?),
+            id => Self::Other(id, data.to_vec()),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MscMessage {
+    pub device_id: u8,
+    pub command_format: u8,
+    pub command: MscCommand,
+}
+
+impl MscMessage {
+    pub fn create(device_id: u8, command_format: u8, command: MscCommand) -> Self {
+        Self {
+            device_id,
+            command_format,
+            command,
+        }
+    }
+
+    pub fn go(device_id: u8, command_format: u8, cue: Option<Cue>) -> Self {
+        Self::create(device_id, command_format, MscCommand::Go(cue))
+    }
+
+    pub fn stop(device_id: u8, command_format: u8, cue: Option<Cue>) -> Self {
+        Self::create(device_id, command_format, MscCommand::Stop(cue))
+    }
+
+    pub fn resume(device_id: u8, command_format: u8, cue: Option<Cue>) -> Self {
+        Self::create(device_id, command_format, MscCommand::Resume(cue))
+    }
+
+    pub fn to_sysex(&self) -> SysExEvent {
+        let mut data = vec![self.device_id, MSC, self.command_format, self.command.id()];
+        data.extend(self.command.data());
+        SysExEvent::create(ManufacturerId::Short(0x7f), data)
+    }
+
+    /// The full message including F0 and F7
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_sysex().to_bytes()
+    }
+
+    pub fn from_sysex(sysex: &SysExEvent) -> Option<Self> {
+        if sysex.manufacturer != ManufacturerId::Short(0x7f) {
+            return None;
+        }
+        match sysex.data[..] {
+            [device_id, MSC, command_format, id, ref data @ ..] => Some(Self {
+                device_id,
+                command_format,
+                command: MscCommand::parse(id, data)?,
+            }),
+            _ => None,
+        }
+    }
+
+    /// Parses a message with or without its leading F0
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        Self::from_sysex(&SysExEvent::from_payload(bytes)?)
+    }
+}
+
+impl EventData {
+    pub fn msc(&self) -> Option<MscMessage> {
+        MscMessage::from_sysex(&self.sysex()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn go_with_cue_and_list_matches_the_spec() {
+        let cue = Cue::create("235.6").and_then(|cue| cue.in_list("36.6"));
+        let message = MscMessage::go(0x01, LIGHTING, cue);
+        let bytes = message.to_bytes();
+        // cue and list are ASCII, separated by a zero byte
+        assert_eq!(
+            bytes,
+            [
+                0xf0, 0x7f, 0x01, 0x02, 0x01, 0x01, b'2', b'3', b'5', b'.', b'6', 0x00, b'3', b'6',
+                b'.', b'6', 0xf7
+            ]
+        );
+        assert_eq!(MscMessage::parse(&bytes), Some(message));
+    }
+
+    #[test]
+    fn commands_without_cues_match_the_spec() {
+        let go = MscMessage::go(0x7f, ALL_TYPES, None);
+        assert_eq!(go.to_bytes(), [0xf0, 0x7f, 0x7f, 0x02, 0x7f, 0x01, 0xf7]);
+        assert_eq!(MscMessage::parse(&go.to_bytes()), Some(go));
+        let fire = MscMessage::create(0x01, LIGHTING, MscCommand::Fire(5));
+        assert_eq!(
+            fire.to_bytes(),
+            [0xf0, 0x7f, 0x01, 0x02, 0x01, 0x07, 0x05, 0xf7]
+        );
+        let off = MscMessage::create(0x02, SOUND, MscCommand::AllOff);
+        assert_eq!(off.to_bytes(), [0xf0, 0x7f, 0x02, 0x02, 0x10, 0x08, 0xf7]);
+    }
+
+    #[test]
+    fn cues_must_be_digits_and_dots() {
+        assert_eq!(Cue::create("1a"), None);
+        assert_eq!(Cue::create(""), None);
+        assert_eq!(Cue::create("1").and_then(|cue| cue.in_path("x")), None);
+        let load = [0xf0, 0x7f, 0x01, 0x02, 0x01, 0x05, b'x', 0xf7];
+        assert_eq!(MscMessage::parse(&load), None);
+    }
+}
@@ -0,0 +1,308 @@
+use crate::parser::{EventData, MetaData, MidiEvent};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameRate {
+    Fps24 = 0,
+    Fps25 = 1,
+    /// 29.97 drop-frame
+    Fps30Drop = 2,
+    Fps30 = 3,
+}
+
+impl FrameRate {
+    pub fn from(code: u8) -> Self {
+        match code & 0x03 {
+            0 => Self::Fps24,
+            1 => Self::Fps25,
+            2 => Self::Fps30Drop,
+            _ => Self::Fps30,
+        }
+    }
+
+    /// Frames counted per second in the timecode
+    pub fn nominal(&self) -> u32 {
+        match self {
+            Self::Fps24 => 24,
+            Self::Fps25 => 25,
+            Self::Fps30Drop | Self::Fps30 => 30,
+        }
+    }
+
+    /// Frames actually played per second
+    pub fn fps(&self) -> f64 {
+        match self {
+            Self::Fps30Drop => 30000.0 / 1001.0,
+            _ => self.nominal() as f64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Timecode {
+    pub hours: u8,
+    pub minutes: u8,
+    pub seconds: u8,
+    pub frames: u8,
+    pub rate: FrameRate,
+}
+
+impl Timecode {
+    pub fn create(hours: u8, minutes: u8, seconds: u8, frames: u8, rate: FrameRate) -> Self {
+        Self {
+            hours,
+            minutes,
+            seconds,
+            frames,
+            rate,
+        }
+    }
+
+    /// Timecode of the `frame`-th frame, skipping the numbers drop-frame omits
+    pub fn from_frame(frame: u64, rate: FrameRate) -> Self {
+        let mut frame = frame;
+        if rate == FrameRate::Fps30Drop {
+            // two frame numbers are skipped every minute except every tenth
+            let tens = frame / 17982;
+            let rest = frame % 17982;
+            frame += 18 * tens + if rest > 1 { 2 * ((rest - 2) / 1798) } else { 0 };
+        }
+        let nominal = rate.nominal() as u64;
+        Self {
+            hours: (frame / (nominal * 3600) % 24) as u8,
+            minutes: (frame / (nominal * 60) % 60) as u8,
+            seconds: (frame / nominal % 60) as u8,
+            frames: (frame % nominal) as u8,
+            rate,
+        }
+    }
+
+    pub fn from_seconds(seconds: f64, rate: FrameRate) -> Self {
+        Self::from_frame((seconds.max(0.0) * rate.fps()).floor() as u64, rate)
+    }
+
+    pub fn frame(&self) -> u64 {
+        let nominal = self.rate.nominal() as u64;
+        let total_minutes = self.hours as u64 * 60 + self.minutes as u64;
+        let frame = (total_minutes * 60 + self.seconds as u64) * nominal + self.frames as u64;
+        match self.rate {
+            FrameRate::Fps30Drop => frame - 2 * (total_minutes - total_minutes / 10),
+            _ => frame,
+        }
+    }
+
+    pub fn to_seconds(&self) -> f64 {
+        self.frame() as f64 / self.rate.fps()
+    }
+
+    /// Data bytes of the eight quarter-frame messages (0xF1) that spell out
+    /// this timecode, piece 0 first
+    pub fn quarter_frames(&self) -> [u8; 8] {
+        let values = [
+            self.frames & 0x0f,
+            self.frames >> 4,
+            self.seconds & 0x0f,
+            self.seconds >> 4,
+            self.minutes & 0x0f,
+            self.minutes >> 4,
+            self.hours & 0x0f,
+            (self.hours >> 4) & 0x01 | (self.rate as u8) << 1,
+        ];
+        let mut data = [0; 8];
+        for (piece, value) in values.iter().enumerate() {
+            data[piece] = (piece as u8) << 4 | value;
+        }
+        data
+    }
+
+    /// Full-frame SysEx message, F0 through F7, used to locate
+    pub fn full_frame(&self) -> Vec<u8> {
+        vec![
+            0xf0,
+            0x7f,
+            0x7f,
+            0x01,
+            0x01,
+            (self.rate as u8) << 5 | self.hours,
+            self.minutes,
+            self.seconds,
+            self.frames,
+            0xf7,
+        ]
+    }
+
+    /// Parses a full-frame message with or without its leading F0
+    pub fn from_full_frame(bytes: &[u8]) -> Option<Self> {
+        let bytes = bytes.strip_prefix(&[0xf0]).unwrap_or(bytes);
+        match *bytes {
+            [0x7f, _, 0x01, 0x01, hours, minutes, seconds, frames, ..] => Some(Self {
+                hours: hours & 0x1f,
+                minutes,
+                seconds,
+                frames,
+                rate: FrameRate::from(hours >> 5),
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Emits quarter frames for a playback position. A full timecode takes eight
+/// quarter frames, i.e. two frames, so every other frame's time is sent.
+#[derive(Debug, Clone)]
+pub struct MtcGenerator {
+    pub rate: FrameRate,
+    quarter: u64,
+}
+
+impl MtcGenerator {
+    pub fn create(rate: FrameRate) -> Self {
+        Self { rate, quarter: 0 }
+    }
+
+    /// Restarts at the first cycle at or after `seconds`. Send a full frame
+    /// there as well so receivers relocate immediately.
+    pub fn seek(&mut self, seconds: f64) {
+        let quarters = (seconds.max(0.0) * self.rate.fps() * 4.0).ceil() as u64;
+        self.quarter = quarters.div_ceil(8) * 8;
+    }
+
+    /// Time in seconds of the next quarter frame
+    pub fn peek(&self) -> f64 {
+        self.quarter as f64 / 4.0 / self.rate.fps()
+    }
+
+    /// Returns the time and data byte of the next quarter frame
+    pub fn next_quarter_frame(&mut self) -> (f64, u8) {
+        let seconds = self.peek();
+        let cycle_start = self.quarter / 8 * 2;
+        let data = Timecode::from_frame(cycle_start, self.rate).quarter_frames()
+            [(self.quarter % 8) as usize];
+        self.quarter += 1;
+        (seconds, data)
+    }
+}
+
+/// Rebuilds timecode from incoming quarter frames and full frames
+#[derive(Debug, Clone)]
+pub struct MtcReader {
+    pieces: [u8; 8],
+    received: u8,
+    pub last: Option<Timecode>,
+}
+
+impl MtcReader {
+    pub fn create() -> Self {
+        Self {
+            pieces: [0; 8],
+            received: 0,
+            last: None,
+        }
+    }
+
+    /// Returns the timecode once all eight pieces have arrived. Quarter frame
+    /// timecode describes the moment piece 0 was sent, two frames back.
+    pub fn feed_quarter_frame(&mut self, piece: u8, value: u8) -> Option<Timecode> {
+        let piece = (piece & 0x07) as usize;
+        self.pieces[piece] = value & 0x0f;
+        self.received |= 1 << piece;
+        if piece != 7 || self.received != 0xff {
+            return None;
+        }
+        self.received = 0;
+        let p = self.pieces;
+        let timecode = Timecode {
+            frames: p[0] | p[1] << 4,
+            seconds: p[2] | p[3] << 4,
+            minutes: p[4] | p[5] << 4,
+            hours: p[6] | (p[7] & 0x01) << 4,
+            rate: FrameRate::from(p[7] >> 1),
+        };
+        self.last = Some(timecode);
+        Some(timecode)
+    }
+
+    pub fn feed(&mut self, event: &MidiEvent) -> Option<Timecode> {
+        match &event.data {
+            EventData::QuarterFrameData { piece, value } => self.feed_quarter_frame(*piece, *value),
+            EventData::SysexData {
+                meta_type: None,
+                meta: MetaData::Bytes(bytes),
+            } => {
+                let timecode = Timecode::from_full_frame(bytes)?;
+                self.received = 0;
+                self.last = Some(timecode);
+                Some(timecode)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarter_frames_match_the_spec() {
+        let timecode = Timecode::create(1, 2, 3, 4, FrameRate::Fps25);
+        assert_eq!(
+            timecode.quarter_frames(),
+            [0x04, 0x10, 0x23, 0x30, 0x42, 0x50, 0x61, 0x72]
+        );
+        // piece 7 is 0111 0rrh: the rate and the top bit of the hours
+        let timecode = Timecode::create(23, 59, 59, 29, FrameRate::Fps30);
+        assert_eq!(
+            timecode.quarter_frames(),
+            [0x0d, 0x11, 0x2b, 0x33, 0x4b, 0x53, 0x67, 0x77]
+        );
+    }
+
+    #[test]
+    fn quarter_frames_read_back() {
+        let timecode = Timecode::create(23, 59, 59, 28, FrameRate::Fps30Drop);
+        let mut reader = MtcReader::create();
+        let data = timecode.quarter_frames();
+        for byte in &data[..7] {
+            assert_eq!(reader.feed_quarter_frame(byte >> 4, byte & 0x0f), None);
+        }
+        assert_eq!(
+            reader.feed_quarter_frame(data[7] >> 4, data[7] & 0x0f),
+            Some(timecode)
+        );
+    }
+
+    #[test]
+    fn full_frame_matches_the_spec() {
+        let timecode = Timecode::create(1, 2, 3, 4, FrameRate::Fps30Drop);
+        let bytes = timecode.full_frame();
+        // hours byte is 0rrhhhhh
+        assert_eq!(
+            bytes,
+            [0xf0, 0x7f, 0x7f, 0x01, 0x01, 0x41, 0x02, 0x03, 0x04, 0xf7]
+        );
+        assert_eq!(Timecode::from_full_frame(&bytes), Some(timecode));
+        assert_eq!(Timecode::from_full_frame(&bytes[1..]), Some(timecode));
+    }
+
+    #[test]
+    fn drop_frame_skips_two_numbers_a_minute() {
+        let rate = FrameRate::Fps30Drop;
+        for (frame, timecode) in [
+            (1799, (0, 0, 59, 29)),
+            (1800, (0, 1, 0, 2)),
+            (3598, (0, 2, 0, 2)),
+            (17981, (0, 9, 59, 29)),
+            // every tenth minute keeps its first two frames
+            (17982, (0, 10, 0, 0)),
+            (107892, (1, 0, 0, 0)),
+        ] {
+            let (hours, minutes, seconds, frames) = timecode;
+            let expected = Timecode::create(hours, minutes, seconds, frames, rate);
+            assert_eq!(Timecode::from_frame(frame, rate), expected);
+            assert_eq!(expected.frame(), frame);
+        }
+        // an hour of drop-frame timecode is an hour of real time, to 3.6 ms
+        let hour = Timecode::create(1, 0, 0, 0, rate).to_seconds();
+        assert!((hour - 3600.0).abs() < 0.004);
+    }
+}
@@ -0,0 +1,370 @@
+use crate::{
+    parser::EventData,
+    pitch::{
+        frequency_to_key, frequency_to_key_with, key_to_frequency, reference_pitch,
+        DEFAULT_REFERENCE, REFERENCE_KEY,
+    },
+    sysex::{ManufacturerId, SysExEvent},
+};
+
+/// Universal SysEx sub-id for the MIDI Tuning Standard
+const MTS: u8 = 0x08;
+const DUMP_REQUEST: u8 = 0x00;
+const BULK_DUMP: u8 = 0x01;
+const SINGLE_NOTE: u8 = 0x02;
+
+/// Frequency bytes meaning "leave this key as it is"
+const NO_CHANGE: [u8; 3] = [0x7f, 0x7f, 0x7f];
+const NAME_LENGTH: usize = 16;
+
+/// The pitch every key sounds at, as a fractional key: 60.5 is a quarter
+/// tone above middle C
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    pub pitches: [f64; 128],
+}
+
+impl Tuning {
+    /// Twelve-tone equal temperament, every key at its own pitch
+    pub fn equal() -> Self {
+        Self {
+            pitches: core::array::from_fn(|key| key as f64),
+        }
+    }
+
+    /// An octave-repeating scale given as each pitch class's offset from
+    /// equal temperament in cents, C first
+    pub fn from_cents(offsets: &[f64; 12]) -> Self {
+        Self {
+            pitches: core::array::from_fn(|key| key as f64 + offsets[key % 12] / 100.0),
+        }
+    }
+
+    pub fn cents(&self, key: u8) -> f64 {
+        (self.pitches[key as usize & 0x7f] - (key & 0x7f) as f64) * 100.0
+    }
+
+    /// Frequency of `key` at the crate's reference pitch
+    pub fn frequency(&self, key: u8) -> f64 {
+        key_to_frequency(self.pitches[key as usize & 0x7f])
+    }
+
+    pub fn set_frequency(&mut self, key: u8, hz: f64) {
+        self.pitches[key as usize & 0x7f] = frequency_to_key(hz);
+    }
+
+    /// A bulk tuning dump carrying the whole table
+    pub fn to_bulk_dump(&self, device_id: u8, program: u8, name: &str) -> MtsMessage {
+        MtsMessage {
+            device_id,
+            change: TuningChange::BulkDump {
+                program,
+                name: name.to_string(),
+                tuning: Box::new(self.clone()),
+            },
+        }
+    }
+
+    /// A real-time change retuning just `keys`, taking effect on notes
+    /// already sounding
+    pub fn to_note_change(&self, device_id: u8, program: u8, keys: &[u8]) -> MtsMessage {
+        let changes = keys
+            .iter()
+            .map(|key| (key & 0x7f, Some(self.pitches[*key as usize & 0x7f])))
+            .collect();
+        MtsMessage {
+            device_id,
+            change: TuningChange::SingleNote { program, changes },
+        }
+    }
+
+    /// Retunes the keys a message changes; other messages change nothing
+    pub fn apply(&mut self, change: &TuningChange) {
+        match change {
+            TuningChange::BulkDump { tuning, .. } => *self = *tuning.clone(),
+            TuningChange::SingleNote { changes, .. } => {
+                for (key, pitch) in changes {
+                    if let Some(pitch) = pitch {
+                        self.pitches[*key as usize & 0x7f] = *pitch;
+                    }
+                }
+            }
+            TuningChange::DumpRequest { .. } => {}
+        }
+    }
+}
+
+/// Semitones the crate's reference pitch lies above the A4 = 440 Hz that MTS
+/// frequencies are fixed to
+fn reference_offset() -> f64 {
+    frequency_to_key_with(reference_pitch(), DEFAULT_REFERENCE) - REFERENCE_KEY
+}
+
+/// The three frequency bytes MTS gives a pitch: the equal-tempered key at
+/// or below it, then the rest in 1/16384ths of a semitone. MTS keys sound
+/// at A4 = 440 Hz, so the pitch is moved off the crate's reference first
+pub fn encode_pitch(pitch: f64) -> [u8; 3] {
+    let pitch = pitch + reference_offset();
+    let steps = (pitch.clamp(0.0, 128.0) * 16384.0).round() as u32;
+    // the top value is reserved for "no change"
+    let steps = steps.min(128 * 16384 - 2);
+    [
+        (steps >> 14) as u8,
+        (steps >> 7 & 0x7f) as u8,
+        (steps & 0x7f) as u8,
+    ]
+}
+
+/// The pitch relative to the crate's reference, None for the reserved "no
+/// change" value
+pub fn decode_pitch(bytes: [u8; 3]) -> Option<f64> {
+    if bytes == NO_CHANGE {
+        return None;
+    }
+    let [key, msb, lsb] = bytes.map(|b| b & 0x7f);
+    let pitch = key as f64 + ((msb as u32) << 7 | lsb as u32) as f64 / 16384.0;
+    Some(pitch - reference_offset())
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum TuningChange {
+    /// Asks a device to send its tuning program as a bulk dump
+    DumpRequest { program: u8 },
+    BulkDump {
+        program: u8,
+        name: String,
+        tuning: Box<Tuning>,
+    },
+    /// Pitches for some keys, None leaving a key as it was
+    SingleNote {
+        program: u8,
+        changes: Vec<(u8, Option<f64>)>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MtsMessage {
+    pub device_id: u8,
+    pub change: TuningChange,
+}
+
+fn checksum(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, b| sum ^ b) & 0x7f
+}
+
+impl MtsMessage {
+    pub fn dump_request(device_id: u8, program: u8) -> Self {
+        Self {
+            device_id,
+            change: TuningChange::DumpRequest { program },
+        }
+    }
+
+    /// Dumps and requests are non-real-time messages, note changes real-time
+    pub fn to_sysex(&self) -> SysExEvent {
+        let (manufacturer, sub_id, mut data) = match &self.change {
+            TuningChange::DumpRequest { program } => (
+                ManufacturerId::UNIVERSAL_NON_REAL_TIME,
+                DUMP_REQUEST,
+                vec![program & 0x7f],
+            ),
+            TuningChange::BulkDump {
+                program,
+                name,
+                tuning,
+            } => {
+                let mut data = vec![program & 0x7f];
+                // the name is 16 ASCII characters, padded with spaces
+                let name = name.bytes().filter(u8::is_ascii).chain([b' '; NAME_LENGTH]);
+                data.extend(name.take(NAME_LENGTH));
+                data.extend(tuning.pitches.iter().flat_map(|pitch| encode_pitch(*pitch)));
+                (ManufacturerId::UNIVERSAL_NON_REAL_TIME, BULK_DUMP, data)
+            }
+            TuningChange::SingleNote { program, changes } => {
+                let mut data = vec![program & 0x7f, changes.len().min(127) as u8];
+                for (key, pitch) in changes.iter().take(127) {
+                    data.push(key & 0x7f);
+                    data.extend(pitch.map_or(NO_CHANGE, encode_pitch));
+                }
+                (ManufacturerId::UNIVERSAL_REAL_TIME, SINGLE_NOTE, data)
+            }
+        };
+        let mut bytes = vec![self.device_id & 0x7f, MTS, sub_id];
+        bytes.append(&mut data);
+        if sub_id == BULK_DUMP {
+            let mut summed = manufacturer.to_bytes();
+            summed.extend(bytes.iter());
+            bytes.push(checksum(&summed));
+        }
+        SysExEvent::create(manufacturer, bytes)
+    }
+
+    /// The full message including F0 and F7
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.to_sysex().to_bytes()
+    }
+
+    /// None for other messages and for bulk dumps whose checksum is wrong
+    pub fn from_sysex(sysex: &SysExEvent) -> Option<Self> {
+        let change = match (sysex.manufacturer, &sysex.data[..]) {
+            (ManufacturerId::UNIVERSAL_NON_REAL_TIME, [_, MTS, DUMP_REQUEST, program]) => {
+                TuningChange::DumpRequest { program: *program }
+            }
+            (ManufacturerId::UNIVERSAL_NON_REAL_TIME, [_, MTS, BULK_DUMP, program, rest @ ..])
+                if rest.len() == NAME_LENGTH + 128 * 3 + 1 =>
+            {
+                let (body, sum) = sysex.data.split_at(sysex.data.len() - 1);
+                let mut summed = sysex.manufacturer.to_bytes();
+                summed.extend(body);
+                if checksum(&summed) != sum[0] {
+                    return None;
+                }
+                let (name, pitches) = rest.split_at(NAME_LENGTH);
+                let mut tuning = Tuning::equal();
+                for (key, bytes) in pitches.chunks_exact(3).enumerate() {
+                    if let Some(pitch) = decode_pitch([bytes[0], bytes[1], bytes[2]]) {
+                        tuning.pitches[key] = pitch;
+                    }
+                }
+                TuningChange::BulkDump {
+                    program: *program,
+                    name: String::from_utf8_lossy(name).trim_end().to_string(),
+                    tuning: Box::new(tuning),
+                }
+            }
+            (
+                ManufacturerId::UNIVERSAL_REAL_TIME,
+                [_, MTS, SINGLE_NOTE, program, count, rest @ ..],
+            ) if rest.len() == *count as usize * 4 => {
+                let changes = rest
+                    .chunks_exact(4)
+                    .map(|change| {
+                        let pitch = decode_pitch([change[1], change[2], change[3]]);
+                        (change[0] & 0x7f, pitch)
+                    })
+                    .collect();
+                TuningChange::SingleNote {
+                    program: *program,
+                    changes,
+                }
+            }
+            _ => return None,
+        };
+        Some(Self {
+            device_id: sysex.data[0],
+            change,
+        })
+    }
+
+    /// Parses a message with or without its leading F0
+    pub fn parse(bytes: &[u8]) -> Option<Self> {
+        Self::from_sysex(&SysExEvent::from_payload(bytes)?)
+    }
+}
+
+impl EventData {
+    pub fn mts(&self) -> Option<MtsMessage> {
+        MtsMessage::from_sysex(&self.sysex()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::pitch::set_reference_pitch;
+
+    /// The reference pitch is crate-wide, so tests that move it must not
+    /// overlap with tests reading it
+    static REFERENCE: Mutex<()> = Mutex::new(());
+
+    #[test]
+    fn pitches_are_encoded_against_a440() {
+        let _lock = REFERENCE.lock().unwrap();
+        set_reference_pitch(442.0).unwrap();
+        let a4 = Tuning::equal().pitches[69];
+        let bytes = encode_pitch(a4);
+        let decoded = decode_pitch(bytes);
+        set_reference_pitch(DEFAULT_REFERENCE).unwrap();
+        // A4 at 442 Hz is about 7.85 cents above the MTS A4
+        assert_eq!(bytes[0], 69);
+        let steps = (bytes[1] as u32) << 7 | bytes[2] as u32;
+        let cents = 1200.0 * (442.0f64 / 440.0).log2();
+        assert_eq!(steps, (cents / 100.0 * 16384.0).round() as u32);
+        assert!((decoded.unwrap() - a4).abs() < 1e-4);
+    }
+
+    #[test]
+    fn tuning_round_trips_at_another_reference() {
+        let _lock = REFERENCE.lock().unwrap();
+        set_reference_pitch(415.0).unwrap();
+        let tuning = Tuning::from_cents(&[
+            0.0, -10.0, 4.0, 0.0, -14.0, 2.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+        ]);
+        let dump = tuning.to_bulk_dump(0x7f, 1, "baroque").to_bytes();
+        let parsed = MtsMessage::parse(&dump);
+        let frequency = tuning.frequency(69);
+        set_reference_pitch(DEFAULT_REFERENCE).unwrap();
+        assert!((frequency - 415.0).abs() < 1e-9);
+        let TuningChange::BulkDump { tuning: parsed, .. } = parsed.unwrap().change else {
+            panic!("not a bulk dump");
+        };
+        // a semitone below A440 the two lowest keys fall off the MTS range
+        for (parsed, pitch) in parsed.pitches.iter().zip(tuning.pitches).skip(2) {
+            assert!((parsed - pitch).abs() < 1e-4);
+        }
+    }
+
+    #[test]
+    fn bulk_dump_matches_the_spec() {
+        let _lock = REFERENCE.lock().unwrap();
+        let bytes = Tuning::equal().to_bulk_dump(0, 0, "Equal").to_bytes();
+        assert_eq!(bytes.len(), 408);
+        assert_eq!(bytes[..6], [0xf0, 0x7e, 0x00, 0x08, 0x01, 0x00]);
+        assert_eq!(&bytes[6..22], b"Equal           ");
+        assert_eq!(bytes[22 + 69 * 3..22 + 70 * 3], [0x45, 0x00, 0x00]);
+        // 7E ^ 00 ^ 08 ^ 01 ^ 00 ^ name; the pitches cancel out
+        assert_eq!(bytes[406..], [0x1b, 0xf7]);
+    }
+
+    #[test]
+    fn bulk_dump_with_a_bad_checksum_is_rejected() {
+        let _lock = REFERENCE.lock().unwrap();
+        let mut bytes = Tuning::equal().to_bulk_dump(0, 0, "Equal").to_bytes();
+        assert!(MtsMessage::parse(&bytes).is_some());
+        bytes[406] ^= 0x01;
+        assert_eq!(MtsMessage::parse(&bytes), None);
+    }
+
+    #[test]
+    fn single_note_change_matches_the_spec() {
+        let _lock = REFERENCE.lock().unwrap();
+        let mut tuning = Tuning::equal();
+        tuning.pitches[61] = 61.5;
+        let bytes = tuning.to_note_change(0x7f, 0, &[61]).to_bytes();
+        assert_eq!(
+            bytes,
+            [0xf0, 0x7f, 0x7f, 0x08, 0x02, 0x00, 0x01, 0x3d, 0x3d, 0x40, 0x00, 0xf7]
+        );
+        let change = MtsMessage::parse(&bytes).unwrap().change;
+        assert_eq!(
+            change,
+            TuningChange::SingleNote {
+                program: 0,
+                changes: vec![(61, Some(61.5))],
+            }
+        );
+    }
+
+    #[test]
+    fn no_change_and_dump_request_match_the_spec() {
+        assert_eq!(decode_pitch([0x7f, 0x7f, 0x7f]), None);
+        let bytes = MtsMessage::dump_request(0x00, 3).to_bytes();
+        assert_eq!(bytes, [0xf0, 0x7e, 0x00, 0x08, 0x00, 0x03, 0xf7]);
+        assert_eq!(
+            MtsMessage::parse(&bytes),
+            Some(MtsMessage::dump_request(0x00, 3))
+        );
+    }
+}
@@ -0,0 +1,308 @@
+use std::{error::Error, fmt::Write, fs};
+
+use crate::{
+    key::{Key, Mode},
+    note::Notes,
+    parser::MidiFile,
+};
+
+const STEPS: [char; 7] = ['C', 'D', 'E', 'F', 'G', 'A', 'B'];
+
+/// Lengths in sixteenth notes with the note type and dots that write them,
+/// longest first
+const TYPES: [(u32, &str, u8); 8] = [
+    (16, "whole", 0),
+    (12, "half", 1),
+    (8, "half", 0),
+    (6, "quarter", 1),
+    (4, "quarter", 0),
+    (3, "eighth", 1),
+    (2, "eighth", 0),
+    (1, "16th", 0),
+];
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// One written note, chord or rest inside a measure
+struct Piece {
+    keys: Vec<u8>,
+    duration: u32,
+    kind: &'static str,
+    dots: u8,
+    tie_stop: bool,
+    tie_start: bool,
+}
+
+/// Splits `length` ticks into pieces with a single note type each, the last
+/// one taking up any rounding so measures add up exactly
+fn split(length: u32, division: u16) -> Vec<(u32, &'static str, u8)> {
+    let division = division.max(1) as u64;
+    let mut sixteenths = ((length as u64 * 4 + division / 2) / division).max(1);
+    let mut pieces = vec![];
+    let mut used = 0;
+    while sixteenths > 0 {
+        let (count, kind, dots) = TYPES
+            .iter()
+            .find(|(count, _, _)| *count as u64 <= sixteenths)
+            .copied()
+            .unwrap_or(TYPES[7]);
+        sixteenths -= count as u64;
+        let duration = match sixteenths {
+            0 => length - used,
+            _ => (count as u64 * division / 4) as u32,
+        };
+        used += duration;
+        pieces.push((duration, kind, dots));
+    }
+    pieces
+}
+
+fn key_element(key: &Key) -> String {
+    let mode = match key.mode {
+        Mode::Major => "major",
+        Mode::Minor => "minor",
+    };
+    format!(
+        "<key><fifths>{}</fifths><mode>{}</mode></key>",
+        key.accidentals, mode
+    )
+}
+
+fn write_piece(out: &mut String, piece: &Piece, key: &Key) {
+    let ties = |out: &mut String, element: &str| {
+        if piece.tie_stop {
+            let _ = write!(out, "<{} type=\"stop\"/>", element);
+        }
+        if piece.tie_start {
+            let _ = write!(out, "<{} type=\"start\"/>", element);
+        }
+    };
+    let finish = |out: &mut String| {
+        let _ = write!(out, "<voice>1</voice><type>{}</type>", piece.kind);
+        for _ in 0..piece.dots {
+            out.push_str("<dot/>");
+        }
+    };
+    if piece.keys.is_empty() {
+        let _ = write!(
+            out,
+            "      <note><rest/><duration>{}</duration>",
+            piece.duration
+        );
+        finish(out);
+        out.push_str("</note>\n");
+        return;
+    }
+    for (i, midi_key) in piece.keys.iter().enumerate() {
+        let (letter, alteration, octave) = key.spell(*midi_key);
+        out.push_str("      <note>");
+        if i > 0 {
+            out.push_str("<chord/>");
+        }
+        let _ = write!(out, "<pitch><step>{}</step>", STEPS[letter as usize]);
+        if alteration != 0 {
+            let _ = write!(out, "<alter>{}</alter>", alteration);
+        }
+        let _ = write!(
+            out,
+            "<octave>{}</octave></pitch><duration>{}</duration>",
+            octave, piece.duration
+        );
+        ties(out, "tie");
+        finish(out);
+        if piece.tie_stop || piece.tie_start {
+            out.push_str("<notations>");
+            ties(out, "tied");
+            out.push_str("</notations>");
+        }
+        out.push_str("</note>\n");
+    }
+}
+
+impl MidiFile {
+    /// A partwise MusicXML score with a part per track that has notes,
+    /// measured out by the time signature map and snapped to sixteenth
+    /// notes. Notes struck together are written as chords.
+    pub fn to_musicxml(&self) -> String {
+        let file = self.loaded();
+        let grid = (file.division as u32 / 4).max(1);
+        let map = file.signature_map();
+        let parts: Vec<_> = file
+            .tracks
+            .iter()
+            .enumerate()
+            .map(|(index, track)| {
+                let name = match track.name.as_str() {
+                    "" => format!("Track {}", index + 1),
+                    name => name.to_string(),
+                };
+                (name, track.chords(grid))
+            })
+            .filter(|(_, chords)| !chords.is_empty())
+            .collect();
+
+        // every part gets the same measures, enough for the longest
+        let end = parts
+            .iter()
+            .filter_map(|(_, chords)| chords.last().map(|(start, length, _)| start + length))
+            .max()
+            .unwrap_or(0);
+        let mut measures = vec![];
+        let mut bar = 1;
+        loop {
+            let start = map.bar_beat_to_tick(bar, 1);
+            let stop = map.bar_beat_to_tick(bar + 1, 1);
+            if stop <= start || (start >= end && !measures.is_empty()) {
+                break;
+            }
+            measures.push((start, stop));
+            bar += 1;
+        }
+
+        let mut out = String::new();
+        out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+        out.push_str(
+            "<!DOCTYPE score-partwise PUBLIC \"-//Recordare//DTD MusicXML 4.0 Partwise//EN\" \
+             \"http://www.musicxml.org/dtds/partwise.dtd\">\n",
+        );
+        out.push_str("<score-partwise version=\"4.0\">\n");
+        out.push_str("  <part-list>\n");
+        for (i, (name, _)) in parts.iter().enumerate() {
+            let _ = writeln!(
+                out,
+                "    <score-part id=\"P{}\"><part-name>{}</part-name></score-part>",
+                i + 1,
+                escape(name)
+            );
+        }
+        out.push_str("  </part-list>\n");
+
+        for (i, (_, chords)) in parts.iter().enumerate() {
+            let _ = writeln!(out, "  <part id=\"P{}\">", i + 1);
+            let count: usize = chords.iter().map(|(_, _, keys)| keys.len()).sum();
+            let mean = chords
+                .iter()
+                .flat_map(|(_, _, keys)| keys.iter())
+                .map(|k| *k as usize)
+                .sum::<usize>()
+                / count;
+            let clef = if mean < 60 { ("F", 4) } else { ("G", 2) };
+
+            // chords with the rests between them, covering every measure
+            let mut spans = vec![];
+            let mut position = 0;
+            for (start, length, keys) in chords.iter() {
+                if *start > position {
+                    spans.push((position, *start, vec![]));
+                }
+                spans.push((*start, start + length, keys.clone()));
+                position = start + length;
+            }
+            if let Some(&(_, last)) = measures.last() {
+                if last > position {
+                    spans.push((position, last, vec![]));
+                }
+            }
+
+            let mut keys = file.key_signatures();
+            let mut key = Key {
+                tonic: Notes::C,
+                mode: Mode::Major,
+                accidentals: 0,
+            };
+            let mut spans = spans.into_iter();
+            let mut carried: Option<(u32, u32, Vec<u8>)> = None;
+            for (number, &(start, stop)) in measures.iter().enumerate() {
+                let _ = writeln!(out, "    <measure number=\"{}\">", number + 1);
+
+                let mut attributes = String::new();
+                if number == 0 {
+                    let _ = write!(attributes, "<divisions>{}</divisions>", file.division);
+                }
+                let mut new_key = None;
+                while let Some(&(_, k)) = keys.first().filter(|(t, _)| *t <= start) {
+                    keys.remove(0);
+                    new_key = Some(k);
+                }
+                match new_key {
+                    Some(k) if k != key || number == 0 => {
+                        key = k;
+                        attributes.push_str(&key_element(&key));
+                    }
+                    _ if number == 0 => attributes.push_str(&key_element(&key)),
+                    _ => {}
+                }
+                let change = map.signature_at(start);
+                if number == 0 || change.tick == start {
+                    let _ = write!(
+                        attributes,
+                        "<time><beats>{}</beats><beat-type>{}</beat-type></time>",
+                        change.signature.numerator, change.signature.denominator
+                    );
+                }
+                if number == 0 {
+                    let _ = write!(
+                        attributes,
+                        "<clef><sign>{}</sign><line>{}</line></clef>",
+                        clef.0, clef.1
+                    );
+                }
+                if !attributes.is_empty() {
+                    let _ = writeln!(out, "      <attributes>{}</attributes>", attributes);
+                }
+                if number == 0 && i == 0 {
+                    let _ = writeln!(
+                        out,
+                        "      <direction placement=\"above\"><direction-type><metronome>\
+                         <beat-unit>quarter</beat-unit><per-minute>{}</per-minute>\
+                         </metronome></direction-type><sound tempo=\"{}\"/></direction>",
+                        file.bpm, file.bpm
+                    );
+                }
+
+                // write the spans that fall in this measure, carrying the
+                // rest of any that cross the bar line into the next
+                while let Some((span_start, span_end, span_keys)) =
+                    carried.take().or_else(|| spans.next())
+                {
+                    let piece_end = span_end.min(stop);
+                    let pieces = split(piece_end - span_start.max(start), file.division);
+                    let count = pieces.len();
+                    for (j, (duration, kind, dots)) in pieces.into_iter().enumerate() {
+                        let piece = Piece {
+                            keys: span_keys.clone(),
+                            duration,
+                            kind,
+                            dots,
+                            tie_stop: !span_keys.is_empty() && (span_start < start || j > 0),
+                            tie_start: !span_keys.is_empty() && (j + 1 < count || span_end > stop),
+                        };
+                        write_piece(&mut out, &piece, &key);
+                    }
+                    if span_end > stop {
+                        carried = Some((span_start, span_end, span_keys));
+                        break;
+                    }
+                    if span_end == stop {
+                        break;
+                    }
+                }
+                out.push_str("    </measure>\n");
+            }
+            out.push_str("  </part>\n");
+        }
+        out.push_str("</score-partwise>\n");
+        out
+    }
+
+    /// Writes `to_musicxml` to a `.musicxml` file
+    pub fn save_musicxml(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.to_musicxml())?;
+        Ok(())
+    }
+}
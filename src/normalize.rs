@@ -0,0 +1,206 @@
+use std::hash::{Hash, Hasher};
+
+use crate::{
+    parser::{EventData, MidiEvent, MidiFile, MidiTrack},
+    status::{Status, StatusType},
+};
+
+/// Sort key for events sharing a tick: meta, sysex, setup (program,
+/// controllers, bends, pressure), NoteOff, NoteOn, anything else, then notes
+/// struck and released on that tick, each on ahead of its off
+fn canonical_order(event: &MidiEvent, zero_length: bool) -> (u8, u8, u16, u16) {
+    let status = event.status;
+    let channel = status.channel();
+    match &event.data {
+        EventData::NoteOnOffData { key, .. } if zero_length => {
+            let off = status.status_type == StatusType::NoteOff;
+            (9, channel, *key as u16, off as u16)
+        }
+        EventData::SysexData {
+            meta_type: Some(meta_type),
+            ..
+        } => (0, 0, *meta_type as u16, 0),
+        EventData::SysexData { .. } => (1, 0, 0, 0),
+        EventData::ProgramChangeData { program_id } => (2, channel, *program_id as u16, 0),
+        EventData::RpnData { change } => (3, channel, change.parameter, change.value),
+        EventData::ControlData {
+            control_id,
+            control_value,
+        } => (3, channel, *control_id as u16, *control_value as u16),
+        EventData::Control14Data { control_id, value } => (3, channel, *control_id as u16, *value),
+        EventData::PitchBendData { bend } => (4, channel, bend.raw(), 0),
+        EventData::ChannelData { channel_pressure } => (5, channel, *channel_pressure as u16, 0),
+        EventData::NoteOnOffData { key, velocity } => {
+            let rank = match status.status_type {
+                StatusType::PolyphonicAftertouch => 5,
+                StatusType::NoteOff => 6,
+                _ => 7,
+            };
+            (rank, channel, *key as u16, *velocity as u16)
+        }
+        _ => (8, 0, status.raw_status as u16, 0),
+    }
+}
+
+/// Marks the NoteOns and NoteOffs of notes that start and end on the same
+/// tick, pairing them as `pair_notes` does
+fn zero_length(events: &[(u32, MidiEvent)]) -> Vec<bool> {
+    let mut marks = vec![false; events.len()];
+    // (event, tick, channel, key) of every note still sounding
+    let mut open: Vec<(usize, u32, u8, u8)> = vec![];
+    for (i, (tick, event)) in events.iter().enumerate() {
+        let EventData::NoteOnOffData { key, .. } = event.data else {
+            continue;
+        };
+        let channel = event.status.channel();
+        match event.status.status_type {
+            StatusType::NoteOn => open.push((i, *tick, channel, key)),
+            StatusType::NoteOff => {
+                let Some(pos) = open.iter().position(|o| o.2 == channel && o.3 == key) else {
+                    continue;
+                };
+                let (on, start, ..) = open.remove(pos);
+                if start == *tick {
+                    marks[on] = true;
+                    marks[i] = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    marks
+}
+
+impl MidiTrack {
+    /// Rewrites the track into a canonical form: zero-velocity NoteOns become
+    /// NoteOffs and events on the same tick are put in a fixed order, so
+    /// tracks that sound the same end up with identical events
+    pub fn normalize(&mut self) {
+        let mut events = self.take_absolute();
+        for (_, event) in events.iter_mut() {
+            if let EventData::NoteOnOffData { velocity: 0, .. } = event.data {
+                if event.status.status_type == StatusType::NoteOn {
+                    event.status =
+                        Status::channel_message(StatusType::NoteOff, event.status.channel());
+                }
+            }
+        }
+        let marks = zero_length(&events);
+        let mut marked: Vec<((u32, MidiEvent), bool)> = events.into_iter().zip(marks).collect();
+        marked.sort_by_key(|((tick, event), zero)| (*tick, canonical_order(event, *zero)));
+        self.set_absolute(marked.into_iter().map(|(event, _)| event).collect());
+        self.chunk_length = 0;
+        self.parsed_length = 0;
+    }
+
+    /// The normalized track as an `MTrk` chunk: the same bytes for every
+    /// track that normalizes to the same events
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut track = self.clone();
+        track.normalize();
+        track.to_chunk()
+    }
+}
+
+/// Hashes the canonical bytes, so tracks that normalize alike hash alike
+impl Hash for MidiTrack {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.canonical_bytes().hash(state);
+    }
+}
+
+impl MidiFile {
+    /// Normalizes every track. Running status is a property of the encoding,
+    /// not of the events, so it is reset as well.
+    pub fn normalize(&mut self) {
+        self.load_leniently();
+        for track in self.tracks.iter_mut() {
+            track.normalize();
+        }
+        self.prev_status = 0;
+    }
+
+    /// The normalized file as a Standard MIDI File, for caching and finding
+    /// duplicates: files that normalize alike give the same bytes
+    pub fn canonical_bytes(&self) -> Vec<u8> {
+        let mut file = self.clone();
+        file.normalize();
+        file.to_smf()
+    }
+}
+
+impl Hash for MidiFile {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.canonical_bytes().hash(state);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::hash_map::DefaultHasher;
+
+    use super::*;
+    use crate::note::pair_notes;
+
+    fn parse(track: &[u8]) -> MidiFile {
+        let mut data = b"MThd\0\0\0\x06\0\x00\0\x01\0\x60MTrk".to_vec();
+        data.extend((track.len() as u32).to_be_bytes());
+        data.extend(track);
+        let mut file = MidiFile::create();
+        file.parse_bytes(&data).unwrap();
+        file
+    }
+
+    fn hash(file: &MidiFile) -> u64 {
+        let mut hasher = DefaultHasher::new();
+        file.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    #[test]
+    fn encodings_of_the_same_music_normalize_alike() {
+        // running status and zero-velocity NoteOns, program after the note
+        let a = parse(&[
+            0x00, 0x90, 60, 100, 0x00, 0xc0, 5, 0x60, 0x90, 60, 0, 0x00, 0xff, 0x2f, 0x00,
+        ]);
+        // full status bytes and a NoteOff, program first
+        let b = parse(&[
+            0x00, 0xc0, 5, 0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0, 0x00, 0xff, 0x2f, 0x00,
+        ]);
+        assert!(a != b);
+        assert_eq!(a.canonical_bytes(), b.canonical_bytes());
+        assert_eq!(hash(&a), hash(&b));
+
+        let (mut a, mut b) = (a, b);
+        a.normalize();
+        b.normalize();
+        assert!(a == b);
+    }
+
+    #[test]
+    fn zero_length_notes_survive() {
+        let mut file = parse(&[
+            0x00, 0x90, 60, 100, 0x00, 0x80, 60, 0, 0x00, 0x90, 62, 100, 0x60, 0x80, 62, 0, 0x00,
+            0xff, 0x2f, 0x00,
+        ]);
+        file.normalize();
+        let notes = pair_notes(file.tracks[0].iter_ticks());
+        let found: Vec<(u8, u32, u32)> =
+            notes.iter().map(|n| (n.key, n.start, n.duration)).collect();
+        assert_eq!(found, vec![(62, 0, 96), (60, 0, 0)]);
+        let events = &file.tracks[0].events;
+        assert_eq!(events[1].status.status_type, StatusType::NoteOn);
+        assert_eq!(events[2].status.status_type, StatusType::NoteOff);
+    }
+
+    #[test]
+    fn different_music_hashes_differently() {
+        let a = parse(&[
+            0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0, 0x00, 0xff, 0x2f, 0x00,
+        ]);
+        let b = parse(&[
+            0x00, 0x90, 61, 100, 0x60, 0x80, 61, 0, 0x00, 0xff, 0x2f, 0x00,
+        ]);
+        assert_ne!(hash(&a), hash(&b));
+    }
+}
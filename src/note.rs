@@ -1,7 +1,13 @@
 #![allow(dead_code)]
-use std::error::Error;
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::error::Error;
 
-#[derive(Debug, Clone, Copy)]
+use crate::{
+    parser::{EventData, MidiEvent, MidiTrack},
+    status::{Status, StatusType},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Notes {
     C = 12,
     CSharp = 13,
@@ -27,6 +33,12 @@ impl Notes {
         }
     }
 
+    pub fn transpose(self, semitones: i32) -> (Self, i32) {
+        let shifted = self as i32 - 12 + semitones;
+        let (note, _) = Self::from((shifted.rem_euclid(12) + 12) as u32).unwrap();
+        (note, shifted.div_euclid(12))
+    }
+
     pub fn from(n: u32) -> Option<(Self, u8)> {
         let modulo = n % 12;
         let octave_raw = ((n - modulo) / 12 - 1) as i8;
@@ -51,3 +63,265 @@ impl Notes {
         }
     }
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    pub channel: u8,
+    pub key: u8,
+    pub velocity: u8,
+    pub start: u32,
+    pub duration: u32,
+    /// NoteOff velocity; 0 when the note was ended by a zero-velocity NoteOn
+    /// or gave none
+    pub release_velocity: u8,
+}
+
+impl Note {
+    pub fn end(&self) -> u32 {
+        self.start + self.duration
+    }
+
+    pub fn events(&self) -> [(u32, MidiEvent); 2] {
+        [
+            (
+                self.start,
+                MidiEvent {
+                    status: Status::channel_message(StatusType::NoteOn, self.channel),
+                    data: EventData::NoteOnOffData {
+                        key: self.key,
+                        velocity: self.velocity,
+                    },
+                    delta_tick: 0,
+                },
+            ),
+            (
+                self.end(),
+                MidiEvent {
+                    status: Status::channel_message(StatusType::NoteOff, self.channel),
+                    data: EventData::NoteOnOffData {
+                        key: self.key,
+                        velocity: self.release_velocity,
+                    },
+                    delta_tick: 0,
+                },
+            ),
+        ]
+    }
+}
+
+/// Turns a packed NoteOff without a release velocity, or a zero-velocity
+/// NoteOn, into a NoteOff released at `velocity`. Other messages pass
+/// through.
+pub fn with_release_velocity(message: u32, velocity: u8) -> u32 {
+    let status = message & 0xf0;
+    let released = message >> 16 & 0x7f != 0;
+    if (status == StatusType::NoteOff as u32 || status == StatusType::NoteOn as u32) && !released {
+        StatusType::NoteOff as u32
+            | message & 0x0f
+            | message & 0x7f00
+            | ((velocity & 0x7f) as u32) << 16
+    } else {
+        message
+    }
+}
+
+fn is_note_event(event: &MidiEvent) -> bool {
+    matches!(
+        event.status.status_type,
+        StatusType::NoteOn | StatusType::NoteOff
+    )
+}
+
+/// Pairs NoteOn events with the NoteOff (or zero-velocity NoteOn) that ends
+/// them, first in first out per channel and key. Notes still sounding when the
+/// events run out are closed at the last tick.
+pub fn pair_notes<'a>(events: impl Iterator<Item = (u32, &'a MidiEvent)>) -> Vec<Note> {
+    let mut notes: Vec<Note> = vec![];
+    let mut open: Vec<usize> = vec![];
+    let mut last_tick = 0;
+
+    for (tick, event) in events {
+        last_tick = tick;
+        let (key, velocity) = match event.data {
+            EventData::NoteOnOffData { key, velocity } if is_note_event(event) => (key, velocity),
+            _ => continue,
+        };
+        let channel = event.status.channel();
+
+        if event.status.status_type == StatusType::NoteOn && velocity > 0 {
+            open.push(notes.len());
+            notes.push(Note {
+                channel,
+                key,
+                velocity,
+                start: tick,
+                duration: 0,
+                release_velocity: 0,
+            });
+        } else if let Some(pos) = open
+            .iter()
+            .position(|&i| notes[i].channel == channel && notes[i].key == key)
+        {
+            let note = &mut notes[open.remove(pos)];
+            note.duration = tick - note.start;
+            if event.status.status_type == StatusType::NoteOff {
+                note.release_velocity = velocity;
+            }
+        }
+    }
+
+    for i in open {
+        notes[i].duration = last_tick - notes[i].start;
+    }
+    notes
+}
+
+/// Separates note events from everything else so note-level edits can be
+/// made on the paired notes and merged back with `merge_notes`
+pub fn split_notes(events: Vec<(u32, MidiEvent)>) -> (Vec<Note>, Vec<(u32, MidiEvent)>) {
+    let notes = pair_notes(events.iter().map(|(tick, event)| (*tick, event)));
+    let others = events
+        .into_iter()
+        .filter(|(_, event)| !is_note_event(event))
+        .collect();
+    (notes, others)
+}
+
+pub fn merge_notes(notes: &[Note], others: Vec<(u32, MidiEvent)>) -> Vec<(u32, MidiEvent)> {
+    let mut events: Vec<(u32, u8, MidiEvent)> = others
+        .into_iter()
+        .map(|(tick, event)| (tick, 1, event))
+        .collect();
+    // NoteOffs go first and NoteOns last on a shared tick, so a repeated key
+    // is released before it is struck again and setup events land first. A
+    // note with no length is struck and released after all of them.
+    for note in notes {
+        let [(start, on), (end, off)] = note.events();
+        let (on_order, off_order) = match note.duration {
+            0 => (3, 3),
+            _ => (2, 0),
+        };
+        events.push((start, on_order, on));
+        events.push((end, off_order, off));
+    }
+    events.sort_by_key(|(tick, order, _)| (*tick, *order));
+    events
+        .into_iter()
+        .map(|(tick, _, event)| (tick, event))
+        .collect()
+}
+
+impl MidiTrack {
+    /// The track as a single line for notation: starts and ends snapped to
+    /// `grid` ticks, only the highest of notes struck together kept, and
+    /// each note cut off where the next one starts
+    pub fn melody(&self, grid: u32) -> Vec<Note> {
+        let grid = grid.max(1);
+        let snap = |tick: u32| (tick + grid / 2) / grid * grid;
+        let mut notes = pair_notes(self.iter_ticks());
+        for note in notes.iter_mut() {
+            let start = snap(note.start);
+            note.duration = snap(note.end()).max(start + grid) - start;
+            note.start = start;
+        }
+        notes.sort_by_key(|note| (note.start, core::cmp::Reverse(note.key)));
+        notes.dedup_by_key(|note| note.start);
+        for i in 1..notes.len() {
+            let next = notes[i].start;
+            let note = &mut notes[i - 1];
+            note.duration = note.duration.min(next - note.start);
+        }
+        notes
+    }
+
+    /// Notes struck together, snapped to `grid` ticks, as (start, length,
+    /// keys). A chord lasts as long as its shortest note and ends where the
+    /// next one starts.
+    pub fn chords(&self, grid: u32) -> Vec<(u32, u32, Vec<u8>)> {
+        let grid = grid.max(1);
+        let snap = |tick: u32| (tick + grid / 2) / grid * grid;
+        let mut notes = pair_notes(self.iter_ticks());
+        notes.sort_by_key(|note| (snap(note.start), note.key));
+        let mut chords: Vec<(u32, u32, Vec<u8>)> = vec![];
+        for note in notes {
+            let start = snap(note.start);
+            let end = snap(note.end()).max(start + grid);
+            match chords.last_mut() {
+                Some((s, e, keys)) if *s == start => {
+                    *e = (*e).min(end);
+                    if !keys.contains(&note.key) {
+                        keys.push(note.key);
+                    }
+                }
+                _ => chords.push((start, end, vec![note.key])),
+            }
+        }
+        for i in 1..chords.len() {
+            let next = chords[i].0;
+            chords[i - 1].1 = chords[i - 1].1.min(next);
+        }
+        chords
+            .into_iter()
+            .map(|(start, end, keys)| (start, end - start, keys))
+            .collect()
+    }
+
+    /// Gives every note release without a velocity one, turning zero-velocity
+    /// NoteOns into NoteOffs. Returns how many were changed.
+    pub fn fill_release_velocities(&mut self, velocity: u8) -> usize {
+        let mut filled = 0;
+        for event in self.events.iter_mut().filter(|e| is_note_event(e)) {
+            if let EventData::NoteOnOffData { velocity: v, .. } = &mut event.data {
+                if *v == 0 {
+                    *v = velocity & 0x7f;
+                    event.status =
+                        Status::channel_message(StatusType::NoteOff, event.status.channel());
+                    filled += 1;
+                }
+            }
+        }
+        filled
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn note(key: u8, start: u32, duration: u32) -> Note {
+        Note {
+            channel: 0,
+            key,
+            velocity: 100,
+            start,
+            duration,
+            release_velocity: 0,
+        }
+    }
+
+    #[test]
+    fn zero_length_note_is_struck_before_released() {
+        let notes = [note(60, 0, 96), note(60, 96, 0), note(60, 96, 96)];
+        let events = merge_notes(&notes, vec![]);
+        let paired = pair_notes(events.iter().map(|(tick, event)| (*tick, event)));
+        let mut found: Vec<(u32, u32)> = paired.iter().map(|n| (n.start, n.duration)).collect();
+        found.sort();
+        assert_eq!(found, vec![(0, 96), (96, 0), (96, 96)]);
+    }
+
+    #[test]
+    fn repeated_key_is_released_first() {
+        let notes = [note(60, 0, 96), note(60, 96, 96)];
+        let events = merge_notes(&notes, vec![]);
+        let statuses: Vec<StatusType> = events.iter().map(|(_, e)| e.status.status_type).collect();
+        assert_eq!(
+            statuses,
+            vec![
+                StatusType::NoteOn,
+                StatusType::NoteOff,
+                StatusType::NoteOn,
+                StatusType::NoteOff
+            ]
+        );
+    }
+}
@@ -0,0 +1,61 @@
+use crate::tempo::TempoMap;
+
+/// How far to move a track's events in time. Negative values play early.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Offset {
+    Millis(f64),
+    Ticks(i32),
+}
+
+impl Offset {
+    /// The shift in microseconds for an event at `tick`. Tick offsets follow
+    /// the tempo map, so they stay musically constant across tempo changes.
+    pub fn micros_at(&self, tick: u32, tempo_map: &TempoMap) -> f64 {
+        match *self {
+            Self::Millis(millis) => millis * 1000.0,
+            Self::Ticks(ticks) => {
+                let target = tick as f64 + ticks as f64;
+                tempo_map.micros_at(target) - tempo_map.micros_at(tick as f64)
+            }
+        }
+    }
+}
+
+/// Per-track nudges applied at schedule time, leaving the file untouched
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct TrackOffsets {
+    pub offsets: Vec<(usize, Offset)>,
+}
+
+impl TrackOffsets {
+    pub fn create() -> Self {
+        Self { offsets: vec![] }
+    }
+
+    /// Replaces any existing offset for `track`
+    pub fn set(&mut self, track: usize, offset: Offset) {
+        self.offsets.retain(|(t, _)| *t != track);
+        self.offsets.push((track, offset));
+    }
+
+    pub fn get(&self, track: usize) -> Option<Offset> {
+        self.offsets
+            .iter()
+            .find(|(t, _)| *t == track)
+            .map(|(_, o)| *o)
+    }
+
+    pub fn micros_at(&self, track: usize, tick: u32, tempo_map: &TempoMap) -> f64 {
+        self.get(track)
+            .map_or(0.0, |offset| offset.micros_at(tick, tempo_map))
+    }
+
+    /// How far before time zero the earliest nudged event can fall. Playback
+    /// is delayed by this much so early tracks are not clipped.
+    pub fn lookahead(&self, tempo_map: &TempoMap) -> f64 {
+        self.offsets
+            .iter()
+            .map(|(_, offset)| -offset.micros_at(0, tempo_map))
+            .fold(0.0, f64::max)
+    }
+}
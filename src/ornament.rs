@@ -0,0 +1,103 @@
+use crate::note::Note;
+
+/// `per_quarter` is how many ornament notes fit in a quarter note, so 8 gives
+/// thirty-second notes at whatever division the file uses
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ornament {
+    Trill {
+        interval: i8,
+        per_quarter: u32,
+    },
+    Mordent {
+        interval: i8,
+        per_quarter: u32,
+    },
+    Turn {
+        upper: i8,
+        lower: i8,
+        per_quarter: u32,
+    },
+    Tremolo {
+        per_quarter: u32,
+    },
+}
+
+impl Ornament {
+    pub fn trill() -> Self {
+        Self::Trill {
+            interval: 2,
+            per_quarter: 8,
+        }
+    }
+
+    pub fn mordent() -> Self {
+        Self::Mordent {
+            interval: 2,
+            per_quarter: 8,
+        }
+    }
+
+    pub fn inverted_mordent() -> Self {
+        Self::Mordent {
+            interval: -2,
+            per_quarter: 8,
+        }
+    }
+
+    pub fn turn() -> Self {
+        Self::Turn {
+            upper: 2,
+            lower: -2,
+            per_quarter: 8,
+        }
+    }
+
+    pub fn tremolo() -> Self {
+        Self::Tremolo { per_quarter: 8 }
+    }
+
+    fn per_quarter(&self) -> u32 {
+        match *self {
+            Self::Trill { per_quarter, .. }
+            | Self::Mordent { per_quarter, .. }
+            | Self::Turn { per_quarter, .. }
+            | Self::Tremolo { per_quarter } => per_quarter,
+        }
+    }
+
+    /// Expands `note` into the ornament's note sequence. The result always
+    /// spans the original note exactly, with the last note absorbing any remainder.
+    pub fn expand(&self, note: &Note, division: u16) -> Vec<Note> {
+        let step = (division as u32 / self.per_quarter().max(1)).max(1);
+        let offsets: Vec<i8> = match *self {
+            Self::Trill { interval, .. } => {
+                let count = (note.duration / step).max(1);
+                (0..count)
+                    .map(|i| if i % 2 == 0 { 0 } else { interval })
+                    .collect()
+            }
+            Self::Mordent { interval, .. } => vec![0, interval, 0],
+            Self::Turn { upper, lower, .. } => vec![upper, 0, lower, 0],
+            Self::Tremolo { .. } => vec![0; (note.duration / step).max(1) as usize],
+        };
+
+        if offsets.len() as u32 * step > note.duration {
+            return vec![*note];
+        }
+
+        let mut notes: Vec<Note> = offsets
+            .iter()
+            .enumerate()
+            .map(|(i, &offset)| Note {
+                key: (note.key as i32 + offset as i32).clamp(0, 127) as u8,
+                start: note.start + i as u32 * step,
+                duration: step,
+                ..*note
+            })
+            .collect();
+        if let Some(last) = notes.last_mut() {
+            last.duration = note.end() - last.start;
+        }
+        notes
+    }
+}
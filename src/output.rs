@@ -0,0 +1,28 @@
+use crate::status::StatusType;
+
+pub const ALL_SOUND_OFF: u8 = 120;
+pub const RESET_ALL_CONTROLLERS: u8 = 121;
+pub const ALL_NOTES_OFF: u8 = 123;
+
+/// Anything short messages can be sent to, packed like `midiOutShortMsg`
+/// expects: status in the low byte followed by the data bytes
+pub trait MidiOutput {
+    fn send_short(&mut self, message: u32);
+
+    /// Silences everything: All Notes Off, All Sound Off and Reset All
+    /// Controllers on every channel
+    fn panic(&mut self) {
+        for channel in 0..16 {
+            for control in [ALL_NOTES_OFF, ALL_SOUND_OFF, RESET_ALL_CONTROLLERS] {
+                self.send_short(StatusType::CtrlChange as u32 | channel | (control as u32) << 8);
+            }
+        }
+    }
+}
+
+/// Collects messages instead of sending them
+impl MidiOutput for Vec<u32> {
+    fn send_short(&mut self, message: u32) {
+        self.push(message);
+    }
+}
@@ -1,13 +1,40 @@
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::{error::Error, fmt};
+#[cfg(feature = "std")]
 use std::{
-    error::Error,
     fs::{self, File},
     io::prelude::*,
 };
 
-use bytes::{Buf, BytesMut};
+use bytes::{Buf, Bytes, BytesMut};
+
+use crate::bend::PitchBend;
+use crate::control::ControlChange;
+use crate::gm;
+use crate::lazy::TrackChunk;
+use crate::rpn::RpnChange;
+use crate::status::{Status, StatusType, DRUM_CHANNEL};
+use crate::sysex::{SYSEX_END, SYSEX_START};
+#[cfg(feature = "std")]
+use crate::transform::Transform;
+
+/// Status given to unparsed bytes whose own status could not be read; F4 is
+/// undefined, so nothing mistakes them for a real message
+const UNDEFINED_STATUS: u8 = 0xf4;
 
-use crate::status::Status;
+/// Microseconds per quarter note assumed before the first tempo event (120 BPM)
+pub const DEFAULT_TEMPO: u32 = 500_000;
+/// Ticks per quarter note assumed when a header gives none
+pub const DEFAULT_DIVISION: u16 = 480;
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum SysExMeta {
     MetaSequence = 0x00,
     MetaText = 0x01,
@@ -17,7 +44,11 @@ pub enum SysExMeta {
     MetaLyrics = 0x05,
     MetaMarker = 0x06,
     MetaCuePoint = 0x07,
+    MetaProgramName = 0x08,
+    MetaDeviceName = 0x09,
     MetaChannelPrefix = 0x20,
+    /// MIDI port (cable) the track plays on
+    MetaPort = 0x21,
     MetaEndOfTrack = 0x2F,
     MetaSetTempo = 0x51,
     MetaSMPTEOffset = 0x54,
@@ -26,7 +57,8 @@ pub enum SysExMeta {
     MetaSequencerSpecific = 0x7F,
 }
 
-#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
 pub enum MetaData {
     SingleU8(u8),
     DoubleU8(u8, u8),
@@ -34,71 +66,418 @@ pub enum MetaData {
     QuadU8(u8, u8, u8, u8),
     QuintripleU8(u8, u8, u8, u8, u8),
     SingleString(String),
+    Bytes(Vec<u8>),
     None,
 }
 
-impl SysExMeta {
-    pub fn from(d: u8) -> Option<Self> {
-        match d {
-            0x00 => Some(Self::MetaSequence),
-            0x01 => Some(Self::MetaText),
-            0x02 => Some(Self::MetaCopyright),
-            0x03 => Some(Self::MetaTrackName),
-            0x04 => Some(Self::MetaInstrumentName),
-            0x05 => Some(Self::MetaLyrics),
-            0x06 => Some(Self::MetaMarker),
-            0x07 => Some(Self::MetaCuePoint),
-            0x20 => Some(Self::MetaChannelPrefix),
-            0x2F => Some(Self::MetaEndOfTrack),
-            0x51 => Some(Self::MetaSetTempo),
-            0x54 => Some(Self::MetaSMPTEOffset),
-            0x58 => Some(Self::MetaTimeSignature),
-            0x59 => Some(Self::MetaKeySignature),
-            0x7F => Some(Self::MetaSequencerSpecific),
-            _ => None,
+impl MetaData {
+    /// A text meta's payload: a string when it is UTF-8, otherwise the raw
+    /// bytes so that writing it back loses nothing
+    pub fn text(bytes: Vec<u8>) -> Self {
+        match String::from_utf8(bytes) {
+            Ok(text) => Self::SingleString(text),
+            Err(e) => Self::Bytes(e.into_bytes()),
         }
     }
 }
 
+impl SysExMeta {
+    /// Every meta type, in byte order
+    ///
+    /// cbindgen:ignore
+    pub const ALL: [Self; 18] = [
+        Self::MetaSequence,
+        Self::MetaText,
+        Self::MetaCopyright,
+        Self::MetaTrackName,
+        Self::MetaInstrumentName,
+        Self::MetaLyrics,
+        Self::MetaMarker,
+        Self::MetaCuePoint,
+        Self::MetaProgramName,
+        Self::MetaDeviceName,
+        Self::MetaChannelPrefix,
+        Self::MetaPort,
+        Self::MetaEndOfTrack,
+        Self::MetaSetTempo,
+        Self::MetaSMPTEOffset,
+        Self::MetaTimeSignature,
+        Self::MetaKeySignature,
+        Self::MetaSequencerSpecific,
+    ];
+}
+
+impl TryFrom<u8> for SysExMeta {
+    type Error = Box<dyn Error>;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|meta| *meta as u8 == byte)
+            .ok_or_else(|| format!("Unknown meta event type 0x{:02x}", byte).into())
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
 pub enum EventData {
-    NoteOnOffData { key: u8, velocity: u8 },
-    ControlData { control_id: u8, control_value: u8 },
-    ProgramChangeData { program_id: u8 },
-    ChannelData { channel_pressure: u8 },
-    PitchBendData { least_bytes: u8, most_bytes: u8 },
-    SysexData { meta: MetaData },
-    Error(String),
+    NoteOnOffData {
+        key: u8,
+        velocity: u8,
+    },
+    ControlData {
+        control_id: u8,
+        control_value: u8,
+    },
+    ProgramChangeData {
+        program_id: u8,
+    },
+    ChannelData {
+        channel_pressure: u8,
+    },
+    PitchBendData {
+        bend: PitchBend,
+    },
+    SysexData {
+        meta_type: Option<SysExMeta>,
+        meta: MetaData,
+    },
+    RpnData {
+        change: RpnChange,
+    },
+    Control14Data {
+        control_id: u8,
+        value: u16,
+    },
+    QuarterFrameData {
+        piece: u8,
+        value: u8,
+    },
+    /// Position in MIDI beats (sixteenth notes) from the start of the song
+    SongPositionData {
+        position: u16,
+    },
+    SongSelectData {
+        song: u8,
+    },
+    /// Tune request and real-time messages, which carry no data bytes
+    NoData,
+    /// Bytes the parser could not make sense of, kept verbatim along with
+    /// their position in the file so they can be inspected or repaired
+    Unparsed {
+        raw: Vec<u8>,
+        offset: u64,
+        reason: String,
+    },
 }
 
+impl fmt::Display for EventData {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoteOnOffData { key, velocity } => {
+                write!(f, "Key: {}, Velocity: {}", key, velocity)
+            }
+            Self::ControlData {
+                control_id,
+                control_value,
+            } => write!(
+                f,
+                "Control: {} ({}), Value: {}",
+                control_id,
+                ControlChange::from(*control_id).name(),
+                control_value
+            ),
+            Self::ProgramChangeData { program_id } => match gm::program_name(*program_id) {
+                Some(name) => write!(f, "Program: {} ({})", program_id, name),
+                None => write!(f, "Program: {}", program_id),
+            },
+            Self::ChannelData { channel_pressure } => {
+                write!(f, "Pressure: {}", channel_pressure)
+            }
+            Self::PitchBendData { bend } => write!(f, "Bend: {}", bend.value()),
+            Self::SysexData { meta_type, meta } => match meta_type {
+                Some(meta_type) => write!(f, "{:?}: {:?}", meta_type, meta),
+                None => match self.sysex() {
+                    Some(sysex) => write!(
+                        f,
+                        "SysEx: {} ({} bytes)",
+                        sysex.manufacturer.name().unwrap_or("Unknown manufacturer"),
+                        sysex.data.len()
+                    ),
+                    None => write!(f, "SysEx: {:?}", meta),
+                },
+            },
+            Self::RpnData { change } => write!(
+                f,
+                "{:?} Parameter: {}, Value: {}",
+                change.kind, change.parameter, change.value
+            ),
+            Self::Control14Data { control_id, value } => write!(
+                f,
+                "Control: {} ({}), Value: {} (14-bit)",
+                control_id,
+                ControlChange::from(*control_id).name(),
+                value
+            ),
+            Self::QuarterFrameData { piece, value } => {
+                write!(f, "Quarter Frame: {}, Value: {}", piece, value)
+            }
+            Self::SongPositionData { position } => write!(f, "Song Position: {}", position),
+            Self::SongSelectData { song } => write!(f, "Song: {}", song),
+            Self::NoData => Ok(()),
+            Self::Unparsed {
+                raw,
+                offset,
+                reason,
+            } => write!(
+                f,
+                "Unparsed at {}: {} ({} bytes)",
+                offset,
+                reason,
+                raw.len()
+            ),
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
 pub struct MidiEvent {
     pub status: Status,
     pub data: EventData,
     pub delta_tick: u32,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone)]
 pub struct MidiTrack {
     pub name: String,
     pub instrument: String,
     pub events: Vec<MidiEvent>,
     pub end_of_track: bool,
+    /// Left out when serialized; they are edits on top of the parsed events
+    #[cfg(feature = "std")]
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub transforms: Vec<Transform>,
+    /// Length stored in the MTrk header, and the bytes the parser actually read
+    pub chunk_length: u32,
+    pub parsed_length: u32,
+}
+
+/// Tracks are equal when they hold the same events; the chunk lengths only
+/// describe the file they were read from
+impl PartialEq for MidiTrack {
+    fn eq(&self, other: &Self) -> bool {
+        #[cfg(feature = "std")]
+        if self.transforms != other.transforms {
+            return false;
+        }
+        self.events == other.events
+            && self.name == other.name
+            && self.instrument == other.instrument
+    }
+}
+
+impl MidiEvent {
+    /// Decodes a packed short message as delivered by MIDI input callbacks:
+    /// status in the low byte followed by up to two data bytes
+    pub fn from_short_message(message: u32) -> Result<Self, Box<dyn Error>> {
+        let status = Status::from_live_byte((message & 0xff) as u8)?;
+        let data = status.short_data((message >> 8 & 0xff) as u8, (message >> 16 & 0xff) as u8);
+        Ok(Self {
+            status,
+            data,
+            delta_tick: 0,
+        })
+    }
+
+    /// Packed wire form of a channel message, status in the low byte.
+    /// `None` for meta, SysEx and decoded multi-message events.
+    pub fn to_short_message(&self) -> Option<u32> {
+        if !self.status.is_channel_message() {
+            return None;
+        }
+        let (first, second) = match self.data {
+            EventData::NoteOnOffData { key, velocity } => (key, velocity),
+            EventData::ControlData {
+                control_id,
+                control_value,
+            } => (control_id, control_value),
+            EventData::ProgramChangeData { program_id } => (program_id, 0),
+            EventData::ChannelData { channel_pressure } => (channel_pressure, 0),
+            EventData::PitchBendData { bend } => (bend.least_bytes(), bend.most_bytes()),
+            _ => return None,
+        };
+        Some(
+            self.status.raw_status as u32
+                | ((first & 0x7f) as u32) << 8
+                | ((second & 0x7f) as u32) << 16,
+        )
+    }
+
+    pub fn is_end_of_track(&self) -> bool {
+        matches!(
+            self.data,
+            EventData::SysexData {
+                meta_type: Some(SysExMeta::MetaEndOfTrack),
+                ..
+            }
+        )
+    }
+
+    pub fn end_of_track(delta_tick: u32) -> Self {
+        Self {
+            delta_tick,
+            ..Self::meta(SysExMeta::MetaEndOfTrack, MetaData::None)
+        }
+    }
+
+    pub fn meta(meta_type: SysExMeta, meta: MetaData) -> Self {
+        Self {
+            status: Status::from_byte(0xFF).unwrap(),
+            data: EventData::SysexData {
+                meta_type: Some(meta_type),
+                meta,
+            },
+            delta_tick: 0,
+        }
+    }
+
+    pub fn channel_message(status_type: StatusType, channel: u8, data: EventData) -> Self {
+        Self {
+            status: Status::channel_message(status_type, channel),
+            data,
+            delta_tick: 0,
+        }
+    }
+}
+
+impl MidiTrack {
+    pub fn create() -> Self {
+        Self {
+            name: String::new(),
+            instrument: String::new(),
+            events: vec![],
+            end_of_track: false,
+            #[cfg(feature = "std")]
+            transforms: vec![],
+            chunk_length: 0,
+            parsed_length: 0,
+        }
+    }
+
+    pub fn from_absolute(events: Vec<(u32, MidiEvent)>) -> Self {
+        let mut track = Self::create();
+        track.set_absolute(events);
+        track
+    }
+
+    /// Replaces the events with ones at absolute ticks, recomputing the deltas.
+    /// Any end-of-track events are collapsed into a single one at the end.
+    pub fn set_absolute(&mut self, mut events: Vec<(u32, MidiEvent)>) {
+        events.sort_by_key(|(tick, _)| *tick);
+        let mut end = events.last().map_or(0, |(tick, _)| *tick);
+        events.retain(|(tick, event)| {
+            if event.is_end_of_track() {
+                end = end.max(*tick);
+                false
+            } else {
+                true
+            }
+        });
+
+        let mut prev_tick = 0;
+        self.events = events
+            .into_iter()
+            .map(|(tick, mut event)| {
+                event.delta_tick = tick - prev_tick;
+                prev_tick = tick;
+                event
+            })
+            .collect();
+        self.events.push(MidiEvent::end_of_track(end - prev_tick));
+        self.end_of_track = true;
+    }
+
+    pub fn take_absolute(&mut self) -> Vec<(u32, MidiEvent)> {
+        let mut tick = 0;
+        core::mem::take(&mut self.events)
+            .into_iter()
+            .map(|event| {
+                tick += event.delta_tick;
+                (tick, event)
+            })
+            .collect()
+    }
+
+    pub fn iter_ticks(&self) -> impl Iterator<Item = (u32, &MidiEvent)> {
+        self.events.iter().scan(0u32, |tick, event| {
+            *tick += event.delta_tick;
+            Some((*tick, event))
+        })
+    }
+
+    pub fn end_tick(&self) -> u32 {
+        self.events.iter().map(|event| event.delta_tick).sum()
+    }
+
+    pub fn transpose(&mut self, semitones: i32) -> Result<(), Box<dyn Error>> {
+        self.transpose_with_drums(semitones, Some(DRUM_CHANNEL))
+    }
+
+    /// Like `transpose`, leaving `drum_channel` alone rather than channel 10
+    pub fn transpose_with_drums(
+        &mut self,
+        semitones: i32,
+        drum_channel: Option<u8>,
+    ) -> Result<(), Box<dyn Error>> {
+        let transposable = |event: &MidiEvent| {
+            matches!(
+                event.status.status_type,
+                StatusType::NoteOn | StatusType::NoteOff | StatusType::PolyphonicAftertouch
+            ) && Some(event.status.channel()) != drum_channel
+        };
+
+        for event in self.events.iter().filter(|e| transposable(e)) {
+            if let EventData::NoteOnOffData { key, .. } = event.data {
+                let shifted = key as i32 + semitones;
+                if !(0..=127).contains(&shifted) {
+                    return Err(format!(
+                        "Transposing key {} by {} is out of range",
+                        key, semitones
+                    )
+                    .into());
+                }
+            }
+        }
+
+        for event in self.events.iter_mut().filter(|e| transposable(e)) {
+            if let EventData::NoteOnOffData { key, .. } = &mut event.data {
+                *key = (*key as i32 + semitones) as u8;
+            }
+        }
+        Ok(())
+    }
 }
 
 pub fn read_str(bytes: &mut BytesMut, length: usize) -> Box<String> {
-    let slice = (0..length)
-        .into_iter()
-        .map(|_| bytes.get_u8())
-        .collect::<Vec<u8>>();
+    let slice = (0..length).map(|_| bytes.get_u8()).collect::<Vec<u8>>();
     let s = String::from_utf8_lossy(slice.as_slice());
     Box::new(String::from(s))
 }
 
+pub fn read_bytes(bytes: &mut BytesMut, length: usize) -> Vec<u8> {
+    bytes.split_to(length).to_vec()
+}
+
 pub fn read_value(bytes: &mut BytesMut) -> u32 {
     let mut n_value = bytes.get_u8() as u32;
     let mut n_byte;
 
     if n_value & 0x80 != 0 {
-        n_value = n_value & 0x7F;
-        loop {
+        n_value &= 0x7F;
+        // a value cut short by the end of the data keeps what was read
+        while bytes.has_remaining() {
             n_byte = bytes.get_u8();
             n_value = (n_value << 7) | (n_byte as u32 & 0x7F);
             if n_byte & 0x80 == 0 {
@@ -110,12 +489,70 @@ pub fn read_value(bytes: &mut BytesMut) -> u32 {
     n_value
 }
 
+/// Fallbacks for what a file may leave out
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ParseOptions {
+    /// Microseconds per quarter note before the first tempo event, and the
+    /// file's `tempo` when it has none
+    pub default_tempo: u32,
+    /// Ticks per quarter note when the header gives 0
+    pub default_division: u16,
+    /// Only index the track chunks after the first, parsing each when
+    /// `MidiFile::track` first asks for it
+    pub lazy: bool,
+}
+
+impl ParseOptions {
+    /// 120 BPM at 480 ticks per quarter note
+    pub fn create() -> Self {
+        Self {
+            default_tempo: DEFAULT_TEMPO,
+            default_division: DEFAULT_DIVISION,
+            lazy: false,
+        }
+    }
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Clone)]
 pub struct MidiFile {
+    /// The first tempo event's value, or `options.default_tempo` once parsed
+    /// if there is none
     pub tempo: u32,
     pub bpm: u32,
     pub tracks: Vec<MidiTrack>,
+    /// The header's format as read: 0 a single track, 1 tracks played
+    /// together, 2 independent sequences. None for files made another way,
+    /// which are written by their track count.
+    pub format: Option<u16>,
     pub division: u16,
     pub prev_status: u8,
+    pub decode_rpn: bool,
+    pub pair_controllers: bool,
+    /// Keep going past malformed data, recording it as `EventData::Unparsed`
+    /// events instead of failing
+    pub lenient: bool,
+    pub options: ParseOptions,
+    /// Channel given GM percussion handling: left alone by transposition
+    /// and scale snapping, counted as drums by role detection and shared by
+    /// channel allocation. None for files that play channel 10 melodically.
+    pub drum_channel: Option<u8>,
+    /// By track index, where each track `parse` left unparsed in lazy mode
+    /// sits, along with the file's bytes to parse it from
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub unloaded: Vec<Option<TrackChunk>>,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub source: Option<Bytes>,
+}
+
+/// Files are equal when their contents are, however they were parsed
+impl PartialEq for MidiFile {
+    fn eq(&self, other: &Self) -> bool {
+        self.header_format() == other.header_format()
+            && self.division == other.division
+            && self.tracks == other.tracks
+    }
 }
 
 impl MidiFile {
@@ -124,64 +561,462 @@ impl MidiFile {
             tempo: 0,
             bpm: 0,
             tracks: vec![],
+            format: None,
             division: 0,
             prev_status: 0,
+            decode_rpn: false,
+            pair_controllers: false,
+            lenient: false,
+            options: ParseOptions::create(),
+            drum_channel: Some(DRUM_CHANNEL),
+            unloaded: vec![],
+            source: None,
         }
     }
+    #[cfg(feature = "std")]
     pub fn parse(&mut self, filename: &str) -> Result<(), Box<dyn Error>> {
-        let mut file = File::open(filename).unwrap();
-        let metadata = fs::metadata(filename).unwrap();
+        let mut file = File::open(filename)?;
+        let metadata = fs::metadata(filename)?;
         let mut bytes = BytesMut::with_capacity(metadata.len() as usize);
         unsafe {
             bytes.set_len(metadata.len() as usize);
         }
-        file.read(&mut bytes).unwrap();
+        file.read_exact(&mut bytes)?;
+        self.parse_buffer(bytes)
+    }
+
+    /// The format a header for the file gives: `format` while the tracks
+    /// still fit it, otherwise 0 for a single track and 1 for several
+    pub fn header_format(&self) -> u16 {
+        match (self.format, self.tracks.len()) {
+            (Some(0) | None, 1) => 0,
+            (Some(2), _) => 2,
+            _ => 1,
+        }
+    }
+
+    /// Parses a file already in memory, such as one stored in flash. The
+    /// data is copied, so it need not outlive the call.
+    pub fn parse_bytes(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        self.parse_buffer(BytesMut::from(data))
+    }
+
+    fn parse_buffer(&mut self, mut bytes: BytesMut) -> Result<(), Box<dyn Error>> {
+        let file_length = bytes.len();
+        self.unloaded = vec![];
+        self.source = match self.options.lazy {
+            true => Some(Bytes::copy_from_slice(&bytes[..])),
+            false => None,
+        };
 
+        if bytes.remaining() < 14 {
+            return Err(format!("Truncated header: {} bytes", file_length).into());
+        }
         let _file_id = bytes.get_u32();
         let _header_len = bytes.get_u32();
-        let _format = bytes.get_u16();
+        self.format = Some(bytes.get_u16());
         let track_chunks = bytes.get_u16();
         let division = bytes.get_u16();
-        self.division = division;
+        self.division = match division {
+            0 => self.options.default_division.max(1),
+            division => division,
+        };
+        self.tempo = 0;
 
         let mut tracks: Vec<MidiTrack> = vec![];
-        for _chunk in 0..track_chunks {
-            let _n_track_id = bytes.get_u32();
-            let _n_track_len = bytes.get_u32();
-
-            let mut track = MidiTrack {
-                events: vec![],
-                name: String::new(),
-                instrument: String::new(),
-                end_of_track: false,
-            };
+        for index in 0..track_chunks {
+            let offset = file_length - bytes.remaining();
+            if bytes.remaining() < 8 {
+                // a lenient parse keeps the tracks that were there
+                if self.lenient {
+                    break;
+                }
+                return Err(format!("Truncated track header at byte {}", offset).into());
+            }
+            // the first track stays eager, since it usually holds the tempo
+            if self.options.lazy && index > 0 {
+                bytes.advance(4);
+                let length = bytes.get_u32();
+                bytes.advance((length as usize).min(bytes.remaining()));
+                let mut track = MidiTrack::create();
+                track.chunk_length = length;
+                tracks.push(track);
+                self.unloaded.resize(index as usize, None);
+                self.unloaded.push(Some(TrackChunk { offset, length }));
+                continue;
+            }
+            tracks.push(self.parse_track(&mut bytes, file_length)?);
+        }
 
-            self.prev_status = 0u8;
-            while bytes.remaining() != 0 && !track.end_of_track {
-                let delta_tick = read_value(&mut bytes);
-                let mut status = bytes.get_u8();
-                let split = bytes.clone();
+        self.tracks = tracks;
+        if self.tempo == 0 {
+            self.tempo = self.options.default_tempo.max(1);
+        }
+        self.bpm = 60_000_000 / self.tempo;
+        Ok(())
+    }
 
-                if status < 0x80 {
-                    status = self.prev_status;
-                    bytes = split;
+    /// Parses one `MTrk` chunk from the start of `bytes`, which ends at byte
+    /// `end` of the file
+    pub(crate) fn parse_track(
+        &mut self,
+        bytes: &mut BytesMut,
+        end: usize,
+    ) -> Result<MidiTrack, Box<dyn Error>> {
+        if bytes.remaining() < 8 {
+            let offset = end - bytes.remaining();
+            return Err(format!("Truncated track header at byte {}", offset).into());
+        }
+        let _n_track_id = bytes.get_u32();
+        let n_track_len = bytes.get_u32();
+        // the track ends with its chunk, so one missing its end of track
+        // stops short of the next chunk; a chunk running past the end of the
+        // data is cut to what is there
+        let track_start = (n_track_len as usize).min(bytes.remaining());
+        let end = end - bytes.remaining() + track_start;
+        let mut chunk = bytes.split_to(track_start);
+        let bytes = &mut chunk;
+
+        let mut track = MidiTrack::create();
+        track.chunk_length = n_track_len;
+
+        self.prev_status = 0u8;
+        // index of an F0 event still waiting for F7 continuation packets
+        let mut pending_sysex: Option<usize> = None;
+        let mut carried_delta = 0;
+        while bytes.remaining() != 0 && !track.end_of_track {
+            let delta_tick = read_value(bytes).saturating_add(carried_delta);
+            carried_delta = 0;
+            let start = bytes.clone();
+            let offset = (end - bytes.remaining()) as u64;
+
+            let status = match bytes.has_remaining() {
+                false => Err("Delta time without an event".into()),
+                true => match bytes.get_u8() {
+                    // running status: the byte is the first data byte
+                    0x00..=0x7f if self.prev_status != 0 => {
+                        *bytes = start.clone();
+                        Status::from_byte(self.prev_status)
+                    }
+                    0x00..=0x7f => Err("Data byte without a running status".into()),
+                    byte => Status::from_byte(byte),
+                },
+            };
+            let mut truncated = false;
+            let (status, data) = match status {
+                Ok(status) => match status.parse_data(self, &mut track, bytes) {
+                    Ok(data) => (status, data),
+                    Err(e) => {
+                        // nothing after a truncated event can be trusted,
+                        // so the rest of the chunk goes with it
+                        truncated = true;
+                        *bytes = start.clone();
+                        bytes.advance(bytes.remaining());
+                        (
+                            status,
+                            EventData::Unparsed {
+                                raw: vec![],
+                                offset,
+                                reason: e.to_string(),
+                            },
+                        )
+                    }
+                },
+                Err(e) => (
+                    Status {
+                        status_type: StatusType::SystemMsg,
+                        raw_status: UNDEFINED_STATUS,
+                    },
+                    EventData::Unparsed {
+                        raw: vec![],
+                        offset,
+                        reason: e.to_string(),
+                    },
+                ),
+            };
+            let data = match data {
+                EventData::Unparsed { reason, .. } => {
+                    if !self.lenient {
+                        return Err(format!("{} at byte {}", reason, offset).into());
+                    }
+                    EventData::Unparsed {
+                        raw: start[..start.len() - bytes.len()].to_vec(),
+                        offset,
+                        reason,
+                    }
                 }
+                data => data,
+            };
 
-                let status = Status::from_byte(status)?;
-                let data = status.parse_data(self, &mut track, &mut bytes);
+            if let EventData::SysexData {
+                meta_type: None,
+                meta: MetaData::Bytes(packet),
+            } = &data
+            {
+                let terminated = packet.last() == Some(&SYSEX_END);
+                if status.raw_status == SYSEX_START {
+                    pending_sysex = (!terminated).then_some(track.events.len());
+                } else if let Some(i) = pending_sysex {
+                    if let EventData::SysexData {
+                        meta: MetaData::Bytes(payload),
+                        ..
+                    } = &mut track.events[i].data
+                    {
+                        payload.extend_from_slice(packet);
+                    }
+                    if terminated {
+                        pending_sysex = None;
+                    }
+                    carried_delta = delta_tick;
+                    continue;
+                }
+            }
 
-                let event = MidiEvent {
-                    status,
-                    data,
-                    delta_tick,
-                };
-                track.events.push(event);
+            let event = MidiEvent {
+                status,
+                data,
+                delta_tick,
+            };
+            track.events.push(event);
+            if truncated {
+                break;
             }
+        }
 
-            tracks.push(track);
+        track.parsed_length = (track_start - bytes.remaining()) as u32;
+        if self.decode_rpn {
+            track.decode_rpn();
+        }
+        if self.pair_controllers {
+            track.pair_controllers();
         }
+        Ok(track)
+    }
+
+    /// Sets `tempo` and `bpm` as `parse` does, from the first tempo event in
+    /// file order or the default
+    pub(crate) fn refresh_tempo(&mut self) {
+        self.tempo = self
+            .tracks
+            .iter()
+            .flat_map(|track| track.events.iter())
+            .find_map(|event| match event.data {
+                EventData::SysexData {
+                    meta_type: Some(SysExMeta::MetaSetTempo),
+                    meta: MetaData::TripleU8(a, b, c),
+                } => Some((a as u32) << 16 | (b as u32) << 8 | c as u32),
+                _ => None,
+            })
+            .unwrap_or(self.options.default_tempo);
+        self.bpm = 60_000_000 / self.tempo.max(1);
+    }
 
+    /// Transposes every track except on the drum channel. Fails without
+    /// changing anything if a key would leave the range.
+    pub fn transpose(&mut self, semitones: i32) -> Result<(), Box<dyn Error>> {
+        self.load_all()?;
+        let mut tracks = self.tracks.clone();
+        for track in tracks.iter_mut() {
+            track.transpose_with_drums(semitones, self.drum_channel)?;
+        }
         self.tracks = tracks;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A format 1 file at 96 ticks per quarter holding `tracks` as given
+    fn smf(tracks: &[&[u8]]) -> Vec<u8> {
+        let mut out = b"MThd\0\0\0\x06\0\x01".to_vec();
+        out.extend((tracks.len() as u16).to_be_bytes());
+        out.extend(96u16.to_be_bytes());
+        for data in tracks {
+            out.extend(b"MTrk");
+            out.extend((data.len() as u32).to_be_bytes());
+            out.extend(*data);
+        }
+        out
+    }
+
+    const NOTE: &[u8] = &[
+        0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0, 0x00, 0xff, 0x2f, 0x00,
+    ];
+
+    fn parse(data: &[u8], lenient: bool) -> Result<MidiFile, Box<dyn Error>> {
+        let mut file = MidiFile::create();
+        file.lenient = lenient;
+        file.parse_bytes(data)?;
+        Ok(file)
+    }
+
+    #[test]
+    fn truncated_header_is_an_error() {
+        let data = smf(&[NOTE]);
+        for cut in 0..14 {
+            assert!(parse(&data[..cut], true).is_err());
+        }
+    }
+
+    #[test]
+    fn every_truncation_parses_leniently() {
+        let data = smf(&[NOTE, NOTE]);
+        for cut in 14..data.len() {
+            assert!(parse(&data[..cut], true).is_ok(), "cut at {}", cut);
+        }
+    }
+
+    #[test]
+    fn every_byte_flip_parses_without_panicking() {
+        let data = smf(&[NOTE, NOTE]);
+        for i in 0..data.len() {
+            for value in [0x00, 0x7f, 0x80, 0xf0, 0xf7, 0xff] {
+                let mut flipped = data.clone();
+                flipped[i] = value;
+                let _ = parse(&flipped, true);
+                let _ = parse(&flipped, false);
+            }
+        }
+    }
+
+    #[test]
+    fn missing_track_header_fails_strictly() {
+        let data = smf(&[NOTE, NOTE]);
+        let cut = data.len() - NOTE.len() - 4;
+        assert!(parse(&data[..cut], false).is_err());
+        assert_eq!(parse(&data[..cut], true).unwrap().tracks.len(), 1);
+    }
+
+    #[test]
+    fn trailing_delta_ends_in_unparsed() {
+        let data = smf(&[&[0x00, 0x90, 60, 100, 0x10]]);
+        assert!(parse(&data, false).is_err());
+        let file = parse(&data, true).unwrap();
+        let last = file.tracks[0].events.last().unwrap();
+        assert!(matches!(last.data, EventData::Unparsed { .. }));
+        assert_eq!(file.tracks[0].events.len(), 2);
+    }
+
+    #[test]
+    fn truncated_event_ends_in_unparsed() {
+        let data = smf(&[&[0x00, 0x90, 60]]);
+        assert!(parse(&data, false).is_err());
+        let file = parse(&data, true).unwrap();
+        assert!(matches!(
+            file.tracks[0].events[0].data,
+            EventData::Unparsed { .. }
+        ));
+    }
+
+    #[test]
+    fn track_stops_at_its_chunk() {
+        let data = smf(&[&NOTE[..8], NOTE]);
+        let file = parse(&data, false).unwrap();
+        assert_eq!(file.tracks.len(), 2);
+        assert_eq!(file.tracks[0].events.len(), 2);
+        assert!(!file.tracks[0].end_of_track);
+        assert_eq!(file.tracks[1].events.len(), 3);
+        assert_eq!(file.tracks[1].name, "");
+    }
+
+    #[test]
+    fn truncated_event_keeps_later_tracks() {
+        let data = smf(&[&[0x00, 0x90, 60], NOTE]);
+        let file = parse(&data, true).unwrap();
+        assert_eq!(file.tracks.len(), 2);
+        assert!(file.tracks[1].end_of_track);
+        assert_eq!(file.tracks[1].events.len(), 3);
+    }
+
+    #[test]
+    fn bytes_after_end_of_track_are_skipped() {
+        let mut padded = NOTE.to_vec();
+        padded.extend([0x00, 0x90, 61, 100]);
+        let data = smf(&[&padded, NOTE]);
+        let file = parse(&data, false).unwrap();
+        assert_eq!(file.tracks[0].events.len(), 3);
+        assert_eq!(file.tracks[0].chunk_length, 16);
+        assert_eq!(file.tracks[0].parsed_length, 12);
+        assert_eq!(file.tracks[1].events.len(), 3);
+    }
+
+    fn parse_with(data: &[u8], options: ParseOptions) -> MidiFile {
+        let mut file = MidiFile::create();
+        file.options = options;
+        file.parse_bytes(data).unwrap();
+        file
+    }
+
+    #[test]
+    fn zero_division_falls_back_to_the_default() {
+        let mut data = smf(&[NOTE]);
+        data[12..14].copy_from_slice(&[0, 0]);
+        let options = ParseOptions {
+            default_division: 240,
+            ..ParseOptions::create()
+        };
+        assert_eq!(parse_with(&data, options).division, 240);
+        assert_eq!(parse_with(&smf(&[NOTE]), options).division, 96);
+    }
+
+    #[test]
+    fn missing_tempo_falls_back_to_the_default() {
+        let options = ParseOptions {
+            default_tempo: 400_000,
+            ..ParseOptions::create()
+        };
+        let file = parse_with(&smf(&[NOTE]), options);
+        assert_eq!((file.tempo, file.bpm), (400_000, 150));
+        let file = parse(&smf(&[NOTE]), false).unwrap();
+        assert_eq!((file.tempo, file.bpm), (DEFAULT_TEMPO, 120));
+    }
+
+    #[test]
+    fn tempo_event_overrides_the_default() {
+        let mut track = vec![0x00, 0xff, 0x51, 0x03, 0x07, 0xa1, 0x20];
+        track.extend(NOTE);
+        let options = ParseOptions {
+            default_tempo: 400_000,
+            ..ParseOptions::create()
+        };
+        let file = parse_with(&smf(&[&track]), options);
+        assert_eq!((file.tempo, file.bpm), (500_000, 120));
+    }
+
+    /// Sequencer-specific data and a marker that are not UTF-8
+    const BINARY_METAS: &[u8] = &[
+        0x00, 0xff, 0x7f, 0x05, 0x00, 0x41, 0xff, 0x80, 0xc3, 0x00, 0xff, 0x06, 0x03, b'a', 0xe9,
+        b'b', 0x00, 0xff, 0x2f, 0x00,
+    ];
+
+    #[test]
+    fn binary_metas_keep_their_bytes() {
+        let file = parse(&smf(&[BINARY_METAS]), false).unwrap();
+        let data: Vec<&EventData> = file.tracks[0].events.iter().map(|ev| &ev.data).collect();
+        assert_eq!(
+            data[..2],
+            [
+                &EventData::SysexData {
+                    meta_type: Some(SysExMeta::MetaSequencerSpecific),
+                    meta: MetaData::Bytes(vec![0x00, 0x41, 0xff, 0x80, 0xc3]),
+                },
+                &EventData::SysexData {
+                    meta_type: Some(SysExMeta::MetaMarker),
+                    meta: MetaData::Bytes(vec![b'a', 0xe9, b'b']),
+                },
+            ]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn binary_metas_round_trip() {
+        let data = smf(&[BINARY_METAS]);
+        let file = parse(&data, false).unwrap();
+        assert_eq!(file.to_smf(), data);
+        assert!(MidiFile::from_midicsv(&file.to_midicsv()).unwrap() == file);
+        assert!(MidiFile::from_json(&file.to_json()).unwrap() == file);
+    }
+}
@@ -0,0 +1,370 @@
+use std::{error::Error, fs};
+
+use crate::{
+    grid::Grid,
+    input::{InputMessage, MidiInput},
+    json::{field, number, text, uint, Json},
+    key::{Key, Mode},
+    meter::SignatureMap,
+    parser::{MidiEvent, MidiFile, MidiTrack},
+    script::Script,
+    transform::{ArpPattern, Transform},
+};
+
+const VERSION: u64 = 1;
+
+/// Transforms run one after another, e.g. transpose, humanize, velocity
+/// curve, then scale snap. Saved as JSON so recipes can be shared and kept
+/// under version control.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Pipeline {
+    pub name: String,
+    pub steps: Vec<Transform>,
+}
+
+/// 1 to 16 in JSON, or null for none
+fn drum_channel_to_json(channel: Option<u8>) -> Json {
+    channel.map_or(Json::Null, |c| number(c + 1))
+}
+
+fn drum_channel_from_json(object: &Json) -> Result<Option<u8>, Box<dyn Error>> {
+    match object.get("drum_channel") {
+        None | Some(Json::Null) => Ok(None),
+        Some(_) => match uint(object, "drum_channel", 16)? {
+            0 => Err("Field drum_channel must be from 1 to 16".into()),
+            channel => Ok(Some(channel as u8 - 1)),
+        },
+    }
+}
+
+/// Through the shortest decimal for the f32, so 0.8 is written as 0.8
+fn float_to_json(value: f32) -> Json {
+    Json::Number(value.to_string().parse().unwrap_or(0.0))
+}
+
+fn float(object: &Json, name: &str) -> Result<f32, Box<dyn Error>> {
+    field(object, name)?
+        .as_f64()
+        .map(|n| n as f32)
+        .ok_or_else(|| format!("Field {} must be a number", name).into())
+}
+
+fn step_to_json(step: &Transform) -> Json {
+    let (kind, fields): (&str, Vec<(&str, Json)>) = match step {
+        Transform::Arpeggiate {
+            step,
+            pattern,
+            gate,
+        } => (
+            "arpeggiate",
+            vec![
+                ("step", number(*step)),
+                (
+                    "pattern",
+                    Json::String(
+                        match pattern {
+                            ArpPattern::Up => "up",
+                            ArpPattern::Down => "down",
+                            ArpPattern::UpDown => "up_down",
+                        }
+                        .to_string(),
+                    ),
+                ),
+                ("gate", float_to_json(*gate)),
+            ],
+        ),
+        Transform::Echo {
+            delay,
+            repeats,
+            decay,
+        } => (
+            "echo",
+            vec![
+                ("delay", number(*delay)),
+                ("repeats", number(*repeats)),
+                ("decay", float_to_json(*decay)),
+            ],
+        ),
+        Transform::Humanize {
+            timing,
+            velocity,
+            seed,
+        } => (
+            "humanize",
+            vec![
+                ("timing", number(*timing)),
+                ("velocity", number(*velocity)),
+                // as a string, since JSON numbers lose precision past 2^53
+                ("seed", Json::String(seed.to_string())),
+            ],
+        ),
+        Transform::ScaleSnap { key, drum_channel } => (
+            "scale_snap",
+            vec![
+                ("sharps", number(key.accidentals)),
+                ("minor", Json::Bool(key.mode == Mode::Minor)),
+                ("drum_channel", drum_channel_to_json(*drum_channel)),
+            ],
+        ),
+        Transform::Script(script) => (
+            "script",
+            vec![(
+                "rules",
+                Json::Array(
+                    script
+                        .rules
+                        .iter()
+                        .map(|rule| Json::String(rule.source.clone()))
+                        .collect(),
+                ),
+            )],
+        ),
+        Transform::Swing { grid, ratio } => (
+            "swing",
+            vec![
+                ("grid", Json::String(grid.name())),
+                ("ratio", Json::Number(*ratio)),
+            ],
+        ),
+        Transform::Transpose {
+            semitones,
+            drum_channel,
+        } => (
+            "transpose",
+            vec![
+                ("semitones", number(*semitones)),
+                ("drum_channel", drum_channel_to_json(*drum_channel)),
+            ],
+        ),
+        Transform::VelocityCurve { gamma, min, max } => (
+            "velocity_curve",
+            vec![
+                ("gamma", float_to_json(*gamma)),
+                ("min", number(*min)),
+                ("max", number(*max)),
+            ],
+        ),
+    };
+    let mut object = vec![("type".to_string(), Json::String(kind.to_string()))];
+    object.extend(
+        fields
+            .into_iter()
+            .map(|(name, value)| (name.to_string(), value)),
+    );
+    Json::Object(object)
+}
+
+fn step_from_json(object: &Json) -> Result<Transform, Box<dyn Error>> {
+    let kind = text(object, "type")?;
+    Ok(match kind.as_str() {
+        "arpeggiate" => Transform::Arpeggiate {
+            step: uint(object, "step", u32::MAX as u64)? as u32,
+            pattern: match text(object, "pattern")?.as_str() {
+                "up" => ArpPattern::Up,
+                "down" => ArpPattern::Down,
+                "up_down" => ArpPattern::UpDown,
+                other => return Err(format!("Unknown arpeggio pattern {}", other).into()),
+            },
+            gate: float(object, "gate")?,
+        },
+        "echo" => Transform::Echo {
+            delay: uint(object, "delay", u32::MAX as u64)? as u32,
+            repeats: uint(object, "repeats", u32::MAX as u64)? as u32,
+            decay: float(object, "decay")?,
+        },
+        "humanize" => Transform::Humanize {
+            timing: uint(object, "timing", u32::MAX as u64)? as u32,
+            velocity: uint(object, "velocity", 127)? as u8,
+            seed: text(object, "seed")?
+                .parse()
+                .map_err(|_| "Field seed must be a whole number")?,
+        },
+        "scale_snap" => {
+            let sharps = field(object, "sharps")?
+                .as_i64()
+                .filter(|n| (-7..=7).contains(n))
+                .ok_or("Field sharps must be a whole number from -7 to 7")?;
+            let minor = field(object, "minor")?
+                .as_bool()
+                .ok_or("Field minor must be true or false")?;
+            let mode = if minor { Mode::Minor } else { Mode::Major };
+            Transform::ScaleSnap {
+                key: Key::from_signature(sharps as i8, mode).ok_or("Bad key signature")?,
+                drum_channel: drum_channel_from_json(object)?,
+            }
+        }
+        "script" => {
+            let rules: Vec<&str> = field(object, "rules")?
+                .as_array()
+                .ok_or("Field rules must be an array")?
+                .iter()
+                .map(|rule| rule.as_str().ok_or("Field rules must hold strings"))
+                .collect::<Result<_, _>>()?;
+            Transform::Script(Script::parse(&rules.join("\n"))?)
+        }
+        "swing" => Transform::Swing {
+            grid: Grid::parse(&text(object, "grid")?)
+                .ok_or("Field grid must be a grid such as 1/8")?,
+            ratio: field(object, "ratio")?
+                .as_f64()
+                .ok_or("Field ratio must be a number")?,
+        },
+        "transpose" => Transform::Transpose {
+            semitones: field(object, "semitones")?
+                .as_i64()
+                .filter(|n| (-127..=127).contains(n))
+                .ok_or("Field semitones must be a whole number from -127 to 127")?
+                as i32,
+            drum_channel: drum_channel_from_json(object)?,
+        },
+        "velocity_curve" => Transform::VelocityCurve {
+            gamma: float(object, "gamma")?,
+            min: uint(object, "min", 127)? as u8,
+            max: uint(object, "max", 127)? as u8,
+        },
+        other => return Err(format!("Unknown transform {}", other).into()),
+    })
+}
+
+impl Pipeline {
+    pub fn create(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            steps: vec![],
+        }
+    }
+
+    /// Adds a step to the end
+    pub fn then(mut self, step: Transform) -> Self {
+        self.steps.push(step);
+        self
+    }
+
+    pub fn apply(
+        &self,
+        events: Vec<(u32, MidiEvent)>,
+        map: &SignatureMap,
+    ) -> Vec<(u32, MidiEvent)> {
+        self.steps
+            .iter()
+            .fold(events, |events, step| step.apply(events, map))
+    }
+
+    /// Runs every step over the track's events in place
+    pub fn apply_track(&self, track: &mut MidiTrack, map: &SignatureMap) {
+        let events = track.take_absolute();
+        track.set_absolute(self.apply(events, map));
+    }
+
+    /// Runs every step over every track, in the file's own meter
+    pub fn apply_file(&self, file: &mut MidiFile) {
+        file.load_leniently();
+        let map = file.signature_map();
+        for track in file.tracks.iter_mut() {
+            self.apply_track(track, &map);
+        }
+    }
+
+    /// True when every step can run on live input
+    pub fn is_live(&self) -> bool {
+        self.steps.iter().all(Transform::is_live)
+    }
+
+    /// Runs the live steps on one incoming event, None when a step drops it
+    pub fn apply_live(&self, micros: u64, mut event: MidiEvent) -> Option<MidiEvent> {
+        self.steps
+            .iter()
+            .all(|step| step.apply_live(micros, &mut event))
+            .then_some(event)
+    }
+
+    pub fn to_json(&self) -> String {
+        Json::Object(vec![
+            ("version".to_string(), number(VERSION as f64)),
+            ("name".to_string(), Json::String(self.name.clone())),
+            (
+                "steps".to_string(),
+                Json::Array(self.steps.iter().map(step_to_json).collect()),
+            ),
+        ])
+        .to_string()
+    }
+
+    pub fn from_json(text: &str) -> Result<Self, Box<dyn Error>> {
+        let root = Json::parse(text)?;
+        let version = uint(&root, "version", u64::MAX)?;
+        if version != VERSION {
+            return Err(format!("Unsupported pipeline version {}", version).into());
+        }
+        let steps = field(&root, "steps")?
+            .as_array()
+            .ok_or("Field steps must be an array")?
+            .iter()
+            .map(step_from_json)
+            .collect::<Result<_, _>>()?;
+        Ok(Self {
+            name: root
+                .get("name")
+                .and_then(Json::as_str)
+                .unwrap_or("")
+                .to_string(),
+            steps,
+        })
+    }
+
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.to_json())?;
+        Ok(())
+    }
+
+    pub fn load(path: &str) -> Result<Self, Box<dyn Error>> {
+        Self::from_json(&fs::read_to_string(path)?)
+    }
+}
+
+/// Wraps another input and runs the pipeline's live steps on each message.
+/// Messages that do not decode pass through untouched.
+pub struct Piped<I: MidiInput> {
+    pub input: I,
+    pub pipeline: Pipeline,
+}
+
+impl<I: MidiInput> MidiInput for Piped<I> {
+    fn poll(&mut self) -> Option<InputMessage> {
+        loop {
+            let message = self.input.poll()?;
+            let Ok(event) = message.event() else {
+                return Some(message);
+            };
+            let Some(event) = self.pipeline.apply_live(message.micros, event) else {
+                continue;
+            };
+            if let Some(packed) = event.to_short_message() {
+                return Some(InputMessage {
+                    micros: message.micros,
+                    message: packed,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn swing_round_trips_through_json() {
+        let pipeline = Pipeline::create("feel").then(Transform::Swing {
+            grid: Grid::parse("1/16T").unwrap(),
+            ratio: 62.5,
+        });
+        assert_eq!(Pipeline::from_json(&pipeline.to_json()).unwrap(), pipeline);
+    }
+
+    #[test]
+    fn bad_swing_grid_is_an_error() {
+        let json = r#"{"version": 1, "steps": [{"type": "swing", "grid": "1/0", "ratio": 60}]}"#;
+        assert!(Pipeline::from_json(json).is_err());
+    }
+}
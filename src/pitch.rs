@@ -0,0 +1,55 @@
+use std::{
+    error::Error,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use crate::note::Note;
+
+pub const DEFAULT_REFERENCE: f64 = 440.0;
+/// MIDI key of A4, which sounds at the reference pitch
+pub const REFERENCE_KEY: f64 = 69.0;
+
+/// Bits of the f64 reference pitch, so it can be set from any thread
+static REFERENCE: AtomicU64 = AtomicU64::new(DEFAULT_REFERENCE.to_bits());
+
+/// Frequency of A4 in Hz, which every frequency conversion in the crate
+/// tunes to
+pub fn reference_pitch() -> f64 {
+    f64::from_bits(REFERENCE.load(Ordering::Relaxed))
+}
+
+/// Retunes A4 crate-wide, e.g. to 442 Hz for many orchestras or 415 Hz for
+/// baroque pitch
+pub fn set_reference_pitch(hz: f64) -> Result<(), Box<dyn Error>> {
+    if !hz.is_finite() || hz <= 0.0 {
+        return Err(format!("Reference pitch {} Hz must be above 0", hz).into());
+    }
+    REFERENCE.store(hz.to_bits(), Ordering::Relaxed);
+    Ok(())
+}
+
+/// Equal-tempered frequency of a key, which may be fractional for keys
+/// between semitones
+pub fn key_to_frequency(key: f64) -> f64 {
+    key_to_frequency_with(key, reference_pitch())
+}
+
+pub fn key_to_frequency_with(key: f64, reference: f64) -> f64 {
+    reference * 2f64.powf((key - REFERENCE_KEY) / 12.0)
+}
+
+/// The fractional key sounding at `hz`; its distance from the nearest whole
+/// key times 100 is the detune in cents
+pub fn frequency_to_key(hz: f64) -> f64 {
+    frequency_to_key_with(hz, reference_pitch())
+}
+
+pub fn frequency_to_key_with(hz: f64, reference: f64) -> f64 {
+    REFERENCE_KEY + 12.0 * (hz / reference).log2()
+}
+
+impl Note {
+    pub fn frequency(&self) -> f64 {
+        key_to_frequency(self.key as f64)
+    }
+}
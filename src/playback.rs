@@ -0,0 +1,385 @@
+use std::collections::HashMap;
+
+use crate::{
+    clock::{song_position, song_position_tick, Clock, ClockMaster, VirtualClock},
+    conductor::Conductor,
+    metronome::Metronome,
+    note::with_release_velocity,
+    offset::TrackOffsets,
+    parser::{EventData, MidiEvent, MidiFile},
+    region::{PlaybackState, RegionMap},
+    routing::{Destination, RoutingTable},
+    scheduler::Scheduler,
+    status::StatusType,
+};
+
+#[derive(Debug, Default)]
+pub struct PlayOptions<'a> {
+    /// Act as clock master: send Start, 24 PPQN clock following the tempo map,
+    /// then Stop
+    pub send_clock: bool,
+    /// Where playback begins. With `send_clock` this is rounded down to a
+    /// sixteenth note and announced with Song Position and Continue.
+    pub start_tick: u32,
+    /// Click along with the music, routed as `Source::Metronome`
+    pub metronome: Option<Metronome>,
+    /// Per-track nudges. Playback starts late by the largest negative one so
+    /// early tracks still get their lead.
+    pub offsets: TrackOffsets,
+    /// Tempo and meter to play the notes against instead of the file's own,
+    /// typically from a sync file
+    pub conductor: Option<Conductor>,
+    /// Release velocity for NoteOffs that have none, for synths that
+    /// respond to it. Zero-velocity NoteOns are sent as NoteOffs.
+    pub release_velocity: Option<u8>,
+    /// Repeat and flags the file's region rules are checked against, fresh
+    /// when none is given. Each pass counts one repeat here, so the state
+    /// carries on into the next play.
+    pub state: Option<&'a mut PlaybackState>,
+}
+
+/// A message as playback sent it, or would have
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SentMessage {
+    /// From the start of playback
+    pub micros: u64,
+    /// Index into the output devices
+    pub device: usize,
+    /// Packed short message, status in the low byte
+    pub message: u32,
+}
+
+pub fn song_position_message(position: u16) -> u32 {
+    StatusType::SongPosition as u32
+        | (position as u32 & 0x7f) << 8
+        | (position as u32 >> 7 & 0x7f) << 16
+}
+
+/// The short message for a channel event, moved to `channel` when one is
+/// given and with `release` filled in as its release velocity. `None` for
+/// events that are not sent.
+pub fn routed_message(ev: &MidiEvent, channel: Option<u8>, release: Option<u8>) -> Option<u32> {
+    let message = ev.to_short_message()?;
+    let message = match channel {
+        Some(channel) => message & !0x0f | (channel & 0x0f) as u32,
+        None => message,
+    };
+    Some(match release {
+        Some(velocity) => with_release_velocity(message, velocity),
+        None => message,
+    })
+}
+
+/// Whether `ev` ends a note, which goes out even in a skipped region when
+/// its key is held so nothing struck before it hangs
+pub(crate) fn is_release(ev: &MidiEvent) -> bool {
+    match (ev.status.status_type, &ev.data) {
+        (StatusType::NoteOff, _) => true,
+        (StatusType::NoteOn, EventData::NoteOnOffData { velocity, .. }) => *velocity == 0,
+        _ => false,
+    }
+}
+
+/// Keys struck and not yet released on each device and channel, counted so
+/// overlapping notes on one key each get their release
+#[derive(Debug, Default)]
+struct HeldNotes(HashMap<(usize, u32), u32>);
+
+impl HeldNotes {
+    /// Notes `message` on, or off, and whether it should go out: releases of
+    /// keys that are not held are dropped
+    fn sends(&mut self, device: usize, message: u32) -> bool {
+        let (status, velocity) = (message & 0xf0, message >> 16 & 0x7f);
+        // channel and key
+        let note = (device, message & 0x7f0f);
+        match status {
+            0x90 if velocity > 0 => *self.0.entry(note).or_default() += 1,
+            0x80 | 0x90 => match self.0.get_mut(&note) {
+                Some(count) if *count > 1 => *count -= 1,
+                Some(_) => {
+                    self.0.remove(&note);
+                }
+                None => return false,
+            },
+            _ => {}
+        }
+        true
+    }
+}
+
+fn broadcast<E>(
+    devices: usize,
+    message: u32,
+    send: &mut impl FnMut(usize, u32) -> Result<(), E>,
+) -> Result<(), E> {
+    (0..devices).try_for_each(|device| send(device, message))
+}
+
+/// Runs playback against `clock`, handing each message to `send` with the
+/// index of its device. Messages routed past `devices` are dropped. Stops
+/// at the first error `send` returns, otherwise counts the pass in the
+/// options' playback state.
+pub fn run_schedule<E>(
+    midi: &MidiFile,
+    options: &mut PlayOptions,
+    routing: &RoutingTable,
+    clock: &dyn Clock,
+    devices: usize,
+    mut send: impl FnMut(usize, u32) -> Result<(), E>,
+) -> Result<(), E> {
+    let loaded = midi.loaded();
+    let midi: &MidiFile = &loaded;
+    let regions = RegionMap::from_markers(midi);
+    let mut fresh = PlaybackState::create();
+    let state = options.state.as_deref_mut().unwrap_or(&mut fresh);
+    let conductor = match &options.conductor {
+        Some(conductor) => conductor.for_division(midi.division),
+        None => midi.conductor(),
+    };
+    let tempo_map = conductor.tempo_map;
+    let mut pulses = ClockMaster::create(tempo_map.clone());
+    let position = song_position(options.start_tick, midi.division);
+    let start_tick = match options.send_clock {
+        true => song_position_tick(position, midi.division),
+        false => options.start_tick,
+    };
+    pulses.seek(start_tick);
+    // skipped regions take no time, so everything after one moves earlier
+    let skipped: Vec<(f64, f64)> = regions
+        .skipped_spans(state)
+        .into_iter()
+        .map(|(start, end)| {
+            (
+                tempo_map.micros_at(start as f64),
+                tempo_map.micros_at(end as f64),
+            )
+        })
+        .collect();
+    let is_skipped = |micros: f64| {
+        skipped
+            .iter()
+            .any(|(start, end)| (*start..*end).contains(&micros))
+    };
+    let shifted = |micros: f64| {
+        let gone: f64 = skipped
+            .iter()
+            .map(|(start, end)| micros.clamp(*start, *end) - start)
+            .sum();
+        micros - gone
+    };
+    let offset = shifted(tempo_map.micros_at(start_tick as f64));
+
+    let end = midi.tracks.iter().map(|t| t.end_tick()).max().unwrap_or(0);
+    let clicks = options.metronome.map_or(vec![], |metronome| {
+        metronome.events(&conductor.signature_map, end)
+    });
+    let (tempo, offsets) = (&tempo_map, &options.offsets);
+    let mut events: Vec<(u32, f64, Destination, &MidiEvent)> = midi
+        .tracks
+        .iter()
+        .enumerate()
+        .flat_map(|(i, track)| {
+            track.iter_ticks().map(move |(tick, ev)| {
+                let due = tempo.micros_at(tick as f64) + offsets.micros_at(i, tick, tempo);
+                (tick, due, routing.resolve(i, ev.status.channel()), ev)
+            })
+        })
+        .chain(clicks.iter().map(|(tick, ev)| {
+            let due = tempo_map.micros_at(*tick as f64);
+            (*tick, due, routing.metronome(), ev)
+        }))
+        .filter(|(tick, _, _, _)| *tick >= start_tick)
+        .collect();
+    events.sort_by(|a, b| a.1.total_cmp(&b.1));
+    let lookahead = offsets.lookahead(tempo);
+    let mut scheduler = Scheduler::create(clock);
+    let mut wait_until = |micros: f64| {
+        scheduler.wait_until((shifted(micros) - offset + lookahead).max(0.0) as u64);
+    };
+    let mut held = HeldNotes::default();
+
+    if options.send_clock {
+        if start_tick == 0 {
+            broadcast(devices, StatusType::Start as u32, &mut send)?;
+        } else {
+            broadcast(devices, song_position_message(position), &mut send)?;
+            broadcast(devices, StatusType::Continue as u32, &mut send)?;
+        }
+    }
+    for (tick, due, destination, ev) in events {
+        if let EventData::SysexData { .. } = &ev.data {
+            continue;
+        }
+        if !regions.should_play(tick, state) && !is_release(ev) {
+            continue;
+        }
+        if destination.device >= devices {
+            continue;
+        }
+        let message = match routed_message(ev, destination.channel, options.release_velocity) {
+            Some(message) => message,
+            None => continue,
+        };
+        if !held.sends(destination.device, message) {
+            continue;
+        }
+        if options.send_clock {
+            while pulses.peek() <= due {
+                let pulse = pulses.next_pulse();
+                if is_skipped(pulse) {
+                    continue;
+                }
+                wait_until(pulse);
+                broadcast(devices, StatusType::TimingClock as u32, &mut send)?;
+            }
+        }
+        wait_until(due);
+        send(destination.device, message)?;
+    }
+    if options.send_clock {
+        broadcast(devices, StatusType::Stop as u32, &mut send)?;
+    }
+    state.repeat += 1;
+    Ok(())
+}
+
+/// Runs the whole schedule instantly on a virtual clock, without any
+/// device, and returns everything playback would send and when. Clock
+/// messages go to every device the routing table names.
+pub fn dry_run(
+    midi: &MidiFile,
+    options: &mut PlayOptions,
+    routing: &RoutingTable,
+) -> Vec<SentMessage> {
+    let devices = routing
+        .routes
+        .iter()
+        .map(|(_, destination)| destination.device)
+        .chain([routing.default.device])
+        .max()
+        .unwrap_or(0)
+        + 1;
+    let clock = VirtualClock::create();
+    let mut sent = vec![];
+    let _ = run_schedule(
+        midi,
+        options,
+        routing,
+        &clock,
+        devices,
+        |device, message| {
+            sent.push(SentMessage {
+                micros: clock.now(),
+                device,
+                message,
+            });
+            Ok::<(), ()>(())
+        },
+    );
+    sent
+}
+
+impl MidiFile {
+    /// Everything playing to one device would send, without a device
+    pub fn dry_run(&self, options: &mut PlayOptions) -> Vec<SentMessage> {
+        dry_run(self, options, &RoutingTable::create())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::MidiFileBuilder, parser::SysExMeta};
+
+    /// A note before a region skipped on the first pass and ending inside
+    /// it, then one wholly inside it
+    fn file() -> MidiFile {
+        let mut builder = MidiFileBuilder::create();
+        builder.division(96);
+        builder
+            .add_track()
+            .at(100)
+            .text(SysExMeta::MetaMarker, "region bridge skip@1")
+            .at(300)
+            .text(SysExMeta::MetaMarker, "end bridge")
+            .note(60, 100, 0, 200)
+            .note(62, 100, 150, 30);
+        builder.build()
+    }
+
+    /// (status, key) of every note message sent
+    fn notes(sent: &[SentMessage]) -> Vec<(u32, u32)> {
+        sent.iter()
+            .map(|sent| (sent.message & 0xf0, sent.message >> 8 & 0x7f))
+            .filter(|(status, _)| *status == 0x80 || *status == 0x90)
+            .collect()
+    }
+
+    #[test]
+    fn skipped_region_releases_only_held_notes() {
+        let sent = file().dry_run(&mut PlayOptions::default());
+        assert_eq!(notes(&sent), vec![(0x90, 60), (0x80, 60)]);
+    }
+
+    #[test]
+    fn skipped_region_takes_no_time() {
+        let mut builder = MidiFileBuilder::create();
+        builder.division(96);
+        builder
+            .add_track()
+            .at(100)
+            .text(SysExMeta::MetaMarker, "region bridge skip@1")
+            .at(300)
+            .text(SysExMeta::MetaMarker, "end bridge")
+            .note(60, 100, 0, 200)
+            .note(64, 100, 300, 10);
+        let file = builder.build();
+        let sent = file.dry_run(&mut PlayOptions::default());
+        let bridge = file.conductor().tempo_map.micros_at(100.0) as u64;
+        let times: Vec<u64> = sent.iter().map(|sent| sent.micros).collect();
+        assert_eq!(
+            notes(&sent),
+            vec![(0x90, 60), (0x80, 60), (0x90, 64), (0x80, 64)]
+        );
+        assert_eq!(times[..3], [0, bridge, bridge]);
+    }
+
+    #[test]
+    fn each_pass_counts_a_repeat() {
+        let file = file();
+        let mut state = PlaybackState::create();
+        let mut options = PlayOptions {
+            state: Some(&mut state),
+            ..Default::default()
+        };
+        file.dry_run(&mut options);
+        let sent = file.dry_run(&mut options);
+        assert_eq!(
+            notes(&sent),
+            vec![(0x90, 60), (0x90, 62), (0x80, 62), (0x80, 60)]
+        );
+        assert_eq!(state.repeat, 3);
+    }
+
+    #[test]
+    fn flags_come_from_the_given_state() {
+        let mut builder = MidiFileBuilder::create();
+        builder
+            .add_track()
+            .text(SysExMeta::MetaMarker, "region solo if:solo")
+            .note(60, 100, 0, 96);
+        let file = builder.build();
+        let sent = file.dry_run(&mut PlayOptions::default());
+        assert_eq!(notes(&sent), vec![]);
+        let mut state = PlaybackState::create();
+        state.set_flag("solo");
+        let mut options = PlayOptions {
+            state: Some(&mut state),
+            ..Default::default()
+        };
+        assert_eq!(
+            notes(&file.dry_run(&mut options)),
+            vec![(0x90, 60), (0x80, 60)]
+        );
+    }
+}
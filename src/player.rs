@@ -0,0 +1,623 @@
+use std::{
+    error::Error,
+    sync::{Arc, Mutex},
+    thread::{self, JoinHandle},
+};
+
+use windows::Win32::Media::Audio::{midiOutShortMsg, HMIDIOUT};
+
+use crate::{
+    clock::{Clock, SystemClock},
+    conductor::Conductor,
+    meter::SignatureMap,
+    output::MidiOutput,
+    parser::{EventData, MetaData, MidiEvent, MidiFile, SysExMeta},
+    playback::is_release,
+    profile::{Adaptation, DeviceProfile},
+    region::{PlaybackState, RegionMap},
+    status::StatusType,
+    tempo::{TempoMap, DEFAULT_TEMPO},
+    win::send_sysex,
+    window::StreamState,
+};
+
+/// Longest the playback thread sleeps before checking for commands
+const POLL_MICROS: u64 = 5000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Transport {
+    Stopped,
+    Playing,
+    Paused,
+}
+
+/// What subscribers hear about as playback dispatches it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlaybackEvent {
+    NoteOn {
+        tick: u32,
+        channel: u8,
+        key: u8,
+        velocity: u8,
+    },
+    NoteOff {
+        tick: u32,
+        channel: u8,
+        key: u8,
+        /// Release velocity, 0 for a zero-velocity NoteOn
+        velocity: u8,
+    },
+    ProgramChange {
+        tick: u32,
+        channel: u8,
+        program: u8,
+    },
+    Marker {
+        tick: u32,
+        text: String,
+    },
+    Lyric {
+        tick: u32,
+        text: String,
+    },
+    /// Playback ran off the end of the file, as opposed to being stopped
+    End,
+}
+
+impl PlaybackEvent {
+    pub fn from(tick: u32, ev: &MidiEvent) -> Option<Self> {
+        let channel = ev.status.channel();
+        match (ev.status.status_type, &ev.data) {
+            (StatusType::NoteOn, EventData::NoteOnOffData { key, velocity }) if *velocity > 0 => {
+                Some(Self::NoteOn {
+                    tick,
+                    channel,
+                    key: *key,
+                    velocity: *velocity,
+                })
+            }
+            (
+                StatusType::NoteOn | StatusType::NoteOff,
+                EventData::NoteOnOffData { key, velocity },
+            ) => Some(Self::NoteOff {
+                tick,
+                channel,
+                key: *key,
+                velocity: *velocity,
+            }),
+            (_, EventData::ProgramChangeData { program_id }) => Some(Self::ProgramChange {
+                tick,
+                channel,
+                program: *program_id,
+            }),
+            (
+                _,
+                EventData::SysexData {
+                    meta_type: Some(meta_type),
+                    meta: MetaData::SingleString(text),
+                },
+            ) => match meta_type {
+                SysExMeta::MetaMarker => Some(Self::Marker {
+                    tick,
+                    text: text.clone(),
+                }),
+                SysExMeta::MetaLyrics => Some(Self::Lyric {
+                    tick,
+                    text: text.clone(),
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+}
+
+type Callbacks = Mutex<Vec<Box<dyn FnMut(&PlaybackEvent) + Send>>>;
+
+struct Control {
+    transport: Transport,
+    position: u32,
+    /// Set by `seek`, taken by the playback thread
+    relocate: Option<u32>,
+    speed: f64,
+    muted: Vec<usize>,
+    soloed: Vec<usize>,
+    looped: Option<(u32, u32)>,
+    state: PlaybackState,
+}
+
+impl Control {
+    fn audible(&self, track: usize) -> bool {
+        !self.muted.contains(&track) && (self.soloed.is_empty() || self.soloed.contains(&track))
+    }
+}
+
+/// Plays a file on a background thread. Pausing, seeking and stopping turn
+/// off every note that is sounding, so nothing hangs.
+pub struct Player {
+    pub device: HMIDIOUT,
+    /// Tempo to play against instead of the file's own, from the next `play`
+    pub conductor: Option<Conductor>,
+    midi: Arc<MidiFile>,
+    control: Arc<Mutex<Control>>,
+    callbacks: Arc<Callbacks>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Player {
+    pub fn create(device: HMIDIOUT, mut midi: MidiFile) -> Self {
+        midi.load_leniently();
+        Self {
+            device,
+            conductor: None,
+            midi: Arc::new(midi),
+            control: Arc::new(Mutex::new(Control {
+                transport: Transport::Stopped,
+                position: 0,
+                relocate: None,
+                speed: 1.0,
+                muted: vec![],
+                soloed: vec![],
+                looped: None,
+                state: PlaybackState::create(),
+            })),
+            callbacks: Arc::new(Mutex::new(vec![])),
+            thread: None,
+        }
+    }
+
+    pub fn midi(&self) -> &MidiFile {
+        &self.midi
+    }
+
+    /// Stops, fits the file to `profile` and resets the device into the
+    /// profile's dialect, so the next `play` suits that device
+    pub fn adapt_to(&mut self, profile: &DeviceProfile) -> Result<Adaptation, Box<dyn Error>> {
+        self.stop();
+        let mut midi = (*self.midi).clone();
+        let adaptation = midi.adapt_to(profile)?;
+        self.midi = Arc::new(midi);
+        unsafe { send_sysex(self.device, &profile.dialect.reset())? };
+        Ok(adaptation)
+    }
+
+    /// Calls `callback` from the playback thread as each event is sent.
+    /// Keep it short; slow callbacks delay the events after them.
+    pub fn subscribe(&self, callback: impl FnMut(&PlaybackEvent) + Send + 'static) {
+        self.callbacks.lock().unwrap().push(Box::new(callback));
+    }
+
+    pub fn transport(&self) -> Transport {
+        self.control.lock().unwrap().transport
+    }
+
+    /// The tick of the last event sent, or where a seek put playback. Back to
+    /// zero once the end is reached.
+    pub fn position(&self) -> u32 {
+        self.control.lock().unwrap().position
+    }
+
+    /// Starts from the current position; resumes if paused
+    pub fn play(&mut self) {
+        if self.transport() == Transport::Paused {
+            return self.resume();
+        }
+        self.stop();
+        let position = {
+            let mut control = self.control.lock().unwrap();
+            control.transport = Transport::Playing;
+            control.relocate = None;
+            control.position
+        };
+        let device = self.device;
+        let midi = self.midi.clone();
+        let control = self.control.clone();
+        let callbacks = self.callbacks.clone();
+        let tempo_map = match &self.conductor {
+            Some(conductor) => conductor.for_division(midi.division).tempo_map,
+            None => midi.tempo_map(),
+        };
+        self.thread = Some(thread::spawn(move || {
+            run(device, &midi, &tempo_map, &control, &callbacks, position)
+        }));
+    }
+
+    pub fn pause(&self) {
+        let mut control = self.control.lock().unwrap();
+        if control.transport == Transport::Playing {
+            control.transport = Transport::Paused;
+        }
+    }
+
+    pub fn resume(&self) {
+        let mut control = self.control.lock().unwrap();
+        if control.transport == Transport::Paused {
+            control.transport = Transport::Playing;
+        }
+    }
+
+    /// Stops playback and waits for the thread to finish. Interrupted
+    /// playback ends with a panic so nothing is left sounding. The position
+    /// is kept, so `play` carries on from there.
+    pub fn stop(&mut self) {
+        self.control.lock().unwrap().transport = Transport::Stopped;
+        self.wait();
+    }
+
+    /// Scales the tempo map, 0.5 playing at half speed. Takes effect
+    /// immediately, from wherever playback is.
+    pub fn set_speed(&self, speed: f64) {
+        if speed > 0.0 && speed.is_finite() {
+            self.control.lock().unwrap().speed = speed;
+        }
+    }
+
+    pub fn speed(&self) -> f64 {
+        self.control.lock().unwrap().speed
+    }
+
+    /// Silences the notes of `track`. Controllers and program changes still
+    /// go out, so the part sounds right when unmuted.
+    pub fn mute_track(&self, track: usize) {
+        let mut control = self.control.lock().unwrap();
+        if !control.muted.contains(&track) {
+            control.muted.push(track);
+        }
+    }
+
+    pub fn unmute_track(&self, track: usize) {
+        self.control.lock().unwrap().muted.retain(|t| *t != track);
+    }
+
+    /// While any track is soloed, only soloed tracks that are not muted play
+    pub fn solo_track(&self, track: usize) {
+        let mut control = self.control.lock().unwrap();
+        if !control.soloed.contains(&track) {
+            control.soloed.push(track);
+        }
+    }
+
+    pub fn unsolo_track(&self, track: usize) {
+        self.control.lock().unwrap().soloed.retain(|t| *t != track);
+    }
+
+    /// Jumps back to `start` whenever playback reaches `end`. Each pass
+    /// begins with the programs and controllers in force at `start`, so
+    /// every iteration sounds the same. Playback already past `end` is not
+    /// pulled back.
+    pub fn set_loop(&self, start: u32, end: u32) {
+        self.control.lock().unwrap().looped = (start < end).then_some((start, end));
+    }
+
+    /// Loops from the start of `first` to the end of `last`, counting bars
+    /// from 1 in the conductor's meter if one is set
+    pub fn set_loop_bars(&self, first: u32, last: u32) {
+        let map = self.signature_map();
+        self.set_loop(
+            map.bar_beat_to_tick(first.max(1), 1),
+            map.bar_beat_to_tick(last.max(first).max(1) + 1, 1),
+        );
+    }
+
+    pub fn clear_loop(&self) {
+        self.control.lock().unwrap().looped = None;
+    }
+
+    /// The repeat and flags the file's region rules are checked against.
+    /// Each jump back to the start of the loop counts one more repeat.
+    pub fn playback_state(&self) -> PlaybackState {
+        self.control.lock().unwrap().state.clone()
+    }
+
+    /// Takes effect from the next event, whether playing or not
+    pub fn set_playback_state(&self, state: PlaybackState) {
+        self.control.lock().unwrap().state = state;
+    }
+
+    pub fn set_flag(&self, flag: &str) {
+        self.control.lock().unwrap().state.set_flag(flag);
+    }
+
+    pub fn clear_flag(&self, flag: &str) {
+        self.control.lock().unwrap().state.clear_flag(flag);
+    }
+
+    fn signature_map(&self) -> SignatureMap {
+        match &self.conductor {
+            Some(conductor) => conductor.for_division(self.midi.division).signature_map,
+            None => self.midi.signature_map(),
+        }
+    }
+
+    /// Moves playback to `tick`, whether playing, paused or stopped. The
+    /// programs, controllers and bends in force there are sent again so
+    /// playback sounds as if it had run from the start.
+    pub fn seek(&self, tick: u32) {
+        let mut control = self.control.lock().unwrap();
+        control.position = tick;
+        control.relocate = Some(tick);
+    }
+
+    /// Seeks to a beat of a bar, both counted from 1
+    pub fn seek_to_bar(&self, bar: u32, beat: u32) {
+        self.seek(self.signature_map().bar_beat_to_tick(bar, beat));
+    }
+
+    /// Blocks until playback reaches the end or is stopped
+    pub fn wait(&mut self) {
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Player {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Whether the key `ev` releases is sounding on its track
+fn is_held(state: &StreamState, ev: &MidiEvent) -> bool {
+    match &ev.data {
+        EventData::NoteOnOffData { key, .. } => state.channels[ev.status.channel() as usize]
+            .notes
+            .iter()
+            .any(|(held, _)| held == key),
+        _ => false,
+    }
+}
+
+unsafe fn flush(device: HMIDIOUT, states: &mut [StreamState]) {
+    for state in states.iter_mut() {
+        flush_track(device, state);
+    }
+}
+
+unsafe fn flush_track(device: HMIDIOUT, state: &mut StreamState) {
+    for (channel, channel_state) in state.channels.iter_mut().enumerate() {
+        for (key, _) in channel_state.notes.drain(..) {
+            midiOutShortMsg(
+                device,
+                StatusType::NoteOff as u32 | channel as u32 | (key as u32) << 8,
+            );
+        }
+    }
+}
+
+/// Re-sends the program, controllers, bend and pressure in force at `tick`
+/// on every channel the file uses
+unsafe fn restore(device: HMIDIOUT, events: &[(u32, usize, &MidiEvent)], tick: u32) {
+    let mut used = [false; 16];
+    let mut state = StreamState::create(DEFAULT_TEMPO);
+    for (t, _, ev) in events.iter() {
+        if ev.status.is_channel_message() {
+            used[ev.status.channel() as usize] = true;
+        }
+        if *t < tick {
+            state.apply(ev);
+        }
+    }
+    for (channel, channel_state) in state.channels.iter().enumerate() {
+        if !used[channel] {
+            continue;
+        }
+        let channel = channel as u32;
+        if let Some(program) = channel_state.program {
+            midiOutShortMsg(
+                device,
+                StatusType::ProgramChange as u32 | channel | (program as u32) << 8,
+            );
+        }
+        // 120 and up are channel mode messages, not state
+        for (id, value) in channel_state.controls.iter().enumerate().take(120) {
+            if let Some(value) = value {
+                midiOutShortMsg(
+                    device,
+                    StatusType::CtrlChange as u32
+                        | channel
+                        | (id as u32) << 8
+                        | (*value as u32) << 16,
+                );
+            }
+        }
+        let bend = channel_state.bend;
+        midiOutShortMsg(
+            device,
+            StatusType::PitchBendChange as u32
+                | channel
+                | (bend.least_bytes() as u32) << 8
+                | (bend.most_bytes() as u32) << 16,
+        );
+        midiOutShortMsg(
+            device,
+            StatusType::ChannelAftertouch as u32 | channel | (channel_state.pressure as u32) << 8,
+        );
+    }
+}
+
+fn notify(callbacks: &Callbacks, event: &PlaybackEvent) {
+    for callback in callbacks.lock().unwrap().iter_mut() {
+        callback(event);
+    }
+}
+
+fn run(
+    mut device: HMIDIOUT,
+    midi: &MidiFile,
+    tempo_map: &TempoMap,
+    control: &Mutex<Control>,
+    callbacks: &Callbacks,
+    position: u32,
+) {
+    let mut events: Vec<(u32, usize, &MidiEvent)> = midi
+        .tracks
+        .iter()
+        .enumerate()
+        .flat_map(|(i, track)| track.iter_ticks().map(move |(tick, ev)| (tick, i, ev)))
+        .filter(|(tick, _, ev)| {
+            ev.to_short_message().is_some() || PlaybackEvent::from(*tick, ev).is_some()
+        })
+        .collect();
+    events.sort_by_key(|(tick, _, _)| *tick);
+    let regions = RegionMap::from_markers(midi);
+
+    let clock = SystemClock::create();
+    // Notes sounding per track, so a muted track can be silenced on its own
+    let mut states = vec![StreamState::create(tempo_map.tempo_at(0)); midi.tracks.len()];
+    let mut audible = vec![true; midi.tracks.len()];
+    if position > 0 {
+        unsafe { restore(device, &events, position) };
+    }
+    let mut next = events.partition_point(|(tick, _, _)| *tick < position);
+    let mut origin = (clock.now(), tempo_map.micros_at(position as f64));
+    // The furthest tick playback has got to since it last jumped
+    let mut reached = position;
+    let mut looping;
+    // Song time in microseconds where a pause left off
+    let mut paused: Option<f64> = None;
+    let mut speed = control.lock().unwrap().speed;
+    // Song time reached at `now` on the playback clock
+    let song_micros = |origin: (u64, f64), speed: f64, now: u64| {
+        origin.1 + now.saturating_sub(origin.0) as f64 * speed
+    };
+    loop {
+        {
+            let mut control = control.lock().unwrap();
+            if control.speed != speed {
+                let now = clock.now();
+                if paused.is_none() {
+                    origin = (now, song_micros(origin, speed, now));
+                }
+                speed = control.speed;
+            }
+            for (track, state) in states.iter_mut().enumerate() {
+                audible[track] = control.audible(track);
+                if !audible[track] {
+                    unsafe { flush_track(device, state) };
+                }
+            }
+            if let Some(tick) = control.relocate.take() {
+                unsafe {
+                    flush(device, &mut states);
+                    restore(device, &events, tick);
+                }
+                next = events.partition_point(|(t, _, _)| *t < tick);
+                origin = (clock.now(), tempo_map.micros_at(tick as f64));
+                paused = paused.map(|_| origin.1);
+                reached = tick;
+            }
+            looping = control.looped.filter(|(_, end)| reached < *end);
+            match control.transport {
+                Transport::Stopped => {
+                    unsafe { flush(device, &mut states) };
+                    device.panic();
+                    break;
+                }
+                Transport::Paused if paused.is_none() => {
+                    unsafe { flush(device, &mut states) };
+                    paused = Some(song_micros(origin, speed, clock.now()));
+                }
+                Transport::Playing => {
+                    if let Some(micros) = paused.take() {
+                        origin = (clock.now(), micros);
+                    }
+                }
+                _ => {}
+            }
+            if next >= events.len() && looping.is_none() {
+                control.transport = Transport::Stopped;
+                control.position = 0;
+                drop(control);
+                notify(callbacks, &PlaybackEvent::End);
+                break;
+            }
+        }
+        if paused.is_some() {
+            clock.wait_until(clock.now() + POLL_MICROS);
+            continue;
+        }
+        let jump = looping.filter(|(_, end)| events.get(next).is_none_or(|(t, _, _)| t >= end));
+        let tick = match jump {
+            Some((_, end)) => end,
+            None => events[next].0,
+        };
+        // a skipped region is passed over as soon as it starts, costing no time
+        let skip = match jump {
+            Some(_) => None,
+            None => regions.skipped_span(tick, &control.lock().unwrap().state),
+        };
+        let due_tick = skip.map_or(tick, |(start, _)| start.max(reached));
+        let due =
+            origin.0 + ((tempo_map.micros_at(due_tick as f64) - origin.1) / speed).max(0.0) as u64;
+        let now = clock.now();
+        if now < due {
+            clock.wait_until(now + (due - now).min(POLL_MICROS));
+            continue;
+        }
+        if let Some((start, _)) = jump {
+            unsafe {
+                flush(device, &mut states);
+                restore(device, &events, start);
+            }
+            next = events.partition_point(|(t, _, _)| *t < start);
+            origin = (due, tempo_map.micros_at(start as f64));
+            reached = start;
+            let mut control = control.lock().unwrap();
+            if control.relocate.is_none() {
+                control.position = start;
+            }
+            control.state.repeat += 1;
+            continue;
+        }
+        if let Some((_, end)) = skip {
+            let end = looping.map_or(end, |(_, loop_end)| end.min(loop_end));
+            while let Some(&(_, track, ev)) = events.get(next).filter(|(t, _, _)| *t < end) {
+                next += 1;
+                // only notes struck before the region are released in it
+                if is_release(ev) && is_held(&states[track], ev) {
+                    if let Some(message) = ev.to_short_message() {
+                        unsafe { midiOutShortMsg(device, message) };
+                    }
+                    states[track].apply(ev);
+                }
+            }
+            origin = (due, tempo_map.micros_at(end as f64));
+            reached = end;
+            let mut control = control.lock().unwrap();
+            if control.relocate.is_none() {
+                control.position = end;
+            }
+            continue;
+        }
+        let (_, track, ev) = events[next];
+        reached = tick;
+        next += 1;
+        {
+            let mut control = control.lock().unwrap();
+            if control.relocate.is_none() {
+                control.position = tick;
+            }
+        }
+        // a release whose note never sounded, struck in a skipped region or
+        // while muted
+        if is_release(ev) && !is_held(&states[track], ev) {
+            continue;
+        }
+        let is_note = matches!(
+            ev.status.status_type,
+            StatusType::NoteOn | StatusType::NoteOff | StatusType::PolyphonicAftertouch
+        );
+        if is_note && !audible[track] {
+            continue;
+        }
+        if let Some(message) = ev.to_short_message() {
+            unsafe { midiOutShortMsg(device, message) };
+        }
+        states[track].apply(ev);
+        if let Some(event) = PlaybackEvent::from(tick, ev) {
+            notify(callbacks, &event);
+        }
+    }
+    unsafe { flush(device, &mut states) };
+}
@@ -0,0 +1,280 @@
+use std::error::Error;
+
+use crate::{
+    bend::PitchBend,
+    channels::Reassignment,
+    note::{merge_notes, split_notes, Note},
+    parser::{EventData, MetaData, MidiEvent, MidiFile},
+    status::{Status, StatusType},
+    sysex::{SYSEX_END, SYSEX_START},
+};
+
+const GM_CONTROLLERS: &[u8] = &[1, 6, 7, 10, 11, 38, 64, 100, 101, 121, 123];
+const GM2_CONTROLLERS: &[u8] = &[
+    0, 5, 32, 65, 66, 67, 71, 72, 73, 74, 75, 76, 77, 78, 84, 91, 93, 120, 124, 125, 126, 127,
+];
+/// NRPN selection, used by GS and XG for their own parameters
+const NRPN_CONTROLLERS: &[u8] = &[98, 99];
+
+/// Which SysEx family a device speaks, and so how it is reset
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dialect {
+    Gm,
+    Gm2,
+    Gs,
+    Xg,
+}
+
+impl Dialect {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Gm => "GM",
+            Self::Gm2 => "GM2",
+            Self::Gs => "GS",
+            Self::Xg => "XG",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_uppercase().as_str() {
+            "GM" => Some(Self::Gm),
+            "GM2" => Some(Self::Gm2),
+            "GS" => Some(Self::Gs),
+            "XG" => Some(Self::Xg),
+            _ => None,
+        }
+    }
+
+    /// The system-on message that puts a device in this mode, F0 to F7
+    pub fn reset(self) -> Vec<u8> {
+        let body: &[u8] = match self {
+            Self::Gm => &[0x7e, 0x7f, 0x09, 0x01],
+            Self::Gm2 => &[0x7e, 0x7f, 0x09, 0x03],
+            Self::Gs => &[0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7f, 0x00, 0x41],
+            Self::Xg => &[0x43, 0x10, 0x4c, 0x00, 0x00, 0x7e, 0x00],
+        };
+        let mut bytes = vec![SYSEX_START];
+        bytes.extend_from_slice(body);
+        bytes.push(SYSEX_END);
+        bytes
+    }
+
+    /// Controllers every device of the dialect is required to understand
+    pub fn controllers(self) -> u128 {
+        let lists: &[&[u8]] = match self {
+            Self::Gm => &[GM_CONTROLLERS],
+            Self::Gm2 => &[GM_CONTROLLERS, GM2_CONTROLLERS],
+            Self::Gs | Self::Xg => &[GM_CONTROLLERS, GM2_CONTROLLERS, NRPN_CONTROLLERS],
+        };
+        lists
+            .iter()
+            .flat_map(|list| list.iter())
+            .fold(0, |mask, id| mask | 1 << id)
+    }
+}
+
+/// What a target device can do, so a file can be adapted to it before
+/// playback
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DeviceProfile {
+    pub name: String,
+    /// One bit per channel the device responds on, channel 1 lowest
+    pub channels: u16,
+    /// Most notes it can sound at once
+    pub polyphony: usize,
+    /// One bit per controller number it understands
+    pub controllers: u128,
+    /// Pitch bend range in semitones, which the device cannot change
+    pub bend_range: u8,
+    pub dialect: Dialect,
+}
+
+impl DeviceProfile {
+    /// Everything the dialect requires: all 16 channels, its controllers,
+    /// 24 voices for GM and 32 otherwise, and a 2 semitone bend range
+    pub fn create(name: &str, dialect: Dialect) -> Self {
+        Self {
+            name: name.to_string(),
+            channels: 0xffff,
+            polyphony: match dialect {
+                Dialect::Gm => 24,
+                _ => 32,
+            },
+            controllers: dialect.controllers(),
+            bend_range: 2,
+            dialect,
+        }
+    }
+
+    pub fn supports_channel(&self, channel: u8) -> bool {
+        channel < 16 && self.channels & 1 << channel != 0
+    }
+
+    pub fn supports_controller(&self, control_id: u8) -> bool {
+        control_id < 128 && self.controllers & 1 << control_id != 0
+    }
+
+    /// The dialect's reset as an event for the start of a track
+    pub fn reset_event(&self) -> MidiEvent {
+        MidiEvent {
+            status: Status {
+                status_type: StatusType::SystemMsg,
+                raw_status: SYSEX_START,
+            },
+            data: EventData::SysexData {
+                meta_type: None,
+                meta: MetaData::Bytes(self.dialect.reset()[1..].to_vec()),
+            },
+            delta_tick: 0,
+        }
+    }
+}
+
+/// What `adapt_to` changed
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Adaptation {
+    pub remapped: Vec<Reassignment>,
+    pub dropped_controllers: usize,
+    pub rescaled_bends: usize,
+    /// Notes struck while every voice was busy
+    pub dropped_notes: usize,
+    pub reset_added: bool,
+}
+
+impl MidiFile {
+    /// Fits the file to `profile`: moves channels it lacks onto free ones,
+    /// drops controllers it does not understand, rescales pitch bends to its
+    /// range, drops notes beyond its polyphony and adds its reset at the
+    /// start. Bend ranges set in the file are read from decoded RPN events
+    /// (see `decode_rpn`), otherwise 2 semitones is assumed. Nothing changes
+    /// if there are not enough channels.
+    pub fn adapt_to(&mut self, profile: &DeviceProfile) -> Result<Adaptation, Box<dyn Error>> {
+        self.load_all()?;
+        let mut adaptation = Adaptation::default();
+
+        let used: Vec<Vec<u8>> = self.tracks.iter().map(|t| t.channels()).collect();
+        let mut taken = [false; 16];
+        for channel in used.iter().flatten() {
+            taken[*channel as usize] = true;
+        }
+        let mut map: Vec<(u8, u8)> = vec![];
+        let unsupported: Vec<u8> = (0..16)
+            .filter(|c| taken[*c as usize] && !profile.supports_channel(*c))
+            .collect();
+        for channel in unsupported {
+            let to = (0..16)
+                .find(|c| !taken[*c as usize] && profile.supports_channel(*c))
+                .ok_or_else(|| {
+                    format!(
+                        "No free channel on {} for channel {}",
+                        profile.name,
+                        channel + 1
+                    )
+                })?;
+            taken[to as usize] = true;
+            map.push((channel, to));
+        }
+        for (index, track) in self.tracks.iter_mut().enumerate() {
+            for &(from, to) in map.iter().filter(|(from, _)| used[index].contains(from)) {
+                adaptation.remapped.push(Reassignment {
+                    track: index,
+                    from,
+                    to,
+                });
+            }
+            track.remap_channels(&map);
+        }
+
+        for track in self.tracks.iter_mut() {
+            let mut ranges = [2.0f32; 16];
+            let before = track.events.len();
+            let mut carried = 0;
+            let events = std::mem::take(&mut track.events);
+            for mut event in events {
+                event.delta_tick += carried;
+                carried = 0;
+                let channel = event.status.channel() as usize;
+                let keep = match &mut event.data {
+                    EventData::ControlData { control_id, .. }
+                    | EventData::Control14Data { control_id, .. } => {
+                        profile.supports_controller(*control_id)
+                    }
+                    EventData::RpnData { change } => {
+                        if let Some((semitones, cents)) = change.bend_range() {
+                            ranges[channel] = semitones as f32 + cents as f32 / 100.0;
+                        }
+                        true
+                    }
+                    EventData::PitchBendData { bend } => {
+                        let range = profile.bend_range as f32;
+                        if ranges[channel] != range {
+                            let semitones = bend.semitones(ranges[channel]).clamp(-range, range);
+                            *bend = PitchBend::from_semitones(semitones, range);
+                            adaptation.rescaled_bends += 1;
+                        }
+                        true
+                    }
+                    _ => true,
+                };
+                match keep {
+                    true => track.events.push(event),
+                    false => carried = event.delta_tick,
+                }
+            }
+            if let Some(last) = track.events.last_mut() {
+                last.delta_tick += carried;
+            }
+            adaptation.dropped_controllers += before - track.events.len();
+        }
+
+        // voices are taken first come first served across every track
+        let mut split: Vec<_> = self
+            .tracks
+            .iter_mut()
+            .map(|track| split_notes(track.take_absolute()))
+            .collect();
+        let mut order: Vec<(u32, usize, usize)> = split
+            .iter()
+            .enumerate()
+            .flat_map(|(t, (notes, _))| notes.iter().enumerate().map(move |(i, n)| (n.start, t, i)))
+            .collect();
+        order.sort();
+        let mut sounding: Vec<u32> = vec![];
+        let mut dropped: Vec<Vec<bool>> = split
+            .iter()
+            .map(|(notes, _)| vec![false; notes.len()])
+            .collect();
+        for (start, t, i) in order {
+            sounding.retain(|end| *end > start);
+            if sounding.len() >= profile.polyphony {
+                dropped[t][i] = true;
+                adaptation.dropped_notes += 1;
+            } else {
+                sounding.push(split[t].0[i].end());
+            }
+        }
+        for (t, (track, (notes, others))) in self.tracks.iter_mut().zip(split.drain(..)).enumerate()
+        {
+            let notes: Vec<Note> = notes
+                .into_iter()
+                .enumerate()
+                .filter(|(i, _)| !dropped[t][*i])
+                .map(|(_, note)| note)
+                .collect();
+            track.set_absolute(merge_notes(&notes, others));
+        }
+
+        let reset = profile.reset_event();
+        let has_reset = self
+            .tracks
+            .iter()
+            .any(|track| track.events.iter().any(|e| e.data == reset.data));
+        if !has_reset {
+            if let Some(track) = self.tracks.first_mut() {
+                track.events.insert(0, reset);
+                adaptation.reset_added = true;
+            }
+        }
+        Ok(adaptation)
+    }
+}
@@ -0,0 +1,271 @@
+// pyo3 0.22 macros trip this lint on every PyResult-returning method
+#![allow(clippy::useless_conversion)]
+
+use pyo3::{exceptions::PyValueError, prelude::*};
+
+use crate::{
+    key::Mode,
+    note::pair_notes,
+    parser::{EventData, MidiFile},
+    pipeline::Pipeline,
+    region::{PlaybackState, RegionMap},
+};
+
+fn value_error(e: Box<dyn std::error::Error>) -> PyErr {
+    PyValueError::new_err(e.to_string())
+}
+
+/// (tick, status, data1, data2, text) per event. `data1`/`data2` are the
+/// channel message bytes; meta and sysex events carry their meta type and text.
+type PyEvent = (u32, u8, u16, u16, Option<String>);
+
+/// Column names for `MidiFile.notes()`, e.g.
+/// `pandas.DataFrame(file.notes(), columns=midi_rs.NOTE_COLUMNS)`
+const NOTE_COLUMNS: [&str; 9] = [
+    "track",
+    "channel",
+    "key",
+    "velocity",
+    "release_velocity",
+    "start",
+    "duration",
+    "start_seconds",
+    "duration_seconds",
+];
+
+type PyNote = (usize, u8, u8, u8, u8, u32, u32, f64, f64);
+
+#[pyclass(name = "MidiFile")]
+pub struct PyMidiFile {
+    inner: MidiFile,
+}
+
+impl PyMidiFile {
+    fn track(&self, track: usize) -> PyResult<&crate::parser::MidiTrack> {
+        self.inner
+            .tracks
+            .get(track)
+            .ok_or_else(|| PyValueError::new_err(format!("No track {}", track)))
+    }
+}
+
+#[pymethods]
+impl PyMidiFile {
+    #[new]
+    #[pyo3(signature = (path, decode_rpn = false, pair_controllers = false))]
+    fn new(path: &str, decode_rpn: bool, pair_controllers: bool) -> PyResult<Self> {
+        let mut inner = MidiFile::create();
+        inner.decode_rpn = decode_rpn;
+        inner.pair_controllers = pair_controllers;
+        inner.parse(path).map_err(value_error)?;
+        Ok(Self { inner })
+    }
+
+    #[getter]
+    fn division(&self) -> u16 {
+        self.inner.division
+    }
+
+    #[getter]
+    fn tempo(&self) -> u32 {
+        self.inner.tempo
+    }
+
+    #[getter]
+    fn bpm(&self) -> u32 {
+        self.inner.bpm
+    }
+
+    fn track_count(&self) -> usize {
+        self.inner.tracks.len()
+    }
+
+    fn track_name(&self, track: usize) -> PyResult<String> {
+        Ok(self.track(track)?.name.clone())
+    }
+
+    fn events(&self, track: usize) -> PyResult<Vec<PyEvent>> {
+        Ok(self
+            .track(track)?
+            .iter_ticks()
+            .map(|(tick, event)| {
+                let status = event.status.raw_status;
+                match &event.data {
+                    EventData::NoteOnOffData { key, velocity } => {
+                        (tick, status, *key as u16, *velocity as u16, None)
+                    }
+                    EventData::ControlData {
+                        control_id,
+                        control_value,
+                    } => (
+                        tick,
+                        status,
+                        *control_id as u16,
+                        *control_value as u16,
+                        None,
+                    ),
+                    EventData::Control14Data { control_id, value } => {
+                        (tick, status, *control_id as u16, *value, None)
+                    }
+                    EventData::ProgramChangeData { program_id } => {
+                        (tick, status, *program_id as u16, 0, None)
+                    }
+                    EventData::ChannelData { channel_pressure } => {
+                        (tick, status, *channel_pressure as u16, 0, None)
+                    }
+                    EventData::PitchBendData { bend } => (tick, status, bend.raw(), 0, None),
+                    EventData::RpnData { change } => {
+                        (tick, status, change.parameter, change.value, None)
+                    }
+                    EventData::SysexData { meta_type, meta } => (
+                        tick,
+                        status,
+                        meta_type.map_or(0, |t| t as u16),
+                        0,
+                        Some(format!("{:?}", meta)),
+                    ),
+                    EventData::QuarterFrameData { piece, value } => {
+                        (tick, status, *piece as u16, *value as u16, None)
+                    }
+                    EventData::SongPositionData { position } => (tick, status, *position, 0, None),
+                    EventData::SongSelectData { song } => (tick, status, *song as u16, 0, None),
+                    EventData::NoData => (tick, status, 0, 0, None),
+                    EventData::Unparsed { reason, .. } => {
+                        (tick, status, 0, 0, Some(reason.clone()))
+                    }
+                }
+            })
+            .collect())
+    }
+
+    /// Paired notes of one track, or every track, as rows matching
+    /// `NOTE_COLUMNS`
+    #[pyo3(signature = (track = None))]
+    fn notes(&self, track: Option<usize>) -> PyResult<Vec<PyNote>> {
+        if let Some(track) = track {
+            self.track(track)?;
+        }
+        let map = self.inner.tempo_map();
+        let mut rows = vec![];
+        for (index, t) in self.inner.tracks.iter().enumerate() {
+            if track.is_some_and(|track| track != index) {
+                continue;
+            }
+            for note in pair_notes(t.iter_ticks()) {
+                let start = map.seconds_at(note.start);
+                rows.push((
+                    index,
+                    note.channel,
+                    note.key,
+                    note.velocity,
+                    note.release_velocity,
+                    note.start,
+                    note.duration,
+                    start,
+                    map.seconds_at(note.end()) - start,
+                ));
+            }
+        }
+        Ok(rows)
+    }
+
+    /// Runs a pipeline given as its JSON over every track
+    fn apply_pipeline(&mut self, json: &str) -> PyResult<()> {
+        let pipeline = Pipeline::from_json(json).map_err(value_error)?;
+        pipeline.apply_file(&mut self.inner);
+        Ok(())
+    }
+
+    fn to_json(&self) -> String {
+        self.inner.to_json()
+    }
+
+    fn to_midicsv(&self) -> String {
+        self.inner.to_midicsv()
+    }
+
+    fn save(&self, path: &str) -> PyResult<()> {
+        self.inner.save(path).map_err(value_error)
+    }
+
+    fn transpose(&mut self, track: usize, semitones: i32) -> PyResult<()> {
+        self.track(track)?;
+        self.inner.tracks[track]
+            .transpose_with_drums(semitones, self.inner.drum_channel)
+            .map_err(value_error)
+    }
+
+    fn decode_rpn(&mut self) {
+        for track in self.inner.tracks.iter_mut() {
+            track.decode_rpn();
+        }
+    }
+
+    fn pair_controllers(&mut self) {
+        for track in self.inner.tracks.iter_mut() {
+            track.pair_controllers();
+        }
+    }
+
+    /// (tick, tonic, "major" | "minor") for every key signature
+    fn key_signatures(&self) -> Vec<(u32, String, &'static str)> {
+        self.inner
+            .key_signatures()
+            .into_iter()
+            .map(|(tick, key)| {
+                let mode = match key.mode {
+                    Mode::Major => "major",
+                    Mode::Minor => "minor",
+                };
+                (tick, format!("{:?}", key.tonic), mode)
+            })
+            .collect()
+    }
+
+    fn tick_to_bar_beat(&self, tick: u32) -> (u32, u32, u32) {
+        let position = self.inner.tick_to_bar_beat(tick);
+        (position.bar, position.beat, position.tick)
+    }
+
+    fn bar_beat_to_tick(&self, bar: u32, beat: u32) -> u32 {
+        self.inner.bar_beat_to_tick(bar, beat)
+    }
+
+    /// (name, start, end) for every marker-defined region
+    fn regions(&self) -> Vec<(String, u32, u32)> {
+        RegionMap::from_markers(&self.inner)
+            .regions
+            .into_iter()
+            .map(|r| (r.name, r.start, r.end))
+            .collect()
+    }
+
+    #[pyo3(signature = (tick, repeat = 1, flags = vec![]))]
+    fn should_play(&self, tick: u32, repeat: u32, flags: Vec<String>) -> bool {
+        let mut state = PlaybackState::create();
+        state.repeat = repeat;
+        for flag in flags.iter() {
+            state.set_flag(flag);
+        }
+        RegionMap::from_markers(&self.inner).should_play(tick, &state)
+    }
+}
+
+#[pyfunction]
+fn program_name(program: u8) -> Option<&'static str> {
+    crate::gm::program_name(program)
+}
+
+#[pyfunction]
+fn drum_name(key: u8) -> Option<&'static str> {
+    crate::drum::drum_name(key)
+}
+
+#[pymodule]
+fn midi_rs(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyMidiFile>()?;
+    m.add("NOTE_COLUMNS", NOTE_COLUMNS.to_vec())?;
+    m.add_function(wrap_pyfunction!(program_name, m)?)?;
+    m.add_function(wrap_pyfunction!(drum_name, m)?)?;
+    Ok(())
+}
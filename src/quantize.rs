@@ -0,0 +1,158 @@
+use crate::{
+    grid::Grid,
+    meter::SignatureMap,
+    note::{merge_notes, split_notes},
+    parser::MidiTrack,
+    swing::Swing,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuantizeOptions {
+    pub grid: Grid,
+    /// How far notes move toward their grid line, 0.0 not at all to 1.0
+    /// right onto it
+    pub strength: f64,
+    /// Where the offbeat lines fall, in percent as for `Swing::ratio`; 50
+    /// is straight
+    pub swing: f64,
+    /// Snaps note ends as well as starts; otherwise notes keep their length
+    pub ends: bool,
+}
+
+impl QuantizeOptions {
+    pub fn create(grid: Grid) -> Self {
+        Self {
+            grid,
+            strength: 1.0,
+            swing: 50.0,
+            ends: false,
+        }
+    }
+
+    /// Where `tick` moves to: its nearest grid line, swung, then pulled back
+    /// toward where it was by whatever strength is left
+    pub fn target(&self, tick: u32, map: &SignatureMap) -> u32 {
+        let swing = Swing {
+            ratio: self.swing,
+            ..Swing::straight(self.grid)
+        };
+        let line = swing.apply(self.grid.snap(tick, map), map);
+        let strength = self.strength.clamp(0.0, 1.0);
+        (tick as f64 + (line as f64 - tick as f64) * strength).round() as u32
+    }
+}
+
+impl MidiTrack {
+    /// Moves note starts toward the grid. Each NoteOff moves with its NoteOn,
+    /// or to its own grid line with `ends`, though never onto or before the
+    /// start; other events stay where they are.
+    pub fn quantize(&mut self, options: &QuantizeOptions, map: &SignatureMap) {
+        let (mut notes, others) = split_notes(self.take_absolute());
+        for note in notes.iter_mut() {
+            let start = options.target(note.start, map);
+            let duration = match options.ends {
+                true => options.target(note.end(), map).saturating_sub(start),
+                false => note.duration,
+            };
+            // a note ending where it starts would be released before struck
+            note.duration = match duration {
+                0 => note.duration.max(1),
+                duration => duration,
+            };
+            note.start = start;
+        }
+        self.set_absolute(merge_notes(&notes, others));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        builder::MidiFileBuilder,
+        note::pair_notes,
+        parser::{EventData, MidiEvent, MidiFile},
+        status::StatusType,
+    };
+
+    fn file(notes: &[(u32, u32)]) -> MidiFile {
+        let mut builder = MidiFileBuilder::create();
+        builder.division(480).time_signature(4, 4);
+        let track = builder.add_track();
+        for (i, (start, duration)) in notes.iter().enumerate() {
+            track.note(60 + i as u8, 100, *start, *duration);
+        }
+        builder.build()
+    }
+
+    fn quantized(notes: &[(u32, u32)], options: &QuantizeOptions) -> Vec<(u32, u32)> {
+        let mut file = file(notes);
+        let map = file.signature_map();
+        let track = file.tracks.last_mut().unwrap();
+        track.quantize(options, &map);
+        pair_notes(track.iter_ticks())
+            .iter()
+            .map(|note| (note.start, note.duration))
+            .collect()
+    }
+
+    fn eighths() -> QuantizeOptions {
+        QuantizeOptions::create(Grid::parse("1/8").unwrap())
+    }
+
+    #[test]
+    fn snaps_starts_keeping_lengths() {
+        let notes = [(10, 200), (230, 100), (500, 50)];
+        let expected = vec![(0, 200), (240, 100), (480, 50)];
+        assert_eq!(quantized(&notes, &eighths()), expected);
+    }
+
+    #[test]
+    fn partial_strength_moves_part_way() {
+        let options = QuantizeOptions {
+            strength: 0.5,
+            ..eighths()
+        };
+        assert_eq!(quantized(&[(40, 100)], &options), vec![(20, 100)]);
+    }
+
+    #[test]
+    fn swing_moves_offbeats() {
+        let options = QuantizeOptions {
+            swing: 66.7,
+            ..eighths()
+        };
+        let notes = [(0, 100), (250, 100)];
+        assert_eq!(quantized(&notes, &options), vec![(0, 100), (320, 100)]);
+    }
+
+    #[test]
+    fn ends_snap_but_never_onto_the_start() {
+        let options = QuantizeOptions {
+            ends: true,
+            ..eighths()
+        };
+        let notes = [(10, 200), (490, 20)];
+        assert_eq!(quantized(&notes, &options), vec![(0, 240), (480, 20)]);
+    }
+
+    #[test]
+    fn zero_length_notes_keep_their_order() {
+        let mut file = file(&[]);
+        let map = file.signature_map();
+        let track = file.tracks.last_mut().unwrap();
+        let on = EventData::NoteOnOffData {
+            key: 60,
+            velocity: 100,
+        };
+        let off = EventData::NoteOnOffData {
+            key: 60,
+            velocity: 0,
+        };
+        track.insert_at_tick(250, MidiEvent::channel_message(StatusType::NoteOn, 0, on));
+        track.insert_at_tick(250, MidiEvent::channel_message(StatusType::NoteOff, 0, off));
+        track.quantize(&eighths(), &map);
+        let notes = pair_notes(track.iter_ticks());
+        assert_eq!((notes[0].start, notes[0].duration), (240, 1));
+    }
+}
@@ -0,0 +1,305 @@
+use std::{
+    cell::UnsafeCell,
+    mem::MaybeUninit,
+    sync::{
+        atomic::{AtomicU64, AtomicUsize, Ordering},
+        Arc,
+    },
+};
+
+/// What the producer does when the queue is full
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Keep what is queued and discard the incoming value
+    DropNewest,
+    /// Discard the oldest queued value to make room
+    DropOldest,
+}
+
+struct Slot<T> {
+    sequence: AtomicUsize,
+    value: UnsafeCell<MaybeUninit<T>>,
+}
+
+/// Bounded queue after Vyukov: every slot carries a sequence number telling
+/// producers and consumers whose turn it is, so neither side ever blocks or
+/// allocates, which makes it safe to use from driver callbacks.
+struct Queue<T> {
+    slots: Box<[Slot<T>]>,
+    mask: usize,
+    enqueue: AtomicUsize,
+    dequeue: AtomicUsize,
+    dropped: AtomicU64,
+    policy: OverflowPolicy,
+}
+
+unsafe impl<T: Send> Send for Queue<T> {}
+unsafe impl<T: Send> Sync for Queue<T> {}
+
+impl<T> Queue<T> {
+    fn try_push(&self, value: T) -> Result<(), T> {
+        let mut pos = self.enqueue.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let diff = sequence as isize - pos as isize;
+            if diff == 0 {
+                match self.enqueue.compare_exchange_weak(
+                    pos,
+                    pos + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        unsafe { (*slot.value.get()).write(value) };
+                        slot.sequence.store(pos + 1, Ordering::Release);
+                        return Ok(());
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                return Err(value);
+            } else {
+                pos = self.enqueue.load(Ordering::Relaxed);
+            }
+        }
+    }
+
+    fn try_pop(&self) -> Option<T> {
+        let mut pos = self.dequeue.load(Ordering::Relaxed);
+        loop {
+            let slot = &self.slots[pos & self.mask];
+            let sequence = slot.sequence.load(Ordering::Acquire);
+            let diff = sequence as isize - (pos + 1) as isize;
+            if diff == 0 {
+                match self.dequeue.compare_exchange_weak(
+                    pos,
+                    pos + 1,
+                    Ordering::Relaxed,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => {
+                        let value = unsafe { (*slot.value.get()).assume_init_read() };
+                        slot.sequence.store(pos + self.mask + 1, Ordering::Release);
+                        return Some(value);
+                    }
+                    Err(current) => pos = current,
+                }
+            } else if diff < 0 {
+                return None;
+            } else {
+                pos = self.dequeue.load(Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+impl<T> Drop for Queue<T> {
+    fn drop(&mut self) {
+        while self.try_pop().is_some() {}
+    }
+}
+
+/// Sending half, meant to live in a backend callback
+pub struct Producer<T> {
+    queue: Arc<Queue<T>>,
+}
+
+/// Receiving half, for the user thread
+pub struct Consumer<T> {
+    queue: Arc<Queue<T>>,
+}
+
+/// Creates a queue holding `capacity` values, rounded up to a power of two
+pub fn channel<T: Send>(capacity: usize, policy: OverflowPolicy) -> (Producer<T>, Consumer<T>) {
+    let capacity = capacity.max(2).next_power_of_two();
+    let slots = (0..capacity)
+        .map(|i| Slot {
+            sequence: AtomicUsize::new(i),
+            value: UnsafeCell::new(MaybeUninit::uninit()),
+        })
+        .collect();
+    let queue = Arc::new(Queue {
+        slots,
+        mask: capacity - 1,
+        enqueue: AtomicUsize::new(0),
+        dequeue: AtomicUsize::new(0),
+        dropped: AtomicU64::new(0),
+        policy,
+    });
+    (
+        Producer {
+            queue: queue.clone(),
+        },
+        Consumer { queue },
+    )
+}
+
+impl<T> Producer<T> {
+    /// Queues `value`, applying the overflow policy when full. Returns false
+    /// if `value` itself was dropped.
+    pub fn push(&self, value: T) -> bool {
+        let queue = &self.queue;
+        let mut value = value;
+        loop {
+            match queue.try_push(value) {
+                Ok(()) => return true,
+                Err(rejected) => value = rejected,
+            }
+            match queue.policy {
+                OverflowPolicy::DropNewest => {
+                    queue.dropped.fetch_add(1, Ordering::Relaxed);
+                    return false;
+                }
+                OverflowPolicy::DropOldest => {
+                    if queue.try_pop().is_some() {
+                        queue.dropped.fetch_add(1, Ordering::Relaxed);
+                    }
+                }
+            }
+        }
+    }
+
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+}
+
+impl<T> Consumer<T> {
+    pub fn pop(&self) -> Option<T> {
+        self.queue.try_pop()
+    }
+
+    /// Everything queued right now
+    pub fn drain(&self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(|| self.pop())
+    }
+
+    /// Values lost to overflow since the queue was created
+    pub fn dropped(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    /// Number of queued values; only a snapshot while the producer runs
+    pub fn len(&self) -> usize {
+        let enqueue = self.queue.enqueue.load(Ordering::Acquire);
+        let dequeue = self.queue.dequeue.load(Ordering::Acquire);
+        enqueue.saturating_sub(dequeue)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::atomic::AtomicUsize, thread};
+
+    use super::*;
+
+    #[test]
+    fn values_come_out_in_order() {
+        let (producer, consumer) = channel(8, OverflowPolicy::DropNewest);
+        for i in 0..5 {
+            assert!(producer.push(i));
+        }
+        assert_eq!(consumer.len(), 5);
+        assert_eq!(consumer.drain().collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        assert!(consumer.is_empty());
+    }
+
+    #[test]
+    fn slots_are_reused_past_capacity() {
+        let (producer, consumer) = channel(4, OverflowPolicy::DropNewest);
+        for i in 0..100 {
+            assert!(producer.push(i));
+            assert!(producer.push(i + 1000));
+            assert_eq!(consumer.pop(), Some(i));
+            assert_eq!(consumer.pop(), Some(i + 1000));
+        }
+        assert_eq!(consumer.pop(), None);
+        assert_eq!(consumer.dropped(), 0);
+    }
+
+    #[test]
+    fn drop_newest_keeps_what_is_queued() {
+        let (producer, consumer) = channel(4, OverflowPolicy::DropNewest);
+        let pushed: Vec<bool> = (0..6).map(|i| producer.push(i)).collect();
+        assert_eq!(pushed, vec![true, true, true, true, false, false]);
+        assert_eq!((producer.dropped(), consumer.dropped()), (2, 2));
+        assert_eq!(consumer.drain().collect::<Vec<_>>(), vec![0, 1, 2, 3]);
+    }
+
+    #[test]
+    fn drop_oldest_makes_room() {
+        let (producer, consumer) = channel(4, OverflowPolicy::DropOldest);
+        assert!((0..6).all(|i| producer.push(i)));
+        assert_eq!(consumer.dropped(), 2);
+        assert_eq!(consumer.drain().collect::<Vec<_>>(), vec![2, 3, 4, 5]);
+    }
+
+    /// Counts its drops so leaks and double drops show up
+    struct Counted<'a>(&'a AtomicUsize);
+
+    impl Drop for Counted<'_> {
+        fn drop(&mut self) {
+            self.0.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    #[test]
+    fn every_value_is_dropped_exactly_once() {
+        let drops = AtomicUsize::new(0);
+        {
+            let (producer, consumer) = channel(4, OverflowPolicy::DropOldest);
+            for _ in 0..6 {
+                producer.push(Counted(&drops));
+            }
+            // two pushed out by the policy
+            assert_eq!(drops.load(Ordering::Relaxed), 2);
+            drop(consumer.pop());
+            assert_eq!(drops.load(Ordering::Relaxed), 3);
+        }
+        // the three still queued go with the queue
+        assert_eq!(drops.load(Ordering::Relaxed), 6);
+    }
+
+    #[test]
+    fn rejected_values_are_dropped() {
+        let drops = AtomicUsize::new(0);
+        let (producer, _consumer) = channel(2, OverflowPolicy::DropNewest);
+        for _ in 0..3 {
+            producer.push(Counted(&drops));
+        }
+        assert_eq!(drops.load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn producer_and_consumer_threads_agree() {
+        const COUNT: u64 = 100_000;
+        let (producer, consumer) = channel(64, OverflowPolicy::DropNewest);
+        let sender = thread::spawn(move || {
+            for i in 0..COUNT {
+                while !producer.push(i) {
+                    thread::yield_now();
+                }
+            }
+            producer
+        });
+        let mut expected = 0;
+        while expected < COUNT {
+            match consumer.pop() {
+                Some(value) => {
+                    assert_eq!(value, expected);
+                    expected += 1;
+                }
+                None => thread::yield_now(),
+            }
+        }
+        let producer = sender.join().unwrap();
+        assert!(consumer.is_empty());
+        // every retry after a full queue counts as a drop
+        assert_eq!(producer.dropped(), consumer.dropped());
+    }
+}
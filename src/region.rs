@@ -0,0 +1,207 @@
+use std::{collections::HashSet, error::Error};
+
+use crate::parser::{EventData, MetaData, MidiFile, SysExMeta};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PlaybackRule {
+    SkipOnRepeat(u32),
+    OnlyOnRepeat(u32),
+    OnlyWhenFlag(String),
+    SkipWhenFlag(String),
+}
+
+impl PlaybackRule {
+    /// Parses the marker syntax: `skip@2`, `only@1`, `if:flag`, `unless:flag`
+    pub fn parse(s: &str) -> Option<Self> {
+        if let Some(n) = s.strip_prefix("skip@") {
+            n.parse().ok().map(Self::SkipOnRepeat)
+        } else if let Some(n) = s.strip_prefix("only@") {
+            n.parse().ok().map(Self::OnlyOnRepeat)
+        } else if let Some(flag) = s.strip_prefix("if:") {
+            Some(Self::OnlyWhenFlag(flag.to_string()))
+        } else {
+            s.strip_prefix("unless:")
+                .map(|flag| Self::SkipWhenFlag(flag.to_string()))
+        }
+    }
+
+    pub fn allows(&self, state: &PlaybackState) -> bool {
+        match self {
+            Self::SkipOnRepeat(n) => state.repeat != *n,
+            Self::OnlyOnRepeat(n) => state.repeat == *n,
+            Self::OnlyWhenFlag(flag) => state.flags.contains(flag),
+            Self::SkipWhenFlag(flag) => !state.flags.contains(flag),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct PlaybackState {
+    pub repeat: u32,
+    pub flags: HashSet<String>,
+}
+
+impl PlaybackState {
+    pub fn create() -> Self {
+        Self {
+            repeat: 1,
+            flags: HashSet::new(),
+        }
+    }
+
+    pub fn set_flag(&mut self, flag: &str) {
+        self.flags.insert(flag.to_string());
+    }
+
+    pub fn clear_flag(&mut self, flag: &str) {
+        self.flags.remove(flag);
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub name: String,
+    pub start: u32,
+    pub end: u32,
+    pub rules: Vec<PlaybackRule>,
+}
+
+impl Region {
+    pub fn contains(&self, tick: u32) -> bool {
+        tick >= self.start && tick < self.end
+    }
+
+    pub fn allows(&self, state: &PlaybackState) -> bool {
+        self.rules.iter().all(|rule| rule.allows(state))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RegionMap {
+    pub regions: Vec<Region>,
+}
+
+impl RegionMap {
+    pub fn create() -> Self {
+        Self { regions: vec![] }
+    }
+
+    /// Collects regions from marker events. `region <name> [rules..]` opens a
+    /// region and `end <name>` closes it; unclosed regions run to the end of the file.
+    pub fn from_markers(file: &MidiFile) -> Self {
+        let loaded = file.loaded();
+        let file: &MidiFile = &loaded;
+        let mut map = Self::create();
+        let file_end = file.tracks.iter().map(|t| t.end_tick()).max().unwrap_or(0);
+        let mut open: Vec<usize> = vec![];
+
+        for track in file.tracks.iter() {
+            for (tick, event) in track.iter_ticks() {
+                let text = match &event.data {
+                    EventData::SysexData {
+                        meta_type: Some(SysExMeta::MetaMarker),
+                        meta: MetaData::SingleString(text),
+                    } => text,
+                    _ => continue,
+                };
+                let mut words = text.split_whitespace();
+                match words.next() {
+                    Some("region") => {
+                        let name = match words.next() {
+                            Some(name) => name,
+                            None => continue,
+                        };
+                        let rules = words.filter_map(PlaybackRule::parse).collect();
+                        map.regions.push(Region {
+                            name: name.to_string(),
+                            start: tick,
+                            end: file_end,
+                            rules,
+                        });
+                        open.push(map.regions.len() - 1);
+                    }
+                    Some("end") => {
+                        let name = match words.next() {
+                            Some(name) => name,
+                            None => continue,
+                        };
+                        if let Some(pos) = open.iter().rposition(|&i| map.regions[i].name == name) {
+                            map.regions[open.remove(pos)].end = tick;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+
+        map
+    }
+
+    pub fn add(&mut self, name: &str, start: u32, end: u32) -> &mut Region {
+        self.regions.push(Region {
+            name: name.to_string(),
+            start,
+            end,
+            rules: vec![],
+        });
+        self.regions.last_mut().unwrap()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Region> {
+        self.regions.iter().find(|r| r.name == name)
+    }
+
+    pub fn add_rule(&mut self, name: &str, rule: PlaybackRule) -> Result<(), Box<dyn Error>> {
+        let region = self
+            .regions
+            .iter_mut()
+            .find(|r| r.name == name)
+            .ok_or_else(|| format!("No region named {}", name))?;
+        region.rules.push(rule);
+        Ok(())
+    }
+
+    pub fn should_play(&self, tick: u32, state: &PlaybackState) -> bool {
+        self.regions
+            .iter()
+            .filter(|r| r.contains(tick))
+            .all(|r| r.allows(state))
+    }
+
+    /// The run of skipped ticks around `tick` as (start, end), end
+    /// exclusive, where regions that do not play overlap or touch. None
+    /// when `tick` plays.
+    pub fn skipped_span(&self, tick: u32, state: &PlaybackState) -> Option<(u32, u32)> {
+        let skipping = |tick: u32| {
+            self.regions
+                .iter()
+                .filter(move |r| r.contains(tick) && !r.allows(state))
+        };
+        skipping(tick).next()?;
+        let (mut start, mut end) = (tick, tick);
+        // step back region by region while the tick before is skipped too
+        while let Some(earliest) = skipping(start).map(|r| r.start).min() {
+            start = earliest;
+            if start == 0 || skipping(start - 1).next().is_none() {
+                break;
+            }
+            start -= 1;
+        }
+        while let Some(latest) = skipping(end).map(|r| r.end).max() {
+            end = latest;
+        }
+        Some((start, end))
+    }
+
+    /// Every run of skipped ticks, in order
+    pub fn skipped_spans(&self, state: &PlaybackState) -> Vec<(u32, u32)> {
+        let mut spans: Vec<(u32, u32)> = self
+            .regions
+            .iter()
+            .filter_map(|r| self.skipped_span(r.start, state))
+            .collect();
+        spans.sort();
+        spans.dedup();
+        spans
+    }
+}
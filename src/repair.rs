@@ -0,0 +1,213 @@
+use crate::{
+    parser::{EventData, MidiEvent, MidiFile, MidiTrack},
+    status::{Status, StatusType},
+    validate::Problem,
+};
+
+/// Which kinds of problem `repair` is allowed to fix
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RepairOptions {
+    pub terminate_tracks: bool,
+    pub fix_chunk_lengths: bool,
+    pub close_notes: bool,
+    pub drop_orphan_note_offs: bool,
+    pub mask_data_bytes: bool,
+    pub drop_unparsed: bool,
+}
+
+impl RepairOptions {
+    /// Everything enabled
+    pub fn create() -> Self {
+        Self {
+            terminate_tracks: true,
+            fix_chunk_lengths: true,
+            close_notes: true,
+            drop_orphan_note_offs: true,
+            mask_data_bytes: true,
+            drop_unparsed: true,
+        }
+    }
+
+    fn allows(&self, problem: &Problem) -> bool {
+        match problem {
+            Problem::UnterminatedTrack { .. } => self.terminate_tracks,
+            Problem::ChunkLengthMismatch { .. } => self.fix_chunk_lengths,
+            Problem::UnmatchedNoteOn { .. } => self.close_notes,
+            Problem::UnmatchedNoteOff { .. } => self.drop_orphan_note_offs,
+            Problem::DataByteHighBit { .. } => self.mask_data_bytes,
+            Problem::Unparsed { .. } => self.drop_unparsed,
+        }
+    }
+}
+
+fn mask_data(data: &mut EventData) {
+    match data {
+        EventData::NoteOnOffData { key, velocity } => {
+            *key &= 0x7f;
+            *velocity &= 0x7f;
+        }
+        EventData::ControlData {
+            control_id,
+            control_value,
+        } => {
+            *control_id &= 0x7f;
+            *control_value &= 0x7f;
+        }
+        EventData::ProgramChangeData { program_id } => *program_id &= 0x7f,
+        EventData::ChannelData { channel_pressure } => *channel_pressure &= 0x7f,
+        _ => {}
+    }
+}
+
+fn repair_track(track: &mut MidiTrack, problems: &[&Problem]) {
+    for problem in problems {
+        if let Problem::DataByteHighBit { event, .. } = problem {
+            mask_data(&mut track.events[*event].data);
+        }
+    }
+
+    // removed back to front so the remaining indices stay valid; the delta of
+    // a removed event is handed to the one after it
+    let mut orphans: Vec<usize> = problems
+        .iter()
+        .filter_map(|p| match p {
+            Problem::UnmatchedNoteOff { event, .. } | Problem::Unparsed { event, .. } => {
+                Some(*event)
+            }
+            _ => None,
+        })
+        .collect();
+    orphans.sort_unstable();
+    for &i in orphans.iter().rev() {
+        let removed = track.events.remove(i);
+        if let Some(next) = track.events.get_mut(i) {
+            next.delta_tick += removed.delta_tick;
+        }
+    }
+
+    let unclosed: Vec<(u8, u8)> = problems
+        .iter()
+        .filter_map(|p| match p {
+            Problem::UnmatchedNoteOn { channel, key, .. } => Some((*channel, *key)),
+            _ => None,
+        })
+        .collect();
+    if !unclosed.is_empty() {
+        let end = track.end_tick();
+        let mut events = track.take_absolute();
+        events.extend(unclosed.into_iter().map(|(channel, key)| {
+            (
+                end,
+                MidiEvent {
+                    status: Status::channel_message(StatusType::NoteOff, channel),
+                    data: EventData::NoteOnOffData { key, velocity: 0 },
+                    delta_tick: 0,
+                },
+            )
+        }));
+        track.set_absolute(events);
+    }
+
+    let terminate = problems
+        .iter()
+        .any(|p| matches!(p, Problem::UnterminatedTrack { .. }));
+    if terminate && !track.events.last().is_some_and(|e| e.is_end_of_track()) {
+        track.events.push(MidiEvent::end_of_track(0));
+        track.end_of_track = true;
+    }
+
+    // the header is made to declare the chunk the repaired events make
+    let resize = problems
+        .iter()
+        .any(|p| matches!(p, Problem::ChunkLengthMismatch { .. }));
+    if resize {
+        let length = track.to_chunk().len() as u32 - 8;
+        track.chunk_length = length;
+        track.parsed_length = length;
+    }
+}
+
+/// Fixes the problems `validate` finds that `options` allows, returning the
+/// ones that were repaired
+pub fn repair(file: &mut MidiFile, options: RepairOptions) -> Vec<Problem> {
+    file.load_leniently();
+    let problems: Vec<Problem> = file
+        .validate()
+        .into_iter()
+        .filter(|p| options.allows(p))
+        .collect();
+    for (index, track) in file.tracks.iter_mut().enumerate() {
+        let track_problems: Vec<&Problem> =
+            problems.iter().filter(|p| p.track() == index).collect();
+        if !track_problems.is_empty() {
+            repair_track(track, &track_problems);
+        }
+    }
+    problems
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(data: &[u8]) -> MidiFile {
+        let mut file = MidiFile::create();
+        file.lenient = true;
+        file.parse_bytes(data).unwrap();
+        file
+    }
+
+    fn smf(tracks: &[&[u8]]) -> Vec<u8> {
+        let mut out = b"MThd\0\0\0\x06\0\x01".to_vec();
+        out.extend((tracks.len() as u16).to_be_bytes());
+        out.extend(96u16.to_be_bytes());
+        for data in tracks {
+            out.extend(b"MTrk");
+            out.extend((data.len() as u32).to_be_bytes());
+            out.extend(*data);
+        }
+        out
+    }
+
+    const NOTE: &[u8] = &[
+        0x00, 0x90, 60, 100, 0x60, 0x80, 60, 0, 0x00, 0xff, 0x2f, 0x00,
+    ];
+
+    #[test]
+    fn terminates_a_middle_track() {
+        let mut file = parse(&smf(&[&NOTE[..8], NOTE]));
+        let problems = file.validate();
+        assert_eq!(problems, vec![Problem::UnterminatedTrack { track: 0 }]);
+        assert_eq!(repair(&mut file, RepairOptions::create()), problems);
+        assert!(file.tracks[0].events[2].is_end_of_track());
+        assert!(file.validate().is_empty());
+    }
+
+    #[test]
+    fn resizes_a_chunk_to_its_events() {
+        let mut padded = NOTE.to_vec();
+        padded.extend([0x00, 0x90, 61, 100]);
+        let mut file = parse(&smf(&[&padded]));
+        assert!(matches!(
+            file.validate()[..],
+            [Problem::ChunkLengthMismatch {
+                declared: 16,
+                actual: 12,
+                ..
+            }]
+        ));
+        repair(&mut file, RepairOptions::create());
+        assert_eq!(file.tracks[0].chunk_length, 12);
+        assert!(file.validate().is_empty());
+    }
+
+    #[test]
+    fn truncated_file_comes_back_clean() {
+        let data = smf(&[NOTE, NOTE]);
+        let mut file = parse(&data[..data.len() - 6]);
+        assert!(!file.validate().is_empty());
+        repair(&mut file, RepairOptions::create());
+        assert!(file.validate().is_empty());
+        assert_eq!(file.tracks.len(), 2);
+    }
+}
@@ -0,0 +1,132 @@
+use std::collections::BTreeMap;
+
+use crate::{
+    control::ControlChange,
+    parser::{EventData, MidiEvent, MidiTrack},
+    status::{Status, StatusType},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ResampleOptions {
+    /// Ticks between resampled events, the target rate
+    pub interval: u32,
+    /// How far, in controller steps, the resampled curve may stray from an
+    /// original event before that event is kept as well
+    pub tolerance: u8,
+}
+
+impl ResampleOptions {
+    pub fn create(interval: u32) -> Self {
+        Self {
+            interval,
+            tolerance: 2,
+        }
+    }
+}
+
+/// Switches and messages where a ramp between two values makes no sense
+fn is_continuous(control_id: u8) -> bool {
+    let control = ControlChange::from(control_id);
+    !control.is_channel_mode()
+        && !matches!(
+            control,
+            ControlChange::DataEntry | ControlChange::DataEntryLsb
+        )
+        && !(64..=69).contains(&control_id)
+        && !(96..=101).contains(&control_id)
+}
+
+/// Value of the curve through `points` at `tick`, straight lines between them
+fn value_at(points: &BTreeMap<u32, u8>, tick: u32) -> f32 {
+    let before = points.range(..=tick).next_back();
+    let after = points.range(tick.saturating_add(1)..).next();
+    match (before, after) {
+        (Some((&t0, &v0)), Some((&t1, &v1))) => {
+            v0 as f32 + (v1 as f32 - v0 as f32) * (tick - t0) as f32 / (t1 - t0) as f32
+        }
+        (Some((_, &v)), None) | (None, Some((_, &v))) => v as f32,
+        (None, None) => 0.0,
+    }
+}
+
+/// One controller's events resampled every `interval` ticks between its
+/// first and last event, thinning dense curves and filling in sparse ones.
+/// Original events the samples miss by more than the tolerance are kept,
+/// so peaks survive thinning.
+pub fn resample_curve(points: &[(u32, u8)], options: &ResampleOptions) -> Vec<(u32, u8)> {
+    let (Some(&(first, _)), Some(&(last, _))) = (points.first(), points.last()) else {
+        return vec![];
+    };
+    let interval = options.interval.max(1);
+    let original: BTreeMap<u32, u8> = points.iter().copied().collect();
+    let mut samples: BTreeMap<u32, u8> = (first..last)
+        .step_by(interval as usize)
+        .chain([last])
+        .map(|tick| (tick, value_at(&original, tick).round() as u8))
+        .collect();
+    // checked in order against the samples so far, so the event ending a
+    // kept peak is kept too
+    for (&tick, &value) in original.iter() {
+        if (value_at(&samples, tick) - value as f32).abs() > options.tolerance as f32 {
+            samples.insert(tick, value);
+        }
+    }
+
+    // a controller holds its value, so repeats add nothing
+    let mut curve: Vec<(u32, u8)> = vec![];
+    for (tick, value) in samples {
+        if curve.last().map(|(_, v)| *v) != Some(value) {
+            curve.push((tick, value));
+        }
+    }
+    curve
+}
+
+impl MidiTrack {
+    /// Resamples every continuous controller curve, leaving switches such as
+    /// the sustain pedal, RPN/NRPN data and channel mode messages alone
+    pub fn resample_controllers(&mut self, options: &ResampleOptions) {
+        let mut curves: BTreeMap<(u8, u8), Vec<(u32, u8)>> = BTreeMap::new();
+        let mut others = vec![];
+        for (tick, event) in self.take_absolute() {
+            match event.data {
+                EventData::ControlData {
+                    control_id,
+                    control_value,
+                } if event.status.status_type == StatusType::CtrlChange
+                    && is_continuous(control_id) =>
+                {
+                    let curve = curves
+                        .entry((event.status.channel(), control_id))
+                        .or_default();
+                    // the last value on a tick is the one that sticks
+                    match curve.last_mut() {
+                        Some((t, value)) if *t == tick => *value = control_value,
+                        _ => curve.push((tick, control_value)),
+                    }
+                }
+                _ => others.push((tick, event)),
+            }
+        }
+
+        let mut events = vec![];
+        for ((channel, control_id), points) in curves {
+            for (tick, control_value) in resample_curve(&points, options) {
+                events.push((
+                    tick,
+                    MidiEvent {
+                        status: Status::channel_message(StatusType::CtrlChange, channel),
+                        data: EventData::ControlData {
+                            control_id,
+                            control_value,
+                        },
+                        delta_tick: 0,
+                    },
+                ));
+            }
+        }
+        // controllers land before notes struck on the same tick
+        events.extend(others);
+        self.set_absolute(events);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::{
+    gm::Family,
+    note::pair_notes,
+    parser::{EventData, MidiFile, MidiTrack},
+    status::DRUM_CHANNEL,
+};
+
+/// What part a track plays in the arrangement, as far as can be guessed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TrackRole {
+    Drums,
+    Bass,
+    Chords,
+    Melody,
+    /// No notes at all, such as a conductor track
+    Other,
+}
+
+impl TrackRole {
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Drums => "Drums",
+            Self::Bass => "Bass",
+            Self::Chords => "Chords",
+            Self::Melody => "Melody",
+            Self::Other => "Other",
+        }
+    }
+
+    /// Matches words commonly found in track and instrument names
+    pub fn from_name(name: &str) -> Option<Self> {
+        let name = name.to_lowercase();
+        let has = |words: &[&str]| words.iter().any(|w| name.contains(w));
+        if has(&["drum", "perc", "kit", "kick", "snare", "hat", "cymbal"]) {
+            Some(Self::Drums)
+        } else if has(&["bass"]) {
+            Some(Self::Bass)
+        } else if has(&["chord", "pad", "comp", "rhythm", "harmony"]) {
+            Some(Self::Chords)
+        } else if has(&["melody", "lead", "vocal", "voice", "solo", "theme"]) {
+            Some(Self::Melody)
+        } else {
+            None
+        }
+    }
+
+    pub fn from_family(family: Family) -> Option<Self> {
+        match family {
+            Family::Bass => Some(Self::Bass),
+            Family::SynthPad | Family::Ensemble | Family::Organ => Some(Self::Chords),
+            Family::SynthLead | Family::Reed | Family::Pipe | Family::Brass => Some(Self::Melody),
+            Family::Percussive => Some(Self::Drums),
+            _ => None,
+        }
+    }
+}
+
+/// Keys below this (C3) lean towards bass
+const BASS_CEILING: f64 = 48.0;
+/// Share of notes starting while another sounds above which a part is chordal
+const CHORDAL_SHARE: f64 = 0.5;
+
+impl MidiTrack {
+    /// Tries, in order: the drum channel, track and instrument names, the
+    /// first program's GM family, then polyphony and pitch range
+    pub fn role(&self) -> TrackRole {
+        self.role_with_drums(Some(DRUM_CHANNEL))
+    }
+
+    /// Like `role`, with drums on `drum_channel`, or nowhere when None
+    pub fn role_with_drums(&self, drum_channel: Option<u8>) -> TrackRole {
+        let notes = pair_notes(self.iter_ticks());
+        if notes.is_empty() {
+            return TrackRole::Other;
+        }
+        let drums = notes
+            .iter()
+            .filter(|n| Some(n.channel) == drum_channel)
+            .count();
+        if drums * 2 > notes.len() {
+            return TrackRole::Drums;
+        }
+        if let Some(role) =
+            TrackRole::from_name(&self.name).or_else(|| TrackRole::from_name(&self.instrument))
+        {
+            return role;
+        }
+        let program = self.events.iter().find_map(|e| match e.data {
+            EventData::ProgramChangeData { program_id } => Some(program_id),
+            _ => None,
+        });
+        if let Some(role) = program
+            .and_then(Family::from)
+            .and_then(TrackRole::from_family)
+        {
+            return role;
+        }
+
+        let mut overlapping = 0;
+        for (i, note) in notes.iter().enumerate() {
+            if notes[..i]
+                .iter()
+                .rev()
+                .take(16)
+                .any(|other| other.end() > note.start)
+            {
+                overlapping += 1;
+            }
+        }
+        if overlapping as f64 / notes.len() as f64 > CHORDAL_SHARE {
+            return TrackRole::Chords;
+        }
+        let mean = notes.iter().map(|n| n.key as f64).sum::<f64>() / notes.len() as f64;
+        if mean < BASS_CEILING {
+            TrackRole::Bass
+        } else {
+            TrackRole::Melody
+        }
+    }
+}
+
+impl MidiFile {
+    pub fn roles(&self) -> Vec<TrackRole> {
+        self.loaded()
+            .tracks
+            .iter()
+            .map(|t| t.role_with_drums(self.drum_channel))
+            .collect()
+    }
+}
@@ -0,0 +1,62 @@
+/// What produced an event
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+    Track(usize),
+    Channel(u8),
+    Metronome,
+}
+
+/// Where an event goes: an index into the caller's list of output devices,
+/// and optionally a channel to move it to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Destination {
+    pub device: usize,
+    pub channel: Option<u8>,
+}
+
+impl Destination {
+    pub fn create(device: usize, channel: Option<u8>) -> Self {
+        Self { device, channel }
+    }
+}
+
+/// Routes are looked up most specific first: the metronome, then the track,
+/// then the channel, then the default.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RoutingTable {
+    pub routes: Vec<(Source, Destination)>,
+    pub default: Destination,
+}
+
+impl RoutingTable {
+    /// Everything to device 0 on its own channel
+    pub fn create() -> Self {
+        Self {
+            routes: vec![],
+            default: Destination::create(0, None),
+        }
+    }
+
+    /// Replaces any existing route for `source`
+    pub fn route(&mut self, source: Source, destination: Destination) {
+        self.routes.retain(|(s, _)| *s != source);
+        self.routes.push((source, destination));
+    }
+
+    fn find(&self, source: Source) -> Option<Destination> {
+        self.routes
+            .iter()
+            .find(|(s, _)| *s == source)
+            .map(|(_, d)| *d)
+    }
+
+    pub fn resolve(&self, track: usize, channel: u8) -> Destination {
+        self.find(Source::Track(track))
+            .or_else(|| self.find(Source::Channel(channel)))
+            .unwrap_or(self.default)
+    }
+
+    pub fn metronome(&self) -> Destination {
+        self.find(Source::Metronome).unwrap_or(self.default)
+    }
+}
@@ -0,0 +1,346 @@
+use alloc::{vec, vec::Vec};
+
+use crate::{
+    control::ControlChange,
+    parser::{EventData, MidiEvent, MidiTrack},
+    status::{Status, StatusType},
+};
+
+pub const PITCH_BEND_SENSITIVITY: u16 = 0x0000;
+pub const FINE_TUNING: u16 = 0x0001;
+pub const COARSE_TUNING: u16 = 0x0002;
+pub const MPE_CONFIGURATION: u16 = 0x0006;
+pub const NULL_PARAMETER: u16 = 0x3FFF;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ParameterKind {
+    Registered,
+    NonRegistered,
+}
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RpnChange {
+    pub kind: ParameterKind,
+    pub parameter: u16,
+    pub value: u16,
+}
+
+impl RpnChange {
+    /// Control changes that select the parameter and write its 14-bit value
+    pub fn to_controls(&self) -> [(u8, u8); 4] {
+        let (msb, lsb) = match self.kind {
+            ParameterKind::Registered => (ControlChange::RpnMsb, ControlChange::RpnLsb),
+            ParameterKind::NonRegistered => (ControlChange::NrpnMsb, ControlChange::NrpnLsb),
+        };
+        [
+            (msb.id(), (self.parameter >> 7) as u8 & 0x7f),
+            (lsb.id(), self.parameter as u8 & 0x7f),
+            (
+                ControlChange::DataEntry.id(),
+                (self.value >> 7) as u8 & 0x7f,
+            ),
+            (ControlChange::DataEntryLsb.id(), self.value as u8 & 0x7f),
+        ]
+    }
+
+    /// Semitones and cents when this change sets the pitch-bend range
+    pub fn bend_range(&self) -> Option<(u8, u8)> {
+        if self.kind == ParameterKind::Registered && self.parameter == PITCH_BEND_SENSITIVITY {
+            Some(((self.value >> 7) as u8, self.value as u8 & 0x7f))
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RpnInput {
+    Passthrough,
+    Consumed,
+    Change(RpnChange),
+}
+
+#[derive(Debug, Clone, Copy, Default)]
+struct ChannelState {
+    registered: bool,
+    parameter_msb: Option<u8>,
+    parameter_lsb: Option<u8>,
+    value: u16,
+}
+
+impl ChannelState {
+    fn selected(&self) -> Option<(ParameterKind, u16)> {
+        let parameter = (self.parameter_msb? as u16) << 7 | self.parameter_lsb? as u16;
+        if parameter == NULL_PARAMETER {
+            return None;
+        }
+        let kind = if self.registered {
+            ParameterKind::Registered
+        } else {
+            ParameterKind::NonRegistered
+        };
+        Some((kind, parameter))
+    }
+}
+
+pub struct RpnAssembler {
+    channels: [ChannelState; 16],
+}
+
+impl RpnAssembler {
+    pub fn create() -> Self {
+        Self {
+            channels: [ChannelState::default(); 16],
+        }
+    }
+
+    pub fn feed(&mut self, channel: u8, control_id: u8, control_value: u8) -> RpnInput {
+        let state = &mut self.channels[channel as usize & 0x0f];
+        let select = |state: &mut ChannelState, registered: bool| {
+            if state.registered != registered {
+                state.parameter_msb = None;
+                state.parameter_lsb = None;
+                state.registered = registered;
+            }
+            state.value = 0;
+        };
+
+        match ControlChange::from(control_id) {
+            ControlChange::RpnMsb | ControlChange::NrpnMsb => {
+                select(state, control_id == ControlChange::RpnMsb.id());
+                state.parameter_msb = Some(control_value);
+                RpnInput::Consumed
+            }
+            ControlChange::RpnLsb | ControlChange::NrpnLsb => {
+                select(state, control_id == ControlChange::RpnLsb.id());
+                state.parameter_lsb = Some(control_value);
+                RpnInput::Consumed
+            }
+            ControlChange::DataEntry
+            | ControlChange::DataEntryLsb
+            | ControlChange::DataIncrement
+            | ControlChange::DataDecrement => {
+                let (kind, parameter) = match state.selected() {
+                    Some(selected) => selected,
+                    None => return RpnInput::Passthrough,
+                };
+                state.value = match ControlChange::from(control_id) {
+                    ControlChange::DataEntry => (control_value as u16) << 7,
+                    ControlChange::DataEntryLsb => state.value & !0x7f | control_value as u16,
+                    ControlChange::DataIncrement => (state.value + 1).min(0x3FFF),
+                    _ => state.value.saturating_sub(1),
+                };
+                RpnInput::Change(RpnChange {
+                    kind,
+                    parameter,
+                    value: state.value,
+                })
+            }
+            _ => RpnInput::Passthrough,
+        }
+    }
+
+    /// Feeds a packed short message as delivered by the input backends
+    pub fn feed_short_message(&mut self, message: u32) -> RpnInput {
+        let status = (message & 0xff) as u8;
+        if status & 0xf0 != StatusType::CtrlChange as u8 {
+            return RpnInput::Passthrough;
+        }
+        self.feed(
+            status & 0x0f,
+            (message >> 8 & 0x7f) as u8,
+            (message >> 16 & 0x7f) as u8,
+        )
+    }
+}
+
+impl MidiTrack {
+    /// Collapses parameter-select and data-entry controller runs into single
+    /// `RpnData` events. A data-entry LSB that follows its MSB on the same tick
+    /// updates the event emitted for the MSB instead of adding another one.
+    /// A select no data entry follows, such as the null-parameter reset,
+    /// stays as its controllers.
+    pub fn decode_rpn(&mut self) {
+        let mut assembler = RpnAssembler::create();
+        let mut events: Vec<(u32, MidiEvent)> = vec![];
+        let mut last_change: [Option<usize>; 16] = [None; 16];
+        // selects waiting to see whether data entry follows
+        let mut pending: Vec<(u32, MidiEvent)> = vec![];
+
+        for (tick, event) in self.take_absolute() {
+            let channel = event.status.channel();
+            let input = match event.data {
+                EventData::ControlData {
+                    control_id,
+                    control_value,
+                } if event.status.status_type == StatusType::CtrlChange => {
+                    assembler.feed(channel, control_id, control_value)
+                }
+                _ => RpnInput::Passthrough,
+            };
+
+            match input {
+                RpnInput::Passthrough => {
+                    events.append(&mut pending);
+                    events.push((tick, event));
+                }
+                RpnInput::Consumed => pending.push((tick, event)),
+                RpnInput::Change(change) => {
+                    pending.retain(|(_, select)| select.status.channel() != channel);
+                    let previous = last_change[channel as usize].filter(|&i| {
+                        matches!(
+                            events[i],
+                            (t, MidiEvent { data: EventData::RpnData { change: prev }, .. })
+                                if t == tick && prev.parameter == change.parameter && prev.kind == change.kind
+                        )
+                    });
+                    let data = EventData::RpnData { change };
+                    match previous {
+                        Some(i) => events[i].1.data = data,
+                        None => {
+                            last_change[channel as usize] = Some(events.len());
+                            events.push((
+                                tick,
+                                MidiEvent {
+                                    status: Status::channel_message(
+                                        StatusType::CtrlChange,
+                                        channel,
+                                    ),
+                                    data,
+                                    delta_tick: 0,
+                                },
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        events.append(&mut pending);
+        self.set_absolute(events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{builder::MidiFileBuilder, parser::MidiFile};
+
+    /// Bend range set to 2 semitones, then the null-parameter reset
+    fn file() -> MidiFile {
+        let mut builder = MidiFileBuilder::create();
+        builder
+            .add_track()
+            .control(101, 0)
+            .control(100, 0)
+            .control(6, 2)
+            .control(38, 0)
+            .at(10)
+            .control(101, 127)
+            .control(100, 127);
+        builder.build()
+    }
+
+    #[test]
+    fn null_parameter_select_is_kept() {
+        let mut file = file();
+        file.tracks[0].decode_rpn();
+        let data: Vec<&EventData> = file.tracks[0].events.iter().map(|ev| &ev.data).collect();
+        assert_eq!(
+            data[..3],
+            [
+                &EventData::RpnData {
+                    change: RpnChange {
+                        kind: ParameterKind::Registered,
+                        parameter: PITCH_BEND_SENSITIVITY,
+                        value: 2 << 7,
+                    },
+                },
+                &EventData::ControlData {
+                    control_id: 101,
+                    control_value: 127,
+                },
+                &EventData::ControlData {
+                    control_id: 100,
+                    control_value: 127,
+                },
+            ]
+        );
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn null_parameter_select_round_trips() {
+        let original = file();
+        let mut decoded = file();
+        decoded.tracks[0].decode_rpn();
+        assert_eq!(decoded.to_smf(), original.to_smf());
+    }
+
+    #[test]
+    fn controls_match_the_spec() {
+        // bend range of 12 semitones: 101 0, 100 0, 6 12, 38 0
+        let bend = RpnChange {
+            kind: ParameterKind::Registered,
+            parameter: PITCH_BEND_SENSITIVITY,
+            value: 12 << 7,
+        };
+        assert_eq!(bend.to_controls(), [(101, 0), (100, 0), (6, 12), (38, 0)]);
+        assert_eq!(bend.bend_range(), Some((12, 0)));
+        // fine tuning centred at 0x2000
+        let tuning = RpnChange {
+            kind: ParameterKind::Registered,
+            parameter: FINE_TUNING,
+            value: 0x2000,
+        };
+        assert_eq!(
+            tuning.to_controls(),
+            [(101, 0), (100, 1), (6, 0x40), (38, 0)]
+        );
+        let nrpn = RpnChange {
+            kind: ParameterKind::NonRegistered,
+            parameter: 0x0123,
+            value: 0x3fff,
+        };
+        assert_eq!(
+            nrpn.to_controls(),
+            [(99, 0x02), (98, 0x23), (6, 0x7f), (38, 0x7f)]
+        );
+    }
+
+    #[test]
+    fn assembler_reads_spec_messages() {
+        let mut assembler = RpnAssembler::create();
+        for message in [0x0065b0, 0x0064b0] {
+            assert_eq!(assembler.feed_short_message(message), RpnInput::Consumed);
+        }
+        let change = |value| {
+            RpnInput::Change(RpnChange {
+                kind: ParameterKind::Registered,
+                parameter: PITCH_BEND_SENSITIVITY,
+                value,
+            })
+        };
+        assert_eq!(assembler.feed_short_message(0x0c06b0), change(12 << 7));
+        assert_eq!(assembler.feed_short_message(0x3226b0), change(12 << 7 | 50));
+        assert_eq!(assembler.feed_short_message(0x0060b0), change(12 << 7 | 51));
+        // other channels and messages pass through
+        assert_eq!(
+            assembler.feed_short_message(0x0c06b1),
+            RpnInput::Passthrough
+        );
+        assert_eq!(
+            assembler.feed_short_message(0x643c90),
+            RpnInput::Passthrough
+        );
+        // after the null parameter data entry is an ordinary controller again
+        assembler.feed_short_message(0x7f65b0);
+        assembler.feed_short_message(0x7f64b0);
+        assert_eq!(
+            assembler.feed_short_message(0x0c06b0),
+            RpnInput::Passthrough
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use crate::clock::Clock;
+
+/// How long before a deadline the scheduler stops sleeping and spins
+pub const DEFAULT_SPIN_MICROS: u64 = 2_000;
+
+/// Waits for absolute deadlines measured from when the scheduler was
+/// created, so sleep overshoot on one event never pushes back the next.
+/// Sleeps until shortly before each deadline and spins the remainder.
+pub struct Scheduler<'a> {
+    clock: &'a dyn Clock,
+    start: u64,
+    pub spin_micros: u64,
+    /// Worst lateness seen so far, in microseconds
+    pub max_late: u64,
+}
+
+impl<'a> Scheduler<'a> {
+    pub fn create(clock: &'a dyn Clock) -> Self {
+        Self {
+            start: clock.now(),
+            clock,
+            spin_micros: DEFAULT_SPIN_MICROS,
+            max_late: 0,
+        }
+    }
+
+    /// Time since the scheduler started
+    pub fn elapsed(&self) -> u64 {
+        self.clock.now().saturating_sub(self.start)
+    }
+
+    /// Blocks until `micros` after the start and returns how late it woke
+    pub fn wait_until(&mut self, micros: u64) -> u64 {
+        let target = self.start + micros;
+        if target > self.clock.now() + self.spin_micros {
+            self.clock.wait_until(target - self.spin_micros);
+        }
+        self.clock.spin_until(target);
+        let late = self.clock.now().saturating_sub(target);
+        self.max_late = self.max_late.max(late);
+        late
+    }
+}
@@ -0,0 +1,312 @@
+use std::{error::Error, str::FromStr};
+
+use crate::{
+    builder::{MidiFileBuilder, TrackBuilder},
+    chord::ChordSymbol,
+    meter::TimeSignature,
+    parser::{MidiFile, DEFAULT_DIVISION},
+};
+
+const DEFAULT_VELOCITY: u8 = 90;
+
+fn tempo(text: &str) -> Result<f64, Box<dyn Error>> {
+    match text.parse::<f64>() {
+        Ok(bpm) if bpm > 0.0 && bpm.is_finite() => Ok(bpm),
+        _ => Err(format!("Bad tempo {}", text).into()),
+    }
+}
+
+fn time_signature(text: &str) -> Result<TimeSignature, Box<dyn Error>> {
+    let bad = || format!("Bad time signature {}", text);
+    let (numerator, denominator) = text.split_once('/').ok_or_else(bad)?;
+    let numerator: u8 = numerator.parse().map_err(|_| bad())?;
+    let denominator: u8 = denominator.parse().map_err(|_| bad())?;
+    if numerator == 0 || !denominator.is_power_of_two() {
+        return Err(bad().into());
+    }
+    Ok(TimeSignature::create(numerator, denominator))
+}
+
+fn number<T: FromStr + PartialOrd>(text: &str, what: &str, max: T) -> Result<T, Box<dyn Error>> {
+    match text.parse::<T>() {
+        Ok(value) if value <= max => Ok(value),
+        _ => Err(format!("Bad {} {}", what, text).into()),
+    }
+}
+
+/// A key such as "C4", "F#3" or "Bb-1", middle C being C4
+fn pitch(text: &str) -> Result<u8, Box<dyn Error>> {
+    let bad = || format!("Bad pitch {}", text);
+    let mut chars = text.chars();
+    let class: i32 = match chars.next().map(|c| c.to_ascii_uppercase()) {
+        Some('C') => 0,
+        Some('D') => 2,
+        Some('E') => 4,
+        Some('F') => 5,
+        Some('G') => 7,
+        Some('A') => 9,
+        Some('B') => 11,
+        _ => return Err(bad().into()),
+    };
+    let rest = &text[1..];
+    let octave_at = rest.find(|c: char| c == '-' || c.is_ascii_digit());
+    let (accidentals, octave) = rest.split_at(octave_at.ok_or_else(bad)?);
+    let alteration = accidentals.chars().try_fold(0, |sum, c| match c {
+        '#' => Ok(sum + 1),
+        'b' => Ok(sum - 1),
+        _ => Err(bad()),
+    })?;
+    let octave: i32 = octave.parse().map_err(|_| bad())?;
+    let key = (octave + 1) * 12 + class + alteration;
+    u8::try_from(key)
+        .ok()
+        .filter(|key| *key <= 127)
+        .ok_or_else(|| format!("Pitch {} is out of range", text).into())
+}
+
+/// Ticks of a duration such as "4" for a quarter or "8." for a dotted eighth
+fn duration(text: &str, division: u16) -> Result<u32, Box<dyn Error>> {
+    let bad = || format!("Bad duration {}", text);
+    let dots = text.len() - text.trim_end_matches('.').len();
+    let value: u32 = text[..text.len() - dots].parse().map_err(|_| bad())?;
+    if value == 0 || !value.is_power_of_two() {
+        return Err(bad().into());
+    }
+    let base = division as u32 * 4 / value;
+    Ok((0..=dots).map(|dot| base >> dot).sum())
+}
+
+/// Splits a note list at whitespace, keeping bracketed chords whole
+fn tokens(line: &str) -> Vec<&str> {
+    let mut tokens = vec![];
+    let mut start = None;
+    let mut depth = 0;
+    for (i, c) in line.char_indices() {
+        match c {
+            '[' | '{' => depth += 1,
+            ']' | '}' => depth -= 1,
+            _ => {}
+        }
+        match (c.is_whitespace() && depth == 0, start) {
+            (true, Some(s)) => {
+                tokens.push(&line[s..i]);
+                start = None;
+            }
+            (false, None) => start = Some(i),
+            _ => {}
+        }
+    }
+    if let Some(s) = start {
+        tokens.push(&line[s..]);
+    }
+    tokens
+}
+
+/// Where a track of the score has got to
+struct ScoreTrack {
+    name: String,
+    velocity: u8,
+    position: u32,
+    length: u32,
+    signature: TimeSignature,
+    bar_start: u32,
+    bar: usize,
+}
+
+impl ScoreTrack {
+    /// Plays one entry of a note list: a pitch, a rest "r", a chord of
+    /// pitches "[C4 E4 G4]" or a chord symbol "{Am7}", each optionally
+    /// followed by ":" and a duration
+    fn play(
+        &mut self,
+        builder: &mut TrackBuilder,
+        token: &str,
+        division: u16,
+    ) -> Result<(), Box<dyn Error>> {
+        let (sound, length) = match token.rsplit_once(':') {
+            Some((sound, length)) => (sound, Some(length)),
+            None => (token, None),
+        };
+        if let Some(length) = length {
+            self.length = duration(length, division)?;
+        }
+        let keys = if let Some(chord) = sound.strip_prefix('[').and_then(|s| s.strip_suffix(']')) {
+            chord
+                .split_whitespace()
+                .map(pitch)
+                .collect::<Result<_, _>>()?
+        } else if let Some(symbol) = sound.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            ChordSymbol::parse(symbol)
+                .ok_or_else(|| format!("Unknown chord symbol {}", symbol))?
+                .voicing()
+        } else if sound == "r" {
+            vec![]
+        } else {
+            vec![pitch(sound)?]
+        };
+        builder.notes(&keys, self.velocity, self.position, self.length);
+        self.position += self.length;
+        builder.end_at(self.position);
+        Ok(())
+    }
+
+    /// Closes the bar, filling a short one with rest
+    fn bar_line(
+        &mut self,
+        builder: &mut TrackBuilder,
+        division: u16,
+    ) -> Result<(), Box<dyn Error>> {
+        let end = self.bar_start + self.signature.ticks_per_bar(division);
+        if self.position > end {
+            return Err(format!(
+                "Bar {} of {} is {} ticks too long",
+                self.bar + 1,
+                self.name,
+                self.position - end
+            )
+            .into());
+        }
+        self.position = end;
+        self.bar_start = end;
+        builder.end_at(end);
+        self.bar += 1;
+        Ok(())
+    }
+}
+
+/// A text score part way through compiling
+struct Score {
+    file: MidiFileBuilder,
+    division: u16,
+    signature: TimeSignature,
+    tracks: Vec<ScoreTrack>,
+}
+
+impl Score {
+    fn directive(&mut self, word: &str, rest: &str) -> Result<(), Box<dyn Error>> {
+        match word {
+            "tempo" => {
+                self.file.tempo(tempo(rest)?);
+            }
+            "time" => {
+                self.signature = time_signature(rest)?;
+                let TimeSignature {
+                    numerator,
+                    denominator,
+                    ..
+                } = self.signature;
+                self.file.time_signature(numerator, denominator);
+            }
+            "division" if self.tracks.is_empty() => {
+                self.division = number(rest, "division", 0x7fffu16)?.max(1);
+                self.file.division(self.division);
+            }
+            "division" => return Err("The division must be set before any track".into()),
+            "track" => {
+                // channel 10 is left to drums unless asked for
+                let channel = (0..16)
+                    .filter(|c| *c != 9)
+                    .nth(self.tracks.len())
+                    .unwrap_or(0);
+                let builder = self.file.add_track();
+                builder.channel(channel);
+                if !rest.is_empty() {
+                    builder.name(rest);
+                }
+                self.tracks.push(ScoreTrack {
+                    name: rest.to_string(),
+                    velocity: DEFAULT_VELOCITY,
+                    position: 0,
+                    length: self.division as u32,
+                    signature: self.signature,
+                    bar_start: 0,
+                    bar: 0,
+                });
+            }
+            word => return Err(format!("Unknown directive {}", word).into()),
+        }
+        Ok(())
+    }
+
+    fn track_line(&mut self, line: &str, word: &str, rest: &str) -> Result<(), Box<dyn Error>> {
+        let (Some(track), Some(builder)) = (self.tracks.last_mut(), self.file.tracks().last_mut())
+        else {
+            return Err("Indented line outside a track".into());
+        };
+        builder.at(track.position);
+        match word {
+            "tempo" => {
+                self.file.conductor().at(track.position).tempo(tempo(rest)?);
+            }
+            "time" => {
+                track.signature = time_signature(rest)?;
+                let TimeSignature {
+                    numerator,
+                    denominator,
+                    ..
+                } = track.signature;
+                self.file
+                    .conductor()
+                    .at(track.position)
+                    .time_signature(numerator, denominator);
+            }
+            "channel" => match number(rest, "channel", 16u8)? {
+                0 => return Err("Channels are numbered from 1".into()),
+                channel => {
+                    builder.channel(channel - 1);
+                }
+            },
+            "program" => match number(rest, "program", 128u8)? {
+                0 => return Err("Programs are numbered from 1".into()),
+                program => {
+                    builder.program(program - 1);
+                }
+            },
+            "velocity" => track.velocity = number(rest, "velocity", 127u8)?.max(1),
+            _ => {
+                for token in tokens(line) {
+                    match token {
+                        "|" => track.bar_line(builder, self.division)?,
+                        token => track.play(builder, token, self.division)?,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl MidiFile {
+    /// Composes a file from a text score. Top-level lines set the `tempo`
+    /// in BPM, the `time` signature and the `division`, or start a `track`
+    /// with a name; the track's indented lines set its `channel` (1-16),
+    /// `program` (1-128) and `velocity` or list notes such as
+    /// `C4:4 E4 G4 | [C4 E4 G4]:2 {Am}:2 | r:1 |`. A note's duration, given
+    /// after ":" as 1, 2, 4, 8... with optional dots, carries over to the
+    /// notes after it. "|" ends a bar, padding a short one with rest.
+    /// Tempo and time changes inside a track go into the first track, which
+    /// holds the conductor events. Lines starting with "#" are comments.
+    pub fn from_score(text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut score = Score {
+            file: MidiFileBuilder::create(),
+            division: DEFAULT_DIVISION,
+            signature: TimeSignature::create(4, 4),
+            tracks: vec![],
+        };
+        for (index, line) in text.lines().enumerate() {
+            let indented = line.starts_with(char::is_whitespace);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let (word, rest) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+            let result = match indented {
+                true => score.track_line(line, word, rest.trim()),
+                false => score.directive(word, rest.trim()),
+            };
+            result.map_err(|e| format!("Line {}: {}", index + 1, e))?;
+        }
+
+        Ok(score.file.build())
+    }
+}
@@ -0,0 +1,556 @@
+use std::error::Error;
+
+use crate::{
+    bend::PitchBend,
+    parser::{EventData, MidiEvent},
+    status::Status,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Tick,
+    Channel,
+    Key,
+    Velocity,
+    Control,
+    Value,
+    Program,
+    Pressure,
+    Bend,
+}
+
+impl Field {
+    fn from(name: &str) -> Option<Self> {
+        match name {
+            "tick" => Some(Self::Tick),
+            "channel" => Some(Self::Channel),
+            "key" => Some(Self::Key),
+            "velocity" => Some(Self::Velocity),
+            "control" => Some(Self::Control),
+            "value" => Some(Self::Value),
+            "program" => Some(Self::Program),
+            "pressure" => Some(Self::Pressure),
+            "bend" => Some(Self::Bend),
+            _ => None,
+        }
+    }
+
+    fn get(self, tick: u32, event: &MidiEvent) -> Option<f64> {
+        let value = match (self, &event.data) {
+            (Self::Tick, _) => tick,
+            (Self::Channel, _) if event.status.is_channel_message() => {
+                event.status.channel() as u32
+            }
+            (Self::Key, EventData::NoteOnOffData { key, .. }) => *key as u32,
+            (Self::Velocity, EventData::NoteOnOffData { velocity, .. }) => *velocity as u32,
+            (Self::Control, EventData::ControlData { control_id, .. })
+            | (Self::Control, EventData::Control14Data { control_id, .. }) => *control_id as u32,
+            (Self::Value, EventData::ControlData { control_value, .. }) => *control_value as u32,
+            (Self::Value, EventData::Control14Data { value, .. }) => *value as u32,
+            (Self::Program, EventData::ProgramChangeData { program_id }) => *program_id as u32,
+            (Self::Pressure, EventData::ChannelData { channel_pressure }) => {
+                *channel_pressure as u32
+            }
+            (Self::Bend, EventData::PitchBendData { bend }) => bend.raw() as u32,
+            _ => return None,
+        };
+        Some(value as f64)
+    }
+
+    fn set(self, tick: &mut u32, event: &mut MidiEvent, value: f64) -> bool {
+        let byte = value.round().clamp(0.0, 127.0) as u8;
+        match (self, &mut event.data) {
+            (Self::Tick, _) => *tick = value.round().max(0.0) as u32,
+            (Self::Channel, _) if event.status.is_channel_message() => {
+                event.status = Status::channel_message(event.status.status_type, byte.min(15));
+            }
+            (Self::Key, EventData::NoteOnOffData { key, .. }) => *key = byte,
+            (Self::Velocity, EventData::NoteOnOffData { velocity, .. }) => *velocity = byte,
+            (Self::Control, EventData::ControlData { control_id, .. }) => *control_id = byte,
+            (Self::Value, EventData::ControlData { control_value, .. }) => *control_value = byte,
+            (Self::Value, EventData::Control14Data { value: v, .. }) => {
+                *v = value.round().clamp(0.0, 16383.0) as u16
+            }
+            (Self::Program, EventData::ProgramChangeData { program_id }) => *program_id = byte,
+            (Self::Pressure, EventData::ChannelData { channel_pressure }) => {
+                *channel_pressure = byte
+            }
+            (Self::Bend, EventData::PitchBendData { bend }) => {
+                *bend = PitchBend::from_raw(value.round().clamp(0.0, 16383.0) as u16)
+            }
+            _ => return false,
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Number(f64),
+    Field(Field),
+    Not(Box<Expr>),
+    Negate(Box<Expr>),
+    Binary(Box<Expr>, &'static str, Box<Expr>),
+}
+
+impl Expr {
+    /// `None` when the expression reads a field the event doesn't have
+    fn eval(&self, tick: u32, event: &MidiEvent) -> Option<f64> {
+        let truth = |b: bool| if b { 1.0 } else { 0.0 };
+        match self {
+            Self::Number(n) => Some(*n),
+            Self::Field(field) => field.get(tick, event),
+            Self::Not(e) => Some(truth(e.eval(tick, event)? == 0.0)),
+            Self::Negate(e) => Some(-e.eval(tick, event)?),
+            Self::Binary(l, "&&", r) => Some(truth(
+                l.eval(tick, event)? != 0.0 && r.eval(tick, event)? != 0.0,
+            )),
+            Self::Binary(l, "||", r) => {
+                let left = l.eval(tick, event).unwrap_or(0.0) != 0.0;
+                Some(truth(left || r.eval(tick, event).unwrap_or(0.0) != 0.0))
+            }
+            Self::Binary(l, op, r) => {
+                let (a, b) = (l.eval(tick, event)?, r.eval(tick, event)?);
+                Some(match *op {
+                    "==" => truth(a == b),
+                    "!=" => truth(a != b),
+                    "<" => truth(a < b),
+                    "<=" => truth(a <= b),
+                    ">" => truth(a > b),
+                    ">=" => truth(a >= b),
+                    "+" => a + b,
+                    "-" => a - b,
+                    "*" => a * b,
+                    "/" if b != 0.0 => a / b,
+                    "%" if b != 0.0 => a % b,
+                    _ => return None,
+                })
+            }
+        }
+    }
+}
+
+const OPERATORS: [&str; 19] = [
+    "&&", "||", "==", "!=", "<=", ">=", "+=", "-=", "*=", "/=", "->", "<", ">", "+", "-", "*", "/",
+    "%", "=",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Number(f64),
+    Ident(String),
+    Op(&'static str),
+    Open,
+    Close,
+    Bang,
+    Semicolon,
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+    let mut tokens = vec![];
+    let mut rest = s.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if c.is_ascii_digit() || c == '.' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Number(rest[..len].parse()?));
+            rest = &rest[len..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let len = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..len].to_string()));
+            rest = &rest[len..];
+        } else if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(op));
+            rest = &rest[op.len()..];
+        } else {
+            tokens.push(match c {
+                '(' => Token::Open,
+                ')' => Token::Close,
+                '!' => Token::Bang,
+                ';' | ',' => Token::Semicolon,
+                _ => return Err(format!("Unexpected character '{}' in script", c).into()),
+            });
+            rest = &rest[c.len_utf8()..];
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+struct ExprParser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl ExprParser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn eat_op(&mut self, ops: &[&'static str]) -> Option<&'static str> {
+        match self.peek() {
+            Some(Token::Op(op)) if ops.contains(op) => {
+                let op = *op;
+                self.pos += 1;
+                Some(op)
+            }
+            _ => None,
+        }
+    }
+
+    fn binary(
+        &mut self,
+        ops: &[&'static str],
+        next: fn(&mut Self) -> Result<Expr, Box<dyn Error>>,
+    ) -> Result<Expr, Box<dyn Error>> {
+        let mut left = next(self)?;
+        while let Some(op) = self.eat_op(ops) {
+            left = Expr::Binary(Box::new(left), op, Box::new(next(self)?));
+        }
+        Ok(left)
+    }
+
+    fn or(&mut self) -> Result<Expr, Box<dyn Error>> {
+        self.binary(&["||"], Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr, Box<dyn Error>> {
+        self.binary(&["&&"], Self::compare)
+    }
+
+    fn compare(&mut self) -> Result<Expr, Box<dyn Error>> {
+        self.binary(&["==", "!=", "<", "<=", ">", ">="], Self::sum)
+    }
+
+    fn sum(&mut self) -> Result<Expr, Box<dyn Error>> {
+        self.binary(&["+", "-"], Self::product)
+    }
+
+    fn product(&mut self) -> Result<Expr, Box<dyn Error>> {
+        self.binary(&["*", "/", "%"], Self::unary)
+    }
+
+    fn unary(&mut self) -> Result<Expr, Box<dyn Error>> {
+        match self.peek() {
+            Some(Token::Bang) => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            }
+            Some(Token::Op("-")) => {
+                self.pos += 1;
+                Ok(Expr::Negate(Box::new(self.unary()?)))
+            }
+            _ => self.atom(),
+        }
+    }
+
+    fn atom(&mut self) -> Result<Expr, Box<dyn Error>> {
+        match self.next() {
+            Some(Token::Number(n)) => Ok(Expr::Number(n)),
+            Some(Token::Ident(name)) => Field::from(&name)
+                .map(Expr::Field)
+                .ok_or_else(|| format!("Unknown field '{}'", name).into()),
+            Some(Token::Open) => {
+                let expr = self.or()?;
+                match self.next() {
+                    Some(Token::Close) => Ok(expr),
+                    _ => Err("Expected ')'".into()),
+                }
+            }
+            token => Err(format!("Unexpected token {:?}", token).into()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Action {
+    Assign(Field, &'static str, Expr),
+    Delete,
+}
+
+/// One `filter -> action; action` line, e.g.
+/// `channel == 9 && key >= 35 -> velocity *= 0.8`. Scripts can only read and
+/// write event fields, so they are safe to load from untrusted config files.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rule {
+    pub source: String,
+    filter: Option<Expr>,
+    actions: Vec<Action>,
+}
+
+impl Rule {
+    pub fn parse(source: &str) -> Result<Self, Box<dyn Error>> {
+        let tokens = tokenize(source)?;
+        let arrow = tokens.iter().position(|t| *t == Token::Op("->"));
+        let (filter_tokens, action_tokens) = match arrow {
+            Some(i) => (tokens[..i].to_vec(), tokens[i + 1..].to_vec()),
+            None => (vec![], tokens),
+        };
+
+        let filter = if filter_tokens.is_empty() {
+            None
+        } else {
+            let mut parser = ExprParser {
+                tokens: filter_tokens,
+                pos: 0,
+            };
+            let expr = parser.or()?;
+            if parser.pos < parser.tokens.len() {
+                return Err("Trailing tokens after filter".into());
+            }
+            Some(expr)
+        };
+
+        let mut actions = vec![];
+        for action in action_tokens.split(|t| *t == Token::Semicolon) {
+            match action {
+                [] => continue,
+                [Token::Ident(name)] if name == "delete" => actions.push(Action::Delete),
+                [Token::Ident(name), Token::Op(op), rest @ ..]
+                    if ["=", "+=", "-=", "*=", "/="].contains(op) =>
+                {
+                    let field =
+                        Field::from(name).ok_or_else(|| format!("Unknown field '{}'", name))?;
+                    let mut parser = ExprParser {
+                        tokens: rest.to_vec(),
+                        pos: 0,
+                    };
+                    let expr = parser.or()?;
+                    if parser.pos < parser.tokens.len() {
+                        return Err("Trailing tokens after action".into());
+                    }
+                    actions.push(Action::Assign(field, op, expr));
+                }
+                _ => return Err(format!("Invalid action in '{}'", source).into()),
+            }
+        }
+        if actions.is_empty() {
+            return Err(format!("Rule '{}' has no actions", source).into());
+        }
+
+        Ok(Self {
+            source: source.to_string(),
+            filter,
+            actions,
+        })
+    }
+
+    pub fn matches(&self, tick: u32, event: &MidiEvent) -> bool {
+        match &self.filter {
+            Some(filter) => filter.eval(tick, event).is_some_and(|v| v != 0.0),
+            None => true,
+        }
+    }
+
+    /// Returns `false` when the event should be dropped
+    pub fn apply_event(&self, tick: &mut u32, event: &mut MidiEvent) -> bool {
+        if !self.matches(*tick, event) {
+            return true;
+        }
+        for action in self.actions.iter() {
+            match action {
+                Action::Delete => return false,
+                Action::Assign(field, op, expr) => {
+                    let (current, rhs) = match (field.get(*tick, event), expr.eval(*tick, event)) {
+                        (Some(current), Some(rhs)) => (current, rhs),
+                        _ => continue,
+                    };
+                    let value = match *op {
+                        "+=" => current + rhs,
+                        "-=" => current - rhs,
+                        "*=" => current * rhs,
+                        "/=" if rhs != 0.0 => current / rhs,
+                        "/=" => current,
+                        _ => rhs,
+                    };
+                    field.set(tick, event, value);
+                }
+            }
+        }
+        true
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Script {
+    pub rules: Vec<Rule>,
+}
+
+impl Script {
+    /// One rule per line; blank lines and lines starting with `#` are skipped
+    pub fn parse(text: &str) -> Result<Self, Box<dyn Error>> {
+        let rules = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(Rule::parse)
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(Self { rules })
+    }
+
+    pub fn apply(&self, events: Vec<(u32, MidiEvent)>) -> Vec<(u32, MidiEvent)> {
+        let mut events: Vec<(u32, MidiEvent)> = events
+            .into_iter()
+            .filter_map(|(mut tick, mut event)| {
+                for rule in self.rules.iter() {
+                    if !rule.apply_event(&mut tick, &mut event) {
+                        return None;
+                    }
+                }
+                Some((tick, event))
+            })
+            .collect();
+        events.sort_by_key(|(tick, _)| *tick);
+        events
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::status::StatusType;
+
+    fn event(status_type: StatusType, channel: u8, data: EventData) -> MidiEvent {
+        MidiEvent {
+            status: Status::channel_message(status_type, channel),
+            data,
+            delta_tick: 0,
+        }
+    }
+
+    fn note(channel: u8, key: u8, velocity: u8) -> MidiEvent {
+        event(
+            StatusType::NoteOn,
+            channel,
+            EventData::NoteOnOffData { key, velocity },
+        )
+    }
+
+    fn control(control_id: u8, control_value: u8) -> MidiEvent {
+        event(
+            StatusType::CtrlChange,
+            0,
+            EventData::ControlData {
+                control_id,
+                control_value,
+            },
+        )
+    }
+
+    #[test]
+    fn filter_selects_drum_notes() {
+        let rule = Rule::parse("channel == 9 && key >= 35 -> velocity *= 0.5").unwrap();
+        assert!(rule.matches(0, &note(9, 36, 100)));
+        assert!(!rule.matches(0, &note(9, 34, 100)));
+        assert!(!rule.matches(0, &note(0, 36, 100)));
+
+        let (mut tick, mut ev) = (0, note(9, 36, 100));
+        assert!(rule.apply_event(&mut tick, &mut ev));
+        assert_eq!(ev, note(9, 36, 50));
+    }
+
+    #[test]
+    fn operators_follow_precedence() {
+        let rule = Rule::parse("1 + 2 * 3 == 7 && !(key % 2) -> key = -(-key) + 1").unwrap();
+        let (mut tick, mut ev) = (0, note(0, 60, 100));
+        assert!(rule.apply_event(&mut tick, &mut ev));
+        assert_eq!(ev, note(0, 61, 100));
+        assert!(!rule.matches(0, &note(0, 61, 100)));
+    }
+
+    #[test]
+    fn missing_fields_do_not_match() {
+        let rule = Rule::parse("velocity > 0 -> delete").unwrap();
+        assert!(!rule.matches(0, &control(7, 100)));
+        // `||` treats an unreadable side as false
+        let rule = Rule::parse("velocity > 0 || control == 7 -> delete").unwrap();
+        assert!(rule.matches(0, &control(7, 100)));
+    }
+
+    #[test]
+    fn assignments_clamp_to_field_range() {
+        let rule = Rule::parse("velocity += 100; key -= 200; channel = 20").unwrap();
+        let (mut tick, mut ev) = (0, note(0, 60, 100));
+        rule.apply_event(&mut tick, &mut ev);
+        assert_eq!(
+            ev.data,
+            EventData::NoteOnOffData {
+                key: 0,
+                velocity: 127
+            }
+        );
+        assert_eq!(ev.status.channel(), 15);
+
+        let rule = Rule::parse("bend = bend + 10000").unwrap();
+        let mut ev = event(
+            StatusType::PitchBendChange,
+            0,
+            EventData::PitchBendData {
+                bend: PitchBend::from_raw(8192),
+            },
+        );
+        rule.apply_event(&mut tick, &mut ev);
+        assert_eq!(
+            ev.data,
+            EventData::PitchBendData {
+                bend: PitchBend::from_raw(16383)
+            }
+        );
+    }
+
+    #[test]
+    fn division_by_zero_keeps_value() {
+        let rule = Rule::parse("velocity /= 0").unwrap();
+        let (mut tick, mut ev) = (0, note(0, 60, 100));
+        rule.apply_event(&mut tick, &mut ev);
+        assert_eq!(ev, note(0, 60, 100));
+    }
+
+    #[test]
+    fn script_deletes_and_reorders() {
+        let script = Script::parse(
+            "# drop sustain, push notes later\n\
+             \n\
+             control == 64 -> delete\n\
+             key -> tick += 100\n",
+        )
+        .unwrap();
+        assert_eq!(script.rules.len(), 2);
+        let events = vec![
+            (0, note(0, 60, 100)),
+            (10, control(64, 127)),
+            (50, control(7, 90)),
+        ];
+        assert_eq!(
+            script.apply(events),
+            vec![(50, control(7, 90)), (100, note(0, 60, 100))]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_rules() {
+        for source in [
+            "key == 60",
+            "key == 60 ->",
+            "pitch == 60 -> delete",
+            "key == 60 -> loudness = 1",
+            "(key == 60 -> delete",
+            "key == 60 60 -> delete",
+            "key == 60 -> velocity = 1 2",
+            "key == 60 -> velocity",
+            "key == $ -> delete",
+        ] {
+            assert!(Rule::parse(source).is_err(), "{}", source);
+        }
+        assert!(Script::parse("delete\nkey ==").is_err());
+    }
+}
@@ -0,0 +1,170 @@
+use std::error::Error;
+
+use crate::{
+    note::{merge_notes, split_notes, Note},
+    parser::{MidiFile, MidiTrack},
+};
+
+/// The tracks a group edit touched, as they were before it, so the edit can
+/// be taken back as a whole
+#[derive(Debug, Clone)]
+pub struct Transaction {
+    pub label: String,
+    before: Vec<(usize, MidiTrack)>,
+}
+
+impl Transaction {
+    /// True when the edit changed nothing
+    pub fn is_empty(&self) -> bool {
+        self.before.is_empty()
+    }
+
+    pub fn undo(self, file: &mut MidiFile) {
+        for (index, track) in self.before {
+            if let Some(slot) = file.tracks.get_mut(index) {
+                *slot = track;
+            }
+        }
+    }
+}
+
+/// Notes picked out by track, time and pitch, the way a piano roll
+/// selection works
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Selection {
+    /// Track indices; empty selects every track
+    pub tracks: Vec<usize>,
+    /// Notes starting from `start` up to but not including `end`
+    pub start: u32,
+    pub end: u32,
+    /// Lowest and highest key, inclusive
+    pub low: u8,
+    pub high: u8,
+}
+
+impl Selection {
+    /// Everything
+    pub fn create() -> Self {
+        Self {
+            tracks: vec![],
+            start: 0,
+            end: u32::MAX,
+            low: 0,
+            high: 127,
+        }
+    }
+
+    pub fn has_track(&self, track: usize) -> bool {
+        self.tracks.is_empty() || self.tracks.contains(&track)
+    }
+
+    pub fn contains(&self, track: usize, note: &Note) -> bool {
+        self.has_track(track)
+            && (self.start..self.end).contains(&note.start)
+            && (self.low..=self.high).contains(&note.key)
+    }
+
+    /// The selected notes with their track indices
+    pub fn notes(&self, file: &MidiFile) -> Vec<(usize, Note)> {
+        let mut selected = vec![];
+        for (index, track) in file.loaded().tracks.iter().enumerate() {
+            if !self.has_track(index) {
+                continue;
+            }
+            let events = track.iter_ticks().map(|(t, e)| (t, e.clone())).collect();
+            let (notes, _) = split_notes(events);
+            selected.extend(
+                notes
+                    .into_iter()
+                    .filter(|note| self.contains(index, note))
+                    .map(|note| (index, note)),
+            );
+        }
+        selected
+    }
+
+    /// Runs `edit` on every selected note, dropping those it returns false
+    /// for, and records the tracks it changed
+    fn edit(
+        &self,
+        file: &mut MidiFile,
+        label: &str,
+        mut edit: impl FnMut(&mut Note) -> bool,
+    ) -> Transaction {
+        file.load_leniently();
+        let mut before = vec![];
+        for (index, track) in file.tracks.iter_mut().enumerate() {
+            if !self.has_track(index) {
+                continue;
+            }
+            let original = track.clone();
+            let (notes, others) = split_notes(track.take_absolute());
+            let mut changed = false;
+            let mut kept = vec![];
+            for mut note in notes {
+                if self.contains(index, &note) {
+                    let old = note;
+                    if !edit(&mut note) {
+                        changed = true;
+                        continue;
+                    }
+                    changed |= note != old;
+                }
+                kept.push(note);
+            }
+            if changed {
+                track.set_absolute(merge_notes(&kept, others));
+                before.push((index, original));
+            } else {
+                *track = original;
+            }
+        }
+        Transaction {
+            label: label.to_string(),
+            before,
+        }
+    }
+
+    /// Moves the selected notes by `ticks`, stopping at the start of the
+    /// track
+    pub fn move_by(&self, file: &mut MidiFile, ticks: i64) -> Transaction {
+        self.edit(file, "Move", |note| {
+            note.start = (note.start as i64 + ticks).clamp(0, u32::MAX as i64) as u32;
+            true
+        })
+    }
+
+    /// Fails without changing anything if a note would leave the key range
+    pub fn transpose(
+        &self,
+        file: &mut MidiFile,
+        semitones: i32,
+    ) -> Result<Transaction, Box<dyn Error>> {
+        for (_, note) in self.notes(file) {
+            let shifted = note.key as i32 + semitones;
+            if !(0..=127).contains(&shifted) {
+                return Err(format!(
+                    "Transposing key {} by {} is out of range",
+                    note.key, semitones
+                )
+                .into());
+            }
+        }
+        Ok(self.edit(file, "Transpose", |note| {
+            note.key = (note.key as i32 + semitones) as u8;
+            true
+        }))
+    }
+
+    /// Multiplies velocities by `factor`, keeping them between 1 and 127
+    pub fn scale_velocities(&self, file: &mut MidiFile, factor: f64) -> Transaction {
+        self.edit(file, "Scale velocities", |note| {
+            note.velocity = (note.velocity as f64 * factor).round().clamp(1.0, 127.0) as u8;
+            true
+        })
+    }
+
+    pub fn delete(&self, file: &mut MidiFile) -> Transaction {
+        self.edit(file, "Delete", |_| false)
+    }
+}
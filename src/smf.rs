@@ -0,0 +1,149 @@
+use std::{error::Error, fs};
+
+use crate::{
+    control::split_14bit,
+    parser::{EventData, MetaData, MidiEvent, MidiFile, MidiTrack, SysExMeta},
+    status::StatusType,
+};
+
+fn write_value(out: &mut Vec<u8>, value: u32) {
+    let mut groups = vec![(value & 0x7f) as u8];
+    let mut rest = value >> 7;
+    while rest > 0 {
+        groups.push((rest & 0x7f) as u8 | 0x80);
+        rest >>= 7;
+    }
+    out.extend(groups.iter().rev());
+}
+
+pub(crate) fn meta_bytes(meta_type: SysExMeta, meta: &MetaData) -> Vec<u8> {
+    match meta {
+        MetaData::SingleU8(a) => vec![*a],
+        MetaData::DoubleU8(a, b) => vec![*a, *b],
+        MetaData::TripleU8(a, b, c) => vec![*a, *b, *c],
+        // the parser keeps the denominator itself rather than its power of 2
+        MetaData::QuadU8(n, d, c, b) if meta_type == SysExMeta::MetaTimeSignature => {
+            vec![*n, d.trailing_zeros() as u8, *c, *b]
+        }
+        MetaData::QuadU8(a, b, c, d) => vec![*a, *b, *c, *d],
+        MetaData::QuintripleU8(a, b, c, d, e) => vec![*a, *b, *c, *d, *e],
+        MetaData::SingleString(text) => text.as_bytes().to_vec(),
+        MetaData::Bytes(data) => data.clone(),
+        MetaData::None => vec![],
+    }
+}
+
+/// The event's bytes after its delta time, as one or more messages since
+/// decoded controller events expand to the control changes they came from.
+/// Empty for events that cannot be written.
+fn messages(event: &MidiEvent) -> Vec<Vec<u8>> {
+    let status = event.status.raw_status;
+    let controls = |pairs: &[(u8, u8)]| -> Vec<Vec<u8>> {
+        pairs
+            .iter()
+            .map(|(id, value)| vec![0xb0 | event.status.channel(), *id, *value])
+            .collect()
+    };
+    let message = match &event.data {
+        EventData::Control14Data { control_id, value } => {
+            return controls(&split_14bit(*control_id, *value))
+        }
+        EventData::RpnData { change } => return controls(&change.to_controls()),
+        EventData::SysexData {
+            meta_type: Some(meta_type),
+            meta,
+        } => {
+            let data = meta_bytes(*meta_type, meta);
+            let mut message = vec![0xff, *meta_type as u8];
+            write_value(&mut message, data.len() as u32);
+            message.extend(data);
+            message
+        }
+        EventData::SysexData {
+            meta_type: None,
+            meta: MetaData::Bytes(data),
+        } => {
+            let mut message = vec![status];
+            write_value(&mut message, data.len() as u32);
+            message.extend(data);
+            message
+        }
+        EventData::QuarterFrameData { piece, value } => {
+            vec![status, (piece & 0x07) << 4 | (value & 0x0f)]
+        }
+        EventData::SongPositionData { position } => {
+            vec![
+                status,
+                (position & 0x7f) as u8,
+                (position >> 7 & 0x7f) as u8,
+            ]
+        }
+        EventData::SongSelectData { song } => vec![status, song & 0x7f],
+        EventData::NoData if event.status.status_type != StatusType::Reset => vec![status],
+        // kept verbatim when they start at a status byte, so unknown meta
+        // events survive a round trip
+        EventData::Unparsed { raw, .. } if raw.first().is_some_and(|b| *b >= 0x80) => raw.clone(),
+        _ => match event.to_short_message() {
+            Some(packed) => {
+                let length = event.status.data_length().unwrap_or(0);
+                packed.to_le_bytes()[..1 + length].to_vec()
+            }
+            None => return vec![],
+        },
+    };
+    vec![message]
+}
+
+impl MidiTrack {
+    /// The track as an `MTrk` chunk, without running status. An end of track
+    /// event is added if the track lacks one.
+    pub fn to_chunk(&self) -> Vec<u8> {
+        let mut data = vec![];
+        let mut delta = 0;
+        let mut ended = false;
+        for event in self.events.iter() {
+            delta += event.delta_tick;
+            if ended {
+                continue;
+            }
+            for message in messages(event) {
+                write_value(&mut data, delta);
+                data.extend(message);
+                delta = 0;
+            }
+            ended = event.is_end_of_track();
+        }
+        if !ended {
+            write_value(&mut data, delta);
+            data.extend([0xff, SysExMeta::MetaEndOfTrack as u8, 0]);
+        }
+        let mut chunk = b"MTrk".to_vec();
+        chunk.extend((data.len() as u32).to_be_bytes());
+        chunk.extend(data);
+        chunk
+    }
+}
+
+impl MidiFile {
+    /// A Standard MIDI File in `header_format`. Unparsed events are left out
+    /// unless their raw bytes were kept.
+    pub fn to_smf(&self) -> Vec<u8> {
+        let file = self.loaded();
+        let format = file.header_format();
+        let mut out = b"MThd".to_vec();
+        out.extend(6u32.to_be_bytes());
+        out.extend(format.to_be_bytes());
+        out.extend((file.tracks.len() as u16).to_be_bytes());
+        out.extend(file.division.to_be_bytes());
+        for track in file.tracks.iter() {
+            out.extend(track.to_chunk());
+        }
+        out
+    }
+
+    /// Writes `to_smf` to a `.mid` file
+    pub fn save(&self, path: &str) -> Result<(), Box<dyn Error>> {
+        fs::write(path, self.to_smf())?;
+        Ok(())
+    }
+}
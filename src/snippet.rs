@@ -0,0 +1,136 @@
+use crate::{
+    note::{merge_notes, split_notes, Note},
+    parser::{EventData, MidiEvent, MidiFile, MidiTrack, SysExMeta},
+    rpn::ParameterKind,
+};
+
+/// What a setup event sets; a later event with the same key replaces it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum SetupKey {
+    Meta(SysExMeta),
+    /// Status byte and controller number
+    Control(u8, u8),
+    /// Status byte, registered or not, and parameter number
+    Parameter(u8, bool, u16),
+    /// Program, pressure or bend, by status byte
+    Channel(u8),
+}
+
+fn setup_key(event: &MidiEvent) -> Option<SetupKey> {
+    let status = event.status.raw_status;
+    match &event.data {
+        EventData::SysexData {
+            meta_type:
+                Some(
+                    meta @ (SysExMeta::MetaSetTempo
+                    | SysExMeta::MetaTimeSignature
+                    | SysExMeta::MetaKeySignature
+                    | SysExMeta::MetaTrackName
+                    | SysExMeta::MetaInstrumentName),
+                ),
+            ..
+        } => Some(SetupKey::Meta(*meta)),
+        // 120 and up are channel mode messages, not state
+        EventData::ControlData { control_id, .. } if *control_id < 120 => {
+            Some(SetupKey::Control(status, *control_id))
+        }
+        EventData::Control14Data { control_id, .. } => Some(SetupKey::Control(status, *control_id)),
+        EventData::RpnData { change } => Some(SetupKey::Parameter(
+            status,
+            change.kind == ParameterKind::Registered,
+            change.parameter,
+        )),
+        EventData::ProgramChangeData { .. }
+        | EventData::ChannelData { .. }
+        | EventData::PitchBendData { .. } => Some(SetupKey::Channel(status)),
+        _ => None,
+    }
+}
+
+impl MidiTrack {
+    /// The events between `start` and `end`, moved to begin at tick 0 and
+    /// preceded by the setup in force at `start`. Notes are cut off at `end`;
+    /// ones struck before `start` are left out.
+    pub fn excerpt(&self, start: u32, end: u32) -> MidiTrack {
+        let events: Vec<(u32, MidiEvent)> = self
+            .iter_ticks()
+            .map(|(tick, event)| (tick, event.clone()))
+            .collect();
+        let (notes, others) = split_notes(events);
+
+        let mut setup: Vec<(SetupKey, MidiEvent)> = vec![];
+        let mut body: Vec<(u32, MidiEvent)> = vec![];
+        for (tick, event) in others.into_iter().take_while(|(tick, _)| *tick < end) {
+            if tick >= start {
+                if !event.is_end_of_track() {
+                    body.push((tick - start, event));
+                }
+                continue;
+            }
+            if let Some(key) = setup_key(&event) {
+                setup.retain(|(k, _)| *k != key);
+                setup.push((key, event));
+            }
+        }
+        // anything reset right on the cut needs no setup of its own
+        setup.retain(|(key, _)| {
+            !body
+                .iter()
+                .take_while(|(tick, _)| *tick == 0)
+                .any(|(_, event)| setup_key(event) == Some(*key))
+        });
+
+        let mut events: Vec<(u32, MidiEvent)> =
+            setup.into_iter().map(|(_, event)| (0, event)).collect();
+        events.extend(body);
+        events.push((end - start, MidiEvent::end_of_track(0)));
+        let notes: Vec<Note> = notes
+            .into_iter()
+            .filter(|n| n.start >= start && n.start < end)
+            .map(|mut note| {
+                note.duration = note.duration.min(end - note.start);
+                note.start -= start;
+                note
+            })
+            .collect();
+
+        let mut track = MidiTrack::from_absolute(merge_notes(&notes, events));
+        track.name = self.name.clone();
+        track.instrument = self.instrument.clone();
+        track
+    }
+}
+
+impl MidiFile {
+    /// Cuts the file into snippets of `bars` bars each, every one a complete
+    /// file that starts with the tempo, meter, programs and controllers in
+    /// force where it was cut
+    pub fn split_by_bars(&self, bars: u32) -> Vec<MidiFile> {
+        let file = self.loaded();
+        let bars = bars.max(1);
+        let map = file.signature_map();
+        let tempo_map = file.tempo_map();
+        let end = file.tracks.iter().map(|t| t.end_tick()).max().unwrap_or(0);
+
+        let mut snippets = vec![];
+        let mut bar = 1;
+        let mut start = 0;
+        while start < end {
+            let next = map.bar_beat_to_tick(bar + bars, 1).max(start + 1);
+            let mut snippet = MidiFile::create();
+            snippet.division = file.division;
+            snippet.options = file.options;
+            snippet.tempo = tempo_map.tempo_at(start);
+            snippet.bpm = 60_000_000 / snippet.tempo.max(1);
+            snippet.tracks = self
+                .tracks
+                .iter()
+                .map(|track| track.excerpt(start, next))
+                .collect();
+            snippets.push(snippet);
+            bar += bars;
+            start = next;
+        }
+        snippets
+    }
+}
@@ -1,10 +1,20 @@
-use std::error::Error;
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec,
+};
+use core::error::Error;
 
 use bytes::{Buf, BytesMut};
 
-use crate::parser::{read_str, read_value, EventData, MetaData, MidiFile, MidiTrack, SysExMeta};
+use crate::bend::PitchBend;
+use crate::parser::{read_bytes, read_value, EventData, MetaData, MidiFile, MidiTrack, SysExMeta};
 
-#[derive(PartialEq, Debug)]
+pub const DRUM_CHANNEL: u8 = 9;
+
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum StatusType {
     NoteOff = 0x80,
     NoteOn = 0x90,
@@ -14,13 +24,70 @@ pub enum StatusType {
     ChannelAftertouch = 0xd0,
     PitchBendChange = 0xe0,
     SystemMsg = 0xf0,
+    TimeCodeQuarterFrame = 0xf1,
+    SongPosition = 0xf2,
+    SongSelect = 0xf3,
+    TuneRequest = 0xf6,
+    TimingClock = 0xf8,
+    Start = 0xfa,
+    Continue = 0xfb,
+    Stop = 0xfc,
+    ActiveSensing = 0xfe,
+    /// Only produced by `Status::from_live_byte`; in files 0xFF starts a meta event
+    Reset = 0xff,
 }
 
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub struct Status {
     pub status_type: StatusType,
     pub raw_status: u8,
 }
 
+impl StatusType {
+    /// Every status type, channel messages by their high nibble
+    ///
+    /// cbindgen:ignore
+    pub const ALL: [Self; 18] = [
+        Self::NoteOff,
+        Self::NoteOn,
+        Self::PolyphonicAftertouch,
+        Self::CtrlChange,
+        Self::ProgramChange,
+        Self::ChannelAftertouch,
+        Self::PitchBendChange,
+        Self::SystemMsg,
+        Self::TimeCodeQuarterFrame,
+        Self::SongPosition,
+        Self::SongSelect,
+        Self::TuneRequest,
+        Self::TimingClock,
+        Self::Start,
+        Self::Continue,
+        Self::Stop,
+        Self::ActiveSensing,
+        Self::Reset,
+    ];
+}
+
+/// Reads a status byte as live input does, so 0xFF is a reset; in files use
+/// `Status::from_byte`, which reads it as the start of a meta event
+impl TryFrom<u8> for StatusType {
+    type Error = Box<dyn Error>;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Ok(Status::from_live_byte(byte)?.status_type)
+    }
+}
+
+impl TryFrom<u8> for Status {
+    type Error = Box<dyn Error>;
+
+    fn try_from(byte: u8) -> Result<Self, Self::Error> {
+        Self::from_byte(byte)
+    }
+}
+
 impl Status {
     pub fn from_byte(byte: u8) -> Result<Self, Box<dyn Error>> {
         match byte & 0xf0 {
@@ -52,163 +119,269 @@ impl Status {
                 status_type: StatusType::PitchBendChange,
                 raw_status: byte,
             }),
-            0xf0 => Ok(Self {
-                status_type: StatusType::SystemMsg,
+            0xf0 => {
+                let status_type = match byte {
+                    0xf0 | 0xf7 | 0xff => StatusType::SystemMsg,
+                    0xf1 => StatusType::TimeCodeQuarterFrame,
+                    0xf2 => StatusType::SongPosition,
+                    0xf3 => StatusType::SongSelect,
+                    0xf6 => StatusType::TuneRequest,
+                    0xf8 => StatusType::TimingClock,
+                    0xfa => StatusType::Start,
+                    0xfb => StatusType::Continue,
+                    0xfc => StatusType::Stop,
+                    0xfe => StatusType::ActiveSensing,
+                    _ => return Err(format!("Undefined System Status: {:X}", byte).into()),
+                };
+                Ok(Self {
+                    status_type,
+                    raw_status: byte,
+                })
+            }
+            _ => Err(format!("Invalid Status Byte: {}", byte).into()),
+        }
+    }
+
+    /// Like `from_byte`, but reads 0xFF as a system reset as it is on the wire
+    pub fn from_live_byte(byte: u8) -> Result<Self, Box<dyn Error>> {
+        match byte {
+            0xff => Ok(Self {
+                status_type: StatusType::Reset,
                 raw_status: byte,
             }),
-            _ => Err(format!("Invalid Status Byte: {}", byte).into()),
+            _ => Self::from_byte(byte),
+        }
+    }
+
+    pub fn channel_message(status_type: StatusType, channel: u8) -> Self {
+        Self {
+            raw_status: status_type as u8 | (channel & 0x0f),
+            status_type,
+        }
+    }
+
+    pub fn channel(&self) -> u8 {
+        self.raw_status & 0x0f
+    }
+
+    pub fn is_channel_message(&self) -> bool {
+        self.raw_status < 0xf0
+    }
+
+    /// Clock and transport messages, which may appear anywhere in a stream
+    /// without disturbing running status
+    pub fn is_realtime(&self) -> bool {
+        self.raw_status >= 0xf8 && self.status_type != StatusType::SystemMsg
+    }
+
+    /// Number of data bytes following the status, or `None` for messages with
+    /// a variable length payload (SysEx and meta events)
+    pub fn data_length(&self) -> Option<usize> {
+        match self.status_type {
+            StatusType::ProgramChange
+            | StatusType::ChannelAftertouch
+            | StatusType::TimeCodeQuarterFrame
+            | StatusType::SongSelect => Some(1),
+            StatusType::SystemMsg => None,
+            StatusType::SongPosition
+            | StatusType::NoteOff
+            | StatusType::NoteOn
+            | StatusType::PolyphonicAftertouch
+            | StatusType::CtrlChange
+            | StatusType::PitchBendChange => Some(2),
+            _ => Some(0),
+        }
+    }
+
+    /// Decodes the data bytes of a fixed length message
+    pub fn short_data(&self, first: u8, second: u8) -> EventData {
+        match self.status_type {
+            StatusType::NoteOn | StatusType::NoteOff | StatusType::PolyphonicAftertouch => {
+                EventData::NoteOnOffData {
+                    key: first,
+                    velocity: second,
+                }
+            }
+            StatusType::CtrlChange => EventData::ControlData {
+                control_id: first,
+                control_value: second,
+            },
+            StatusType::ProgramChange => EventData::ProgramChangeData { program_id: first },
+            StatusType::ChannelAftertouch => EventData::ChannelData {
+                channel_pressure: first,
+            },
+            StatusType::PitchBendChange => EventData::PitchBendData {
+                bend: PitchBend::from_bytes(first, second),
+            },
+            StatusType::TimeCodeQuarterFrame => EventData::QuarterFrameData {
+                piece: first >> 4,
+                value: first & 0x0f,
+            },
+            StatusType::SongPosition => EventData::SongPositionData {
+                position: (second as u16 & 0x7f) << 7 | (first as u16 & 0x7f),
+            },
+            StatusType::SongSelect => EventData::SongSelectData { song: first },
+            StatusType::SystemMsg => EventData::Unparsed {
+                raw: vec![self.raw_status, first, second],
+                offset: 0,
+                reason: "System exclusive and meta events have no short form".to_string(),
+            },
+            _ => EventData::NoData,
         }
     }
 
+    /// Reads the data following this status. Undecodable but well-delimited
+    /// data comes back as `EventData::Unparsed`, with `raw` and `offset` left
+    /// for the caller to fill in; running out of bytes is an error.
     pub fn parse_data(
         &self,
         file: &mut MidiFile,
         track: &mut MidiTrack,
         bytes: &mut BytesMut,
-    ) -> EventData {
-        file.prev_status = self.raw_status;
+    ) -> Result<EventData, Box<dyn Error>> {
+        if self.is_channel_message() {
+            file.prev_status = self.raw_status;
+        } else if !self.is_realtime() {
+            file.prev_status = 0;
+        }
 
         match self.status_type {
-            StatusType::NoteOn | StatusType::NoteOff | StatusType::PolyphonicAftertouch => {
-                let key = bytes.get_u8();
-                let velocity = bytes.get_u8();
-                EventData::NoteOnOffData { key, velocity }
-            }
-            StatusType::CtrlChange => {
-                let control_id = bytes.get_u8();
-                let control_value = bytes.get_u8();
-                EventData::ControlData {
-                    control_id,
-                    control_value,
-                }
-            }
-            StatusType::ProgramChange => {
-                let program_id = bytes.get_u8();
-                EventData::ProgramChangeData { program_id }
-            }
-            StatusType::ChannelAftertouch => {
-                let channel_pressure = bytes.get_u8();
-                EventData::ChannelData { channel_pressure }
-            }
-            StatusType::PitchBendChange => {
-                let least_bytes = bytes.get_u8();
-                let most_bytes = bytes.get_u8();
-                EventData::PitchBendData {
-                    least_bytes,
-                    most_bytes,
-                }
-            }
             StatusType::SystemMsg => {
-                file.prev_status = 0;
                 if self.raw_status == 0xFF {
+                    if bytes.remaining() < 2 {
+                        return Err("Truncated meta event".into());
+                    }
                     let ty = bytes.get_u8();
-                    let len = read_value(bytes);
-                    match SysExMeta::from(ty).unwrap() {
-                        SysExMeta::MetaSequence => {
-                            return EventData::SysexData {
-                                meta: MetaData::DoubleU8(bytes.get_u8(), bytes.get_u8()),
-                            }
-                        }
+                    let len = read_value(bytes) as usize;
+                    if bytes.remaining() < len {
+                        return Err(format!(
+                            "Meta event needs {} bytes but only {} remain",
+                            len,
+                            bytes.remaining()
+                        )
+                        .into());
+                    }
+                    let data = bytes.split_to(len);
+                    let meta_type = match SysExMeta::try_from(ty) {
+                        Ok(meta_type) => meta_type,
+                        Err(e) => return Ok(unparsed(e.to_string())),
+                    };
+                    let meta = match (meta_type, &data[..]) {
+                        (SysExMeta::MetaSequence, [a, b]) => MetaData::DoubleU8(*a, *b),
+                        (SysExMeta::MetaSequence, []) => MetaData::None,
 
-                        SysExMeta::MetaChannelPrefix => {
-                            return EventData::SysexData {
-                                meta: MetaData::SingleU8(bytes.get_u8()),
-                            }
+                        (SysExMeta::MetaChannelPrefix | SysExMeta::MetaPort, [value]) => {
+                            MetaData::SingleU8(*value)
                         }
 
-                        SysExMeta::MetaLyrics
-                        | SysExMeta::MetaCuePoint
-                        | SysExMeta::MetaMarker
-                        | SysExMeta::MetaSequencerSpecific
-                        | SysExMeta::MetaCopyright
-                        | SysExMeta::MetaText => {
-                            return EventData::SysexData {
-                                meta: MetaData::SingleString(*read_str(bytes, len as usize)),
-                            }
-                        }
+                        (
+                            SysExMeta::MetaLyrics
+                            | SysExMeta::MetaCuePoint
+                            | SysExMeta::MetaMarker
+                            | SysExMeta::MetaCopyright
+                            | SysExMeta::MetaText
+                            | SysExMeta::MetaProgramName
+                            | SysExMeta::MetaDeviceName,
+                            text,
+                        ) => MetaData::text(text.to_vec()),
+
+                        (SysExMeta::MetaSequencerSpecific, data) => MetaData::Bytes(data.to_vec()),
 
-                        SysExMeta::MetaTrackName => {
-                            track.name = *read_str(bytes, len as usize);
-                            return EventData::SysexData {
-                                meta: MetaData::SingleString(track.name.clone()),
-                            };
+                        (SysExMeta::MetaTrackName, text) => {
+                            track.name = String::from_utf8_lossy(text).to_string();
+                            MetaData::text(text.to_vec())
                         }
 
-                        SysExMeta::MetaInstrumentName => {
-                            track.instrument = *read_str(bytes, len as usize);
-                            return EventData::SysexData {
-                                meta: MetaData::SingleString(track.instrument.clone()),
-                            };
+                        (SysExMeta::MetaInstrumentName, text) => {
+                            track.instrument = String::from_utf8_lossy(text).to_string();
+                            MetaData::text(text.to_vec())
                         }
 
-                        SysExMeta::MetaEndOfTrack => {
+                        (SysExMeta::MetaEndOfTrack, _) => {
                             track.end_of_track = true;
-                            return EventData::SysexData {
-                                meta: MetaData::None,
-                            };
+                            MetaData::None
                         }
 
-                        SysExMeta::MetaSetTempo => {
+                        (SysExMeta::MetaSetTempo, [first, second, third]) => {
                             if file.tempo == 0 {
-                                let first = bytes.get_u8();
-                                let second = bytes.get_u8();
-                                let third = bytes.get_u8();
-                                file.tempo |= (first as u32) << 16;
-                                file.tempo |= (second as u32) << 8;
-                                file.tempo |= (third as u32) << 0;
-                                file.bpm = 60000000 / file.tempo;
-                                return EventData::SysexData {
-                                    meta: MetaData::TripleU8(first, second, third),
-                                };
+                                file.tempo =
+                                    (*first as u32) << 16 | (*second as u32) << 8 | *third as u32;
+                                file.bpm = 60000000 / file.tempo.max(1);
                             }
-                            return EventData::SysexData {
-                                meta: MetaData::None,
-                            };
+                            MetaData::TripleU8(*first, *second, *third)
                         }
 
-                        SysExMeta::MetaSMPTEOffset => {
-                            return EventData::SysexData {
-                                meta: MetaData::QuintripleU8(
-                                    bytes.get_u8(),
-                                    bytes.get_u8(),
-                                    bytes.get_u8(),
-                                    bytes.get_u8(),
-                                    bytes.get_u8(),
-                                ),
-                            };
+                        (SysExMeta::MetaSMPTEOffset, [a, b, c, d, e]) => {
+                            MetaData::QuintripleU8(*a, *b, *c, *d, *e)
                         }
 
-                        SysExMeta::MetaTimeSignature => {
-                            return EventData::SysexData {
-                                meta: MetaData::QuadU8(
-                                    bytes.get_u8(),
-                                    2 << bytes.get_u8(),
-                                    bytes.get_u8(),
-                                    bytes.get_u8(),
-                                ),
-                            };
+                        (SysExMeta::MetaTimeSignature, [a, b, c, d]) => {
+                            match 1u8.checked_shl(*b as u32) {
+                                Some(denominator) => MetaData::QuadU8(*a, denominator, *c, *d),
+                                None => {
+                                    return Ok(unparsed(format!(
+                                        "Time signature denominator 2^{} is out of range",
+                                        b
+                                    )))
+                                }
+                            }
                         }
 
-                        SysExMeta::MetaKeySignature => {
-                            return EventData::SysexData {
-                                meta: MetaData::DoubleU8(bytes.get_u8(), bytes.get_u8()),
-                            };
+                        (SysExMeta::MetaKeySignature, [a, b]) => MetaData::DoubleU8(*a, *b),
+
+                        (meta_type, data) => {
+                            return Ok(unparsed(format!(
+                                "{:?} event has an unexpected length of {}",
+                                meta_type,
+                                data.len()
+                            )))
                         }
-                    }
-                } else if self.raw_status == 0xF0 {
-                    let len = read_value(bytes) as usize;
-                    return EventData::SysexData {
-                        meta: MetaData::SingleString(*read_str(bytes, len)),
                     };
-                } else if self.raw_status == 0xf7 {
+                    Ok(EventData::SysexData {
+                        meta_type: Some(meta_type),
+                        meta,
+                    })
+                } else if self.raw_status == 0xF0 || self.raw_status == 0xF7 {
+                    if bytes.remaining() == 0 {
+                        return Err("Truncated SysEx event".into());
+                    }
                     let len = read_value(bytes) as usize;
-                    return EventData::SysexData {
-                        meta: MetaData::SingleString(*read_str(bytes, len)),
-                    };
+                    if bytes.remaining() < len {
+                        return Err(format!(
+                            "SysEx event needs {} bytes but only {} remain",
+                            len,
+                            bytes.remaining()
+                        )
+                        .into());
+                    }
+                    Ok(EventData::SysexData {
+                        meta_type: None,
+                        meta: MetaData::Bytes(read_bytes(bytes, len)),
+                    })
                 } else {
-                    EventData::Error("Failed to parse data from system message".to_string())
+                    Ok(unparsed(format!(
+                        "System message 0x{:02x} cannot appear in a file",
+                        self.raw_status
+                    )))
+                }
+            }
+            _ => {
+                let length = self.data_length().unwrap_or(0);
+                if bytes.remaining() < length {
+                    return Err(format!("{:?} is missing its data bytes", self.status_type).into());
                 }
+                let first = if length > 0 { bytes.get_u8() } else { 0 };
+                let second = if length > 1 { bytes.get_u8() } else { 0 };
+                Ok(self.short_data(first, second))
             }
         }
     }
 }
+
+fn unparsed(reason: String) -> EventData {
+    EventData::Unparsed {
+        raw: vec![],
+        offset: 0,
+        reason,
+    }
+}
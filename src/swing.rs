@@ -0,0 +1,78 @@
+use crate::{grid::Grid, meter::SignatureMap, note::pair_notes, parser::MidiTrack};
+
+/// Ratios within this many percent of 50 count as straight
+const STRAIGHT_TOLERANCE: f64 = 4.0;
+/// How far an offbeat may stray from the measured ratio and still agree
+const AGREEMENT: f64 = 5.0;
+
+/// The feel of a track on one subdivision. `ratio` is where the offbeat
+/// falls within each pair of grid steps, in percent: 50 is straight, 66.7
+/// triplet swing.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Swing {
+    pub grid: Grid,
+    pub ratio: f64,
+    /// Share of offbeats within a few percent of `ratio`
+    pub confidence: f64,
+    /// Offbeat notes the estimate rests on
+    pub samples: usize,
+}
+
+impl Swing {
+    pub fn straight(grid: Grid) -> Self {
+        Self {
+            grid,
+            ratio: 50.0,
+            confidence: 1.0,
+            samples: 0,
+        }
+    }
+
+    pub fn is_swung(&self) -> bool {
+        (self.ratio - 50.0).abs() > STRAIGHT_TOLERANCE
+    }
+
+    /// Moves a tick on the straight grid to where this feel puts it: offbeat
+    /// lines shift by the swing, downbeat lines stay
+    pub fn apply(&self, tick: u32, map: &SignatureMap) -> u32 {
+        let step = self.grid.step_at(tick, map);
+        let bar_start = map.bar_start(tick);
+        let phase = (tick - bar_start) % (2 * step);
+        if phase != step {
+            return tick;
+        }
+        let pair_start = tick - step;
+        pair_start + (2.0 * step as f64 * self.ratio / 100.0).round() as u32
+    }
+}
+
+impl MidiTrack {
+    /// Estimates the swing on `grid` from where notes near each offbeat
+    /// land. Returns None when there are no offbeats to measure.
+    pub fn detect_swing(&self, grid: Grid, map: &SignatureMap) -> Option<Swing> {
+        let mut ratios: Vec<f64> = pair_notes(self.iter_ticks())
+            .iter()
+            .filter_map(|note| {
+                let step = grid.step_at(note.start, map) as f64;
+                let phase = ((note.start - map.bar_start(note.start)) as f64) % (2.0 * step);
+                // only notes nearer the offbeat than either downbeat
+                (phase >= step * 0.5 && phase < step * 1.5).then(|| phase / (2.0 * step) * 100.0)
+            })
+            .collect();
+        if ratios.is_empty() {
+            return None;
+        }
+        ratios.sort_by(f64::total_cmp);
+        let ratio = ratios[ratios.len() / 2];
+        let agreeing = ratios
+            .iter()
+            .filter(|r| (*r - ratio).abs() <= AGREEMENT)
+            .count();
+        Some(Swing {
+            grid,
+            ratio,
+            confidence: agreeing as f64 / ratios.len() as f64,
+            samples: ratios.len(),
+        })
+    }
+}
@@ -0,0 +1,168 @@
+use alloc::{vec, vec::Vec};
+
+use crate::parser::{EventData, MetaData};
+
+pub const SYSEX_START: u8 = 0xF0;
+pub const SYSEX_END: u8 = 0xF7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ManufacturerId {
+    Short(u8),
+    Extended(u8, u8),
+}
+
+impl ManufacturerId {
+    pub const NON_COMMERCIAL: Self = Self::Short(0x7D);
+    pub const UNIVERSAL_NON_REAL_TIME: Self = Self::Short(0x7E);
+    pub const UNIVERSAL_REAL_TIME: Self = Self::Short(0x7F);
+
+    /// Reads the id from the start of a payload (after F0), returning it with
+    /// the number of bytes it took
+    pub fn parse(payload: &[u8]) -> Option<(Self, usize)> {
+        match payload {
+            [0x00, a, b, ..] => Some((Self::Extended(*a, *b), 3)),
+            [0x00, ..] => None,
+            [id, ..] if *id < 0x80 => Some((Self::Short(*id), 1)),
+            _ => None,
+        }
+    }
+
+    pub fn to_bytes(self) -> Vec<u8> {
+        match self {
+            Self::Short(id) => vec![id],
+            Self::Extended(a, b) => vec![0x00, a, b],
+        }
+    }
+
+    pub fn is_universal(self) -> bool {
+        self == Self::UNIVERSAL_NON_REAL_TIME || self == Self::UNIVERSAL_REAL_TIME
+    }
+
+    pub fn name(self) -> Option<&'static str> {
+        match self {
+            Self::Short(0x01) => Some("Sequential"),
+            Self::Short(0x04) => Some("Moog"),
+            Self::Short(0x0F) => Some("Ensoniq"),
+            Self::Short(0x10) => Some("Oberheim"),
+            Self::Short(0x18) => Some("E-mu"),
+            Self::Short(0x3E) => Some("Waldorf"),
+            Self::Short(0x40) => Some("Kawai"),
+            Self::Short(0x41) => Some("Roland"),
+            Self::Short(0x42) => Some("Korg"),
+            Self::Short(0x43) => Some("Yamaha"),
+            Self::Short(0x44) => Some("Casio"),
+            Self::Short(0x47) => Some("Akai"),
+            Self::Short(0x7D) => Some("Non-Commercial"),
+            Self::Short(0x7E) => Some("Universal Non-Real Time"),
+            Self::Short(0x7F) => Some("Universal Real Time"),
+            Self::Extended(0x00, 0x0E) => Some("Alesis"),
+            Self::Extended(0x20, 0x29) => Some("Focusrite/Novation"),
+            Self::Extended(0x20, 0x32) => Some("Behringer"),
+            Self::Extended(0x20, 0x33) => Some("Access Music"),
+            Self::Extended(0x20, 0x3C) => Some("Elektron"),
+            Self::Extended(0x20, 0x6B) => Some("Arturia"),
+            _ => None,
+        }
+    }
+}
+
+/// A complete system exclusive message. `data` excludes the F0, the
+/// manufacturer id and the terminating F7.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SysExEvent {
+    pub manufacturer: ManufacturerId,
+    pub data: Vec<u8>,
+}
+
+impl SysExEvent {
+    pub fn create(manufacturer: ManufacturerId, data: Vec<u8>) -> Self {
+        Self { manufacturer, data }
+    }
+
+    /// Parses a payload as stored in a file, i.e. without the leading F0
+    pub fn from_payload(payload: &[u8]) -> Option<Self> {
+        let payload = payload.strip_prefix(&[SYSEX_START]).unwrap_or(payload);
+        let (manufacturer, len) = ManufacturerId::parse(payload)?;
+        let data = payload[len..]
+            .strip_suffix(&[SYSEX_END])
+            .unwrap_or(&payload[len..]);
+        Some(Self {
+            manufacturer,
+            data: data.to_vec(),
+        })
+    }
+
+    /// The full message including F0 and F7, as sent to a device
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![SYSEX_START];
+        bytes.extend(self.manufacturer.to_bytes());
+        bytes.extend(self.data.iter());
+        bytes.push(SYSEX_END);
+        bytes
+    }
+}
+
+impl EventData {
+    pub fn sysex(&self) -> Option<SysExEvent> {
+        match self {
+            EventData::SysexData {
+                meta_type: None,
+                meta: MetaData::Bytes(payload),
+            } => SysExEvent::from_payload(payload),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn universal_message_matches_the_spec() {
+        // General MIDI System On
+        let gm_on = SysExEvent::create(
+            ManufacturerId::UNIVERSAL_NON_REAL_TIME,
+            vec![0x7f, 0x09, 0x01],
+        );
+        assert_eq!(gm_on.to_bytes(), [0xf0, 0x7e, 0x7f, 0x09, 0x01, 0xf7]);
+        assert_eq!(SysExEvent::from_payload(&gm_on.to_bytes()), Some(gm_on));
+        assert!(ManufacturerId::UNIVERSAL_NON_REAL_TIME.is_universal());
+    }
+
+    #[test]
+    fn manufacturer_ids_match_the_spec() {
+        // Roland GS reset, as a file stores it without the F0
+        let gs_reset = [0x41, 0x10, 0x42, 0x12, 0x40, 0x00, 0x7f, 0x00, 0x41, 0xf7];
+        let sysex = SysExEvent::from_payload(&gs_reset).unwrap();
+        assert_eq!(sysex.manufacturer.name(), Some("Roland"));
+        assert_eq!(sysex.data, gs_reset[1..9]);
+        // three-byte ids start with zero
+        assert_eq!(
+            ManufacturerId::parse(&[0x00, 0x20, 0x29, 0x02]),
+            Some((ManufacturerId::Extended(0x20, 0x29), 3))
+        );
+        assert_eq!(
+            ManufacturerId::Extended(0x20, 0x29).to_bytes(),
+            [0x00, 0x20, 0x29]
+        );
+        assert_eq!(ManufacturerId::parse(&[0x00, 0x20]), None);
+        assert_eq!(ManufacturerId::parse(&[0x80]), None);
+    }
+
+    #[test]
+    fn only_sysex_events_are_read() {
+        let event = EventData::SysexData {
+            meta_type: None,
+            meta: MetaData::Bytes(vec![0x43, 0x10, 0x4c, 0xf7]),
+        };
+        assert_eq!(
+            event.sysex(),
+            Some(SysExEvent::create(
+                ManufacturerId::Short(0x43),
+                vec![0x10, 0x4c]
+            ))
+        );
+        assert_eq!(EventData::NoData.sysex(), None);
+    }
+}
@@ -0,0 +1,217 @@
+use std::fmt::Write;
+
+use crate::{
+    note::{pair_notes, Note},
+    parser::MidiTrack,
+};
+
+const NAMES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+
+/// Open string keys from the lowest string up
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tuning {
+    pub strings: Vec<u8>,
+}
+
+impl Tuning {
+    pub fn create(strings: Vec<u8>) -> Self {
+        Self { strings }
+    }
+
+    /// E2 A2 D3 G3 B3 E4
+    pub fn standard() -> Self {
+        Self::create(vec![40, 45, 50, 55, 59, 64])
+    }
+
+    pub fn drop_d() -> Self {
+        Self::create(vec![38, 45, 50, 55, 59, 64])
+    }
+
+    /// E1 A1 D2 G2
+    pub fn bass() -> Self {
+        Self::create(vec![28, 33, 38, 43])
+    }
+}
+
+/// The instrument and what a hand can reach on it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fretboard {
+    pub tuning: Tuning,
+    pub frets: u8,
+    /// Widest stretch between fretted notes of one chord, in frets
+    pub span: u8,
+}
+
+impl Fretboard {
+    pub fn create(tuning: Tuning) -> Self {
+        Self {
+            tuning,
+            frets: 22,
+            span: 4,
+        }
+    }
+
+    fn fret(&self, string: usize, key: u8) -> Option<u8> {
+        let fret = key.checked_sub(self.tuning.strings[string])?;
+        (fret <= self.frets).then_some(fret)
+    }
+
+    /// The cheapest way to finger `keys` at once: every key on its own
+    /// string, within the span, as close as possible to `hand`
+    fn finger(&self, keys: &[u8], hand: f64) -> Option<Vec<(usize, u8)>> {
+        let mut best: Option<(f64, Vec<(usize, u8)>)> = None;
+        let mut current = vec![];
+        self.search(keys, hand, &mut current, &mut best);
+        best.map(|(_, positions)| positions)
+    }
+
+    fn search(
+        &self,
+        keys: &[u8],
+        hand: f64,
+        current: &mut Vec<(usize, u8)>,
+        best: &mut Option<(f64, Vec<(usize, u8)>)>,
+    ) {
+        let Some(&key) = keys.get(current.len()) else {
+            let fretted: Vec<f64> = current
+                .iter()
+                .filter(|(_, f)| *f > 0)
+                .map(|(_, f)| *f as f64)
+                .collect();
+            let low = fretted.iter().copied().fold(f64::MAX, f64::min);
+            let high = fretted.iter().copied().fold(0.0, f64::max);
+            if !fretted.is_empty() && high - low > self.span as f64 {
+                return;
+            }
+            // stay near the hand, preferring lower positions
+            let centre = match fretted.is_empty() {
+                true => hand,
+                false => fretted.iter().sum::<f64>() / fretted.len() as f64,
+            };
+            let cost = (centre - hand).abs() + centre * 0.1;
+            if best.as_ref().is_none_or(|(c, _)| cost < *c) {
+                *best = Some((cost, current.clone()));
+            }
+            return;
+        };
+        for string in 0..self.tuning.strings.len() {
+            if current.iter().any(|(s, _)| *s == string) {
+                continue;
+            }
+            if let Some(fret) = self.fret(string, key) {
+                current.push((string, fret));
+                self.search(keys, hand, current, best);
+                current.pop();
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TabNote {
+    pub note: Note,
+    /// Index into the tuning, 0 being the lowest string
+    pub string: usize,
+    pub fret: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Tab {
+    pub fretboard: Fretboard,
+    pub notes: Vec<TabNote>,
+    /// Notes out of range, or chords no hand could hold
+    pub unplayable: Vec<Note>,
+}
+
+impl Tab {
+    /// Assigns strings and frets chord by chord, keeping the hand close to
+    /// where it was. Notes starting on the same tick form a chord; when one
+    /// cannot be fingered, its highest notes are dropped until it can.
+    pub fn create(track: &MidiTrack, fretboard: Fretboard) -> Self {
+        let notes = pair_notes(track.iter_ticks());
+        let mut tab = Self {
+            fretboard,
+            notes: vec![],
+            unplayable: vec![],
+        };
+        let mut hand = 0.0;
+        let mut i = 0;
+        while i < notes.len() {
+            let start = notes[i].start;
+            let end = i + notes[i..].iter().take_while(|n| n.start == start).count();
+            let mut chord: Vec<Note> = notes[i..end].to_vec();
+            chord.sort_by_key(|n| n.key);
+            i = end;
+            loop {
+                let keys: Vec<u8> = chord.iter().map(|n| n.key).collect();
+                if let Some(positions) = tab.fretboard.finger(&keys, hand) {
+                    let fretted: Vec<f64> = positions
+                        .iter()
+                        .filter(|(_, f)| *f > 0)
+                        .map(|(_, f)| *f as f64)
+                        .collect();
+                    if !fretted.is_empty() {
+                        hand = fretted.iter().sum::<f64>() / fretted.len() as f64;
+                    }
+                    for (note, (string, fret)) in chord.iter().zip(positions) {
+                        tab.notes.push(TabNote {
+                            note: *note,
+                            string,
+                            fret,
+                        });
+                    }
+                    break;
+                }
+                match chord.pop() {
+                    Some(note) => tab.unplayable.push(note),
+                    None => break,
+                }
+            }
+        }
+        tab
+    }
+
+    /// ASCII tab with the highest string on top. Each column is one `step`
+    /// of ticks, with a bar line every `bar` ticks and four bars per line.
+    pub fn to_text(&self, step: u32, bar: u32) -> String {
+        let step = step.max(1);
+        let columns_per_bar = (bar / step).max(1) as usize;
+        let end = self.notes.iter().map(|n| n.note.start).max().unwrap_or(0);
+        let columns = (end / step) as usize + 1;
+        let bars = columns.div_ceil(columns_per_bar);
+        let strings = self.fretboard.tuning.strings.len();
+
+        let mut grid = vec![vec![String::new(); bars * columns_per_bar]; strings];
+        for note in self.notes.iter() {
+            let column = ((note.note.start + step / 2) / step) as usize;
+            if let Some(cell) = grid[note.string].get_mut(column) {
+                *cell = note.fret.to_string();
+            }
+        }
+
+        let mut out = String::new();
+        for first in (0..bars).step_by(4) {
+            for string in (0..strings).rev() {
+                let key = self.fretboard.tuning.strings[string];
+                let _ = write!(out, "{:<2}|", NAMES[key as usize % 12]);
+                for bar in first..(first + 4).min(bars) {
+                    for cell in &grid[string][bar * columns_per_bar..(bar + 1) * columns_per_bar] {
+                        let _ = write!(out, "{:-<3}", cell);
+                    }
+                    out.push('|');
+                }
+                out.push('\n');
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl MidiTrack {
+    pub fn tab(&self, fretboard: Fretboard) -> Tab {
+        Tab::create(self, fretboard)
+    }
+}
@@ -0,0 +1,376 @@
+use std::{error::Error, fmt::Write};
+
+use crate::{
+    json::{field, number, uint, Json},
+    parser::{EventData, MetaData, MidiEvent, MidiFile, SysExMeta},
+    status::{Status, StatusType},
+};
+
+pub use crate::parser::{DEFAULT_DIVISION, DEFAULT_TEMPO};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TempoChange {
+    pub tick: u32,
+    /// Microseconds per quarter note
+    pub tempo: u32,
+}
+
+impl TempoChange {
+    pub fn bpm(&self) -> f64 {
+        60_000_000.0 / self.tempo as f64
+    }
+}
+
+/// Tempo changes from every track, sorted by tick. The first entry is always
+/// at tick 0.
+#[derive(Debug, Clone)]
+pub struct TempoMap {
+    pub division: u16,
+    pub changes: Vec<TempoChange>,
+}
+
+impl TempoMap {
+    /// Before the first tempo event the file's `options.default_tempo`
+    /// applies
+    pub fn from_file(file: &MidiFile) -> Self {
+        let mut changes = vec![TempoChange {
+            tick: 0,
+            tempo: file.options.default_tempo.max(1),
+        }];
+        for track in file.loaded().tracks.iter() {
+            for (tick, event) in track.iter_ticks() {
+                if let EventData::SysexData {
+                    meta_type: Some(SysExMeta::MetaSetTempo),
+                    meta: MetaData::TripleU8(a, b, c),
+                } = event.data
+                {
+                    let tempo = (a as u32) << 16 | (b as u32) << 8 | c as u32;
+                    if tempo > 0 {
+                        changes.push(TempoChange { tick, tempo });
+                    }
+                }
+            }
+        }
+        Self::from_changes(file.division, changes)
+    }
+
+    pub fn from_changes(division: u16, mut changes: Vec<TempoChange>) -> Self {
+        changes.sort_by_key(|c| c.tick);
+        // a later change on the same tick wins
+        changes.reverse();
+        changes.dedup_by_key(|c| c.tick);
+        changes.reverse();
+        if changes.first().is_none_or(|c| c.tick > 0) {
+            changes.insert(
+                0,
+                TempoChange {
+                    tick: 0,
+                    tempo: DEFAULT_TEMPO,
+                },
+            );
+        }
+        Self {
+            division: division.max(1),
+            changes,
+        }
+    }
+
+    fn change_at(&self, tick: u32) -> &TempoChange {
+        let i = self.changes.partition_point(|c| c.tick <= tick);
+        &self.changes[i.saturating_sub(1)]
+    }
+
+    pub fn tempo_at(&self, tick: u32) -> u32 {
+        self.change_at(tick).tempo
+    }
+
+    pub fn bpm_at(&self, tick: u32) -> f64 {
+        self.change_at(tick).bpm()
+    }
+
+    /// Time of a (possibly fractional) tick in microseconds
+    pub fn micros_at(&self, tick: f64) -> f64 {
+        let mut micros = 0.0;
+        let mut prev = self.changes[0];
+        for change in self.changes.iter().skip(1) {
+            if change.tick as f64 >= tick {
+                break;
+            }
+            micros += (change.tick - prev.tick) as f64 * prev.tempo as f64 / self.division as f64;
+            prev = *change;
+        }
+        micros + (tick - prev.tick as f64) * prev.tempo as f64 / self.division as f64
+    }
+
+    /// Inverse of `micros_at`
+    pub fn tick_at(&self, micros: f64) -> f64 {
+        let mut elapsed = 0.0;
+        let mut prev = self.changes[0];
+        for change in self.changes.iter().skip(1) {
+            let span = (change.tick - prev.tick) as f64 * prev.tempo as f64 / self.division as f64;
+            if elapsed + span > micros {
+                break;
+            }
+            elapsed += span;
+            prev = *change;
+        }
+        prev.tick as f64 + (micros - elapsed) * self.division as f64 / prev.tempo as f64
+    }
+
+    pub fn seconds_at(&self, tick: u32) -> f64 {
+        self.micros_at(tick as f64) / 1_000_000.0
+    }
+
+    /// The same map with its ticks rescaled to `division`
+    pub fn for_division(&self, division: u16) -> Self {
+        let changes = self
+            .changes
+            .iter()
+            .map(|c| TempoChange {
+                tick: (c.tick as u64 * division as u64 / self.division as u64) as u32,
+                tempo: c.tempo,
+            })
+            .collect();
+        Self::from_changes(division, changes)
+    }
+
+    /// One `tick,seconds,bpm` row per change, after a header row
+    pub fn to_csv(&self) -> String {
+        let mut out = String::from("tick,seconds,bpm\n");
+        for change in self.changes.iter() {
+            let _ = writeln!(
+                out,
+                "{},{:.6},{:.6}",
+                change.tick,
+                self.seconds_at(change.tick),
+                change.bpm()
+            );
+        }
+        out
+    }
+
+    pub fn to_json(&self) -> String {
+        // to the microsecond, as the CSV has it
+        let rounded = |value: f64| number((value * 1e6).round() / 1e6);
+        let changes = self
+            .changes
+            .iter()
+            .map(|change| {
+                Json::Object(vec![
+                    ("tick".to_string(), number(change.tick)),
+                    ("seconds".to_string(), rounded(self.seconds_at(change.tick))),
+                    ("bpm".to_string(), rounded(change.bpm())),
+                ])
+            })
+            .collect();
+        Json::Object(vec![
+            ("division".to_string(), number(self.division)),
+            ("changes".to_string(), Json::Array(changes)),
+        ])
+        .to_string()
+    }
+
+    /// Reads rows with a `bpm` column and either a `tick` or a `seconds`
+    /// column, named in the header. Ticks win when both are present.
+    pub fn from_csv(division: u16, text: &str) -> Result<Self, Box<dyn Error>> {
+        let mut lines = text
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'));
+        let header: Vec<String> = lines
+            .next()
+            .ok_or("Missing header row")?
+            .split(',')
+            .map(|name| name.trim().to_lowercase())
+            .collect();
+        let mut rows = vec![];
+        for line in lines {
+            let mut row = vec![];
+            for (name, value) in header.iter().zip(line.split(',')) {
+                let value: f64 = value
+                    .trim()
+                    .parse()
+                    .map_err(|_| format!("Bad number in row: {}", line))?;
+                row.push((name.clone(), value));
+            }
+            rows.push(row);
+        }
+        Self::from_rows(division, rows)
+    }
+
+    /// Reads the object written by `to_json`. Entries may give `tick` or
+    /// `seconds`; a `division` field overrides the one passed in.
+    pub fn from_json(division: u16, text: &str) -> Result<Self, Box<dyn Error>> {
+        let root = Json::parse(text)?;
+        let division = match root.get("division") {
+            Some(_) => uint(&root, "division", u16::MAX as u64)? as u16,
+            None => division,
+        };
+        let rows = field(&root, "changes")?
+            .as_array()
+            .ok_or("Field changes must be an array")?
+            .iter()
+            .map(|change| match change {
+                // only the numbers matter, as with CSV columns
+                Json::Object(fields) => Ok(fields
+                    .iter()
+                    .filter_map(|(name, value)| Some((name.to_lowercase(), value.as_f64()?)))
+                    .collect()),
+                _ => Err("Tempo changes must be objects".into()),
+            })
+            .collect::<Result<Vec<_>, Box<dyn Error>>>()?;
+        Self::from_rows(division, rows)
+    }
+
+    fn from_rows(division: u16, rows: Vec<Vec<(String, f64)>>) -> Result<Self, Box<dyn Error>> {
+        let mut changes = vec![];
+        for row in rows {
+            let field = |name: &str| row.iter().find(|(n, _)| n == name).map(|(_, v)| *v);
+            let bpm = field("bpm").ok_or("Missing bpm")?;
+            if bpm <= 0.0 {
+                return Err(format!("Bad bpm: {}", bpm).into());
+            }
+            let tick = match (field("tick"), field("seconds")) {
+                (Some(tick), _) => tick,
+                // resolved against the changes read so far
+                (None, Some(seconds)) => Self::from_changes(division, changes.clone())
+                    .tick_at(seconds * 1_000_000.0)
+                    .round(),
+                (None, None) => return Err("Missing tick or seconds".into()),
+            };
+            changes.push(TempoChange {
+                tick: tick.max(0.0) as u32,
+                tempo: (60_000_000.0 / bpm).round() as u32,
+            });
+        }
+        Ok(Self::from_changes(division, changes))
+    }
+}
+
+impl MidiFile {
+    pub fn tempo_map(&self) -> TempoMap {
+        TempoMap::from_file(self)
+    }
+
+    /// Replaces every SetTempo event with the changes in `map`, placed in the
+    /// first track and rescaled to the file's division
+    pub fn set_tempo_map(&mut self, map: &TempoMap) {
+        let map = map.for_division(self.division.max(1));
+        self.load_leniently();
+        for track in self.tracks.iter_mut() {
+            let mut events = track.take_absolute();
+            events.retain(|(_, event)| {
+                !matches!(
+                    event.data,
+                    EventData::SysexData {
+                        meta_type: Some(SysExMeta::MetaSetTempo),
+                        ..
+                    }
+                )
+            });
+            track.set_absolute(events);
+        }
+        if let Some(track) = self.tracks.first_mut() {
+            let mut events = track.take_absolute();
+            events.extend(map.changes.iter().map(|change| {
+                let [_, a, b, c] = change.tempo.min(0xff_ffff).to_be_bytes();
+                (
+                    change.tick,
+                    MidiEvent {
+                        status: Status {
+                            status_type: StatusType::SystemMsg,
+                            raw_status: 0xff,
+                        },
+                        data: EventData::SysexData {
+                            meta_type: Some(SysExMeta::MetaSetTempo),
+                            meta: MetaData::TripleU8(a, b, c),
+                        },
+                        delta_tick: 0,
+                    },
+                )
+            }));
+            track.set_absolute(events);
+        }
+        self.tempo = map.changes[0].tempo;
+        self.bpm = 60_000_000 / self.tempo.max(1);
+    }
+
+    /// Replaces the tempo map with a single `target_bpm`. With
+    /// `preserve_duration` every event is moved so it still sounds at the
+    /// same moment; otherwise the ticks stay put and the file plays faster or
+    /// slower.
+    pub fn retempo(
+        &mut self,
+        target_bpm: f64,
+        preserve_duration: bool,
+    ) -> Result<(), Box<dyn Error>> {
+        if !(target_bpm > 0.0 && target_bpm.is_finite()) {
+            return Err(format!("Bad bpm: {}", target_bpm).into());
+        }
+        self.load_all()?;
+        let tempo = (60_000_000.0 / target_bpm)
+            .round()
+            .clamp(1.0, 0xff_ffff as f64) as u32;
+        if preserve_duration {
+            let old = self.tempo_map();
+            let division = self.division.max(1) as f64;
+            for track in self.tracks.iter_mut() {
+                let events = track
+                    .take_absolute()
+                    .into_iter()
+                    .map(|(tick, event)| {
+                        let micros = old.micros_at(tick as f64);
+                        ((micros * division / tempo as f64).round() as u32, event)
+                    })
+                    .collect();
+                track.set_absolute(events);
+            }
+        }
+        let changes = vec![TempoChange { tick: 0, tempo }];
+        self.set_tempo_map(&TempoMap::from_changes(self.division, changes));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn map() -> TempoMap {
+        TempoMap::from_changes(
+            480,
+            vec![
+                TempoChange {
+                    tick: 0,
+                    tempo: 500_000,
+                },
+                TempoChange {
+                    tick: 960,
+                    tempo: 400_000,
+                },
+            ],
+        )
+    }
+
+    #[test]
+    fn json_round_trips() {
+        let json = map().to_json();
+        assert_eq!(
+            json,
+            r#"{"division":480,"changes":[{"tick":0,"seconds":0,"bpm":120},{"tick":960,"seconds":1,"bpm":150}]}"#
+        );
+        assert_eq!(
+            TempoMap::from_json(96, &json).unwrap().changes,
+            map().changes
+        );
+    }
+
+    #[test]
+    fn json_changes_may_be_given_in_seconds() {
+        let json = r#"{"changes": [{"seconds": 0, "bpm": 120}, {"seconds": 1.0, "bpm": 150}]}"#;
+        let map = TempoMap::from_json(480, json).unwrap();
+        assert_eq!(map.changes, self::map().changes);
+        assert!(TempoMap::from_json(480, r#"{"changes": [1, 2]}"#).is_err());
+        assert!(TempoMap::from_json(480, "[{\"tick\": 0, \"bpm\": 120}").is_err());
+    }
+}
@@ -0,0 +1,371 @@
+use crate::{
+    grid::Grid,
+    key::Key,
+    meter::SignatureMap,
+    note::{merge_notes, split_notes, Note},
+    parser::{EventData, MidiEvent, MidiTrack},
+    script::Script,
+    status::StatusType,
+    swing::Swing,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArpPattern {
+    Up,
+    Down,
+    UpDown,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transform {
+    Arpeggiate {
+        step: u32,
+        pattern: ArpPattern,
+        gate: f32,
+    },
+    Echo {
+        delay: u32,
+        repeats: u32,
+        decay: f32,
+    },
+    Humanize {
+        timing: u32,
+        velocity: u8,
+        seed: u64,
+    },
+    /// Leaves notes on `drum_channel` alone
+    ScaleSnap {
+        key: Key,
+        drum_channel: Option<u8>,
+    },
+    Script(Script),
+    /// Moves notes near each offbeat line of `grid` as `Swing::apply` moves
+    /// the line, `ratio` percent of the way through its pair of steps: 50 is
+    /// straight, 66.7 triplet swing. Lines restart at every bar.
+    Swing {
+        grid: Grid,
+        ratio: f64,
+    },
+    /// Keys pushed past either end are folded back by octaves. Leaves notes
+    /// on `drum_channel` alone.
+    Transpose {
+        semitones: i32,
+        drum_channel: Option<u8>,
+    },
+    /// Maps velocity `v` to `min + (max - min) * (v / 127) ^ gamma`, so a
+    /// gamma below 1 lifts soft notes and above 1 tames them
+    VelocityCurve {
+        gamma: f32,
+        min: u8,
+        max: u8,
+    },
+}
+
+struct Rng(u64);
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    fn offset(&mut self, max: u32) -> i64 {
+        if max == 0 {
+            return 0;
+        }
+        (self.next() % (2 * max as u64 + 1)) as i64 - max as i64
+    }
+}
+
+impl Transform {
+    /// `map` places the bars and beats for steps that follow the meter
+    pub fn apply(
+        &self,
+        events: Vec<(u32, MidiEvent)>,
+        map: &SignatureMap,
+    ) -> Vec<(u32, MidiEvent)> {
+        if let Self::Script(script) = self {
+            return script.apply(events);
+        }
+        let (notes, others) = split_notes(events);
+        let notes = self.apply_notes(notes, map);
+        merge_notes(&notes, others)
+    }
+
+    pub fn apply_notes(&self, notes: Vec<Note>, map: &SignatureMap) -> Vec<Note> {
+        match *self {
+            Self::Arpeggiate {
+                step,
+                pattern,
+                gate,
+            } => arpeggiate(notes, step.max(1), pattern, gate),
+            Self::Echo {
+                delay,
+                repeats,
+                decay,
+            } => {
+                let mut echoed = notes.clone();
+                for note in notes.iter() {
+                    let mut velocity = note.velocity as f32;
+                    for i in 1..=repeats {
+                        velocity *= decay;
+                        if velocity < 1.0 {
+                            break;
+                        }
+                        echoed.push(Note {
+                            start: note.start + delay * i,
+                            velocity: velocity.round() as u8,
+                            ..*note
+                        });
+                    }
+                }
+                echoed
+            }
+            Self::Humanize {
+                timing,
+                velocity,
+                seed,
+            } => {
+                let mut rng = Rng(seed.max(1));
+                notes
+                    .into_iter()
+                    .map(|note| {
+                        let start = (note.start as i64 + rng.offset(timing)).max(0) as u32;
+                        let vel =
+                            (note.velocity as i64 + rng.offset(velocity as u32)).clamp(1, 127);
+                        Note {
+                            start,
+                            velocity: vel as u8,
+                            ..note
+                        }
+                    })
+                    .collect()
+            }
+            Self::Swing { grid, ratio } => {
+                let swing = Swing {
+                    ratio: ratio.clamp(0.0, 100.0),
+                    ..Swing::straight(grid)
+                };
+                notes
+                    .into_iter()
+                    .map(|note| {
+                        // only notes nearer the offbeat than either downbeat
+                        let line = grid.snap(note.start, map);
+                        let shift = swing.apply(line, map) as i64 - line as i64;
+                        let start = (note.start as i64 + shift).max(0) as u32;
+                        Note { start, ..note }
+                    })
+                    .collect()
+            }
+            Self::Transpose { .. } | Self::ScaleSnap { .. } => notes
+                .into_iter()
+                .map(|note| Note {
+                    key: self.map_key(note.channel, note.key),
+                    ..note
+                })
+                .collect(),
+            Self::VelocityCurve { .. } => notes
+                .into_iter()
+                .map(|note| Note {
+                    velocity: self.map_velocity(note.velocity),
+                    ..note
+                })
+                .collect(),
+            Self::Script(_) => notes,
+        }
+    }
+
+    fn map_key(&self, channel: u8, key: u8) -> u8 {
+        match *self {
+            Self::Transpose {
+                semitones,
+                drum_channel,
+            } if Some(channel) != drum_channel => {
+                let mut shifted = key as i32 + semitones;
+                while shifted > 127 {
+                    shifted -= 12;
+                }
+                while shifted < 0 {
+                    shifted += 12;
+                }
+                shifted as u8
+            }
+            Self::ScaleSnap {
+                key: scale_key,
+                drum_channel,
+            } if Some(channel) != drum_channel => {
+                let scale: Vec<i32> = scale_key
+                    .scale_notes()
+                    .iter()
+                    .map(|note| (*note as i32).rem_euclid(12))
+                    .collect();
+                let key = key as i32;
+                let snapped = [0, -1, 1, -2, 2]
+                    .iter()
+                    .map(|d| key + d)
+                    .find(|k| scale.contains(&k.rem_euclid(12)))
+                    .unwrap_or(key);
+                snapped.clamp(0, 127) as u8
+            }
+            _ => key,
+        }
+    }
+
+    fn map_velocity(&self, velocity: u8) -> u8 {
+        match *self {
+            Self::VelocityCurve { gamma, min, max } => {
+                let curved = (velocity as f32 / 127.0).powf(gamma.max(0.0));
+                (min as f32 + (max as f32 - min as f32) * curved)
+                    .round()
+                    .clamp(1.0, 127.0) as u8
+            }
+            _ => velocity,
+        }
+    }
+
+    /// Whether the step works one event at a time, so it can run on live
+    /// input. Live, Humanize only varies velocity.
+    pub fn is_live(&self) -> bool {
+        !matches!(
+            self,
+            Self::Arpeggiate { .. } | Self::Echo { .. } | Self::Swing { .. }
+        )
+    }
+
+    /// Runs the step on a single incoming event, returning false when it
+    /// should be dropped. Steps that are not live leave it alone.
+    pub fn apply_live(&self, micros: u64, event: &mut MidiEvent) -> bool {
+        if let Self::Script(script) = self {
+            let mut tick = 0;
+            return script
+                .rules
+                .iter()
+                .all(|rule| rule.apply_event(&mut tick, event));
+        }
+        let status_type = event.status.status_type;
+        let channel = event.status.channel();
+        let EventData::NoteOnOffData { key, velocity } = &mut event.data else {
+            return true;
+        };
+        if !matches!(
+            status_type,
+            StatusType::NoteOn | StatusType::NoteOff | StatusType::PolyphonicAftertouch
+        ) {
+            return true;
+        }
+        *key = self.map_key(channel, *key);
+        if status_type == StatusType::NoteOn && *velocity > 0 {
+            *velocity = match *self {
+                Self::Humanize {
+                    velocity: amount,
+                    seed,
+                    ..
+                } => {
+                    let mut rng = Rng((seed ^ micros).max(1));
+                    (*velocity as i64 + rng.offset(amount as u32)).clamp(1, 127) as u8
+                }
+                _ => self.map_velocity(*velocity),
+            };
+        }
+        true
+    }
+}
+
+fn arpeggiate(notes: Vec<Note>, step: u32, pattern: ArpPattern, gate: f32) -> Vec<Note> {
+    let mut result = vec![];
+    let mut chords: Vec<Vec<Note>> = vec![];
+    for note in notes {
+        match chords
+            .iter_mut()
+            .find(|c| c[0].start == note.start && c[0].channel == note.channel)
+        {
+            Some(chord) => chord.push(note),
+            None => chords.push(vec![note]),
+        }
+    }
+
+    for mut chord in chords {
+        if chord.len() < 2 {
+            result.extend(chord);
+            continue;
+        }
+        chord.sort_by_key(|n| n.key);
+        let order: Vec<usize> = match pattern {
+            ArpPattern::Up => (0..chord.len()).collect(),
+            ArpPattern::Down => (0..chord.len()).rev().collect(),
+            ArpPattern::UpDown => (0..chord.len()).chain((1..chord.len() - 1).rev()).collect(),
+        };
+        let start = chord[0].start;
+        let end = chord.iter().map(|n| n.end()).max().unwrap_or(start);
+        let length = ((step as f32 * gate.clamp(0.0, 1.0)).round() as u32).max(1);
+        let mut tick = start;
+        for &i in order.iter().cycle() {
+            if tick >= end {
+                break;
+            }
+            result.push(Note {
+                start: tick,
+                duration: length.min(end - tick),
+                ..chord[i]
+            });
+            tick += step;
+        }
+    }
+    result
+}
+
+impl MidiTrack {
+    /// Bakes the attached transforms into the track's events and detaches them
+    pub fn freeze(&mut self, map: &SignatureMap) {
+        let mut events = self.take_absolute();
+        for transform in std::mem::take(&mut self.transforms) {
+            events = transform.apply(events, map);
+        }
+        self.set_absolute(events);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::meter::TimeSignature;
+
+    fn swung(starts: &[u32], grid: &str, map: &SignatureMap) -> Vec<u32> {
+        let notes = starts
+            .iter()
+            .map(|start| Note {
+                channel: 0,
+                key: 60,
+                velocity: 100,
+                start: *start,
+                duration: 10,
+                release_velocity: 0,
+            })
+            .collect();
+        let swing = Transform::Swing {
+            grid: Grid::parse(grid).unwrap(),
+            ratio: 66.7,
+        };
+        swing
+            .apply_notes(notes, map)
+            .iter()
+            .map(|note| note.start)
+            .collect()
+    }
+
+    #[test]
+    fn swing_moves_notes_near_offbeats() {
+        let map = SignatureMap::from_signatures(96, vec![]);
+        let starts = [0, 48, 52, 90, 96, 140];
+        assert_eq!(swung(&starts, "1/8", &map), vec![0, 64, 68, 90, 96, 156]);
+    }
+
+    #[test]
+    fn swing_restarts_at_every_bar() {
+        let map = SignatureMap::from_signatures(96, vec![(0, TimeSignature::create(3, 4))]);
+        let starts = [96, 192, 288, 384, 480];
+        assert_eq!(swung(&starts, "beat", &map), vec![128, 192, 288, 416, 480]);
+    }
+}
@@ -0,0 +1,387 @@
+use alloc::{boxed::Box, format};
+use core::error::Error;
+
+use crate::{
+    bend::PitchBend,
+    parser::{EventData, MidiEvent},
+    rpn::{ParameterKind, RpnChange},
+    status::{Status, StatusType},
+};
+
+/// Message type of 32-bit MIDI 1.0 channel voice packets
+pub const MIDI1_CHANNEL_VOICE: u8 = 0x2;
+/// Message type of 64-bit MIDI 2.0 channel voice packets
+pub const MIDI2_CHANNEL_VOICE: u8 = 0x4;
+
+/// MIDI 2.0 opcodes for registered and assignable (NRPN) controllers
+const REGISTERED_CONTROLLER: u8 = 0x2;
+const ASSIGNABLE_CONTROLLER: u8 = 0x3;
+
+/// Release velocity given to MIDI 1.0 NoteOns with velocity 0, which become
+/// MIDI 2.0 NoteOffs: 64, the MIDI 1.0 default, scaled up
+const DEFAULT_RELEASE: u16 = 0x8000;
+
+/// Widens a value the way the UMP spec's min-center-max scaling does: 0 stays
+/// 0, the center stays the center and the maximum reaches the new maximum
+pub fn scale_up(value: u32, from_bits: u32, to_bits: u32) -> u32 {
+    let shift = to_bits - from_bits;
+    let shifted = (value as u64) << shift;
+    if value <= 1 << (from_bits - 1) {
+        return shifted as u32;
+    }
+    // fill the low bits by repeating the bits below the top one
+    let repeat_bits = from_bits - 1;
+    let mut repeat = value as u64 & ((1 << repeat_bits) - 1);
+    if shift > repeat_bits {
+        repeat <<= shift - repeat_bits;
+    } else {
+        repeat >>= repeat_bits - shift;
+    }
+    let mut result = shifted;
+    while repeat != 0 {
+        result |= repeat;
+        repeat >>= repeat_bits;
+    }
+    result as u32
+}
+
+pub fn scale_down(value: u32, from_bits: u32, to_bits: u32) -> u32 {
+    value >> (from_bits - to_bits)
+}
+
+fn packet_header(message_type: u8, group: u8, status: u8) -> u32 {
+    (message_type as u32) << 28 | (group as u32 & 0x0f) << 24 | (status as u32) << 16
+}
+
+/// The event as a 32-bit MIDI 1.0 channel voice packet on `group`, its
+/// first data byte in bits 15-8 and the second in bits 7-0. None for events
+/// that are not channel voice messages.
+pub fn to_midi1_packet(event: &MidiEvent, group: u8) -> Option<u32> {
+    let message = event.to_short_message()?;
+    let status = message as u8;
+    Some(
+        packet_header(MIDI1_CHANNEL_VOICE, group, status)
+            | (message >> 8 & 0x7f) << 8
+            | message >> 16 & 0x7f,
+    )
+}
+
+/// The group and event carried by a MIDI 1.0 channel voice packet
+pub fn from_midi1_packet(packet: u32) -> Result<(u8, MidiEvent), Box<dyn Error>> {
+    let message_type = (packet >> 28) as u8;
+    if message_type != MIDI1_CHANNEL_VOICE {
+        return Err(format!(
+            "Packet type 0x{:x} is not MIDI 1.0 channel voice",
+            message_type
+        )
+        .into());
+    }
+    let group = (packet >> 24 & 0x0f) as u8;
+    let message = packet >> 16 & 0xff | (packet >> 8 & 0x7f) << 8 | (packet & 0x7f) << 16;
+    let event = MidiEvent::from_short_message(message)?;
+    Ok((group, event))
+}
+
+/// The event as a 64-bit MIDI 2.0 channel voice packet on `group`, with
+/// velocities scaled to 16 bits and controller, pressure and bend values to
+/// 32. A NoteOn with velocity 0 becomes a NoteOff. None for events with no
+/// MIDI 2.0 form.
+pub fn to_midi2_packet(event: &MidiEvent, group: u8) -> Option<[u32; 2]> {
+    let channel = event.status.channel();
+    let header = |status_type: StatusType| {
+        packet_header(MIDI2_CHANNEL_VOICE, group, status_type as u8 | channel)
+    };
+    let packet = match (event.status.status_type, &event.data) {
+        (StatusType::NoteOn, EventData::NoteOnOffData { key, velocity: 0 }) => [
+            header(StatusType::NoteOff) | (*key as u32 & 0x7f) << 8,
+            (DEFAULT_RELEASE as u32) << 16,
+        ],
+        (
+            status_type @ (StatusType::NoteOn | StatusType::NoteOff),
+            EventData::NoteOnOffData { key, velocity },
+        ) => [
+            header(status_type) | (*key as u32 & 0x7f) << 8,
+            scale_up(*velocity as u32 & 0x7f, 7, 16) << 16,
+        ],
+        (StatusType::PolyphonicAftertouch, EventData::NoteOnOffData { key, velocity }) => [
+            header(StatusType::PolyphonicAftertouch) | (*key as u32 & 0x7f) << 8,
+            scale_up(*velocity as u32 & 0x7f, 7, 32),
+        ],
+        (
+            StatusType::CtrlChange,
+            EventData::ControlData {
+                control_id,
+                control_value,
+            },
+        ) => [
+            header(StatusType::CtrlChange) | (*control_id as u32 & 0x7f) << 8,
+            scale_up(*control_value as u32 & 0x7f, 7, 32),
+        ],
+        (StatusType::CtrlChange, EventData::Control14Data { control_id, value }) => [
+            header(StatusType::CtrlChange) | (*control_id as u32 & 0x7f) << 8,
+            scale_up(*value as u32 & 0x3fff, 14, 32),
+        ],
+        (_, EventData::RpnData { change }) => {
+            let opcode = match change.kind {
+                ParameterKind::Registered => REGISTERED_CONTROLLER,
+                ParameterKind::NonRegistered => ASSIGNABLE_CONTROLLER,
+            };
+            [
+                packet_header(MIDI2_CHANNEL_VOICE, group, opcode << 4 | channel)
+                    | (change.parameter as u32 >> 7 & 0x7f) << 8
+                    | change.parameter as u32 & 0x7f,
+                scale_up(change.value as u32 & 0x3fff, 14, 32),
+            ]
+        }
+        (StatusType::ProgramChange, EventData::ProgramChangeData { program_id }) => [
+            header(StatusType::ProgramChange),
+            (*program_id as u32 & 0x7f) << 24,
+        ],
+        (StatusType::ChannelAftertouch, EventData::ChannelData { channel_pressure }) => [
+            header(StatusType::ChannelAftertouch),
+            scale_up(*channel_pressure as u32 & 0x7f, 7, 32),
+        ],
+        (StatusType::PitchBendChange, EventData::PitchBendData { bend }) => [
+            header(StatusType::PitchBendChange),
+            scale_up(bend.raw() as u32, 14, 32),
+        ],
+        _ => return None,
+    };
+    Some(packet)
+}
+
+/// The group and event carried by a MIDI 2.0 channel voice packet, values
+/// scaled back down to MIDI 1.0 resolution. Registered and assignable
+/// controllers come back as `EventData::RpnData`. A NoteOn whose velocity
+/// scales down to 0 is sent at 1, so it is not read as a NoteOff.
+pub fn from_midi2_packet(packet: [u32; 2]) -> Result<(u8, MidiEvent), Box<dyn Error>> {
+    let [first, second] = packet;
+    let message_type = (first >> 28) as u8;
+    if message_type != MIDI2_CHANNEL_VOICE {
+        return Err(format!(
+            "Packet type 0x{:x} is not MIDI 2.0 channel voice",
+            message_type
+        )
+        .into());
+    }
+    let group = (first >> 24 & 0x0f) as u8;
+    let opcode = (first >> 20 & 0x0f) as u8;
+    let channel = (first >> 16 & 0x0f) as u8;
+    let index = (first >> 8 & 0x7f) as u8;
+    let low = (first & 0x7f) as u8;
+    let seven = || scale_down(second, 32, 7) as u8;
+
+    let (status_type, data) = match opcode {
+        0x8 | 0x9 => {
+            let velocity = scale_down(second >> 16, 16, 7) as u8;
+            let status_type = match opcode {
+                0x9 => StatusType::NoteOn,
+                _ => StatusType::NoteOff,
+            };
+            let velocity = match status_type {
+                StatusType::NoteOn => velocity.max(1),
+                _ => velocity,
+            };
+            (
+                status_type,
+                EventData::NoteOnOffData {
+                    key: index,
+                    velocity,
+                },
+            )
+        }
+        0xa => (
+            StatusType::PolyphonicAftertouch,
+            EventData::NoteOnOffData {
+                key: index,
+                velocity: seven(),
+            },
+        ),
+        0xb => (
+            StatusType::CtrlChange,
+            EventData::ControlData {
+                control_id: index,
+                control_value: seven(),
+            },
+        ),
+        REGISTERED_CONTROLLER | ASSIGNABLE_CONTROLLER => (
+            StatusType::CtrlChange,
+            EventData::RpnData {
+                change: RpnChange {
+                    kind: match opcode {
+                        REGISTERED_CONTROLLER => ParameterKind::Registered,
+                        _ => ParameterKind::NonRegistered,
+                    },
+                    parameter: (index as u16) << 7 | low as u16,
+                    value: scale_down(second, 32, 14) as u16,
+                },
+            },
+        ),
+        0xc => (
+            StatusType::ProgramChange,
+            EventData::ProgramChangeData {
+                program_id: (second >> 24 & 0x7f) as u8,
+            },
+        ),
+        0xd => (
+            StatusType::ChannelAftertouch,
+            EventData::ChannelData {
+                channel_pressure: seven(),
+            },
+        ),
+        0xe => (
+            StatusType::PitchBendChange,
+            EventData::PitchBendData {
+                bend: PitchBend::from_raw(scale_down(second, 32, 14) as u16),
+            },
+        ),
+        _ => return Err(format!("MIDI 2.0 opcode 0x{:x} has no MIDI 1.0 form", opcode).into()),
+    };
+    Ok((
+        group,
+        MidiEvent {
+            status: Status::channel_message(status_type, channel),
+            data,
+            delta_tick: 0,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(status_type: StatusType, channel: u8, data: EventData) -> MidiEvent {
+        MidiEvent::channel_message(status_type, channel, data)
+    }
+
+    fn note_on(key: u8, velocity: u8) -> MidiEvent {
+        event(
+            StatusType::NoteOn,
+            0,
+            EventData::NoteOnOffData { key, velocity },
+        )
+    }
+
+    #[test]
+    fn midi1_packets_match_the_spec() {
+        let cases = [
+            (note_on(60, 100), 0, 0x2090_3c64),
+            (
+                event(
+                    StatusType::CtrlChange,
+                    5,
+                    EventData::ControlData {
+                        control_id: 7,
+                        control_value: 100,
+                    },
+                ),
+                3,
+                0x23b5_0764,
+            ),
+            (
+                event(
+                    StatusType::ProgramChange,
+                    9,
+                    EventData::ProgramChangeData { program_id: 42 },
+                ),
+                0,
+                0x20c9_2a00,
+            ),
+            (
+                event(
+                    StatusType::PitchBendChange,
+                    0,
+                    EventData::PitchBendData {
+                        bend: PitchBend::from_raw(0x2000),
+                    },
+                ),
+                0,
+                0x20e0_0040,
+            ),
+        ];
+        for (event, group, packet) in cases {
+            assert_eq!(to_midi1_packet(&event, group), Some(packet));
+            let (back_group, back) = from_midi1_packet(packet).unwrap();
+            assert_eq!((back_group, back), (group, event));
+        }
+    }
+
+    #[test]
+    fn spec_midi1_packet_decodes_key_then_velocity() {
+        let (_, event) = from_midi1_packet(0x2090_3c64).unwrap();
+        assert_eq!(
+            event.data,
+            EventData::NoteOnOffData {
+                key: 60,
+                velocity: 100
+            }
+        );
+    }
+
+    #[test]
+    fn other_packet_types_are_errors() {
+        assert!(from_midi1_packet(0x4090_3c64).is_err());
+        assert!(from_midi2_packet([0x2090_3c64, 0]).is_err());
+    }
+
+    #[test]
+    fn scaling_keeps_zero_center_and_maximum() {
+        assert_eq!(scale_up(0, 7, 16), 0);
+        assert_eq!(scale_up(64, 7, 16), 0x8000);
+        assert_eq!(scale_up(100, 7, 16), 0xc924);
+        assert_eq!(scale_up(127, 7, 16), 0xffff);
+        assert_eq!(scale_up(127, 7, 32), 0xffff_ffff);
+        assert_eq!(scale_up(0x2000, 14, 32), 0x8000_0000);
+        assert_eq!(scale_up(0x3fff, 14, 32), 0xffff_ffff);
+        assert_eq!(scale_down(0xc924, 16, 7), 100);
+    }
+
+    #[test]
+    fn midi2_packets_match_the_spec() {
+        assert_eq!(
+            to_midi2_packet(&note_on(60, 100), 0),
+            Some([0x4090_3c00, 0xc924_0000])
+        );
+        // a zero-velocity NoteOn is a NoteOff at the default release
+        assert_eq!(
+            to_midi2_packet(&note_on(60, 0), 1),
+            Some([0x4180_3c00, 0x8000_0000])
+        );
+        let volume = event(
+            StatusType::CtrlChange,
+            2,
+            EventData::ControlData {
+                control_id: 7,
+                control_value: 127,
+            },
+        );
+        assert_eq!(
+            to_midi2_packet(&volume, 0),
+            Some([0x40b2_0700, 0xffff_ffff])
+        );
+        let bend_range = event(
+            StatusType::CtrlChange,
+            0,
+            EventData::RpnData {
+                change: RpnChange {
+                    kind: ParameterKind::Registered,
+                    parameter: 0,
+                    value: 2 << 7,
+                },
+            },
+        );
+        let packet = to_midi2_packet(&bend_range, 0).unwrap();
+        assert_eq!(packet[0], 0x4020_0000);
+        assert_eq!(from_midi2_packet(packet).unwrap().1, bend_range);
+    }
+
+    #[test]
+    fn midi2_round_trips_at_midi1_resolution() {
+        for velocity in 1..128 {
+            let (_, back) =
+                from_midi2_packet(to_midi2_packet(&note_on(60, velocity), 0).unwrap()).unwrap();
+            assert_eq!(back, note_on(60, velocity));
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::fmt;
+
+use crate::{
+    parser::{EventData, MidiFile, MidiTrack},
+    status::StatusType,
+};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    UnterminatedTrack {
+        track: usize,
+    },
+    ChunkLengthMismatch {
+        track: usize,
+        declared: u32,
+        actual: u32,
+    },
+    UnmatchedNoteOn {
+        track: usize,
+        event: usize,
+        tick: u32,
+        channel: u8,
+        key: u8,
+    },
+    UnmatchedNoteOff {
+        track: usize,
+        event: usize,
+        tick: u32,
+        channel: u8,
+        key: u8,
+    },
+    /// A data byte of a channel message is above 0x7f
+    DataByteHighBit {
+        track: usize,
+        event: usize,
+        tick: u32,
+    },
+    /// Bytes a lenient parse kept as `EventData::Unparsed`
+    Unparsed {
+        track: usize,
+        event: usize,
+        tick: u32,
+        offset: u64,
+        reason: String,
+    },
+}
+
+impl Problem {
+    pub fn track(&self) -> usize {
+        match *self {
+            Self::UnterminatedTrack { track }
+            | Self::ChunkLengthMismatch { track, .. }
+            | Self::UnmatchedNoteOn { track, .. }
+            | Self::UnmatchedNoteOff { track, .. }
+            | Self::DataByteHighBit { track, .. }
+            | Self::Unparsed { track, .. } => track,
+        }
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match *self {
+            Self::UnterminatedTrack { track } => {
+                write!(f, "track {}: missing end-of-track", track)
+            }
+            Self::ChunkLengthMismatch {
+                track,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "track {}: chunk declares {} bytes but holds {}",
+                track, declared, actual
+            ),
+            Self::UnmatchedNoteOn {
+                track,
+                tick,
+                channel,
+                key,
+                ..
+            } => write!(
+                f,
+                "track {}: note {} on channel {} at tick {} is never released",
+                track, key, channel, tick
+            ),
+            Self::UnmatchedNoteOff {
+                track,
+                tick,
+                channel,
+                key,
+                ..
+            } => write!(
+                f,
+                "track {}: note off {} on channel {} at tick {} has no note on",
+                track, key, channel, tick
+            ),
+            Self::DataByteHighBit { track, event, tick } => write!(
+                f,
+                "track {}: event {} at tick {} has a data byte above 0x7f",
+                track, event, tick
+            ),
+            Self::Unparsed {
+                track,
+                offset,
+                ref reason,
+                ..
+            } => write!(
+                f,
+                "track {}: unreadable data at byte {}: {}",
+                track, offset, reason
+            ),
+        }
+    }
+}
+
+fn has_high_bit(data: &EventData) -> bool {
+    match *data {
+        EventData::NoteOnOffData { key, velocity } => (key | velocity) > 0x7f,
+        EventData::ControlData {
+            control_id,
+            control_value,
+        } => (control_id | control_value) > 0x7f,
+        EventData::ProgramChangeData { program_id } => program_id > 0x7f,
+        EventData::ChannelData { channel_pressure } => channel_pressure > 0x7f,
+        _ => false,
+    }
+}
+
+fn validate_track(index: usize, track: &MidiTrack, problems: &mut Vec<Problem>) {
+    if !track.events.last().is_some_and(|e| e.is_end_of_track()) {
+        problems.push(Problem::UnterminatedTrack { track: index });
+    }
+    if track.chunk_length != track.parsed_length {
+        problems.push(Problem::ChunkLengthMismatch {
+            track: index,
+            declared: track.chunk_length,
+            actual: track.parsed_length,
+        });
+    }
+
+    // (event, tick, channel, key) of every note still sounding
+    let mut open: Vec<(usize, u32, u8, u8)> = vec![];
+    for (event_index, (tick, event)) in track.iter_ticks().enumerate() {
+        if let EventData::Unparsed { offset, reason, .. } = &event.data {
+            problems.push(Problem::Unparsed {
+                track: index,
+                event: event_index,
+                tick,
+                offset: *offset,
+                reason: reason.clone(),
+            });
+        }
+        if has_high_bit(&event.data) {
+            problems.push(Problem::DataByteHighBit {
+                track: index,
+                event: event_index,
+                tick,
+            });
+        }
+        let (key, velocity) = match event.data {
+            EventData::NoteOnOffData { key, velocity } => (key, velocity),
+            _ => continue,
+        };
+        let channel = event.status.channel();
+        match event.status.status_type {
+            StatusType::NoteOn if velocity > 0 => open.push((event_index, tick, channel, key)),
+            StatusType::NoteOn | StatusType::NoteOff => {
+                match open.iter().position(|n| n.2 == channel && n.3 == key) {
+                    Some(pos) => {
+                        open.remove(pos);
+                    }
+                    None => problems.push(Problem::UnmatchedNoteOff {
+                        track: index,
+                        event: event_index,
+                        tick,
+                        channel,
+                        key,
+                    }),
+                }
+            }
+            _ => {}
+        }
+    }
+    for (event, tick, channel, key) in open {
+        problems.push(Problem::UnmatchedNoteOn {
+            track: index,
+            event,
+            tick,
+            channel,
+            key,
+        });
+    }
+}
+
+impl MidiFile {
+    /// Lists structural problems in the parsed file, track by track
+    pub fn validate(&self) -> Vec<Problem> {
+        let mut problems = vec![];
+        for (index, track) in self.loaded().tracks.iter().enumerate() {
+            validate_track(index, track, &mut problems);
+        }
+        problems
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs::{self, OpenOptions},
+    io::Write,
+    panic::{self, AssertUnwindSafe},
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc, Mutex,
+    },
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use notify::{RecursiveMode, Watcher as _};
+
+use crate::{parser::MidiFile, pipeline::Pipeline};
+
+/// Appended to as files are converted, in the output folder
+pub const REPORT_FILE: &str = "conversion-report.txt";
+
+/// A format to write each converted file in
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Export {
+    Json,
+    MidiCsv,
+    LilyPond,
+    MusicXml,
+    /// The melody of one track
+    Abc(usize),
+}
+
+impl Export {
+    pub fn extension(self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::MidiCsv => "csv",
+            Self::LilyPond => "ly",
+            Self::MusicXml => "musicxml",
+            Self::Abc(_) => "abc",
+        }
+    }
+
+    pub fn render(self, file: &MidiFile) -> Result<String, Box<dyn Error>> {
+        Ok(match self {
+            Self::Json => file.to_json(),
+            Self::MidiCsv => file.to_midicsv(),
+            Self::LilyPond => file.to_lilypond(),
+            Self::MusicXml => file.to_musicxml(),
+            Self::Abc(track) => file.to_abc(track)?,
+        })
+    }
+}
+
+/// What happens to each file: parse, run the pipeline, write every export
+/// to the output folder under the file's own name
+#[derive(Debug, Clone)]
+pub struct Converter {
+    pub pipeline: Pipeline,
+    pub exports: Vec<Export>,
+    pub output: PathBuf,
+}
+
+impl Converter {
+    pub fn create(pipeline: Pipeline, exports: Vec<Export>, output: &str) -> Self {
+        Self {
+            pipeline,
+            exports,
+            output: PathBuf::from(output),
+        }
+    }
+
+    /// Returns the files written
+    pub fn convert(&self, path: &Path) -> Result<Vec<PathBuf>, Box<dyn Error>> {
+        let mut file = MidiFile::create();
+        file.parse(path.to_str().ok_or("Path is not valid UTF-8")?)?;
+        self.pipeline.apply_file(&mut file);
+        let stem = path.file_stem().ok_or("Path has no file name")?;
+        fs::create_dir_all(&self.output)?;
+        let mut written = vec![];
+        for export in self.exports.iter() {
+            let target = self.output.join(stem).with_extension(export.extension());
+            fs::write(&target, export.render(&file)?)?;
+            written.push(target);
+        }
+        Ok(written)
+    }
+}
+
+/// Outcome of every file seen so far
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Report {
+    pub converted: Vec<(PathBuf, Vec<PathBuf>)>,
+    pub failed: Vec<(PathBuf, String)>,
+}
+
+fn is_midi(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("mid") || e.eq_ignore_ascii_case("midi"))
+}
+
+/// Converts `path`, recording the outcome in `report` and the report file.
+/// A file bad enough to panic the parser is reported rather than taking the
+/// watcher down.
+fn process(converter: &Converter, path: &Path, report: &Mutex<Report>) {
+    let outcome = panic::catch_unwind(AssertUnwindSafe(|| converter.convert(path)))
+        .unwrap_or_else(|_| Err("Conversion panicked".into()));
+    let line = match outcome {
+        Ok(written) => {
+            let line = format!("ok {} -> {} files", path.display(), written.len());
+            report
+                .lock()
+                .unwrap()
+                .converted
+                .push((path.to_path_buf(), written));
+            line
+        }
+        Err(e) => {
+            let line = format!("error {}: {}", path.display(), e);
+            report
+                .lock()
+                .unwrap()
+                .failed
+                .push((path.to_path_buf(), e.to_string()));
+            line
+        }
+    };
+    let _ = fs::create_dir_all(&converter.output);
+    if let Ok(mut log) = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(converter.output.join(REPORT_FILE))
+    {
+        let _ = writeln!(log, "{}", line);
+    }
+}
+
+/// A folder being watched on a background thread
+pub struct Watcher {
+    report: Arc<Mutex<Report>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Watcher {
+    pub fn report(&self) -> Report {
+        self.report.lock().unwrap().clone()
+    }
+
+    /// Stops watching and waits for the file being converted, if any
+    pub fn stop(&mut self) {
+        self.running.store(false, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Watcher {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Converts every `.mid` or `.midi` file created in or copied into `dir`
+/// until stopped. A file is picked up once it has stopped changing for
+/// `settle`, so large copies are not read half written.
+pub fn watch(dir: &str, converter: Converter, settle: Duration) -> Result<Watcher, Box<dyn Error>> {
+    let (sender, receiver) = mpsc::channel();
+    let mut notifier = notify::recommended_watcher(sender)?;
+    notifier.watch(Path::new(dir), RecursiveMode::NonRecursive)?;
+
+    let report = Arc::new(Mutex::new(Report::default()));
+    let running = Arc::new(AtomicBool::new(true));
+    let thread = {
+        let report = report.clone();
+        let running = running.clone();
+        thread::spawn(move || {
+            // keeps the notifier alive for as long as the thread runs
+            let _notifier = notifier;
+            let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+            while running.load(Ordering::SeqCst) {
+                match receiver.recv_timeout(Duration::from_millis(100)) {
+                    Ok(Ok(event)) if event.kind.is_create() || event.kind.is_modify() => {
+                        for path in event.paths.into_iter().filter(|p| is_midi(p)) {
+                            pending.insert(path, Instant::now());
+                        }
+                    }
+                    Err(mpsc::RecvTimeoutError::Disconnected) => break,
+                    _ => {}
+                }
+                let ready: Vec<PathBuf> = pending
+                    .iter()
+                    .filter(|(_, seen)| seen.elapsed() >= settle)
+                    .map(|(path, _)| path.clone())
+                    .collect();
+                for path in ready {
+                    pending.remove(&path);
+                    if path.is_file() {
+                        process(&converter, &path, &report);
+                    }
+                }
+            }
+        })
+    };
+    Ok(Watcher {
+        report,
+        running,
+        thread: Some(thread),
+    })
+}
@@ -1,101 +1,479 @@
-use std::{os::raw::c_int, thread::sleep, time::Duration};
+use std::{
+    error::Error,
+    mem::size_of,
+    os::raw::c_int,
+    ptr::addr_of,
+    thread::sleep,
+    time::{Duration, Instant},
+};
 
-use super::note::Notes;
-use super::parser::{EventData, MidiFile};
+use super::bend::PitchBend;
+use super::clock::{Clock, ClockFollower, SystemClock};
+use super::control::split_14bit;
+use super::error::MidiError;
+use super::handle::{InputHandle, OutputHandle};
+use super::keyboard::{KeyAction, QwertyKeyboard};
+use super::monitor::Monitor;
+use super::output::MidiOutput;
+use super::parser::{EventData, MidiEvent, MidiFile};
+pub use super::playback::PlayOptions;
+use super::playback::{dry_run, routed_message, run_schedule, song_position_message, SentMessage};
+use super::queue::OverflowPolicy;
+use super::routing::RoutingTable;
 use super::status::StatusType;
+use super::sysex::{SYSEX_END, SYSEX_START};
 
 #[cfg(windows)]
-use windows::Win32::Media::{
-    Audio::{
-        midiInClose, midiInOpen, midiInStart, midiInStop, midiOutClose, midiOutOpen, midiOutReset,
-        midiOutShortMsg, CALLBACK_FUNCTION, CALLBACK_NULL, HMIDIIN, HMIDIOUT,
+use windows::{
+    core::PSTR,
+    Win32::Media::{
+        Audio::{
+            midiInGetErrorTextA, midiOutGetErrorTextA, midiOutLongMsg, midiOutPrepareHeader,
+            midiOutShortMsg, midiOutUnprepareHeader, midiStreamClose, midiStreamOpen,
+            midiStreamOut, midiStreamProperty, midiStreamRestart, midiStreamStop, CALLBACK_NULL,
+            HMIDIOUT, HMIDISTRM, MHDR_DONE, MIDIHDR, MIDIPROPTIMEDIV, MIDIPROP_SET,
+            MIDIPROP_TIMEDIV,
+        },
+        MAXERRORLENGTH,
     },
-    MM_MIM_DATA,
 };
 
-pub unsafe fn send_midi(device: HMIDIOUT, status: StatusType, channel: u32, low: u32, high: u32) {
+fn backend_error(code: u32, get_text: unsafe fn(u32, &mut [u8]) -> u32) -> MidiError {
+    let mut text = [0u8; MAXERRORLENGTH as usize];
+    let text = match unsafe { get_text(code, &mut text) } {
+        0 => {
+            let length = text.iter().position(|b| *b == 0).unwrap_or(text.len());
+            String::from_utf8_lossy(&text[..length]).into_owned()
+        }
+        _ => "Unknown error".to_string(),
+    };
+    MidiError::Backend(code, text)
+}
+
+/// Turns the result of a `midiOut*` or `midiStream*` call into a Result
+pub(crate) fn check_out(code: u32) -> Result<(), MidiError> {
+    match code {
+        0 => Ok(()),
+        code => Err(backend_error(code, midiOutGetErrorTextA)),
+    }
+}
+
+/// Turns the result of a `midiIn*` call into a Result
+pub(crate) fn check_in(code: u32) -> Result<(), MidiError> {
+    match code {
+        0 => Ok(()),
+        code => Err(backend_error(code, midiInGetErrorTextA)),
+    }
+}
+
+/// Failures are dropped here, as the trait cannot report them; use
+/// `send_midi` where they matter
+impl MidiOutput for HMIDIOUT {
+    fn send_short(&mut self, message: u32) {
+        unsafe { midiOutShortMsg(*self, message) };
+    }
+}
+
+/// Sends a channel message, `low` and `high` being its data bytes
+///
+/// # Safety
+///
+/// `device` must be an open output handle, not closed until this returns.
+pub unsafe fn send_midi(
+    device: HMIDIOUT,
+    status: StatusType,
+    channel: u32,
+    low: u32,
+    high: u32,
+) -> Result<(), MidiError> {
     let dw_msg = status as u32 | channel | (high << 16) | (low << 8);
-    midiOutShortMsg(device, dw_msg);
+    check_out(midiOutShortMsg(device, dw_msg))
 }
 
-pub unsafe fn send_midi_single(device: HMIDIOUT, status: StatusType, channel: u32, low: u32) {
+/// Sends a channel message with a single data byte
+///
+/// # Safety
+///
+/// `device` must be an open output handle, not closed until this returns.
+pub unsafe fn send_midi_single(
+    device: HMIDIOUT,
+    status: StatusType,
+    channel: u32,
+    low: u32,
+) -> Result<(), MidiError> {
     let dw_msg = status as u32 | channel | (low << 8);
-    midiOutShortMsg(device, dw_msg);
+    check_out(midiOutShortMsg(device, dw_msg))
 }
 
-pub unsafe fn output() {
-    let mut h_device = HMIDIOUT::default();
-    midiOutOpen(&mut h_device, 0u32, 0, 0, CALLBACK_NULL);
+/// # Safety
+///
+/// `device` must be an open output handle, not closed until this returns.
+pub unsafe fn send_pitch_bend(
+    device: HMIDIOUT,
+    channel: u32,
+    bend: PitchBend,
+) -> Result<(), MidiError> {
+    send_midi(
+        device,
+        StatusType::PitchBendChange,
+        channel,
+        bend.least_bytes() as u32,
+        bend.most_bytes() as u32,
+    )
+}
 
-    // send_midi_single(h_device, Status::ProgramChange, 0, 1);
+/// Sends a 14-bit controller as its MSB and LSB messages
+///
+/// # Safety
+///
+/// `device` must be an open output handle, not closed until this returns.
+pub unsafe fn send_control_14bit(
+    device: HMIDIOUT,
+    channel: u32,
+    control_id: u8,
+    value: u16,
+) -> Result<(), MidiError> {
+    for (id, value) in split_14bit(control_id, value) {
+        send_midi(
+            device,
+            StatusType::CtrlChange,
+            channel,
+            id as u32,
+            value as u32,
+        )?;
+    }
+    Ok(())
+}
 
+/// Plays `test.mid` to the first output device
+///
+/// # Safety
+///
+/// Opens and closes the devices it uses itself, so the only requirement is
+/// that the Windows MIDI API is usable from this thread.
+pub unsafe fn output() {
     let mut midi = MidiFile::create();
     midi.parse("test.mid").unwrap();
-    println!("A: {}!", midi.tempo);
-    let mut prev_tick = 0;
-    send_midi_single(h_device, StatusType::ProgramChange, 0, 0);
-    for i in midi.tracks.iter() {
-        for ev in i.events.iter() {
-            if ev.delta_tick > 1000 {
-                if let EventData::SysexData { .. } = &ev.data {
-                    continue;
-                }
-            }
-            sleep(Duration::from_millis(
-                ((ev.delta_tick as f64) * (midi.tempo as f64) / midi.division as f64 / 1000.0).round()
-                    as u64,
-            ));
-            prev_tick += ev.delta_tick;
-            if let EventData::NoteOnOffData { key, velocity } = ev.data {
-                let note = Notes::from(key as u32).unwrap();
-                if ev.status.status_type == StatusType::NoteOn {
-                    send_midi(
-                        h_device,
-                        StatusType::NoteOn,
-                        0,
-                        note.0.octave(note.1 as u32).unwrap(),
-                        velocity as u32,
-                    );
-                } else {
-                    send_midi(
-                        h_device,
-                        StatusType::NoteOff,
-                        0,
-                        note.0.octave(note.1 as u32).unwrap(),
-                        0,
-                    );
-                }
+    play_stream(0, &midi).unwrap();
+}
+
+const MEVT_SHORTMSG: u32 = 0x00;
+const MEVT_TEMPO: u32 = 0x01;
+/// Messages an input device may queue between two polls
+const INPUT_CAPACITY: usize = 1024;
+/// Events per stream buffer, keeping each well under the 64K a buffer may hold
+const STREAM_CHUNK: usize = 4096;
 
-                println!(
-                    "Status: {:?}, DeltaTick: {}, Total: {}, Millis: {}, Note: {:?}",
-                    ev.status.status_type,
-                    ev.delta_tick,
-                    prev_tick,
-                    ((ev.delta_tick * midi.tempo) as f32 / midi.division as f32 / 1000.0).round()
-                        as u64,
-                    note.0
-                );
+/// Stream ticks per quarter note and microseconds per quarter note, so one
+/// tick of the stream is one microsecond
+const STREAM_DIVISION: u32 = 1000;
+const STREAM_TEMPO: u32 = 1000;
+
+/// The schedule as MIDIEVENTs: delta microseconds, stream id and the
+/// message, after a MEVT_TEMPO that sets the microsecond tick
+fn stream_events(sent: &[SentMessage]) -> Vec<[u32; 3]> {
+    let mut prev = 0;
+    let events = sent.iter().map(|sent| {
+        let delta = (sent.micros - prev) as u32;
+        prev = sent.micros;
+        [delta, 0, MEVT_SHORTMSG << 24 | sent.message]
+    });
+    [[0, 0, MEVT_TEMPO << 24 | STREAM_TEMPO]]
+        .into_iter()
+        .chain(events)
+        .collect()
+}
+
+/// Plays through the stream API so the driver times every event rather than
+/// a sleeping thread. Blocks until the end of the file or Esc.
+///
+/// # Safety
+///
+/// Opens and closes the devices it uses itself, so the only requirement is
+/// that the Windows MIDI API is usable from this thread.
+pub unsafe fn play_stream(device_id: u32, midi: &MidiFile) -> Result<(), Box<dyn Error>> {
+    play_stream_with(device_id, midi, PlayOptions::default())
+}
+
+/// Streams the same schedule `play_file_with` would play, so regions,
+/// offsets and release velocity apply here too
+///
+/// # Safety
+///
+/// As for `play_stream`.
+pub unsafe fn play_stream_with(
+    device_id: u32,
+    midi: &MidiFile,
+    mut options: PlayOptions,
+) -> Result<(), Box<dyn Error>> {
+    let sent = dry_run(midi, &mut options, &RoutingTable::create());
+    let mut stream = HMIDISTRM::default();
+    check_out(midiStreamOpen(
+        &mut stream,
+        &mut [device_id],
+        0,
+        0,
+        CALLBACK_NULL.0,
+    ))?;
+    let mut division = MIDIPROPTIMEDIV {
+        cbStruct: size_of::<MIDIPROPTIMEDIV>() as u32,
+        dwTimeDiv: STREAM_DIVISION,
+    };
+    if let Err(e) = check_out(midiStreamProperty(
+        stream,
+        &mut division as *mut MIDIPROPTIMEDIV as *mut u8,
+        (MIDIPROP_SET | MIDIPROP_TIMEDIV) as u32,
+    )) {
+        midiStreamClose(stream);
+        return Err(e.into());
+    }
+
+    let mut buffers: Vec<Vec<[u32; 3]>> = stream_events(&sent)
+        .chunks(STREAM_CHUNK)
+        .map(|chunk| chunk.to_vec())
+        .collect();
+    // the driver holds on to these until each is marked done; the flags are
+    // read through raw pointers since the driver writes them behind our back
+    let mut headers: Vec<MIDIHDR> = buffers
+        .iter_mut()
+        .map(|buffer| {
+            let length = (buffer.len() * size_of::<[u32; 3]>()) as u32;
+            MIDIHDR {
+                lpData: PSTR(buffer.as_mut_ptr() as *mut u8),
+                dwBufferLength: length,
+                dwBytesRecorded: length,
+                ..Default::default()
             }
+        })
+        .collect();
+    let out = HMIDIOUT(stream.0);
+    let size = size_of::<MIDIHDR>() as u32;
+    let mut prepared = 0;
+    let mut result = Ok(());
+    for header in headers.iter_mut() {
+        result = check_out(midiOutPrepareHeader(out, header, size));
+        if result.is_err() {
+            break;
+        }
+        prepared += 1;
+        result = check_out(midiStreamOut(stream, header, size));
+        if result.is_err() {
+            break;
+        }
+    }
+    if result.is_ok() {
+        result = check_out(midiStreamRestart(stream));
+    }
+    if result.is_ok() {
+        while !headers
+            .iter()
+            .all(|header| addr_of!(header.dwFlags).read_unaligned() & MHDR_DONE != 0)
+        {
+            if _kbhit() != 0 && _getch() == 0x1B {
+                break;
+            }
+            sleep(Duration::from_millis(10));
+        }
+    }
+
+    // stopping returns every buffer still queued and turns notes off
+    midiStreamStop(stream);
+    for header in headers.iter_mut().take(prepared) {
+        midiOutUnprepareHeader(out, header, size);
+    }
+    midiStreamClose(stream);
+    Ok(result?)
+}
+
+/// Sends a single-byte real-time message such as clock or start/stop
+///
+/// # Safety
+///
+/// `device` must be an open output handle, not closed until this returns.
+pub unsafe fn send_realtime(device: HMIDIOUT, status: StatusType) -> Result<(), MidiError> {
+    check_out(midiOutShortMsg(device, status as u32))
+}
+
+/// # Safety
+///
+/// `device` must be an open output handle, not closed until this returns.
+pub unsafe fn send_song_position(device: HMIDIOUT, position: u16) -> Result<(), MidiError> {
+    check_out(midiOutShortMsg(device, song_position_message(position)))
+}
+
+/// Sends a system exclusive message, adding the F0 and F7 framing if `data`
+/// lacks it. Blocks until the driver has sent the whole message.
+///
+/// # Safety
+///
+/// `device` must be an open output handle, not closed until this returns.
+pub unsafe fn send_sysex(device: HMIDIOUT, data: &[u8]) -> Result<(), Box<dyn Error>> {
+    let mut message = data.to_vec();
+    if message.first() != Some(&SYSEX_START) {
+        message.insert(0, SYSEX_START);
+    }
+    if message.last() != Some(&SYSEX_END) || message.len() == 1 {
+        message.push(SYSEX_END);
+    }
+    if message[1..message.len() - 1].iter().any(|b| *b > 0x7f) {
+        return Err("SysEx data bytes must be below 0x80".into());
+    }
+
+    let length = message.len() as u32;
+    let mut header = MIDIHDR {
+        lpData: PSTR(message.as_mut_ptr()),
+        dwBufferLength: length,
+        dwBytesRecorded: length,
+        ..Default::default()
+    };
+    let size = size_of::<MIDIHDR>() as u32;
+    check_out(midiOutPrepareHeader(device, &mut header, size))?;
+    let result = check_out(midiOutLongMsg(device, &header, size));
+    if result.is_ok() {
+        while addr_of!(header.dwFlags).read_unaligned() & MHDR_DONE == 0 {
+            sleep(Duration::from_millis(1));
         }
     }
+    let unprepared = check_out(midiOutUnprepareHeader(device, &mut header, size));
+    Ok(result.and(unprepared)?)
+}
+
+/// Sends a channel message, moved to `channel` when one is given and with
+/// `release` filled in as its release velocity
+unsafe fn send_event(
+    h_device: HMIDIOUT,
+    ev: &MidiEvent,
+    channel: Option<u8>,
+    release: Option<u8>,
+) -> Result<(), MidiError> {
+    match routed_message(ev, channel, release) {
+        Some(message) => check_out(midiOutShortMsg(h_device, message)),
+        None => Ok(()),
+    }
+}
+
+/// # Safety
+///
+/// `h_device` must be an open output handle, not closed until this returns.
+pub unsafe fn play_file(h_device: HMIDIOUT, midi: &MidiFile) -> Result<(), MidiError> {
+    play_file_with(h_device, midi, PlayOptions::default())
+}
+
+/// # Safety
+///
+/// `h_device` must be an open output handle, not closed until this returns.
+pub unsafe fn play_file_with(
+    h_device: HMIDIOUT,
+    midi: &MidiFile,
+    options: PlayOptions,
+) -> Result<(), MidiError> {
+    play_with_clock(h_device, midi, options, &SystemClock::create())
+}
+
+/// Plays against `clock`, whose time zero is the moment playback begins
+///
+/// # Safety
+///
+/// `h_device` must be an open output handle, not closed until this returns.
+pub unsafe fn play_with_clock(
+    h_device: HMIDIOUT,
+    midi: &MidiFile,
+    options: PlayOptions,
+    clock: &dyn Clock,
+) -> Result<(), MidiError> {
+    play_routed(&[h_device], midi, options, &RoutingTable::create(), clock)
+}
 
-    midiOutReset(h_device);
-    midiOutClose(h_device);
+/// Plays to several devices, with `routing` picking the device and channel
+/// for each track, channel and the metronome. Clock messages go to every
+/// device. Stops at the first message a device refuses.
+///
+/// # Safety
+///
+/// Every handle in `devices` must be an open output handle, not closed until
+/// this returns.
+pub unsafe fn play_routed(
+    devices: &[HMIDIOUT],
+    midi: &MidiFile,
+    mut options: PlayOptions,
+    routing: &RoutingTable,
+    clock: &dyn Clock,
+) -> Result<(), MidiError> {
+    run_schedule(
+        midi,
+        &mut options,
+        routing,
+        clock,
+        devices.len(),
+        |device, message| check_out(midiOutShortMsg(devices[device], message)),
+    )
 }
 
-pub fn midi_in_proc(
-    _h_device: HMIDIIN,
-    w_msg: u32,
-    _dw_instance: u32,
-    dw_param1: u32,
-    _dw_param2: u32,
-) {
-    if w_msg == MM_MIM_DATA {
-        let status = dw_param1 & 0xff;
-        let high = dw_param1 >> 8 & 0xff;
-        let low = dw_param1 >> 16 & 0xff;
-        println!("Status: {:X} - High: {:X} - Low: {:X}", status, high, low);
+/// Plays `midi` as a clock slave of the device on `input_id`: nothing moves
+/// until the master sends Start or Continue, tempo follows the incoming
+/// clock and Song Position relocates playback. Returns on Esc or at the end.
+///
+/// # Safety
+///
+/// `h_device` must be an open output handle, not closed until this returns.
+/// The input device is opened and closed here.
+pub unsafe fn play_following_clock(
+    h_device: HMIDIOUT,
+    midi: &MidiFile,
+    input_id: u32,
+) -> Result<(), MidiError> {
+    let loaded = midi.loaded();
+    let midi: &MidiFile = &loaded;
+    let (input, messages) =
+        InputHandle::open(input_id, INPUT_CAPACITY, OverflowPolicy::DropOldest)?;
+    let mut follower = ClockFollower::create();
+
+    let mut events: Vec<(u32, &MidiEvent)> = midi
+        .tracks
+        .iter()
+        .flat_map(|track| track.iter_ticks())
+        .filter(|(_, ev)| !matches!(ev.data, EventData::SysexData { .. }))
+        .collect();
+    events.sort_by_key(|(tick, _)| *tick);
+
+    let mut next = 0;
+    let mut last_position = 0.0;
+    let mut relocations = 0;
+    let mut result = Ok(());
+    while next < events.len() && result.is_ok() {
+        if _kbhit() != 0 && _getch() == 0x1B {
+            let mut device = h_device;
+            device.panic();
+            break;
+        }
+        for message in messages.drain() {
+            if let Ok(event) = message.event() {
+                follower.feed(&event, message.micros as f64);
+            }
+        }
+        let (position, relocated) = match follower.running {
+            true => {
+                let relocated = follower.relocations != relocations;
+                relocations = follower.relocations;
+                let micros = input.started().elapsed().as_micros() as f64;
+                (Some(follower.position(midi.division, micros)), relocated)
+            }
+            false => (None, false),
+        };
+        if let Some(position) = position {
+            // jump to the new sixteenth note instead of replaying what lies between
+            if relocated || position < last_position {
+                next = events.partition_point(|(tick, _)| (*tick as f64) < position);
+            }
+            last_position = position;
+            while result.is_ok() && next < events.len() && events[next].0 as f64 <= position {
+                result = send_event(h_device, events[next].1, None, None);
+                next += 1;
+            }
+        }
+        sleep(Duration::from_millis(1));
     }
+    result
 }
 
 extern "C" {
@@ -103,21 +481,35 @@ extern "C" {
     fn _kbhit() -> c_int;
 }
 
-pub unsafe fn input() {
-    let mut h_device = HMIDIIN::default();
-    midiInOpen(
-        &mut h_device,
-        0u32,
-        midi_in_proc as usize,
-        0usize,
-        CALLBACK_FUNCTION,
-    );
-    midiInStart(h_device);
+/// Prints input from the first device until escape or q
+///
+/// # Safety
+///
+/// Opens and closes the devices it uses itself, so the only requirement is
+/// that the Windows MIDI API is usable from this thread.
+pub unsafe fn input() -> Result<(), MidiError> {
+    input_with(&Monitor::human())
+}
+
+/// Prints input from the first device through `monitor` until escape or q
+///
+/// # Safety
+///
+/// Opens and closes the devices it uses itself, so the only requirement is
+/// that the Windows MIDI API is usable from this thread.
+pub unsafe fn input_with(monitor: &Monitor) -> Result<(), MidiError> {
+    if let Some(header) = monitor.header() {
+        println!("{}", header);
+    }
+    let (_input, messages) = InputHandle::open(0, INPUT_CAPACITY, OverflowPolicy::DropOldest)?;
 
     loop {
+        for message in messages.drain() {
+            println!("{}", monitor.line(&message));
+        }
         // BREAK IF
         if _kbhit() == 0 {
-            sleep(Duration::from_millis(100));
+            sleep(Duration::from_millis(10));
             continue;
         }
         let c = _getch();
@@ -128,7 +520,67 @@ pub unsafe fn input() {
             break;
         };
     }
+    Ok(())
+}
+
+/// Plays the computer keyboard as an instrument on `device_id` until escape
+/// is pressed. The console reports no key releases, so each note lasts
+/// `hold` after its last press; holding a key down keeps it sounding through
+/// the key repeat.
+///
+/// # Safety
+///
+/// Opens and closes the devices it uses itself, so the only requirement is
+/// that the Windows MIDI API is usable from this thread.
+pub unsafe fn keyboard(device_id: u32, hold: Duration) -> Result<(), MidiError> {
+    let output = OutputHandle::open(device_id)?;
+    let mut keyboard = QwertyKeyboard::create();
+    let mut sounding: Vec<(u8, Instant)> = vec![];
+
+    loop {
+        let now = Instant::now();
+        for (note, _) in sounding.iter().filter(|(_, until)| *until <= now) {
+            output.send(
+                StatusType::NoteOff,
+                keyboard.channel as u32,
+                *note as u32,
+                0,
+            )?;
+        }
+        sounding.retain(|(_, until)| *until > now);
 
-    midiInStop(h_device);
-    midiInClose(h_device);
+        if _kbhit() == 0 {
+            sleep(Duration::from_millis(5));
+            continue;
+        }
+        let c = _getch();
+        // arrows and function keys come as a prefix and a second code
+        if c == 0 || c == 0xe0 {
+            _getch();
+            continue;
+        }
+        match keyboard.press(c as u8) {
+            Some(KeyAction::Quit) => break,
+            Some(KeyAction::Note(note)) => match sounding.iter_mut().find(|(n, _)| *n == note) {
+                Some((_, until)) => *until = now + hold,
+                None => {
+                    output.send(
+                        StatusType::NoteOn,
+                        keyboard.channel as u32,
+                        note as u32,
+                        keyboard.velocity as u32,
+                    )?;
+                    sounding.push((note, now + hold));
+                }
+            },
+            Some(KeyAction::Octave(octave)) => println!("Octave {}", octave),
+            Some(KeyAction::Velocity(velocity)) => println!("Velocity {}", velocity),
+            None => {}
+        }
+    }
+
+    for (note, _) in sounding {
+        output.send(StatusType::NoteOff, keyboard.channel as u32, note as u32, 0)?;
+    }
+    Ok(())
 }
@@ -0,0 +1,166 @@
+use crate::{
+    bend::PitchBend,
+    parser::{EventData, MetaData, MidiEvent, MidiFile, SysExMeta},
+    status::StatusType,
+    tempo::TempoMap,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChannelState {
+    pub program: Option<u8>,
+    pub controls: [Option<u8>; 128],
+    pub bend: PitchBend,
+    pub pressure: u8,
+    /// (key, velocity) of every note still sounding
+    pub notes: Vec<(u8, u8)>,
+}
+
+impl ChannelState {
+    pub fn create() -> Self {
+        Self {
+            program: None,
+            controls: [None; 128],
+            bend: PitchBend::center(),
+            pressure: 0,
+            notes: vec![],
+        }
+    }
+}
+
+/// Everything a consumer joining mid-stream needs to render from a point on
+#[derive(Debug, Clone, PartialEq)]
+pub struct StreamState {
+    pub tempo: u32,
+    pub channels: Vec<ChannelState>,
+}
+
+impl StreamState {
+    pub fn create(tempo: u32) -> Self {
+        Self {
+            tempo,
+            channels: vec![ChannelState::create(); 16],
+        }
+    }
+
+    pub fn apply(&mut self, event: &MidiEvent) {
+        if let EventData::SysexData {
+            meta_type: Some(SysExMeta::MetaSetTempo),
+            meta: MetaData::TripleU8(a, b, c),
+        } = event.data
+        {
+            self.tempo = (a as u32) << 16 | (b as u32) << 8 | c as u32;
+        }
+        if !event.status.is_channel_message() {
+            return;
+        }
+        let channel = &mut self.channels[event.status.channel() as usize];
+        match (event.status.status_type, &event.data) {
+            (StatusType::NoteOn, EventData::NoteOnOffData { key, velocity }) if *velocity > 0 => {
+                channel.notes.push((*key, *velocity))
+            }
+            (StatusType::NoteOn | StatusType::NoteOff, EventData::NoteOnOffData { key, .. }) => {
+                if let Some(i) = channel.notes.iter().position(|(k, _)| k == key) {
+                    channel.notes.remove(i);
+                }
+            }
+            (
+                _,
+                EventData::ControlData {
+                    control_id,
+                    control_value,
+                },
+            ) if *control_id < 128 => channel.controls[*control_id as usize] = Some(*control_value),
+            (_, EventData::ProgramChangeData { program_id }) => channel.program = Some(*program_id),
+            (_, EventData::ChannelData { channel_pressure }) => {
+                channel.pressure = *channel_pressure
+            }
+            (_, EventData::PitchBendData { bend }) => channel.bend = *bend,
+            _ => {}
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct TimedEvent<'a> {
+    pub tick: u32,
+    pub seconds: f64,
+    pub track: usize,
+    pub event: &'a MidiEvent,
+}
+
+#[derive(Debug, Clone)]
+pub struct EventWindow<'a> {
+    pub index: usize,
+    pub start: f64,
+    pub end: f64,
+    /// State in effect at `start`, before any of `events`
+    pub state: StreamState,
+    pub events: Vec<TimedEvent<'a>>,
+}
+
+pub struct EventWindows<'a> {
+    events: Vec<TimedEvent<'a>>,
+    pos: usize,
+    seconds: f64,
+    index: usize,
+    state: StreamState,
+}
+
+impl<'a> Iterator for EventWindows<'a> {
+    type Item = EventWindow<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.pos >= self.events.len() {
+            return None;
+        }
+        let start = self.index as f64 * self.seconds;
+        let end = start + self.seconds;
+        let state = self.state.clone();
+        let count = self.events[self.pos..].partition_point(|e| e.seconds < end);
+        let events = self.events[self.pos..self.pos + count].to_vec();
+        for timed in events.iter() {
+            self.state.apply(timed.event);
+        }
+        self.pos += count;
+        self.index += 1;
+        Some(EventWindow {
+            index: self.index - 1,
+            start,
+            end,
+            state,
+            events,
+        })
+    }
+}
+
+impl MidiFile {
+    /// Splits the merged tracks into consecutive windows of `seconds` each,
+    /// including empty ones, so a stream can be consumed with bounded lookahead.
+    /// Tracks left unparsed by lazy parsing count as empty until loaded.
+    pub fn windows(&self, seconds: f64) -> EventWindows<'_> {
+        let tempo_map = TempoMap::from_file(self);
+        let mut events: Vec<TimedEvent> = self
+            .tracks
+            .iter()
+            .enumerate()
+            .flat_map(|(track, t)| {
+                t.iter_ticks()
+                    .map(move |(tick, event)| (tick, track, event))
+            })
+            .map(|(tick, track, event)| TimedEvent {
+                tick,
+                seconds: tempo_map.seconds_at(tick),
+                track,
+                event,
+            })
+            .collect();
+        events.sort_by_key(|e| e.tick);
+        EventWindows {
+            events,
+            pos: 0,
+            seconds: seconds.max(f64::EPSILON),
+            index: 0,
+            state: StreamState::create(tempo_map.tempo_at(0)),
+        }
+    }
+}